@@ -173,37 +173,11 @@ impl Application {
 
         let emulator = self.cpu.clone();
 
-        enum TwoStep<Y, T> {
-            One(T),
-            Two(Y),
-            None,
-        }
-        let mut socket = TwoStep::One(std::net::TcpListener::bind("localhost:1234"));
-
         let builder = debugger_thread::mips_emulator_debugger_builder(
             emulator,
-            Box::new(move || {
-                let mut tmp = TwoStep::None;
-                std::mem::swap(&mut tmp, &mut socket);
-                if let TwoStep::One(tmp) = tmp {
-                    let tmp = tmp?;
-                    tmp.set_nonblocking(true)?;
-                    socket = TwoStep::Two(tmp);
-                } else {
-                    socket = tmp;
-                }
-
-                if let TwoStep::Two(socket) = &mut socket {
-                    match socket.incoming().next() {
-                        Some(Ok(socket)) => Ok(Some(socket)),
-                        Some(Err(e)) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
-                        Some(Err(e)) => Err(e.into()),
-                        None => Ok(None),
-                    }
-                } else {
-                    panic!();
-                }
-            }),
+            debugger_thread::transport_connection(gdb::transport::Transport::Tcp(
+                "localhost:1234".into(),
+            )),
         );
 
         match debugger_thread::start(builder) {
@@ -8,6 +8,7 @@ use gdb::{
     async_target::{GDBAsyncNotifier, GDBAsyncStub},
     connection::Connection,
     target::Target,
+    transport::{Transport, TransportConnection},
 };
 use mips_emulator::cpu::{CpuExternalHandler, EmulatorInterface};
 
@@ -26,6 +27,30 @@ pub struct DebuggerBuilder<C: Connection + Send + Sync + 'static, T: Target + Se
     pub attach: AttachDebugger<C, T>,
 }
 
+impl<T: Target + Send + Sync + 'static> DebuggerBuilder<TransportConnection, T> {
+    /// Makes the debugger wait for its client on `transport` instead
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.create_connetion = transport_connection(transport);
+        self
+    }
+}
+
+/// Accepts a client on `transport`, the listener is only bound once the debugger thread asks for a connection
+pub fn transport_connection(transport: Transport) -> CreateConnection<TransportConnection> {
+    let mut listener = None;
+    Box::new(move || {
+        let bound = match listener.take() {
+            Some(bound) => bound,
+            None => {
+                let bound = transport.listen()?;
+                log::info!("Waiting for gdb on {}", transport);
+                bound
+            }
+        };
+        Ok(listener.insert(bound).accept()?)
+    })
+}
+
 //------------------------------------------------------------------------
 
 #[derive(Debug, Clone, Copy)]
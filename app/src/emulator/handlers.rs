@@ -7,6 +7,7 @@ use eframe::epaint::{Color32, ColorImage};
 use mips_emulator::{
     cpu::{CpuExternalHandler, MipsCpu},
     memory::page_pool::MemoryDefaultAccess,
+    semihosting::{HostSemihosting, SEMIHOSTING_BREAK_CODE},
 };
 
 use crate::{
@@ -83,6 +84,7 @@ pub struct ExternalHandler {
     image: ColorImage,
     screen_x: usize,
     screen_y: usize,
+    semihosting: HostSemihosting,
 }
 
 impl ExternalHandler {
@@ -109,6 +111,7 @@ impl ExternalHandler {
             screen_y: 0,
             last_106: time,
             rand_seed: time,
+            semihosting: HostSemihosting::default(),
             image_sender,
             access_info,
         }
@@ -373,8 +376,12 @@ unsafe impl CpuExternalHandler for ExternalHandler {
         cpu.stop();
     }
 
-    fn breakpoint(&mut self, cpu: &mut MipsCpu<Self>, _call_id: u32) {
-        cpu.stop();
+    fn breakpoint(&mut self, cpu: &mut MipsCpu<Self>, call_id: u32) {
+        if call_id == SEMIHOSTING_BREAK_CODE {
+            self.semihosting.handle(cpu);
+        } else {
+            cpu.stop();
+        }
     }
 }
 
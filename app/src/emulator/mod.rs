@@ -1,4 +1,4 @@
-pub mod debug_target;
+pub use gdbserver::debug_target;
 pub mod debugger_thread;
 pub mod handlers;
 pub mod screen;
@@ -2,15 +2,14 @@ use std::{
     cell::RefCell,
     collections::{HashMap, LinkedList},
     error::Error,
-    fs::File,
-    io::Read,
+    io::Write,
     ops::Deref,
     rc::Rc,
 };
 
 pub enum Define {
     Replacement(Vec<PPToken>),
-    Macro(()),
+    Macro(Macro),
     Label(String),
     Nothing,
 }
@@ -33,6 +32,7 @@ impl Scope {
 struct Report {
     level: ReportLevel,
     r#type: ReportType,
+    kind: DiagnosticKind,
     message: String,
     cause_area: Option<PPArea>,
 }
@@ -57,47 +57,137 @@ impl Report {
         let TokenizerError { error, part } = error;
         Report {
             r#type: ReportType::Tokenizer,
+            kind: DiagnosticKind::InvalidToken,
             message: error,
             cause_area: part.map(PPArea::from_t_data),
             level: ReportLevel::Error,
         }
     }
 
-    fn preprocessor_error(message: String) -> Self {
+    fn preprocessor_error(kind: DiagnosticKind, message: String) -> Self {
         Report {
             r#type: ReportType::PreProcessor,
+            kind,
             message,
             cause_area: Option::None,
             level: ReportLevel::Error,
         }
     }
-    fn preprocessor_error_in_area(message: String, area: impl Into<PPArea>) -> Self {
+    fn preprocessor_error_in_area(
+        kind: DiagnosticKind,
+        message: String,
+        area: impl Into<PPArea>,
+    ) -> Self {
         Report {
             r#type: ReportType::PreProcessor,
+            kind,
             message,
             cause_area: Option::Some(area.into()),
             level: ReportLevel::Error,
         }
     }
 
-    // fn assembler_error_in_area(message: String, area: impl Into<PPArea>) -> Self {
-    //     Report {
-    //         r#type: ReportType::Assembler,
-    //         message,
-    //         cause_area: Option::Some(area.into()),
-    //         level: ReportLevel::Error,
-    //     }
-    // }
+    fn assembler_warning_in_area(
+        kind: DiagnosticKind,
+        message: String,
+        area: impl Into<PPArea>,
+    ) -> Self {
+        Report {
+            r#type: ReportType::Assembler,
+            kind,
+            message,
+            cause_area: Option::Some(area.into()),
+            level: ReportLevel::Warning,
+        }
+    }
+
+    fn assembler_error_in_area(
+        kind: DiagnosticKind,
+        message: String,
+        area: impl Into<PPArea>,
+    ) -> Self {
+        Report {
+            r#type: ReportType::Assembler,
+            kind,
+            message,
+            cause_area: Option::Some(area.into()),
+            level: ReportLevel::Error,
+        }
+    }
 
     fn os_error(message: String) -> Self {
         Report {
             r#type: ReportType::OS,
+            kind: DiagnosticKind::Io,
             message,
             cause_area: Option::None,
             level: ReportLevel::Error,
         }
     }
 
+    fn diagnostic(&self, assembler: &AssemblerState) -> Diagnostic {
+        let span = |area: &TokenData| Span {
+            file: area.file,
+            path: area
+                .file
+                .map(|file| assembler.get_file(file as usize).file.clone()),
+            line: area.line + 1,
+            column: area.column + 1,
+            bytes: area.get_real_index()..area.get_real_index() + area.get_real_size(),
+        };
+        let location = |span: &Span| match &span.path {
+            Some(path) => format!("{}:{}:{}", path, span.line, span.column),
+            None => "a predefine".into(),
+        };
+
+        // the chain starts at the line that was expanded and ends at the text of the token
+        let mut chain = Vec::new();
+        let mut parent = self.cause_area.as_ref();
+        while let Option::Some(p) = parent {
+            chain.push(&p.area);
+            parent = p.parent.as_deref();
+        }
+        let source = self.cause_area.as_ref().map(|area| area.source());
+        let primary = source.map(span);
+        let secondary: Vec<_> = chain
+            .into_iter()
+            .rev()
+            .filter(|area| !source.is_some_and(|source| std::ptr::eq(*area, source)))
+            .map(span)
+            .collect();
+        let mut notes: Vec<_> = secondary
+            .iter()
+            .map(|span| format!("expanded from {}", location(span)))
+            .collect();
+        let mut file = primary.as_ref().and_then(|span| span.file);
+        while let Option::Some(id) = file {
+            let info = assembler.get_file(id as usize);
+            let Option::Some(site) = &info.included_from else {
+                break;
+            };
+            let site = span(&site.area);
+            notes.push(format!(
+                "{} is included from {}",
+                info.file,
+                location(&site)
+            ));
+            file = site.file;
+        }
+
+        Diagnostic {
+            severity: match self.level {
+                ReportLevel::Error => Severity::Error,
+                ReportLevel::Warning => Severity::Warning,
+                ReportLevel::Message => Severity::Note,
+            },
+            kind: self.kind,
+            message: self.message.clone(),
+            primary,
+            secondary,
+            notes,
+        }
+    }
+
     fn to_string(&self, assembler: &AssemblerState) -> String {
         if let Option::Some(area) = &self.cause_area {
             fn generate_file_display(file: &FileInfo, area: &TokenData) -> String {
@@ -193,6 +283,24 @@ impl AssemblerReport {
     }
 }
 
+impl AssemblerReport {
+    /// Every error and warning in the order they were reported
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let state = self.state.deref().borrow();
+        let assembler = state.deref();
+        assembler
+            .errors
+            .iter()
+            .map(|report| report.diagnostic(assembler))
+            .collect()
+    }
+
+    /// `diagnostics` as a JSON array
+    pub fn to_json(&self) -> String {
+        diagnostic::to_json(&self.diagnostics())
+    }
+}
+
 impl std::fmt::Display for AssemblerReport {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut string = String::new();
@@ -210,43 +318,97 @@ impl std::fmt::Display for AssemblerReport {
 pub struct FileInfo {
     pub file: String,
     pub data: String,
+    /// The path of the `#include` that read this file, `None` for the input
+    pub included_from: Option<PPArea>,
 }
 
 //------------------------------------------------------------------------
 
 pub struct AssemblerSettings {
     pub max_token_iterators: usize,
+    /// Defines every input starts out with, name and replacement (`-D NAME=VALUE`)
+    pub defines: Vec<(String, String)>,
+    /// Records every assembled line for `Assembler::listing`
+    pub listing: bool,
+    /// Adds DWARF line info for the instructions so debuggers can show the source (`-g`)
+    pub debug_info: bool,
+}
+
+impl AssemblerSettings {
+    /// Adds a define given the way it is on the command line, `NAME=VALUE` or just `NAME`
+    /// which defines it as 1
+    pub fn define(&mut self, define: &str) {
+        let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+        self.defines.push((name.to_owned(), value.to_owned()));
+    }
 }
 
 impl Default for AssemblerSettings {
     fn default() -> Self {
         Self {
             max_token_iterators: 128,
+            defines: Vec::new(),
+            listing: false,
+            debug_info: false,
         }
     }
 }
 
 //------------------------------------------------------------------------
+
+struct PendingFixup {
+    /// Index into `AssemblerState::sections`
+    section: usize,
+    offset: u32,
+    fixup: Fixup,
+    area: PPArea,
+}
+
 pub struct AssemblerState {
-    cur_addr: u32,
-    curr_sec: u16,
+    /// Every section in the order it was first switched to, `.text` is always first
+    sections: Vec<Section>,
+    curr_sec: usize,
+    /// Labels defined at the current location with nothing emitted after them yet
+    pending_labels: Vec<String>,
+    /// Pseudo instructions may use `$at` without a warning, cleared by `.set noat`
+    allow_at: bool,
+    /// Fields referring to labels, filled in once every label is defined
+    fixups: Vec<PendingFixup>,
     symbols: HashMap<String, Symbol>,
     errors: LinkedList<Report>,
     files: LinkedList<Rc<FileInfo>>,
     scope: Vec<Scope>,
     settings: AssemblerSettings,
+    /// What `add_file` reads from
+    sources: Box<dyn SourceProvider>,
+    /// Only filled in when `AssemblerSettings::listing` is set
+    listing: Vec<ListingEntry>,
+    /// Section and address the line being assembled starts at
+    listing_start: (usize, u32),
+    /// Instructions the pseudo instruction being assembled expanded to
+    listing_expansion: Vec<ListingRange>,
+    /// Only filled in when `AssemblerSettings::debug_info` is set
+    line_rows: Vec<LineRow>,
 }
 
 impl AssemblerState {
-    fn new(settings: AssemblerSettings) -> Self {
+    pub(crate) fn new(settings: AssemblerSettings) -> Self {
         Self {
             scope: Vec::new(),
-            cur_addr: 0,
+            sections: vec![Section::with_default_attributes(".text")],
             curr_sec: 0,
+            pending_labels: Vec::new(),
+            allow_at: true,
+            fixups: Vec::new(),
             errors: LinkedList::new(),
             files: LinkedList::new(),
             symbols: HashMap::new(),
             settings,
+            sources: Box::new(FileSystem::default()),
+            listing: Vec::new(),
+            listing_start: (0, 0),
+            listing_expansion: Vec::new(),
+            line_rows: Vec::new(),
         }
     }
 
@@ -254,23 +416,370 @@ impl AssemblerState {
         &self.settings
     }
 
+    pub fn get_symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// Gets a symbol to modify, referencing an unknown symbol creates it as undefined
+    pub(crate) fn symbol_mut(&mut self, name: &str) -> &mut Symbol {
+        self.symbols
+            .entry(name.to_owned())
+            .or_insert_with(|| Symbol {
+                name: name.to_owned(),
+                section_index: SHN_UNDEF,
+                ..Default::default()
+            })
+    }
+
+    /// Defines a label at the current location of the current section
+    pub(crate) fn define_label(&mut self, name: String) -> Result<(), String> {
+        let value = self.cur_addr();
+        let section_index = self.current_section_index();
+        let sym = self.symbol_mut(&name);
+        if sym.section_index != SHN_UNDEF {
+            return Err(format!("Label {} is already defined", name));
+        }
+        sym.value = value;
+        sym.section_index = section_index;
+        self.pending_labels.push(name);
+        Ok(())
+    }
+
+    pub(crate) fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub(crate) fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values()
+    }
+
+    pub(crate) fn current_section(&self) -> &Section {
+        &self.sections[self.curr_sec]
+    }
+
+    /// The `section_index` of symbols defined in the current section
+    pub(crate) fn current_section_index(&self) -> u16 {
+        self.curr_sec as u16 + 1
+    }
+
+    /// The location counter of the current section
+    pub(crate) fn cur_addr(&self) -> u32 {
+        self.current_section().size()
+    }
+
+    /// Switches to the section with the same name or adds `section` if there is none
+    pub(crate) fn switch_section(&mut self, section: Section) {
+        self.pending_labels.clear();
+        match self
+            .sections
+            .iter()
+            .position(|sec| sec.name == section.name)
+        {
+            Some(index) => self.curr_sec = index,
+            None => {
+                self.sections.push(section);
+                self.curr_sec = self.sections.len() - 1;
+            }
+        }
+    }
+
+    pub(crate) fn emit_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.pending_labels.clear();
+        self.sections[self.curr_sec].emit(bytes)
+    }
+
+    fn emit_word(&mut self, word: u32, area: &PPArea) {
+        if let Err(err) = self.emit_bytes(&word.to_be_bytes()) {
+            self.report_assembler_error(DiagnosticKind::Section, err, area.clone());
+        }
+    }
+
+    /// Emits `word` and leaves the field described by `fixup` to `resolve_fixups`
+    pub(crate) fn emit_fixup(
+        &mut self,
+        word: u32,
+        fixup: Fixup,
+        area: &PPArea,
+    ) -> Result<(), String> {
+        let offset = self.cur_addr();
+        self.emit_bytes(&word.to_be_bytes())?;
+        self.fixups.push(PendingFixup {
+            section: self.curr_sec,
+            offset,
+            fixup,
+            area: area.clone(),
+        });
+        Ok(())
+    }
+
+    /// Fills in the field of every fixup now that all labels have their final address
+    ///
+    /// Branches to local labels in the same section are resolved here, every other reference
+    /// needs a relocation. Local labels are relocated relative to their section with their
+    /// offset stored in the field, everything else (and `%gp_rel`, whose field is too small for
+    /// an offset into a section) is relocated against the symbol itself
+    pub(crate) fn resolve_fixups(&mut self) {
+        for PendingFixup {
+            section,
+            offset,
+            fixup,
+            area,
+        } in std::mem::take(&mut self.fixups)
+        {
+            match self.resolve_fixup(section, offset, &fixup) {
+                Ok((field, target)) => {
+                    let data =
+                        &mut self.sections[section].data[offset as usize..offset as usize + 4];
+                    let word = u32::from_be_bytes(data.try_into().unwrap()) | field;
+                    data.copy_from_slice(&word.to_be_bytes());
+                    if let (Some(target), FixupKind::Relocation(r_type)) = (target, fixup.kind) {
+                        self.sections[section].relocations.push(Relocation {
+                            offset,
+                            r_type,
+                            target,
+                        });
+                    }
+                }
+                Err((kind, err)) => self.report_assembler_error(kind, err, area),
+            }
+        }
+    }
+
+    fn resolve_fixup(
+        &mut self,
+        section: usize,
+        offset: u32,
+        fixup: &Fixup,
+    ) -> Result<(u32, Option<RelocationTarget>), (DiagnosticKind, String)> {
+        let Fixup { kind, target } = fixup;
+        let addend = target.addend as u32;
+        // (section index, value, binding) of a defined symbol
+        let defined = |symbol: &str| {
+            if symbol == LOCATION_COUNTER {
+                // the address is in the addend
+                return Some((section as u16 + 1, 0, SymBind::STB_LOCAL));
+            }
+            self.symbols
+                .get(symbol)
+                .filter(|sym| sym.section_index != SHN_UNDEF)
+                .map(|sym| (sym.section_index, sym.value, sym.binding))
+        };
+        let resolved = match &target.minus {
+            // the distance between two labels is a constant once both are in the same section
+            Some(minus) => match (defined(&target.symbol), defined(minus)) {
+                (Some((a_section, a, _)), Some((b_section, b, _))) if a_section == b_section => {
+                    Some((SHN_ABS, a.wrapping_sub(b), SymBind::STB_LOCAL))
+                }
+                _ => {
+                    return Err((
+                        DiagnosticKind::Expression,
+                        format!(
+                            "Cannot subtract label {} from {} (both have to be defined in the same section)",
+                            minus, target.symbol
+                        ),
+                    ))
+                }
+            },
+            None => defined(&target.symbol),
+        };
+        let r_type = match *kind {
+            FixupKind::Relocation(r_type) => r_type,
+            FixupKind::Immediate { .. } | FixupKind::Offset => {
+                let value = match resolved {
+                    Some((SHN_ABS, value, _)) => Some(value.wrapping_add(addend)),
+                    Some(_) => Option::None,
+                    Option::None => {
+                        return Err((
+                            DiagnosticKind::UndefinedSymbol,
+                            format!("Undefined symbol: {}", target.symbol),
+                        ))
+                    }
+                };
+                return Ok((constant_field(*kind, target, value)?, Option::None));
+            }
+        };
+        // (section index, value)
+        let local = resolved
+            .filter(|(_, _, binding)| *binding == SymBind::STB_LOCAL)
+            .map(|(section_index, value, _)| (section_index, value));
+        Ok(match local {
+            Some((section_index, value))
+                if r_type == MipsRelocationType::R_MIPS_PC16
+                    && (section_index == section as u16 + 1 || section_index == SHN_ABS) =>
+            {
+                let relative = value.wrapping_add(addend).wrapping_sub(offset);
+                (addend_field(r_type, relative)?, Option::None)
+            }
+            Some((SHN_ABS, value)) => (
+                addend_field(r_type, value.wrapping_add(addend))?,
+                Option::None,
+            ),
+            Some((section_index, value)) if r_type != MipsRelocationType::R_MIPS_GPREL16 => (
+                addend_field(r_type, value.wrapping_add(addend))?,
+                Some(RelocationTarget::Section(section_index)),
+            ),
+            _ if target.symbol == LOCATION_COUNTER => {
+                return Err((
+                    DiagnosticKind::Relocation,
+                    "%gp_rel needs a label, not the location counter".into(),
+                ))
+            }
+            _ => {
+                // referencing a symbol that isn't defined declares it
+                self.symbol_mut(&target.symbol);
+                (
+                    addend_field(r_type, addend)?,
+                    Some(RelocationTarget::Symbol(target.symbol.clone())),
+                )
+            }
+        })
+    }
+
+    /// Encodes and emits a single real instruction
+    fn assemble_expanded(&mut self, expanded: Expanded, area: &PPArea) {
+        let source = area.source();
+        if let (true, Some(file)) = (self.settings.debug_info, source.file) {
+            self.line_rows.push(LineRow {
+                section: self.curr_sec,
+                address: self.cur_addr(),
+                file,
+                line: source.line as u32 + 1,
+            });
+        }
+        let Expanded { mnemonic, operands } = expanded;
+        let info = match instruction::lookup(&mnemonic) {
+            Some(info) => info,
+            None => {
+                self.report_assembler_error(
+                    DiagnosticKind::UnknownInstruction,
+                    format!("Unknown instruction: {}", mnemonic),
+                    area.clone(),
+                );
+                self.emit_word(0, area);
+                return;
+            }
+        };
+        match instruction::encode(info, &operands, self.cur_addr(), area) {
+            Ok(encoded) => self.emit_encoded(encoded, area),
+            Err((kind, message, err_area)) => {
+                self.report_assembler_error(kind, message, err_area);
+                self.emit_word(0, area);
+            }
+        }
+    }
+
+    fn emit_encoded(&mut self, encoded: Encoded, area: &PPArea) {
+        let res = match encoded.fixup {
+            Some(fixup) => self.emit_fixup(encoded.word, fixup, area),
+            Option::None => self.emit_bytes(&encoded.word.to_be_bytes()),
+        };
+        if let Err(err) = res {
+            self.report_assembler_error(DiagnosticKind::Section, err, area.clone());
+        }
+    }
+
+    /// Pads the current section with `fill` up to `size` (`.org` and `.space`)
+    pub(crate) fn fill_to(&mut self, size: u32, fill: u8) -> Result<(), String> {
+        self.pending_labels.clear();
+        self.sections[self.curr_sec].fill_to(size, fill)
+    }
+
+    /// Explicit alignment (`.align`), labels before it stay before the padding
+    pub(crate) fn align(&mut self, alignment: u32, fill: u8) -> Result<(), String> {
+        self.pending_labels.clear();
+        self.sections[self.curr_sec].align(alignment, fill)
+    }
+
+    /// Implicit alignment of instructions and data, labels directly before it are moved
+    /// after the padding so they point at what follows them
+    pub(crate) fn auto_align(&mut self, alignment: u32) {
+        let section = &mut self.sections[self.curr_sec];
+        // zeros can be stored in every section
+        section.align(alignment, 0).unwrap();
+        let addr = section.size();
+        for label in &self.pending_labels {
+            if let Some(sym) = self.symbols.get_mut(label) {
+                sym.value = addr;
+            }
+        }
+    }
+
+    pub(crate) fn listing(&self) -> &[ListingEntry] {
+        &self.listing
+    }
+
+    /// Marks where the bytes of the line being assembled start
+    fn begin_listing_line(&mut self) {
+        self.listing_start = (self.curr_sec, self.cur_addr());
+    }
+
+    /// Records the line that was just assembled, `text` is `None` unless listing is enabled
+    fn end_listing_line(&mut self, text: Option<String>, area: PPArea) {
+        let Some(text) = text else {
+            return;
+        };
+        let (section, mut start) = self.listing_start;
+        let end = self.cur_addr();
+        // a section directive, its line belongs to the section it switched to
+        if section != self.curr_sec {
+            start = end;
+        }
+        self.listing.push(ListingEntry {
+            area,
+            section: self.curr_sec,
+            line: ListingRange { text, start, end },
+            expansion: std::mem::take(&mut self.listing_expansion),
+        });
+    }
+
+    pub(crate) fn set_allow_at(&mut self, allow_at: bool) {
+        self.allow_at = allow_at;
+    }
+
     pub fn report_tokenizer_error(&mut self, error: TokenizerError) {
         self.errors.push_back(Report::tokenizer_error(error));
     }
 
-    pub fn report_preprocessor_error(&mut self, error: impl Into<String>, area: PPArea) {
+    pub fn report_preprocessor_error(
+        &mut self,
+        kind: DiagnosticKind,
+        error: impl Into<String>,
+        area: PPArea,
+    ) {
         self.errors
-            .push_back(Report::preprocessor_error_in_area(error.into(), area));
+            .push_back(Report::preprocessor_error_in_area(kind, error.into(), area));
     }
 
-    pub fn report_preprocessor_error_no_area(&mut self, error: impl Into<String>) {
+    pub fn report_preprocessor_error_no_area(
+        &mut self,
+        kind: DiagnosticKind,
+        error: impl Into<String>,
+    ) {
         self.errors
-            .push_back(Report::preprocessor_error(error.into()));
+            .push_back(Report::preprocessor_error(kind, error.into()));
     }
 
-    pub fn report_assembler_error(&mut self, error: impl Into<String>, area: PPArea) {
+    pub fn report_assembler_error(
+        &mut self,
+        kind: DiagnosticKind,
+        error: impl Into<String>,
+        area: PPArea,
+    ) {
         self.errors
-            .push_back(Report::preprocessor_error_in_area(error.into(), area));
+            .push_back(Report::assembler_error_in_area(kind, error.into(), area));
+    }
+
+    pub fn report_assembler_warning(
+        &mut self,
+        kind: DiagnosticKind,
+        warning: impl Into<String>,
+        area: PPArea,
+    ) {
+        self.errors.push_back(Report::assembler_warning_in_area(
+            kind,
+            warning.into(),
+            area,
+        ));
     }
 
     pub fn report_os_error(&mut self, error: impl Into<String>) {
@@ -295,6 +804,14 @@ impl AssemblerState {
         Option::None
     }
 
+    /// Removes the innermost definition of `ident`, returns it if there was one
+    pub fn remove_from_scope(&mut self, ident: &str) -> Option<Define> {
+        self.scope
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.values.remove(ident))
+    }
+
     pub fn put_into_scope(&mut self, ident: String, val: Define) {
         let mut len = self.scope.len();
         if len == 0 {
@@ -304,13 +821,18 @@ impl AssemblerState {
         self.scope[len - 1].values.insert(ident, val);
     }
 
-    pub fn add_file(&mut self, file: String) -> Result<(usize, Rc<FileInfo>), Box<dyn Error>> {
-        let mut input_buf = String::new();
-        let _size = File::open(&file)?.read_to_string(&mut input_buf)?;
+    pub fn add_file(
+        &mut self,
+        file: String,
+        included_from: Option<PPArea>,
+    ) -> Result<(usize, Rc<FileInfo>), Box<dyn Error>> {
+        let file = self.sources.resolve(&file);
+        let data = self.sources.read(&file)?;
 
         let rc = Rc::new(FileInfo {
-            data: input_buf,
+            data,
             file,
+            included_from,
         });
 
         self.files.push_back(rc);
@@ -320,6 +842,11 @@ impl AssemblerState {
     pub fn get_file(&self, file_id: usize) -> Rc<FileInfo> {
         self.files.iter().nth(file_id - 1).unwrap().clone()
     }
+
+    /// Every file that was read, in the order of their ids
+    pub(crate) fn files(&self) -> impl Iterator<Item = &Rc<FileInfo>> {
+        self.files.iter()
+    }
 }
 
 //------------------------------------------------------------------------
@@ -331,9 +858,23 @@ pub struct Assembler {
 //errors
 use util::token::{TokenData, TokenizerError};
 
+use elf::internal::relocation::MipsRelocationType;
+
 use super::{
-    preprocessor::{PPArea, PPToken, PreProcessedLine, PreProcessor},
-    symbol::Symbol,
+    debug::{self, LineRow},
+    diagnostic::{self, Diagnostic, DiagnosticKind, Severity, Span},
+    directive,
+    expression::LOCATION_COUNTER,
+    instruction::{self, Encoded},
+    listing::{self, ListingEntry, ListingRange},
+    object,
+    operand::parse_operands,
+    preprocessor::{Macro, PPArea, PPToken, PreProcessedLine, PreProcessor},
+    pseudo::{self, Expanded},
+    relocation::{addend_field, constant_field, Fixup, FixupKind, Relocation, RelocationTarget},
+    section::Section,
+    source::{FileSystem, SourceProvider},
+    symbol::{SymBind, Symbol, SHN_ABS, SHN_UNDEF},
 };
 
 impl Default for Assembler {
@@ -344,8 +885,24 @@ impl Default for Assembler {
 
 impl Assembler {
     pub fn new() -> Self {
+        Self::with_settings(Default::default())
+    }
+
+    pub fn with_settings(settings: AssemblerSettings) -> Self {
         Assembler {
-            asm_state: Rc::new(RefCell::new(AssemblerState::new(Default::default()))),
+            asm_state: Rc::new(RefCell::new(AssemblerState::new(settings))),
+        }
+    }
+
+    /// Reads the input and includes from `sources` instead of the file system
+    pub fn with_sources(
+        settings: AssemblerSettings,
+        sources: impl SourceProvider + 'static,
+    ) -> Self {
+        let mut state = AssemblerState::new(settings);
+        state.sources = Box::new(sources);
+        Assembler {
+            asm_state: Rc::new(RefCell::new(state)),
         }
     }
 
@@ -357,12 +914,22 @@ impl Assembler {
         self.asm_state.borrow_mut()
     }
 
+    /// The listing of the last `assemble`, empty unless `AssemblerSettings::listing` is set
+    pub fn listing(&self) -> String {
+        listing::write_listing(&self.asm_state.borrow())
+    }
+
+    /// The sections and symbols of the last `assemble`
+    pub fn map(&self) -> String {
+        listing::write_map(&self.asm_state.borrow())
+    }
+
     #[allow(dead_code)]
     #[allow(unused)]
     pub fn assemble(
         &mut self,
         input: String,
-        output: &mut File,
+        output: &mut impl Write,
     ) -> Result<AssemblerReport, AssemblerReport> {
         //let test = memmap::Mmap::map(output)?;
         let pre_processor = match PreProcessor::new(self, input) {
@@ -373,7 +940,30 @@ impl Assembler {
         };
 
         for line in pre_processor {
+            let listing = self.asm_state().settings.listing;
+            let text = listing.then(|| listing::line_text(&line));
+            let area = match &line {
+                PreProcessedLine::Label(_, area) | PreProcessedLine::Instruction(_, _, area) => {
+                    area.clone()
+                }
+            };
+            self.asm_state().begin_listing_line();
             self.assemble_line(line);
+            self.asm_state().end_listing_line(text, area);
+        }
+        self.asm_state().resolve_fixups();
+        if self.asm_state().settings.debug_info {
+            let mut state = self.asm_state();
+            let sections = debug::debug_sections(&state, &state.line_rows);
+            state.sections.extend(sections);
+        }
+
+        if !self.asm_state().has_encountered_error() {
+            let mut state = self.asm_state();
+            let object = object::write_object(&state);
+            if let Err(err) = output.write_all(&object) {
+                state.report_os_error(format!("Failed to write output: {}", err));
+            }
         }
 
         if self.asm_state().has_encountered_error() {
@@ -385,17 +975,71 @@ impl Assembler {
 
     fn assemble_line(&mut self, line: PreProcessedLine) {
         match line {
-            PreProcessedLine::Label(label, _token) => {
+            PreProcessedLine::Label(label, area) => {
                 let mut state = self.asm_state();
-                let sym = Symbol {
-                    name: label.clone(),
-                    value: state.cur_addr,
-                    size: 0,
-                    section_index: state.curr_sec,
-                    ..Default::default()
+                if let Err(err) = state.define_label(label) {
+                    state.report_assembler_error(DiagnosticKind::DuplicateSymbol, err, area);
+                }
+            }
+            PreProcessedLine::Instruction(mnemonic, args, area) if mnemonic.starts_with('.') => {
+                self.assemble_directive(mnemonic, args, area);
+            }
+            PreProcessedLine::Instruction(mnemonic, args, area) => {
+                let mut state = self.asm_state();
+                state.auto_align(4);
+                state.begin_listing_line();
+                let operands = match parse_operands(&state, &args, &area) {
+                    Ok(operands) => operands,
+                    Err((kind, message, err_area)) => {
+                        state.report_assembler_error(kind, message, err_area);
+                        // keep the addresses of everything after this correct
+                        state.emit_word(0, &area);
+                        return;
+                    }
                 };
-                state.symbols.insert(label, sym);
+
+                let mut pseudo = true;
+                let instructions =
+                    match pseudo::expand(&mnemonic, &operands, state.cur_addr(), &area) {
+                        Some(Ok(expansion)) => {
+                            if expansion.uses_at && !state.allow_at {
+                                state.report_assembler_warning(
+                                    DiagnosticKind::AtUsed,
+                                    format!("{} uses $at after .set noat", mnemonic),
+                                    area.clone(),
+                                );
+                            }
+                            expansion.instructions
+                        }
+                        Some(Err((kind, message, err_area))) => {
+                            state.report_assembler_error(kind, message, err_area);
+                            state.emit_word(0, &area);
+                            return;
+                        }
+                        None => {
+                            pseudo = false;
+                            vec![Expanded { mnemonic, operands }]
+                        }
+                    };
+
+                let listing = pseudo && state.settings.listing;
+                for expanded in instructions {
+                    let start = state.cur_addr();
+                    let text = listing.then(|| listing::expanded_text(&expanded));
+                    state.assemble_expanded(expanded, &area);
+                    if let Some(text) = text {
+                        let end = state.cur_addr();
+                        state
+                            .listing_expansion
+                            .push(ListingRange { text, start, end });
+                    }
+                }
             }
         }
     }
+
+    fn assemble_directive(&mut self, directive: String, args: Vec<PPToken>, area: PPArea) {
+        let mut state = self.asm_state();
+        directive::assemble_directive(&mut state, &directive, &args, area);
+    }
 }
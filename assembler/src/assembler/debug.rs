@@ -0,0 +1,650 @@
+//! DWARF debug info so debuggers can map addresses back to source lines
+//!
+//! Only a compile unit without children, its line table and the address ranges it covers are
+//! written, which is enough for stepping, `list` and breakpoints on `file:line`. Line tables
+//! can also be read back, i.e. to show the source of disassembled code
+
+use std::path::Path;
+
+use elf::{internal::relocation::MipsRelocationType, writer::Encoder};
+
+use super::{
+    relocation::{Relocation, RelocationTarget},
+    section::{Section, SectionFlags, SectionKind},
+    AssemblerState,
+};
+
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_CHILDREN_NO: u8 = 0;
+
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA4: u8 = 0x06;
+const DW_FORM_STRING: u8 = 0x08;
+
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+const MIN_INSTRUCTION_LENGTH: u8 = 4;
+const LINE_BASE: i64 = -5;
+const LINE_RANGE: i64 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; OPCODE_BASE as usize - 1] =
+    [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+const ADDRESS_SIZE: u8 = 4;
+
+//------------------------------------------------------------------------
+
+/// An instruction and the line it was written on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LineRow {
+    /// Index into `AssemblerState::sections`
+    pub section: usize,
+    pub address: u32,
+    /// Same as the file ids of the assembler, which start at 1 like DWARF file numbers
+    pub file: u16,
+    /// Starts at 1
+    pub line: u32,
+}
+
+/// Addresses `start..end` of a section that contain instructions
+struct Range {
+    section: usize,
+    start: u32,
+    end: u32,
+}
+
+fn uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = value as u8 & 0x7F;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb128(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = value as u8 & 0x7F;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn string(out: &mut Vec<u8>, string: &str) {
+    out.extend_from_slice(string.as_bytes());
+    out.push(0);
+}
+
+/// A section being written, words that refer to other sections get a `R_MIPS_32` relocation
+struct Writer {
+    data: Vec<u8>,
+    relocations: Vec<Relocation>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            relocations: Vec::new(),
+        }
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// `offset` bytes into the section with `section_index`
+    fn relocated(&mut self, section_index: u16, offset: u32) {
+        self.relocations.push(Relocation {
+            offset: self.data.len() as u32,
+            r_type: MipsRelocationType::R_MIPS_32,
+            target: RelocationTarget::Section(section_index),
+        });
+        self.u32(offset);
+    }
+
+    /// Reserves a 32 bit length that `end_length` fills in
+    fn start_length(&mut self) -> usize {
+        self.u32(0);
+        self.data.len()
+    }
+
+    fn end_length(&mut self, start: usize) {
+        let length = (self.data.len() - start) as u32;
+        self.data[start - 4..start].copy_from_slice(&length.to_be_bytes());
+    }
+
+    fn into_section(self, name: &str) -> Section {
+        let mut section = Section::new(name, SectionKind::Progbits, SectionFlags::default());
+        section.emit(&self.data).unwrap();
+        section.relocations = self.relocations;
+        section
+    }
+}
+
+//------------------------------------------------------------------------
+
+/// Rows sorted by address grouped into the ranges of their sections
+fn ranges(rows: &mut [LineRow]) -> Vec<Range> {
+    rows.sort_by_key(|row| (row.section, row.address));
+    let mut ranges: Vec<Range> = Vec::new();
+    for row in rows.iter() {
+        match ranges.last_mut() {
+            Some(range) if range.section == row.section => range.end = row.address + 4,
+            _ => ranges.push(Range {
+                section: row.section,
+                start: row.address,
+                end: row.address + 4,
+            }),
+        }
+    }
+    ranges
+}
+
+/// `.debug_line` version 3, a sequence for every section with instructions
+fn line_table(state: &AssemblerState, rows: &[LineRow], ranges: &[Range]) -> Writer {
+    let mut out = Writer::new();
+    let unit = out.start_length();
+    out.u16(3);
+    let header = out.start_length();
+    out.data.extend_from_slice(&[
+        MIN_INSTRUCTION_LENGTH,
+        1, // default_is_stmt
+        LINE_BASE as u8,
+        LINE_RANGE as u8,
+        OPCODE_BASE,
+    ]);
+    out.data.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+    // no include directories, file names are relative to the compilation directory
+    out.data.push(0);
+    for file in state.files() {
+        string(&mut out.data, &file.file);
+        // directory, modification time and length
+        out.data.extend_from_slice(&[0, 0, 0]);
+    }
+    out.data.push(0);
+    out.end_length(header);
+
+    for range in ranges {
+        let (mut address, mut file, mut line) = (range.start, 1, 1);
+        out.data
+            .extend_from_slice(&[0, 1 + ADDRESS_SIZE, DW_LNE_SET_ADDRESS]);
+        out.relocated(range.section as u16 + 1, range.start);
+
+        for row in rows.iter().filter(|row| row.section == range.section) {
+            if row.file != file {
+                out.data.push(DW_LNS_SET_FILE);
+                uleb128(&mut out.data, row.file as u64);
+                file = row.file;
+            }
+            let line_delta = row.line as i64 - line as i64;
+            let advance = ((row.address - address) / MIN_INSTRUCTION_LENGTH as u32) as i64;
+            let special = (line_delta - LINE_BASE) + LINE_RANGE * advance + OPCODE_BASE as i64;
+            if (LINE_BASE..LINE_BASE + LINE_RANGE).contains(&line_delta) && special <= 255 {
+                out.data.push(special as u8);
+            } else {
+                if line_delta != 0 {
+                    out.data.push(DW_LNS_ADVANCE_LINE);
+                    sleb128(&mut out.data, line_delta);
+                }
+                if advance != 0 {
+                    out.data.push(DW_LNS_ADVANCE_PC);
+                    uleb128(&mut out.data, advance as u64);
+                }
+                out.data.push(DW_LNS_COPY);
+            }
+            address = row.address;
+            line = row.line;
+        }
+
+        out.data.push(DW_LNS_ADVANCE_PC);
+        uleb128(
+            &mut out.data,
+            ((range.end - address) / MIN_INSTRUCTION_LENGTH as u32) as u64,
+        );
+        out.data.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+    }
+    out.end_length(unit);
+    out
+}
+
+/// The `.debug_abbrev` and `.debug_info` of the compile unit, it only has a pc range if all of
+/// the instructions are in one section, `.debug_aranges` has them all
+fn compile_unit(
+    state: &AssemblerState,
+    ranges: &[Range],
+    abbrev_index: u16,
+    line_index: u16,
+) -> (Writer, Writer) {
+    let single = match ranges {
+        [range] => Some(range),
+        _ => None,
+    };
+
+    let mut abbrev = Writer::new();
+    abbrev
+        .data
+        .extend_from_slice(&[1, DW_TAG_COMPILE_UNIT, DW_CHILDREN_NO]);
+    abbrev
+        .data
+        .extend_from_slice(&[DW_AT_STMT_LIST, DW_FORM_DATA4]);
+    if single.is_some() {
+        abbrev.data.extend_from_slice(&[DW_AT_LOW_PC, DW_FORM_ADDR]);
+        abbrev
+            .data
+            .extend_from_slice(&[DW_AT_HIGH_PC, DW_FORM_ADDR]);
+    }
+    abbrev.data.extend_from_slice(&[
+        DW_AT_NAME,
+        DW_FORM_STRING,
+        DW_AT_COMP_DIR,
+        DW_FORM_STRING,
+        DW_AT_PRODUCER,
+        DW_FORM_STRING,
+        DW_AT_LANGUAGE,
+        DW_FORM_DATA2,
+    ]);
+    abbrev.data.extend_from_slice(&[0, 0, 0]);
+
+    let mut info = Writer::new();
+    let unit = info.start_length();
+    info.u16(3);
+    info.relocated(abbrev_index, 0);
+    info.data.push(ADDRESS_SIZE);
+    uleb128(&mut info.data, 1);
+    info.relocated(line_index, 0);
+    if let Some(range) = single {
+        info.relocated(range.section as u16 + 1, range.start);
+        info.relocated(range.section as u16 + 1, range.end);
+    }
+    string(&mut info.data, &state.get_file(1).file);
+    let comp_dir = std::env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    string(&mut info.data, &comp_dir);
+    string(
+        &mut info.data,
+        concat!("assembler ", env!("CARGO_PKG_VERSION")),
+    );
+    info.u16(DW_LANG_MIPS_ASSEMBLER);
+    info.end_length(unit);
+    (abbrev, info)
+}
+
+/// `.debug_aranges`, the address ranges of the compile unit
+fn address_ranges(ranges: &[Range], info_index: u16) -> Writer {
+    let mut out = Writer::new();
+    let unit = out.start_length();
+    out.u16(2);
+    out.relocated(info_index, 0);
+    out.data.extend_from_slice(&[ADDRESS_SIZE, 0]);
+    // the tuples are aligned to twice the address size
+    out.u32(0);
+    for range in ranges {
+        out.relocated(range.section as u16 + 1, range.start);
+        out.u32(range.end - range.start);
+    }
+    out.u32(0);
+    out.u32(0);
+    out.end_length(unit);
+    out
+}
+
+/// The debug sections for the instructions in `rows`, they go after the existing sections
+///
+/// Nothing is generated if there are no instructions or the source has debug sections of its own
+pub(crate) fn debug_sections(state: &AssemblerState, rows: &[LineRow]) -> Vec<Section> {
+    if rows.is_empty()
+        || state
+            .sections()
+            .iter()
+            .any(|section| section.name.starts_with(".debug_"))
+    {
+        return Vec::new();
+    }
+    let mut rows = rows.to_vec();
+    let ranges = ranges(&mut rows);
+
+    let first = state.sections().len() as u16 + 1;
+    let (abbrev_index, info_index, line_index) = (first, first + 1, first + 2);
+    let (abbrev, info) = compile_unit(state, &ranges, abbrev_index, line_index);
+    vec![
+        abbrev.into_section(".debug_abbrev"),
+        info.into_section(".debug_info"),
+        line_table(state, &rows, &ranges).into_section(".debug_line"),
+        address_ranges(&ranges, info_index).into_section(".debug_aranges"),
+    ]
+}
+
+//------------------------------------------------------------------------
+
+/// A row of a line table read from `.debug_line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTableRow {
+    /// As it is in the section, in objects the address a sequence starts at is relocated
+    pub address: u32,
+    /// Offset in `.debug_line` of the address the sequence of the row starts at, which is where
+    /// its relocation applies
+    pub sequence: usize,
+    /// Index into `LineTable::files`
+    pub file: usize,
+    pub line: u32,
+}
+
+/// The line tables of every unit in a `.debug_line` section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    /// The files of every unit, relative to the directory the unit was compiled in unless
+    /// they are absolute
+    pub files: Vec<String>,
+    pub rows: Vec<LineTableRow>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+    enc: Encoder,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.at..self.at.checked_add(len)?)?;
+        self.at += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let enc = self.enc;
+        Some(enc.read_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let enc = self.enc;
+        Some(enc.read_u32(self.bytes(4)?))
+    }
+
+    /// The value and how many bits were read
+    fn leb128(&mut self) -> Option<(u64, u32)> {
+        let (mut value, mut bits) = (0u64, 0);
+        loop {
+            let byte = self.u8()?;
+            if bits < 64 {
+                value |= ((byte & 0x7F) as u64) << bits;
+            }
+            bits += 7;
+            if byte & 0x80 == 0 {
+                return Some((value, bits));
+            }
+        }
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        Some(self.leb128()?.0)
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let (value, bits) = self.leb128()?;
+        Some(match bits {
+            64.. => value as i64,
+            bits => ((value << (64 - bits)) as i64) >> (64 - bits),
+        })
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.data.get(self.at..)?.iter().position(|b| *b == 0)?;
+        let string = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.at += 1;
+        Some(string)
+    }
+}
+
+/// Reads the line tables of every unit in `data`, units with a DWARF version other than 2 to 4
+/// are skipped. `None` if the section is cut off or malformed
+pub fn read_line_table(data: &[u8], enc: Encoder) -> Option<LineTable> {
+    let mut table = LineTable::default();
+    let mut next_unit = 0;
+    while next_unit < data.len() {
+        let mut reader = Reader {
+            data,
+            at: next_unit,
+            enc,
+        };
+        let end = (reader.u32()? as usize).checked_add(reader.at)?;
+        reader.data = data.get(..end)?;
+        next_unit = end;
+        let version = reader.u16()?;
+        if !(2..=4).contains(&version) {
+            continue;
+        }
+        let program = (reader.u32()? as usize).checked_add(reader.at)?;
+        let min_instruction_length = reader.u8()? as u32;
+        if version == 4 {
+            // maximum operations per instruction, only VLIW architectures have more than one
+            reader.u8()?;
+        }
+        // default_is_stmt
+        reader.u8()?;
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return None;
+        }
+        let opcode_lengths = reader.bytes(opcode_base as usize - 1)?.to_vec();
+
+        // 0 is the directory the unit was compiled in
+        let mut directories = vec![String::new()];
+        loop {
+            match reader.string()? {
+                directory if directory.is_empty() => break,
+                directory => directories.push(directory),
+            }
+        }
+        let first_file = table.files.len();
+        loop {
+            let name = reader.string()?;
+            if name.is_empty() {
+                break;
+            }
+            let directory = reader.uleb128()? as usize;
+            // modification time and length
+            reader.uleb128()?;
+            reader.uleb128()?;
+            table.files.push(match directories.get(directory) {
+                Some(directory) if !directory.is_empty() && !Path::new(&name).is_absolute() => {
+                    format!("{}/{}", directory, name)
+                }
+                _ => name,
+            });
+        }
+
+        reader.at = program;
+        let (mut address, mut file, mut line, mut sequence) = (0u32, 1u64, 1i64, 0);
+        while reader.at < end {
+            let opcode = reader.u8()?;
+            let mut row = false;
+            match opcode {
+                0 => {
+                    let length = reader.uleb128()? as usize;
+                    let next = reader.at.checked_add(length)?;
+                    match reader.u8()? {
+                        DW_LNE_END_SEQUENCE => (address, file, line) = (0, 1, 1),
+                        DW_LNE_SET_ADDRESS => {
+                            sequence = reader.at;
+                            address = reader.u32()?;
+                        }
+                        _ => {}
+                    }
+                    reader.at = next;
+                }
+                DW_LNS_COPY => row = true,
+                DW_LNS_ADVANCE_PC => {
+                    let advance = reader.uleb128()? as u32;
+                    address = address.wrapping_add(advance.wrapping_mul(min_instruction_length));
+                }
+                DW_LNS_ADVANCE_LINE => line += reader.sleb128()?,
+                DW_LNS_SET_FILE => file = reader.uleb128()?,
+                DW_LNS_CONST_ADD_PC => {
+                    let advance = (255 - opcode_base) / line_range;
+                    address = address.wrapping_add(advance as u32 * min_instruction_length);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(reader.u16()? as u32),
+                opcode if opcode < opcode_base => {
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        reader.uleb128()?;
+                    }
+                }
+                special => {
+                    let adjusted = special - opcode_base;
+                    let advance = (adjusted / line_range) as u32 * min_instruction_length;
+                    address = address.wrapping_add(advance);
+                    line += line_base + (adjusted % line_range) as i64;
+                    row = true;
+                }
+            }
+            if row {
+                table.rows.push(LineTableRow {
+                    address,
+                    sequence,
+                    // DWARF 2 to 4 number files from 1
+                    file: first_file + (file as usize).saturating_sub(1),
+                    line: line as u32,
+                });
+            }
+        }
+    }
+    Some(table)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use elf::{
+        external::{from_bytes, TernaryResult},
+        internal::header::header_util::ElfEndian,
+    };
+
+    use super::*;
+    use crate::assembler::{
+        object::tests::assemble_object_with, source::MemorySources, Assembler, AssemblerSettings,
+    };
+
+    /// The rows (address, file number, line) of the first line table in `data`
+    pub(crate) fn decode_line_table(data: &[u8]) -> Vec<(u32, u16, u32)> {
+        let enc = Encoder {
+            endian: ElfEndian::BigEndian,
+        };
+        let end = 4 + enc.read_u32(data) as usize;
+        let table = read_line_table(&data[..end], enc).unwrap();
+        let rows = table.rows.iter();
+        rows.map(|row| (row.address, row.file as u16 + 1, row.line))
+            .collect()
+    }
+
+    /// The contents of every section of an ELF file by name
+    pub(crate) fn sections(elf: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let elf = match from_bytes(elf) {
+            TernaryResult::Ok1(elf) => elf,
+            _ => panic!("not an ELF32 file"),
+        };
+        let mut sections = Vec::new();
+        let mut index = 0;
+        while let Some(section) = elf.section_header(index) {
+            sections.push((section.get_name().to_owned(), section.get_data().to_vec()));
+            index += 1;
+        }
+        sections
+    }
+
+    pub(crate) fn debug_settings() -> AssemblerSettings {
+        AssemblerSettings {
+            debug_info: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn line_table() {
+        let sources = MemorySources::new()
+            .with(
+                "line_table.asm",
+                ".macro clear reg\n  move \\reg, $0\n.endm\nmain:\n  li $t0, 0x12345\n\n\n  clear $t1\n\
+                 #include \"line_table.inc\"\n.data\n  .word 1\n.text\n  nop\n",
+            )
+            .with("line_table.inc", "  addu $v0, $a0, $a1\n  jr $ra\n");
+        let mut object = Vec::new();
+        let res = Assembler::with_sources(debug_settings(), sources)
+            .assemble("line_table.asm".into(), &mut object);
+        assert!(res.is_ok());
+        let sections = sections(&object);
+        let section = |name: &str| {
+            let found = sections.iter().find(|(found, _)| found == name);
+            found.map(|(_, data)| data.as_slice()).unwrap()
+        };
+
+        // li is two instructions, macro lines are in the body and included lines in their file
+        assert_eq!(
+            decode_line_table(section(".debug_line")),
+            [
+                (0, 1, 5),
+                (4, 1, 5),
+                (8, 1, 2),
+                (12, 2, 1),
+                (16, 2, 2),
+                (20, 1, 13)
+            ]
+        );
+        assert!(section(".debug_info").len() > 11);
+        // header, one range and the terminator
+        assert_eq!(section(".debug_aranges").len(), 16 + 8 + 8);
+        // the address of the line table, the compile unit and its range are relocated
+        assert_eq!(section(".rel.debug_line").len(), 8);
+        assert_eq!(section(".rel.debug_info").len(), 8 * 4);
+        assert_eq!(section(".rel.debug_aranges").len(), 8 * 2);
+    }
+
+    #[test]
+    fn no_debug_info() {
+        // data only
+        let object = assemble_object_with("no_debug_info", ".data\n.word 1\n", debug_settings());
+        assert!(sections(&object)
+            .iter()
+            .all(|(name, _)| !name.contains(".debug")));
+        let object = assemble_object_with("no_debug_info", "nop\n", Default::default());
+        assert!(sections(&object)
+            .iter()
+            .all(|(name, _)| !name.contains(".debug")));
+    }
+}
@@ -0,0 +1,367 @@
+//! Errors and warnings in a form tools can consume (editors, CI annotations, language servers)
+
+use std::{fmt::Write, ops::Range};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// Part of a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// Id of the file, 1 is the input and includes follow in the order they were read. `None`
+    /// for text that isn't in a file (predefines)
+    pub file: Option<u16>,
+    pub path: Option<String>,
+    /// Starts at 1
+    pub line: usize,
+    /// Starts at 1, counted in characters
+    pub column: usize,
+    /// Byte offsets into the file
+    pub bytes: Range<usize>,
+}
+
+/// What went wrong, every kind has its own code that doesn't change between versions
+///
+/// The first digit of the code is the stage that reported it: 1 tokenizer, 2 preprocessor,
+/// 3 assembler, 4 linker and 5 the command line driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    /// E0101 text that isn't a token (i.e. an unterminated string or a malformed number)
+    InvalidToken,
+    /// E0201 an `#include` without a path or of a file that can't be read
+    Include,
+    /// E0202 a malformed `#define` or `#undef`
+    Define,
+    /// E0203 a malformed `.macro` header
+    MacroDefinition,
+    /// E0204 a macro used with too many arguments, without a required one or with one that
+    /// can't be pasted
+    MacroArguments,
+    /// E0205 a malformed `#if`, `#ifdef`, `#ifndef` or `#elif` or one whose condition can't be
+    /// evaluated
+    Condition,
+    /// E0206 a block without its end or an end without its block (`#if`/`#endif`,
+    /// `.macro`/`.endm`, `.rept`/`.endr`)
+    UnbalancedBlock,
+    /// E0207 a malformed `.rept` or `.irp`
+    Repeat,
+    /// E0208 a local label (`.loop`, `1f`, `1b`) without the label it is relative to
+    LocalLabel,
+    /// E0209 an unknown preprocessor statement or a token that can't start a line
+    UnexpectedToken,
+    /// E0210 defines, macros and includes nested deeper than `max_token_iterators`
+    ExpansionLimit,
+    /// E0301 a mnemonic that isn't an instruction or pseudo instruction
+    UnknownInstruction,
+    /// E0302 a directive the assembler doesn't know
+    UnknownDirective,
+    /// E0303 the wrong number of operands
+    OperandCount,
+    /// E0304 an operand of the wrong kind (i.e. a label where a register is expected) or a
+    /// register that doesn't exist
+    OperandType,
+    /// E0305 a value that doesn't fit in its field
+    OutOfRange,
+    /// E0306 a branch or jump target that is too far away or not word aligned
+    BranchTarget,
+    /// E0307 a malformed expression, an overflow, a division by zero or arithmetic on label
+    /// addresses
+    Expression,
+    /// E0308 a symbol that has to be defined before it is used
+    UndefinedSymbol,
+    /// E0309 a label or symbol that is defined twice
+    DuplicateSymbol,
+    /// E0310 malformed arguments of a directive
+    DirectiveArguments,
+    /// E0311 data a section can't hold or moving the location counter backwards
+    Section,
+    /// E0312 a relocation operator (`%hi`, `%lo`, `%gp_rel`) where it can't be used
+    Relocation,
+    /// W0301 a pseudo instruction uses `$at` after `.set noat`
+    AtUsed,
+    /// E0401 an input that isn't a valid object
+    InvalidObject,
+    /// E0402 a global symbol defined by more than one object
+    LinkDuplicateSymbol,
+    /// E0403 a symbol that no object defines
+    LinkUndefinedSymbol,
+    /// E0404 the entry symbol isn't defined
+    UndefinedEntry,
+    /// E0405 a relocation whose value doesn't fit
+    LinkRelocation,
+    /// E0406 sections that don't fit in the address space or overlap
+    Layout,
+    /// E0501 a file that can't be read or written
+    Io,
+    /// E0502 warnings were reported and `--werror` turns them into an error
+    WarningsAsErrors,
+}
+
+impl DiagnosticKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::InvalidToken => "E0101",
+            DiagnosticKind::Include => "E0201",
+            DiagnosticKind::Define => "E0202",
+            DiagnosticKind::MacroDefinition => "E0203",
+            DiagnosticKind::MacroArguments => "E0204",
+            DiagnosticKind::Condition => "E0205",
+            DiagnosticKind::UnbalancedBlock => "E0206",
+            DiagnosticKind::Repeat => "E0207",
+            DiagnosticKind::LocalLabel => "E0208",
+            DiagnosticKind::UnexpectedToken => "E0209",
+            DiagnosticKind::ExpansionLimit => "E0210",
+            DiagnosticKind::UnknownInstruction => "E0301",
+            DiagnosticKind::UnknownDirective => "E0302",
+            DiagnosticKind::OperandCount => "E0303",
+            DiagnosticKind::OperandType => "E0304",
+            DiagnosticKind::OutOfRange => "E0305",
+            DiagnosticKind::BranchTarget => "E0306",
+            DiagnosticKind::Expression => "E0307",
+            DiagnosticKind::UndefinedSymbol => "E0308",
+            DiagnosticKind::DuplicateSymbol => "E0309",
+            DiagnosticKind::DirectiveArguments => "E0310",
+            DiagnosticKind::Section => "E0311",
+            DiagnosticKind::Relocation => "E0312",
+            DiagnosticKind::AtUsed => "W0301",
+            DiagnosticKind::InvalidObject => "E0401",
+            DiagnosticKind::LinkDuplicateSymbol => "E0402",
+            DiagnosticKind::LinkUndefinedSymbol => "E0403",
+            DiagnosticKind::UndefinedEntry => "E0404",
+            DiagnosticKind::LinkRelocation => "E0405",
+            DiagnosticKind::Layout => "E0406",
+            DiagnosticKind::Io => "E0501",
+            DiagnosticKind::WarningsAsErrors => "E0502",
+        }
+    }
+}
+
+/// An error, warning or note with where it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Written as its code
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// The text that caused the problem, `None` if it isn't in the source (i.e. a file couldn't
+    /// be read). For a token that is the result of an expansion this is where it was written
+    /// (the argument of a macro, the replacement of a define)
+    pub primary: Option<Span>,
+    /// How the text at `primary` got to where it caused the problem, from the innermost
+    /// expansion out (the parameter in the macro body and then the macro call)
+    pub secondary: Vec<Span>,
+    /// The expansion and include chains as text
+    pub notes: Vec<String>,
+}
+
+//------------------------------------------------------------------------
+
+fn json_string(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl Span {
+    fn write_json(&self, out: &mut String) {
+        out.push_str("{\"file\":");
+        match self.file {
+            Some(file) => {
+                let _ = write!(out, "{}", file);
+            }
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"path\":");
+        match &self.path {
+            Some(path) => json_string(out, path),
+            None => out.push_str("null"),
+        }
+        let _ = write!(
+            out,
+            ",\"line\":{},\"column\":{},\"start\":{},\"end\":{}}}",
+            self.line, self.column, self.bytes.start, self.bytes.end
+        );
+    }
+}
+
+impl Diagnostic {
+    /// A single line JSON object
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    pub(crate) fn write_json(&self, out: &mut String) {
+        out.push_str("{\"severity\":");
+        json_string(out, self.severity.as_str());
+        out.push_str(",\"code\":");
+        json_string(out, self.kind.code());
+        out.push_str(",\"message\":");
+        json_string(out, &self.message);
+        out.push_str(",\"primary\":");
+        match &self.primary {
+            Some(span) => span.write_json(out),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"secondary\":[");
+        for (index, span) in self.secondary.iter().enumerate() {
+            if index != 0 {
+                out.push(',');
+            }
+            span.write_json(out);
+        }
+        out.push_str("],\"notes\":[");
+        for (index, note) in self.notes.iter().enumerate() {
+            if index != 0 {
+                out.push(',');
+            }
+            json_string(out, note);
+        }
+        out.push_str("]}");
+    }
+}
+
+/// A JSON array of `diagnostics`
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::from("[");
+    for (index, diagnostic) in diagnostics.iter().enumerate() {
+        if index != 0 {
+            out.push(',');
+        }
+        diagnostic.write_json(&mut out);
+    }
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_source, source::MemorySources, Assembler};
+
+    #[test]
+    fn json() {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            kind: DiagnosticKind::AtUsed,
+            message: "a \"quoted\"\\path\n\u{1}".into(),
+            primary: Some(Span {
+                file: Some(1),
+                path: Some("main.asm".into()),
+                line: 2,
+                column: 3,
+                bytes: 10..12,
+            }),
+            secondary: vec![Span {
+                file: None,
+                path: None,
+                line: 1,
+                column: 1,
+                bytes: 0..1,
+            }],
+            notes: vec!["note".into()],
+        };
+        assert_eq!(
+            to_json(&[diagnostic.clone(), diagnostic]),
+            format!(
+                "[{0},{0}]",
+                concat!(
+                    r#"{"severity":"warning","code":"W0301","message":"a \"quoted\"\\path\n\u0001","#,
+                    r#""primary":{"file":1,"path":"main.asm","line":2,"column":3,"start":10,"end":12},"#,
+                    r#""secondary":[{"file":null,"path":null,"line":1,"column":1,"start":0,"end":1}],"#,
+                    r#""notes":["note"]}"#
+                )
+            )
+        );
+        assert_eq!(to_json(&[]), "[]");
+    }
+
+    #[test]
+    fn from_reports() {
+        let report = assemble_source("codes.asm", "nop\n  addi $4, $4, 0x10000\n\"unterminated\n")
+            .err()
+            .unwrap();
+        let diagnostics = report.diagnostics();
+        let kinds: Vec<_> = diagnostics.iter().map(|d| (d.severity, d.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (Severity::Error, DiagnosticKind::OutOfRange),
+                (Severity::Error, DiagnosticKind::InvalidToken)
+            ]
+        );
+        let span = diagnostics[0].primary.clone().unwrap();
+        assert_eq!(
+            (span.file, span.path.as_deref(), span.line, span.column),
+            (Some(1), Some("codes.asm"), 2, 16)
+        );
+        assert_eq!(span.bytes, 19..26);
+        assert!(report
+            .to_json()
+            .starts_with("[{\"severity\":\"error\",\"code\":\"E0305\""));
+
+        // a macro used in an included file
+        let sources = MemorySources::new()
+            .with(
+                "main.asm",
+                ".macro load reg\n  lw \\reg, 0($4)\n.endm\n#include \"inc.asm\"\n",
+            )
+            .with("inc.asm", "nop\nload $40\n");
+        let report = Assembler::with_sources(Default::default(), sources)
+            .assemble("main.asm".into(), &mut Vec::new())
+            .err()
+            .unwrap();
+        let diagnostics = report.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        let location = |span: &Span| (span.path.clone().unwrap(), span.line, span.column);
+        // the argument is what is wrong, the macro body and the call lead to it
+        assert_eq!(
+            location(diagnostic.primary.as_ref().unwrap()),
+            ("inc.asm".into(), 2, 6)
+        );
+        assert_eq!(
+            diagnostic
+                .secondary
+                .iter()
+                .map(location)
+                .collect::<Vec<_>>(),
+            [("main.asm".into(), 2, 6), ("inc.asm".into(), 2, 1)]
+        );
+        assert_eq!(
+            diagnostic.notes,
+            [
+                "expanded from main.asm:2:6",
+                "expanded from inc.asm:2:1",
+                "inc.asm is included from main.asm:4:10",
+            ]
+        );
+    }
+}
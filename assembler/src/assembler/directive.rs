@@ -0,0 +1,639 @@
+use elf::internal::relocation::MipsRelocationType;
+
+use crate::lexer::tokenizer::TokenType;
+
+use super::{
+    diagnostic::DiagnosticKind,
+    expression::LOCATION_COUNTER,
+    operand::{integer_literal, parse_constant, parse_value, Value},
+    preprocessor::{PPArea, PPToken},
+    relocation::{Fixup, FixupKind},
+    section::{Section, SectionFlags, SectionKind},
+    symbol::{SymBind, SymType, SHN_ABS, SHN_UNDEF},
+    AssemblerState,
+};
+
+type DirectiveResult<T> = Result<T, (DiagnosticKind, String, PPArea)>;
+
+/// Assembles a line starting with a directive (`.word 1, 2`), errors are reported to `state`
+pub(crate) fn assemble_directive(
+    state: &mut AssemblerState,
+    directive: &str,
+    args: &[PPToken],
+    area: PPArea,
+) {
+    if let Err((kind, message, area)) = run_directive(state, directive, args, &area) {
+        state.report_assembler_error(kind, message, area);
+    }
+}
+
+fn run_directive(
+    state: &mut AssemblerState,
+    directive: &str,
+    args: &[PPToken],
+    area: &PPArea,
+) -> DirectiveResult<()> {
+    let groups = split_args(args, area)?;
+    match directive {
+        ".text" | ".data" | ".rodata" | ".bss" => {
+            let [] = expect_args(&groups, directive, area)?;
+            state.switch_section(Section::with_default_attributes(directive));
+        }
+        ".section" => section(state, &groups, area)?,
+        ".org" => {
+            let (addr, fill) = value_and_fill(state, &groups, directive, area)?;
+            let addr = integer(state, addr, 0, u32::MAX as i64)?;
+            state
+                .fill_to(addr as u32, fill)
+                .map_err(|err| (DiagnosticKind::Section, err, area.clone()))?;
+        }
+        ".align" => {
+            let (power, fill) = value_and_fill(state, &groups, directive, area)?;
+            let power = integer(state, power, 0, 16)?;
+            state
+                .align(1 << power, fill)
+                .map_err(|err| (DiagnosticKind::Section, err, area.clone()))?;
+        }
+        ".space" => {
+            let (size, fill) = value_and_fill(state, &groups, directive, area)?;
+            let end = state.cur_addr() as i64 + integer(state, size, 0, u32::MAX as i64)?;
+            let end = u32::try_from(end).map_err(|_| {
+                (
+                    DiagnosticKind::Section,
+                    "Section is larger than 4GiB".to_owned(),
+                    area.clone(),
+                )
+            })?;
+            state
+                .fill_to(end, fill)
+                .map_err(|err| (DiagnosticKind::Section, err, area.clone()))?;
+        }
+        ".byte" => data(state, &groups, 1)?,
+        ".half" => data(state, &groups, 2)?,
+        ".word" => data(state, &groups, 4)?,
+        ".float" => {
+            state.auto_align(4);
+            for group in groups {
+                let bytes = (float(group)? as f32).to_bits().to_be_bytes();
+                emit(state, &bytes, group)?;
+            }
+        }
+        ".double" => {
+            state.auto_align(8);
+            for group in groups {
+                let bytes = float(group)?.to_bits().to_be_bytes();
+                emit(state, &bytes, group)?;
+            }
+        }
+        ".ascii" | ".asciiz" => {
+            for group in groups {
+                let string = match group {
+                    [PPToken {
+                        tok: TokenType::StringLiteral(string),
+                        ..
+                    }] => string,
+                    _ => {
+                        return Err((
+                            DiagnosticKind::DirectiveArguments,
+                            "Expected string".into(),
+                            group[0].location.clone(),
+                        ))
+                    }
+                };
+                let mut bytes = string.as_bytes().to_vec();
+                if directive == ".asciiz" {
+                    bytes.push(0);
+                }
+                emit(state, &bytes, group)?;
+            }
+        }
+        ".globl" | ".global" | ".local" | ".weak" => {
+            let binding = match directive {
+                ".local" => SymBind::STB_LOCAL,
+                ".weak" => SymBind::STB_WEAK,
+                _ => SymBind::STB_GLOBAL,
+            };
+            if groups.is_empty() {
+                return Err((
+                    DiagnosticKind::DirectiveArguments,
+                    format!("{} expects at least one symbol", directive),
+                    area.clone(),
+                ));
+            }
+            for group in groups {
+                let name = symbol_name(group)?;
+                state.symbol_mut(name).binding = binding;
+            }
+        }
+        ".type" => {
+            let [name, kind] = expect_args(&groups, directive, area)?;
+            let name = symbol_name(name)?;
+            let s_type = match kind {
+                [PPToken {
+                    tok: TokenType::At | TokenType::Percent,
+                    ..
+                }, kind]
+                | [kind] => match &kind.tok {
+                    TokenType::Identifier(kind) if kind == "function" => Some(SymType::STT_FUNC),
+                    TokenType::Identifier(kind) if kind == "object" => Some(SymType::STT_OBJECT),
+                    TokenType::Identifier(kind) if kind == "notype" => Some(SymType::STT_NOTYPE),
+                    _ => None,
+                },
+                _ => None,
+            }
+            .ok_or_else(|| {
+                (
+                    DiagnosticKind::DirectiveArguments,
+                    "Expected symbol type (@function, @object or @notype)".to_owned(),
+                    kind[0].location.clone(),
+                )
+            })?;
+            state.symbol_mut(name).s_type = s_type;
+        }
+        ".size" => {
+            let [name, size] = expect_args(&groups, directive, area)?;
+            let name = symbol_name(name)?;
+            let size = integer(state, size, 0, u32::MAX as i64)?;
+            state.symbol_mut(name).size = size as u32;
+        }
+        ".set" if groups.len() == 1 => match groups[0] {
+            [option] => match &option.tok {
+                TokenType::Identifier(ident) if ident == "at" => state.set_allow_at(true),
+                TokenType::Identifier(ident) if ident == "noat" => state.set_allow_at(false),
+                _ => {
+                    return Err((
+                        DiagnosticKind::DirectiveArguments,
+                        "Unknown .set option (expected at, noat or name, value)".into(),
+                        option.location.clone(),
+                    ))
+                }
+            },
+            _ => {
+                return Err((
+                    DiagnosticKind::DirectiveArguments,
+                    "Expected a single .set option".into(),
+                    area.clone(),
+                ))
+            }
+        },
+        ".set" | ".equ" => {
+            let [name, value] = expect_args(&groups, directive, area)?;
+            define_absolute(state, name, value)?;
+        }
+        _ => {
+            return Err((
+                DiagnosticKind::UnknownDirective,
+                format!("Unknown directive: {}", directive),
+                area.clone(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// `.section name[, "flags"[, @type]]`, the flags and type only apply when the section is new
+fn section(
+    state: &mut AssemblerState,
+    groups: &[&[PPToken]],
+    area: &PPArea,
+) -> DirectiveResult<()> {
+    let (name, flags, kind) = match groups {
+        [name] => (name, None, None),
+        [name, flags] => (name, Some(flags), None),
+        [name, flags, kind] => (name, Some(flags), Some(kind)),
+        _ => {
+            return Err((
+                DiagnosticKind::DirectiveArguments,
+                ".section expects a name, optional flags and an optional type".into(),
+                area.clone(),
+            ))
+        }
+    };
+    let name = match name {
+        [PPToken {
+            tok: TokenType::Identifier(name) | TokenType::StringLiteral(name),
+            ..
+        }] => name,
+        _ => {
+            return Err((
+                DiagnosticKind::DirectiveArguments,
+                "Expected section name".into(),
+                name[0].location.clone(),
+            ))
+        }
+    };
+    let mut section = Section::with_default_attributes(name.as_str());
+    if let Some(flags) = flags {
+        section.flags = match flags {
+            [PPToken {
+                tok: TokenType::StringLiteral(flags),
+                location,
+            }] => SectionFlags::parse(flags).map_err(|flag| {
+                (
+                    DiagnosticKind::DirectiveArguments,
+                    format!("Unknown section flag: {} (expected a, w or x)", flag),
+                    location.clone(),
+                )
+            })?,
+            _ => {
+                return Err((
+                    DiagnosticKind::DirectiveArguments,
+                    "Expected section flags (i.e. \"ax\")".into(),
+                    flags[0].location.clone(),
+                ))
+            }
+        };
+    }
+    if let Some(kind) = kind {
+        section.kind = match kind {
+            [PPToken {
+                tok: TokenType::At | TokenType::Percent,
+                ..
+            }, kind]
+            | [kind] => match &kind.tok {
+                TokenType::Identifier(kind) if kind == "progbits" => Some(SectionKind::Progbits),
+                TokenType::Identifier(kind) if kind == "nobits" => Some(SectionKind::Nobits),
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| {
+            (
+                DiagnosticKind::DirectiveArguments,
+                "Expected section type (@progbits or @nobits)".to_owned(),
+                kind[0].location.clone(),
+            )
+        })?;
+    }
+    state.switch_section(section);
+    Ok(())
+}
+
+/// Splits the arguments of a directive on commas
+fn split_args<'a>(args: &'a [PPToken], area: &PPArea) -> DirectiveResult<Vec<&'a [PPToken]>> {
+    if args.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut groups = Vec::new();
+    let mut last_area = area;
+    for group in args.split(|tok| matches!(tok.tok, TokenType::Comma)) {
+        match group.first() {
+            Some(first) => {
+                groups.push(group);
+                last_area = &first.location;
+            }
+            None => {
+                return Err((
+                    DiagnosticKind::DirectiveArguments,
+                    "Expected argument".into(),
+                    last_area.clone(),
+                ))
+            }
+        }
+    }
+    Ok(groups)
+}
+
+fn expect_args<'a, const N: usize>(
+    groups: &[&'a [PPToken]],
+    directive: &str,
+    area: &PPArea,
+) -> DirectiveResult<[&'a [PPToken]; N]> {
+    groups.try_into().map_err(|_| {
+        (
+            DiagnosticKind::DirectiveArguments,
+            format!(
+                "{} expects {} argument{} but found {}",
+                directive,
+                N,
+                if N == 1 { "" } else { "s" },
+                groups.len()
+            ),
+            area.clone(),
+        )
+    })
+}
+
+/// `value[, fill]` of `.org`, `.align` and `.space`
+fn value_and_fill<'a>(
+    state: &AssemblerState,
+    groups: &[&'a [PPToken]],
+    directive: &str,
+    area: &PPArea,
+) -> DirectiveResult<(&'a [PPToken], u8)> {
+    match groups {
+        [value] => Ok((value, 0)),
+        [value, fill] => Ok((
+            value,
+            integer(state, fill, i8::MIN as i64, u8::MAX as i64)? as u8,
+        )),
+        _ => Err((
+            DiagnosticKind::DirectiveArguments,
+            format!("{} expects a value and an optional fill byte", directive),
+            area.clone(),
+        )),
+    }
+}
+
+fn integer(state: &AssemblerState, group: &[PPToken], min: i64, max: i64) -> DirectiveResult<i64> {
+    let value = parse_constant(state, group)?;
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err((
+            DiagnosticKind::OutOfRange,
+            format!("Value {} is out of range ({}..={})", value, min, max),
+            group[0].location.clone(),
+        ))
+    }
+}
+
+fn float(group: &[PPToken]) -> DirectiveResult<f64> {
+    let (negate, tokens) = match group {
+        [sign, rest @ ..] if matches!(sign.tok, TokenType::Minus) => (true, rest),
+        [sign, rest @ ..] if matches!(sign.tok, TokenType::Plus) => (false, rest),
+        _ => (false, group),
+    };
+    let value = match tokens {
+        [tok] => match &tok.tok {
+            TokenType::F32Literal(val) => *val as f64,
+            TokenType::F64Literal(val) => *val,
+            other => integer_literal(other).ok_or_else(|| {
+                (
+                    DiagnosticKind::DirectiveArguments,
+                    format!("Expected floating point number but found: {:?}", other),
+                    tok.location.clone(),
+                )
+            })? as f64,
+        },
+        _ => {
+            return Err((
+                DiagnosticKind::DirectiveArguments,
+                "Expected floating point number".into(),
+                group[0].location.clone(),
+            ))
+        }
+    };
+    Ok(if negate { -value } else { value })
+}
+
+fn symbol_name(group: &[PPToken]) -> DirectiveResult<&str> {
+    match group {
+        [PPToken {
+            tok: TokenType::Identifier(name),
+            ..
+        }] => Ok(name),
+        _ => Err((
+            DiagnosticKind::DirectiveArguments,
+            "Expected symbol name".into(),
+            group[0].location.clone(),
+        )),
+    }
+}
+
+fn emit(state: &mut AssemblerState, bytes: &[u8], group: &[PPToken]) -> DirectiveResult<()> {
+    state
+        .emit_bytes(bytes)
+        .map_err(|err| (DiagnosticKind::Section, err, group[0].location.clone()))
+}
+
+/// `.byte`, `.half` and `.word`, values may be signed or unsigned and words may be labels
+fn data(state: &mut AssemblerState, groups: &[&[PPToken]], size: usize) -> DirectiveResult<()> {
+    let bits = size as u32 * 8;
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << bits) - 1;
+    state.auto_align(size as u32);
+    for group in groups {
+        if size == 4 {
+            if let Value::Label(target) = parse_value(state, group)? {
+                let fixup = Fixup {
+                    kind: FixupKind::Relocation(MipsRelocationType::R_MIPS_32),
+                    target,
+                };
+                let area = &group[0].location;
+                state
+                    .emit_fixup(0, fixup, area)
+                    .map_err(|err| (DiagnosticKind::Section, err, area.clone()))?;
+                continue;
+            }
+        }
+        let value = integer(state, group, min, max)?;
+        emit(state, &value.to_be_bytes()[8 - size..], group)?;
+    }
+    Ok(())
+}
+
+/// `.set name, value` and `.equ name, value`
+///
+/// A label (plus or minus a constant) as the value makes `name` an alias of that address,
+/// anything else is absolute
+fn define_absolute(
+    state: &mut AssemblerState,
+    name_group: &[PPToken],
+    value: &[PPToken],
+) -> DirectiveResult<()> {
+    let name = symbol_name(name_group)?;
+    let (value, section_index) = match parse_value(state, value)? {
+        Value::Label(label) if label.minus.is_none() && label.symbol == LOCATION_COUNTER => {
+            (label.addend as u32, state.current_section_index())
+        }
+        Value::Label(label) if label.minus.is_none() => match state.get_symbol(&label.symbol) {
+            Some(sym) if sym.section_index != SHN_UNDEF => (
+                sym.value.wrapping_add(label.addend as u32),
+                sym.section_index,
+            ),
+            _ => {
+                return Err((
+                    DiagnosticKind::UndefinedSymbol,
+                    format!(
+                        "Undefined label: {} (labels must be defined before they are used)",
+                        label.symbol
+                    ),
+                    value[0].location.clone(),
+                ))
+            }
+        },
+        // also the difference of labels that aren't defined yet, which is reported there
+        _ => (
+            integer(state, value, i32::MIN as i64, u32::MAX as i64)? as u32,
+            SHN_ABS,
+        ),
+    };
+    let sym = state.symbol_mut(name);
+    if sym.section_index != SHN_UNDEF && sym.section_index != SHN_ABS {
+        return Err((
+            DiagnosticKind::DuplicateSymbol,
+            format!("Label {} is already defined", name),
+            name_group[0].location.clone(),
+        ));
+    }
+    sym.value = value;
+    sym.section_index = section_index;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{preprocessor::PPToken, relocation::RelocationTarget},
+        lexer::tokenizer::Tokenizer,
+    };
+
+    /// Assembles lines made of labels and directives
+    fn try_assemble(lines: &[&str]) -> AssemblerState {
+        let mut state = AssemblerState::new(Default::default());
+        for line in lines {
+            let mut tokens = Tokenizer::new_from_str(line).map(|tok| PPToken::new(tok.unwrap()));
+            let first = tokens.next().unwrap();
+            match first.tok {
+                TokenType::Label(label) => {
+                    if let Err(err) = state.define_label(label) {
+                        state.report_assembler_error(
+                            DiagnosticKind::DuplicateSymbol,
+                            err,
+                            first.location,
+                        )
+                    }
+                }
+                TokenType::Identifier(directive) => {
+                    let args: Vec<_> = tokens.collect();
+                    assemble_directive(&mut state, &directive, &args, first.location)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        state.resolve_fixups();
+        state
+    }
+
+    fn assemble(lines: &[&str]) -> AssemblerState {
+        let state = try_assemble(lines);
+        assert!(!state.has_encountered_error(), "{:?}", lines);
+        state
+    }
+
+    #[test]
+    fn data() {
+        let state = assemble(&[
+            ".byte 1, -1, 255",
+            ".half 0x1234",
+            ".word -2",
+            ".asciiz \"a\\tb\\0\"",
+            ".float 1.5",
+            ".double -2.0",
+            ".space 2, 0x7f",
+        ]);
+        assert_eq!(
+            state.current_section().data,
+            [
+                [0x01, 0xff, 0xff, 0x00, 0x12, 0x34, 0x00, 0x00].as_slice(),
+                &[0xff, 0xff, 0xff, 0xfe],
+                b"a\tb\0\0",
+                &[0x00, 0x00, 0x00, 0x3f, 0xc0, 0x00, 0x00],
+                &[0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                &[0x7f, 0x7f],
+            ]
+            .concat()
+        );
+        assert_eq!(state.current_section().alignment, 8);
+    }
+
+    #[test]
+    fn sections_and_symbols() {
+        let state = assemble(&[
+            ".globl start",
+            "start:",
+            ".word 1",
+            ".data",
+            ".byte 1",
+            "value:",
+            ".word 2",
+            ".type value, @object",
+            ".size value, 4",
+            ".bss",
+            ".align 3",
+            "buffer:",
+            ".space 16",
+            ".equ SIZE, 16",
+            ".section .text",
+            ".org 0x10",
+            "end:",
+        ]);
+        let start = state.get_symbol("start").unwrap();
+        assert_eq!((start.value, start.section_index), (0, 1));
+        assert_eq!(start.binding, SymBind::STB_GLOBAL);
+        // moved past the padding in front of the word
+        let value = state.get_symbol("value").unwrap();
+        assert_eq!((value.value, value.section_index, value.size), (4, 2, 4));
+        assert_eq!(value.s_type, SymType::STT_OBJECT);
+        let buffer = state.get_symbol("buffer").unwrap();
+        assert_eq!((buffer.value, buffer.section_index), (0, 3));
+        let size = state.get_symbol("SIZE").unwrap();
+        assert_eq!((size.value, size.section_index), (16, SHN_ABS));
+        let end = state.get_symbol("end").unwrap();
+        assert_eq!((end.value, end.section_index), (0x10, 1));
+        assert_eq!(state.current_section().data.len(), 0x10);
+    }
+
+    #[test]
+    fn expressions() {
+        let state = assemble(&[
+            "start:",
+            ".word 1, 2",
+            "end:",
+            ".equ SIZE, end - start",
+            ".equ MASK, (1 << SIZE) - 1",
+            ".byte SIZE * 2, MASK, 'a' + 1, ~0 & 0x7f",
+            ".half . - start",
+            ".equ here, .",
+            ".word here - start, end - start + 4",
+            ".word .",
+        ]);
+        assert_eq!(
+            state.current_section().data,
+            [
+                [0, 0, 0, 1, 0, 0, 0, 2].as_slice(),
+                &[16, 0xff, b'b', 0x7f],
+                &[0, 12, 0, 0],
+                &[0, 0, 0, 14, 0, 0, 0, 12],
+                &[0, 0, 0, 24],
+            ]
+            .concat()
+        );
+        let here = state.get_symbol("here").unwrap();
+        assert_eq!((here.value, here.section_index), (14, 1));
+        // the location counter is relocated against the section it is in
+        let relocation = &state.current_section().relocations[0];
+        assert_eq!(relocation.offset, 24);
+        assert!(matches!(relocation.target, RelocationTarget::Section(1)));
+    }
+
+    #[test]
+    fn errors() {
+        for lines in [
+            &[".byte 256"][..],
+            &[".half -32769"],
+            &[".bss", ".word 1"],
+            &[".org 8", ".org 4"],
+            &["a:", "a:"],
+            &[".data", "a:", ".text", ".half a"],
+            &[".space undefined"],
+            &[".section .x, \"q\""],
+            &[".type a, @thing"],
+            &[".bogus"],
+            &[".word 1 / 0"],
+            &[".word 1 << 64"],
+            &[".word 0x7FFFFFFFFFFFFFFF + 1"],
+            &["a:", ".word a * 2"],
+            &["a:", ".word -a"],
+            &[".word a - b"],
+            &[".data", "a:", ".text", "b:", ".word a - b"],
+            &["a:", ".equ SIZE, b - a", "b:"],
+            &[".equ SIZE, (1 + 2"],
+        ] {
+            let state = try_assemble(lines);
+            assert!(state.has_encountered_error(), "{:?}", lines);
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::lexer::tokenizer::TokenType;
+
+use super::{
+    diagnostic::DiagnosticKind,
+    operand::{integer_literal, Value},
+    preprocessor::{PPArea, PPToken},
+    relocation::SymbolRef,
+    symbol::{SHN_ABS, SHN_UNDEF},
+    AssemblerState,
+};
+
+type ExprResult = Result<i64, (DiagnosticKind, String, PPArea)>;
+
+/// Binding power of a binary operator, higher binds tighter (same order as C)
+fn precedence(tok: &TokenType) -> Option<u8> {
+    Some(match tok {
+        TokenType::LogicalOr => 1,
+        TokenType::LogicalAnd => 2,
+        TokenType::BitwiseOr => 3,
+        TokenType::BitwiseXor => 4,
+        TokenType::Ampersand => 5,
+        TokenType::Equals | TokenType::NotEquals => 6,
+        TokenType::LessThan
+        | TokenType::LessThanEq
+        | TokenType::GreaterThan
+        | TokenType::GreaterThanEq => 7,
+        TokenType::ShiftLeft | TokenType::ShiftRight => 8,
+        TokenType::Plus | TokenType::Minus => 9,
+        TokenType::Star | TokenType::Slash | TokenType::Percent => 10,
+        _ => return None,
+    })
+}
+
+/// The name `SymbolRef`s use for the location counter, its address is part of the addend
+///
+/// It always refers to the section the expression is used in
+pub(crate) const LOCATION_COUNTER: &str = ".";
+
+/// The value of a (sub)expression, `symbol` (minus `minus`) is added to `constant` once its
+/// address is known
+#[derive(Debug, Clone)]
+struct Term {
+    constant: i64,
+    symbol: Option<String>,
+    minus: Option<String>,
+}
+
+impl Term {
+    fn constant(constant: i64) -> Self {
+        Self {
+            constant,
+            symbol: None,
+            minus: None,
+        }
+    }
+
+    fn symbol(constant: i64, symbol: &str) -> Self {
+        Self {
+            constant,
+            symbol: Some(symbol.to_owned()),
+            minus: None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    state: &'a AssemblerState,
+    tokens: &'a [PPToken],
+    pos: usize,
+    /// Reported when the expression ends early
+    area: &'a PPArea,
+    /// The operand of `&&` or `||` being parsed doesn't change the result, it is only checked
+    /// for syntax errors
+    skipping: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<&'a PPToken, (String, PPArea)> {
+        let tok = self.tokens.get(self.pos).ok_or_else(|| {
+            let area = self.tokens.last().map_or(self.area, |tok| &tok.location);
+            (
+                "Expected a value but the expression ended".to_owned(),
+                area.clone(),
+            )
+        })?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    /// Section and address of a label that is already defined
+    fn location(&self, symbol: &str) -> Option<(u16, i64)> {
+        if symbol == LOCATION_COUNTER {
+            // the address is in the constant
+            return Some((self.state.current_section_index(), 0));
+        }
+        self.state
+            .get_symbol(symbol)
+            .filter(|sym| sym.section_index != SHN_UNDEF && sym.section_index != SHN_ABS)
+            .map(|sym| (sym.section_index, sym.value as i64))
+    }
+
+    fn binary(&mut self, min: u8) -> Result<Term, (String, PPArea)> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.tokens.get(self.pos) {
+            match precedence(&op.tok) {
+                Some(prec) if prec >= min => {
+                    self.pos += 1;
+                    // `0 && rhs` and `1 || rhs` short circuit like in C
+                    let decided = match (&op.tok, &lhs.symbol) {
+                        (TokenType::LogicalAnd, None) => lhs.constant == 0,
+                        (TokenType::LogicalOr, None) => lhs.constant != 0,
+                        _ => false,
+                    };
+                    if decided {
+                        let skipping = std::mem::replace(&mut self.skipping, true);
+                        self.binary(prec + 1)?;
+                        self.skipping = skipping;
+                        lhs = Term::constant((lhs.constant != 0) as i64);
+                        continue;
+                    }
+                    let rhs = self.binary(prec + 1)?;
+                    lhs = match self.apply(&op.tok, lhs, rhs) {
+                        Err(_) if self.skipping => Term::constant(0),
+                        res => res.map_err(|err| (err, op.location.clone()))?,
+                    };
+                }
+                _ => break,
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Term, (String, PPArea)> {
+        let tok = self.next()?;
+        let location = tok.location.clone();
+        let skipping = self.skipping;
+        let absolute = |term: Term, op: &str| match term.symbol {
+            Some(_) if skipping => Ok(0),
+            Some(symbol) => Err((
+                format!("Cannot apply {} to the address of label {}", op, symbol),
+                location.clone(),
+            )),
+            None => Ok(term.constant),
+        };
+        match &tok.tok {
+            TokenType::Minus => {
+                let value = absolute(self.unary()?, "-")?;
+                match value.checked_neg() {
+                    Some(value) => Ok(Term::constant(value)),
+                    None if skipping => Ok(Term::constant(0)),
+                    None => Err(("Overflow in expression".to_owned(), location)),
+                }
+            }
+            TokenType::Plus => self.unary(),
+            TokenType::BitwiseNot => Ok(Term::constant(!absolute(self.unary()?, "~")?)),
+            TokenType::LogicalNot => {
+                Ok(Term::constant((absolute(self.unary()?, "!")? == 0) as i64))
+            }
+            TokenType::LPar => {
+                let value = self.binary(0)?;
+                match self.next()? {
+                    PPToken {
+                        tok: TokenType::RPar,
+                        ..
+                    } => Ok(value),
+                    tok => Err(("Expected )".into(), tok.location.clone())),
+                }
+            }
+            TokenType::Identifier(ident) if ident == LOCATION_COUNTER => {
+                Ok(Term::symbol(self.state.cur_addr() as i64, ident))
+            }
+            TokenType::Identifier(ident) => Ok(match self.state.get_symbol(ident) {
+                Some(sym) if sym.section_index == SHN_ABS => {
+                    Term::constant(sym.value as i32 as i64)
+                }
+                // labels, including ones that are defined later or in another file
+                _ => Term::symbol(0, ident),
+            }),
+            other => integer_literal(other)
+                .map(Term::constant)
+                .ok_or_else(|| (format!("Expected a value but found {:?}", other), location)),
+        }
+    }
+
+    fn apply(&self, op: &TokenType, lhs: Term, rhs: Term) -> Result<Term, String> {
+        let overflow = || "Overflow in expression".to_owned();
+        let constant = match (op, &lhs.symbol, &rhs.symbol) {
+            (_, None, None) => return apply(op, lhs.constant, rhs.constant).map(Term::constant),
+            (TokenType::Plus, Some(_), Some(_)) => {
+                return Err(format!(
+                    "Cannot add the addresses of labels {} and {}",
+                    lhs.symbol.unwrap(),
+                    rhs.symbol.unwrap()
+                ))
+            }
+            (TokenType::Plus, _, _) => lhs.constant.checked_add(rhs.constant),
+            (TokenType::Minus, Some(_), None) => lhs.constant.checked_sub(rhs.constant),
+            (TokenType::Minus, Some(a), Some(b)) => {
+                // the distance between two labels is known once they are in the same section
+                let (a_offset, b_offset) = match (self.location(a), self.location(b)) {
+                    _ if lhs.minus.is_some() || rhs.minus.is_some() => {
+                        return Err(format!(
+                            "Cannot subtract label {} from {} (only one label can be subtracted)",
+                            b, a
+                        ))
+                    }
+                    _ if a == b => (0, 0),
+                    (Some((a_section, a_offset)), Some((b_section, b_offset)))
+                        if a_section == b_section =>
+                    {
+                        (a_offset, b_offset)
+                    }
+                    (Some(_), Some(_)) => {
+                        return Err(format!(
+                            "Cannot subtract label {} from {} (they are in different sections)",
+                            b, a
+                        ))
+                    }
+                    // one of them is defined later, the fixup works it out
+                    _ => {
+                        return Ok(Term {
+                            constant: lhs
+                                .constant
+                                .checked_sub(rhs.constant)
+                                .ok_or_else(overflow)?,
+                            symbol: lhs.symbol,
+                            minus: rhs.symbol,
+                        })
+                    }
+                };
+                let value = (a_offset + lhs.constant)
+                    .checked_sub(b_offset + rhs.constant)
+                    .ok_or_else(overflow)?;
+                return Ok(Term::constant(value));
+            }
+            (TokenType::Minus, None, Some(symbol)) => {
+                return Err(format!("Cannot negate the address of label {}", symbol))
+            }
+            (_, Some(symbol), _) | (_, _, Some(symbol)) => {
+                return Err(format!(
+                    "Only + and - can be used with the address of label {}",
+                    symbol
+                ))
+            }
+        };
+        Ok(Term {
+            constant: constant.ok_or_else(overflow)?,
+            symbol: lhs.symbol.or(rhs.symbol),
+            minus: lhs.minus.or(rhs.minus),
+        })
+    }
+}
+
+fn apply(op: &TokenType, lhs: i64, rhs: i64) -> Result<i64, String> {
+    let overflow = || "Overflow in expression".to_owned();
+    let shift = || {
+        u32::try_from(rhs)
+            .ok()
+            .filter(|rhs| *rhs < 64)
+            .ok_or_else(|| format!("Shift amount {} is out of range (0 to 63)", rhs))
+    };
+    match op {
+        TokenType::LogicalOr => Ok((lhs != 0 || rhs != 0) as i64),
+        TokenType::LogicalAnd => Ok((lhs != 0 && rhs != 0) as i64),
+        TokenType::BitwiseOr => Ok(lhs | rhs),
+        TokenType::BitwiseXor => Ok(lhs ^ rhs),
+        TokenType::Ampersand => Ok(lhs & rhs),
+        TokenType::Equals => Ok((lhs == rhs) as i64),
+        TokenType::NotEquals => Ok((lhs != rhs) as i64),
+        TokenType::LessThan => Ok((lhs < rhs) as i64),
+        TokenType::LessThanEq => Ok((lhs <= rhs) as i64),
+        TokenType::GreaterThan => Ok((lhs > rhs) as i64),
+        TokenType::GreaterThanEq => Ok((lhs >= rhs) as i64),
+        TokenType::ShiftLeft => Ok(lhs << shift()?),
+        TokenType::ShiftRight => Ok(lhs >> shift()?),
+        TokenType::Plus => lhs.checked_add(rhs).ok_or_else(overflow),
+        TokenType::Minus => lhs.checked_sub(rhs).ok_or_else(overflow),
+        TokenType::Star => lhs.checked_mul(rhs).ok_or_else(overflow),
+        TokenType::Slash | TokenType::Percent if rhs == 0 => Err("Division by zero".into()),
+        TokenType::Slash => lhs.checked_div(rhs).ok_or_else(overflow),
+        TokenType::Percent => lhs.checked_rem(rhs).ok_or_else(overflow),
+        _ => unreachable!("{:?} is not a binary operator", op),
+    }
+}
+
+/// Evaluates an expression with the operators and precedence of C, comparisons and logical
+/// operators result in 1 or 0
+///
+/// Symbols with absolute values (`.equ`) are constants, `.` is the current address. A label
+/// plus or minus a constant is left to be relocated and the difference of two labels in the
+/// same section is a constant, or left to the fixup if one of them is defined later. `area` is
+/// reported for an empty expression
+pub(crate) fn evaluate_value(
+    state: &AssemblerState,
+    tokens: &[PPToken],
+    area: &PPArea,
+) -> Result<Value, (DiagnosticKind, String, PPArea)> {
+    let mut parser = Parser {
+        state,
+        tokens,
+        pos: 0,
+        area,
+        skipping: false,
+    };
+    let value = parser
+        .binary(0)
+        .map_err(|(err, location)| (DiagnosticKind::Expression, err, location))?;
+    if let Some(tok) = tokens.get(parser.pos) {
+        return Err((
+            DiagnosticKind::Expression,
+            format!("Unexpected {:?} in expression", tok.tok),
+            tok.location.clone(),
+        ));
+    }
+    Ok(match value.symbol {
+        Some(symbol) => Value::Label(SymbolRef {
+            symbol,
+            addend: value.constant,
+            minus: value.minus,
+        }),
+        None => Value::Constant(value.constant),
+    })
+}
+
+/// Evaluates an expression that has to be known while assembling, see `evaluate_value`
+pub(crate) fn evaluate(state: &AssemblerState, tokens: &[PPToken], area: &PPArea) -> ExprResult {
+    match evaluate_value(state, tokens, area)? {
+        Value::Constant(value) => Ok(value),
+        Value::Label(label) => {
+            let (kind, message) = match state.get_symbol(&label.symbol) {
+                _ if label.minus.is_some() => (
+                    DiagnosticKind::Expression,
+                    format!(
+                        "Cannot subtract label {} from {} (both have to be defined before in the same section)",
+                        label.minus.unwrap(),
+                        label.symbol
+                    ),
+                ),
+                _ if label.symbol == LOCATION_COUNTER => (
+                    DiagnosticKind::Expression,
+                    "Expected a constant but found the location counter (its address is only known after linking)".to_owned(),
+                ),
+                Some(sym) if sym.section_index != SHN_UNDEF => (
+                    DiagnosticKind::Expression,
+                    format!(
+                        "Expected a constant but found label {} (its address is only known after linking)",
+                        label.symbol
+                    ),
+                ),
+                _ => (
+                    DiagnosticKind::UndefinedSymbol,
+                    format!(
+                        "Undefined symbol: {} (constants must be defined before they are used)",
+                        label.symbol
+                    ),
+                ),
+            };
+            let location = tokens.last().map_or(area, |tok| &tok.location).clone();
+            Err((kind, message, location))
+        }
+        Value::Relocation(..) => unreachable!("relocation operators are handled by parse_value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenizer::Tokenizer;
+
+    fn eval(state: &AssemblerState, expr: &str) -> Result<i64, String> {
+        let tokens: Vec<_> = Tokenizer::new_from_str(expr)
+            .map(|tok| PPToken::new(tok.unwrap()))
+            .collect();
+        let area = tokens[0].location.clone();
+        evaluate(state, &tokens, &area).map_err(|(_, err, _)| err)
+    }
+
+    #[test]
+    fn operators() {
+        let mut state = AssemblerState::new(Default::default());
+        state.define_label("label".into()).unwrap();
+        let sym = state.symbol_mut("SIZE");
+        sym.value = 16;
+        sym.section_index = SHN_ABS;
+
+        assert_eq!(eval(&state, "1 + 2 * 3"), Ok(7));
+        assert_eq!(eval(&state, "(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval(&state, "-SIZE / 3"), Ok(-5));
+        assert_eq!(eval(&state, "SIZE % 5 << 4 | 1"), Ok(0x11));
+        assert_eq!(eval(&state, "~0 ^ 0xF0 & 0xFF"), Ok(!0xF0));
+        assert_eq!(eval(&state, "SIZE >= 16 && SIZE != 8"), Ok(1));
+        assert_eq!(eval(&state, "!SIZE || 2 < 1"), Ok(0));
+        assert_eq!(eval(&state, "'a' == 97"), Ok(1));
+        // the right side is only checked for syntax errors when it doesn't matter
+        assert_eq!(eval(&state, "0 && (1 / 0)"), Ok(0));
+        assert_eq!(eval(&state, "SIZE || label * 2"), Ok(1));
+        assert_eq!(eval(&state, "1 && 0 || 3"), Ok(1));
+        assert!(eval(&state, "0 && (1 +)").is_err());
+        assert!(eval(&state, "1 && 1 / 0").is_err());
+
+        assert!(eval(&state, "1 / (SIZE - 16)").is_err());
+        assert!(eval(&state, "1 << 64").is_err());
+        assert!(eval(&state, "0x7FFFFFFFFFFFFFFF + 1").is_err());
+        assert!(eval(&state, "label + 1").is_err());
+        assert!(eval(&state, "missing").is_err());
+        assert!(eval(&state, "(1 + 2").is_err());
+        assert!(eval(&state, "1 2").is_err());
+        assert!(eval(&state, "1 +").is_err());
+    }
+}
@@ -0,0 +1,695 @@
+use elf::internal::relocation::MipsRelocationType;
+
+use super::{
+    diagnostic::DiagnosticKind,
+    operand::{Operand, OperandKind, Value},
+    preprocessor::PPArea,
+    relocation::{Fixup, FixupKind},
+};
+
+/// The operands an instruction takes and where they are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// no operands
+    None,
+    /// `$d, $s, $t`
+    RdRsRt,
+    /// `$d, $t, shamt`
+    RdRtShamt,
+    /// `$d, $t, $s`
+    RdRtRs,
+    /// `$s, $t`
+    RsRt,
+    /// `$s`
+    Rs,
+    /// `$d`
+    Rd,
+    /// `$s` or `$d, $s`, `$d` defaults to `$ra`
+    Jalr,
+    /// optional 20 bit code
+    Code,
+    /// `$s, $t` with an optional 10 bit code
+    Trap,
+    /// `$t, $s, imm` with a signed 16 bit immediate
+    RtRsSigned,
+    /// `$t, $s, imm` with an unsigned 16 bit immediate
+    RtRsUnsigned,
+    /// `$t, imm` with an unsigned 16 bit immediate
+    RtImm,
+    /// `$s, $t, target`
+    RsRtBranch,
+    /// `$s, target`
+    RsBranch,
+    /// `$t, offset($s)`
+    RtMemory,
+    /// `target` within the current 256MB region
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionInfo {
+    /// The instruction with every operand field zeroed
+    pub base: u32,
+    pub format: Format,
+}
+
+const fn special(funct: u32, format: Format) -> InstructionInfo {
+    InstructionInfo {
+        base: funct,
+        format,
+    }
+}
+
+const fn op(opcode: u32, format: Format) -> InstructionInfo {
+    InstructionInfo {
+        base: opcode << 26,
+        format,
+    }
+}
+
+const fn regimm(rt: u32) -> InstructionInfo {
+    InstructionInfo {
+        base: (0b000001 << 26) | (rt << 16),
+        format: Format::RsBranch,
+    }
+}
+
+/// Every instruction the emulator executes, pseudo instructions are not included
+pub fn lookup(mnemonic: &str) -> Option<InstructionInfo> {
+    use Format::*;
+    Some(match mnemonic.to_ascii_lowercase().as_str() {
+        "nop" => special(0b000000, None),
+        "sync" => special(0b001111, None),
+
+        //arithmatic
+        "add" => special(0b100000, RdRsRt),
+        "addu" => special(0b100001, RdRsRt),
+        "sub" => special(0b100010, RdRsRt),
+        "subu" => special(0b100011, RdRsRt),
+        "and" => special(0b100100, RdRsRt),
+        "or" => special(0b100101, RdRsRt),
+        "xor" => special(0b100110, RdRsRt),
+        "nor" => special(0b100111, RdRsRt),
+        "slt" => special(0b101010, RdRsRt),
+        "sltu" => special(0b101011, RdRsRt),
+
+        "sll" => special(0b000000, RdRtShamt),
+        "srl" => special(0b000010, RdRtShamt),
+        "sra" => special(0b000011, RdRtShamt),
+        "sllv" => special(0b000100, RdRtRs),
+        "srlv" => special(0b000110, RdRtRs),
+        "srav" => special(0b000111, RdRtRs),
+
+        "mult" => special(0b011000, RsRt),
+        "multu" => special(0b011001, RsRt),
+        "div" => special(0b011010, RsRt),
+        "divu" => special(0b011011, RsRt),
+
+        //data movement
+        "mfhi" => special(0b010000, Rd),
+        "mthi" => special(0b010001, Rs),
+        "mflo" => special(0b010010, Rd),
+        "mtlo" => special(0b010011, Rs),
+
+        //jump
+        "jr" => special(0b001000, Rs),
+        "jalr" => special(0b001001, Jalr),
+        "j" => op(0b000010, Jump),
+        "jal" => op(0b000011, Jump),
+
+        //special
+        "syscall" => special(0b001100, Code),
+        "break" => special(0b001101, Code),
+        "tge" => special(0b110000, Trap),
+        "tgeu" => special(0b110001, Trap),
+        "tlt" => special(0b110010, Trap),
+        "tltu" => special(0b110011, Trap),
+        "teq" => special(0b110100, Trap),
+        "tne" => special(0b110110, Trap),
+
+        //immediate
+        "addi" => op(0b001000, RtRsSigned),
+        "addiu" => op(0b001001, RtRsSigned),
+        "slti" => op(0b001010, RtRsSigned),
+        "sltiu" => op(0b001011, RtRsSigned),
+        "andi" => op(0b001100, RtRsUnsigned),
+        "ori" => op(0b001101, RtRsUnsigned),
+        "xori" => op(0b001110, RtRsUnsigned),
+        "lui" => op(0b001111, RtImm),
+
+        //branch
+        "bltz" => regimm(0b00000),
+        "bgez" => regimm(0b00001),
+        "beq" => op(0b000100, RsRtBranch),
+        "bne" => op(0b000101, RsRtBranch),
+        "blez" => op(0b000110, RsBranch),
+        "bgtz" => op(0b000111, RsBranch),
+
+        //memory
+        "lb" => op(0b100000, RtMemory),
+        "lh" => op(0b100001, RtMemory),
+        "lwl" => op(0b100010, RtMemory),
+        "lw" => op(0b100011, RtMemory),
+        "lbu" => op(0b100100, RtMemory),
+        "lhu" => op(0b100101, RtMemory),
+        "lwr" => op(0b100110, RtMemory),
+        "sb" => op(0b101000, RtMemory),
+        "sh" => op(0b101001, RtMemory),
+        "swl" => op(0b101010, RtMemory),
+        "sw" => op(0b101011, RtMemory),
+        "swr" => op(0b101110, RtMemory),
+        "ll" => op(0b110000, RtMemory),
+        "sc" => op(0b111000, RtMemory),
+        _ => return Option::None,
+    })
+}
+
+type EncodeResult<T> = Result<T, (DiagnosticKind, String, PPArea)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoded {
+    /// The instruction with any field referring to a label zeroed
+    pub word: u32,
+    pub fixup: Option<Fixup>,
+}
+
+fn register(operand: &Operand) -> EncodeResult<u32> {
+    match operand.kind {
+        OperandKind::Register(reg) if reg < 32 => Ok(reg),
+        OperandKind::Register(reg) => Err((
+            DiagnosticKind::OperandType,
+            format!("Invalid register: ${}", reg),
+            operand.area.clone(),
+        )),
+        _ => Err((
+            DiagnosticKind::OperandType,
+            "Expected register".into(),
+            operand.area.clone(),
+        )),
+    }
+}
+
+fn immediate(operand: &Operand, min: i64, max: i64, what: &str) -> EncodeResult<u32> {
+    match operand.kind {
+        OperandKind::Immediate(val) if (min..=max).contains(&val) => Ok(val as u32),
+        OperandKind::Immediate(val) => Err((
+            DiagnosticKind::OutOfRange,
+            format!("{} {} is out of range ({}..={})", what, val, min, max),
+            operand.area.clone(),
+        )),
+        OperandKind::Label(ref label) => Err((
+            DiagnosticKind::OperandType,
+            format!(
+                "Expected {} but found label {} (use la to load its address)",
+                what, label
+            ),
+            operand.area.clone(),
+        )),
+        _ => Err((
+            DiagnosticKind::OperandType,
+            format!("Expected {}", what),
+            operand.area.clone(),
+        )),
+    }
+}
+
+/// A 16 bit immediate, or part of the address of a label which is filled in later
+///
+/// A symbol that isn't constant yet is filled in later too, it can be an `.equ` further down
+fn immediate_16(operand: &Operand, signed: bool, fixup: &mut Option<Fixup>) -> EncodeResult<u32> {
+    match &operand.kind {
+        OperandKind::Relocation(r_type, target) => {
+            *fixup = Some(Fixup {
+                kind: FixupKind::Relocation(*r_type),
+                target: target.clone(),
+            });
+            Ok(0)
+        }
+        OperandKind::Label(target) => {
+            *fixup = Some(Fixup {
+                kind: FixupKind::Immediate { signed },
+                target: target.clone(),
+            });
+            Ok(0)
+        }
+        _ if signed => signed_16(operand),
+        _ => unsigned_16(operand),
+    }
+}
+
+fn signed_16(operand: &Operand) -> EncodeResult<u32> {
+    Ok(immediate(operand, i16::MIN as i64, i16::MAX as i64, "immediate")? & 0xFFFF)
+}
+
+fn unsigned_16(operand: &Operand) -> EncodeResult<u32> {
+    immediate(operand, 0, u16::MAX as i64, "immediate")
+}
+
+/// `offset($base)`, a plain immediate is an offset from `$zero`
+///
+/// An offset that isn't constant yet is filled in later like in `immediate_16`
+fn memory(operand: &Operand, fixup: &mut Option<Fixup>) -> EncodeResult<(u32, u32)> {
+    let (offset, base) = match &operand.kind {
+        OperandKind::Memory { offset, base } => (offset, *base),
+        OperandKind::Immediate(offset) => (&Value::Constant(*offset), 0),
+        _ => {
+            return Err((
+                DiagnosticKind::OperandType,
+                "Expected memory operand (i.e. offset($base))".into(),
+                operand.area.clone(),
+            ))
+        }
+    };
+    if base >= 32 {
+        return Err((
+            DiagnosticKind::OperandType,
+            format!("Invalid register: ${}", base),
+            operand.area.clone(),
+        ));
+    }
+    let offset = match offset {
+        Value::Constant(offset) => *offset,
+        Value::Relocation(r_type, target) => {
+            *fixup = Some(Fixup {
+                kind: FixupKind::Relocation(*r_type),
+                target: target.clone(),
+            });
+            return Ok((0, base));
+        }
+        Value::Label(target) => {
+            *fixup = Some(Fixup {
+                kind: FixupKind::Offset,
+                target: target.clone(),
+            });
+            return Ok((0, base));
+        }
+    };
+    if !(i16::MIN as i64..=i16::MAX as i64).contains(&offset) {
+        return Err((
+            DiagnosticKind::OutOfRange,
+            format!(
+                "offset {} is out of range ({}..={})",
+                offset,
+                i16::MIN,
+                i16::MAX
+            ),
+            operand.area.clone(),
+        ));
+    }
+    Ok((offset as u32 & 0xFFFF, base))
+}
+
+/// The encoded offset of a branch at `addr` to `target`, an address in the same section or a label
+fn branch_offset(operand: &Operand, addr: u32, fixup: &mut Option<Fixup>) -> EncodeResult<u32> {
+    if let OperandKind::Label(label) = &operand.kind {
+        *fixup = Some(Fixup {
+            kind: FixupKind::Relocation(MipsRelocationType::R_MIPS_PC16),
+            target: label.clone(),
+        });
+        return Ok(0);
+    }
+    let target = immediate(operand, 0, u32::MAX as i64, "branch target")?;
+    if target & 0b11 != 0 {
+        return Err((
+            DiagnosticKind::BranchTarget,
+            format!("branch target {:#x} is not word aligned", target),
+            operand.area.clone(),
+        ));
+    }
+    // branches are relative to the instruction after the branch
+    let offset = (target as i64 - (addr as i64 + 4)) >> 2;
+    if !(i16::MIN as i64..=i16::MAX as i64).contains(&offset) {
+        return Err((
+            DiagnosticKind::BranchTarget,
+            format!(
+                "branch target {:#x} is too far away ({} instructions, the limit is {}..={})",
+                target,
+                offset,
+                i16::MIN,
+                i16::MAX
+            ),
+            operand.area.clone(),
+        ));
+    }
+    Ok(offset as u32 & 0xFFFF)
+}
+
+fn jump_target(operand: &Operand, addr: u32, fixup: &mut Option<Fixup>) -> EncodeResult<u32> {
+    if let OperandKind::Label(label) = &operand.kind {
+        *fixup = Some(Fixup {
+            kind: FixupKind::Relocation(MipsRelocationType::R_MIPS_26),
+            target: label.clone(),
+        });
+        return Ok(0);
+    }
+    let target = immediate(operand, 0, u32::MAX as i64, "jump target")?;
+    if target & 0b11 != 0 {
+        return Err((
+            DiagnosticKind::BranchTarget,
+            format!("jump target {:#x} is not word aligned", target),
+            operand.area.clone(),
+        ));
+    }
+    let region = addr.wrapping_add(4) & 0xF000_0000;
+    if target & 0xF000_0000 != region {
+        return Err((
+            DiagnosticKind::BranchTarget,
+            format!(
+                "jump target {:#x} is outside of the current 256MB region ({:#x}..={:#x})",
+                target,
+                region,
+                region | 0x0FFF_FFFF
+            ),
+            operand.area.clone(),
+        ));
+    }
+    Ok((target >> 2) & 0x03FF_FFFF)
+}
+
+/// Encodes an instruction at `addr`, `area` is the location of the mnemonic
+pub fn encode(
+    info: InstructionInfo,
+    operands: &[Operand],
+    addr: u32,
+    area: &PPArea,
+) -> EncodeResult<Encoded> {
+    use Format::*;
+    let (min, max) = match info.format {
+        None => (0, 0),
+        Code => (0, 1),
+        Rs | Rd | Jump => (1, 1),
+        Jalr => (1, 2),
+        RsRt | RtImm | RsBranch | RtMemory => (2, 2),
+        Trap => (2, 3),
+        RdRsRt | RdRtShamt | RdRtRs | RtRsSigned | RtRsUnsigned | RsRtBranch => (3, 3),
+    };
+    if operands.len() < min || operands.len() > max {
+        let expected = if min == max {
+            format!("{}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err((
+            DiagnosticKind::OperandCount,
+            format!(
+                "Expected {} operands but found {}",
+                expected,
+                operands.len()
+            ),
+            operands.get(max).map_or(area, |op| &op.area).clone(),
+        ));
+    }
+
+    let s = |reg: u32| reg << 21;
+    let t = |reg: u32| reg << 16;
+    let d = |reg: u32| reg << 11;
+    let shamt = |val: u32| val << 6;
+    let mut fixup = Option::None;
+
+    let fields = match (info.format, operands) {
+        (None, []) => 0,
+        (RdRsRt, [rd, rs, rt]) => d(register(rd)?) | s(register(rs)?) | t(register(rt)?),
+        (RdRtShamt, [rd, rt, sa]) => {
+            d(register(rd)?) | t(register(rt)?) | shamt(immediate(sa, 0, 31, "shift amount")?)
+        }
+        (RdRtRs, [rd, rt, rs]) => d(register(rd)?) | t(register(rt)?) | s(register(rs)?),
+        (RsRt | Trap, [rs, rt]) => s(register(rs)?) | t(register(rt)?),
+        (Trap, [rs, rt, code]) => {
+            s(register(rs)?) | t(register(rt)?) | (immediate(code, 0, 0x3FF, "trap code")? << 6)
+        }
+        (Rs, [rs]) => s(register(rs)?),
+        (Rd, [rd]) => d(register(rd)?),
+        (Jalr, [rs]) => d(31) | s(register(rs)?),
+        (Jalr, [rd, rs]) => d(register(rd)?) | s(register(rs)?),
+        (Code, []) => 0,
+        (Code, [code]) => immediate(code, 0, 0xFFFFF, "code")? << 6,
+        (RtRsSigned, [rt, rs, imm]) => {
+            t(register(rt)?) | s(register(rs)?) | immediate_16(imm, true, &mut fixup)?
+        }
+        (RtRsUnsigned, [rt, rs, imm]) => {
+            t(register(rt)?) | s(register(rs)?) | immediate_16(imm, false, &mut fixup)?
+        }
+        (RtImm, [rt, imm]) => t(register(rt)?) | immediate_16(imm, false, &mut fixup)?,
+        (RsRtBranch, [rs, rt, target]) => {
+            s(register(rs)?) | t(register(rt)?) | branch_offset(target, addr, &mut fixup)?
+        }
+        (RsBranch, [rs, target]) => s(register(rs)?) | branch_offset(target, addr, &mut fixup)?,
+        (RtMemory, [rt, mem]) => {
+            let (offset, base) = memory(mem, &mut fixup)?;
+            t(register(rt)?) | s(base) | offset
+        }
+        (Jump, [target]) => jump_target(target, addr, &mut fixup)?,
+        _ => unreachable!("operand count is checked above"),
+    };
+    Ok(Encoded {
+        word: info.base | fields,
+        fixup,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{
+        assemble_source,
+        debug::tests::sections,
+        operand::parse_operands,
+        preprocessor::{PPArea, PPToken},
+        relocation::SymbolRef,
+        symbol::SHN_ABS,
+        AssemblerState,
+    };
+    use crate::lexer::tokenizer::Tokenizer;
+
+    fn encode_at(line: &str, addr: u32) -> Result<Encoded, String> {
+        let mut state = AssemblerState::new(Default::default());
+        let section_index = state.current_section_index();
+        let label = state.symbol_mut("label");
+        label.value = 0x40;
+        label.section_index = section_index;
+        state.symbol_mut("constant").section_index = SHN_ABS;
+        state.symbol_mut("constant").value = 0x40;
+        let mut tokens = Tokenizer::new_from_str(line).map(|tok| PPToken::new(tok.unwrap()));
+        let mnemonic = tokens.next().unwrap();
+        let args: Vec<_> = tokens.collect();
+        let info = match &mnemonic.tok {
+            crate::lexer::tokenizer::TokenType::Identifier(ident) => lookup(ident).unwrap(),
+            other => panic!("{:?}", other),
+        };
+        let area: &PPArea = &mnemonic.location;
+        parse_operands(&state, &args, area)
+            .and_then(|operands| encode(info, &operands, addr, area))
+            .map_err(|(_, msg, _)| msg)
+    }
+
+    /// Encodes an instruction that doesn't refer to any labels
+    fn assemble_at(line: &str, addr: u32) -> Result<u32, String> {
+        encode_at(line, addr).map(|encoded| {
+            assert_eq!(encoded.fixup, Option::None, "{}", line);
+            encoded.word
+        })
+    }
+
+    fn assemble(line: &str) -> Result<u32, String> {
+        assemble_at(line, 0)
+    }
+
+    #[test]
+    fn register_encoding() {
+        assert_eq!(assemble("add $3, $1, $2"), Ok(0x00221820));
+        assert_eq!(assemble("subu $v0, $a0, $a1"), Ok(0x00851023));
+        assert_eq!(assemble("sll $2, $3, 4"), Ok(0x00031100));
+        assert_eq!(assemble("srav $2, $3, $4"), Ok(0x00831007));
+        assert_eq!(assemble("mult $t0, $t1"), Ok(0x01090018));
+        assert_eq!(assemble("mflo $s0"), Ok(0x00008012));
+        assert_eq!(assemble("jr $ra"), Ok(0x03E00008));
+        assert_eq!(assemble("jalr $t9"), Ok(0x0320F809));
+        assert_eq!(assemble("syscall"), Ok(0x0000000C));
+        assert_eq!(assemble("break 7"), Ok(0x000001CD));
+        assert_eq!(assemble("teq $1, $2"), Ok(0x00220034));
+        assert_eq!(assemble("nop"), Ok(0));
+    }
+
+    #[test]
+    fn immediate_encoding() {
+        assert_eq!(assemble("addi $4, $4, -1"), Ok(0x2084FFFF));
+        assert_eq!(assemble("ori $at, $zero, 0xFFFF"), Ok(0x3401FFFF));
+        assert_eq!(assemble("lui $1, 0x1234"), Ok(0x3C011234));
+        assert_eq!(assemble("lw $4, 8($sp)"), Ok(0x8FA40008));
+        assert_eq!(assemble("sb $4, -1($5)"), Ok(0xA0A4FFFF));
+        assert_eq!(assemble("lw $4, constant($0)"), Ok(0x8C040040));
+        assert_eq!(assemble("addiu $2, $0, 'a'"), Ok(0x24020061));
+        assert_eq!(assemble("addiu $2, $0, constant-4"), Ok(0x2402003C));
+        assert_eq!(assemble("lw $4, constant-0x40+8($sp)"), Ok(0x8FA40008));
+        // the high half is rounded up when the low half is negative
+        assert_eq!(assemble("lui $1, %hi(0x12348000)"), Ok(0x3C011235));
+        assert_eq!(assemble("lui $1, %hi(0x12347FFF)"), Ok(0x3C011234));
+        assert_eq!(assemble("addiu $1, $1, %lo(0x12348000)"), Ok(0x24218000));
+        assert_eq!(assemble("lw $1, %lo(0x1234)($1)"), Ok(0x8C211234));
+        assert_eq!(assemble("addiu $2, $0, (1 << 4) | 3"), Ok(0x24020013));
+        assert_eq!(assemble("addiu $2, $0, constant / 3 % 5"), Ok(0x24020001));
+        assert_eq!(assemble("lw $4, (constant * 2)($sp)"), Ok(0x8FA40080));
+        assert_eq!(assemble("lui $1, %hi(constant << 16)"), Ok(0x3C010040));
+    }
+
+    #[test]
+    fn branches_and_jumps() {
+        assert_eq!(assemble_at("beq $1, $2, 8", 0), Ok(0x10220001));
+        assert_eq!(assemble_at("bne $1, $2, 0x40", 0x40), Ok(0x1422FFFF));
+        assert_eq!(assemble_at("bgez $1, 4", 0), Ok(0x04210000));
+        assert_eq!(assemble_at("bltz $1, 0", 0), Ok(0x0420FFFF));
+        assert_eq!(assemble("j 0x400000"), Ok(0x08100000));
+        assert_eq!(assemble("jal constant"), Ok(0x0C000010));
+    }
+
+    #[test]
+    fn label_fixups() {
+        let fixup = |r_type, symbol: &str, addend| {
+            Some(Fixup {
+                kind: FixupKind::Relocation(r_type),
+                target: SymbolRef {
+                    symbol: symbol.into(),
+                    addend,
+                    minus: Option::None,
+                },
+            })
+        };
+        assert_eq!(
+            encode_at("bne $1, $2, label", 0x40),
+            Ok(Encoded {
+                word: 0x14220000,
+                fixup: fixup(MipsRelocationType::R_MIPS_PC16, "label", 0),
+            })
+        );
+        assert_eq!(
+            encode_at("jal later", 0),
+            Ok(Encoded {
+                word: 0x0C000000,
+                fixup: fixup(MipsRelocationType::R_MIPS_26, "later", 0),
+            })
+        );
+        assert_eq!(
+            encode_at("j later+8", 0),
+            Ok(Encoded {
+                word: 0x08000000,
+                fixup: fixup(MipsRelocationType::R_MIPS_26, "later", 8),
+            })
+        );
+        assert_eq!(
+            encode_at("lui $4, %hi(later-4)", 0),
+            Ok(Encoded {
+                word: 0x3C040000,
+                fixup: fixup(MipsRelocationType::R_MIPS_HI16, "later", -4),
+            })
+        );
+        assert_eq!(
+            encode_at("lw $4, %lo(constant+label)($4)", 0),
+            Ok(Encoded {
+                word: 0x8C840000,
+                fixup: fixup(MipsRelocationType::R_MIPS_LO16, "label", 0x40),
+            })
+        );
+        assert_eq!(
+            encode_at("addiu $4, $gp, %gp_rel(later)", 0),
+            Ok(Encoded {
+                word: 0x27840000,
+                fixup: fixup(MipsRelocationType::R_MIPS_GPREL16, "later", 0),
+            })
+        );
+        // constants defined later are filled in like labels, they are only an error if they
+        // turn out to be an address
+        assert_eq!(
+            encode_at("addiu $4, $4, SIZE", 0),
+            Ok(Encoded {
+                word: 0x24840000,
+                fixup: Some(Fixup {
+                    kind: FixupKind::Immediate { signed: true },
+                    target: SymbolRef::new("SIZE"),
+                }),
+            })
+        );
+        assert_eq!(
+            encode_at("lw $4, end-label($0)", 0).unwrap().fixup,
+            Some(Fixup {
+                kind: FixupKind::Offset,
+                target: SymbolRef {
+                    symbol: "end".into(),
+                    addend: 0,
+                    minus: Some("label".into()),
+                },
+            })
+        );
+        assert!(encode_at("lw $4, %hi(label)", 0).is_err());
+        assert_eq!(
+            encode_at("beq $0, $0, .+8", 0),
+            Ok(Encoded {
+                word: 0x10000000,
+                fixup: fixup(MipsRelocationType::R_MIPS_PC16, ".", 8),
+            })
+        );
+        assert!(encode_at("j label-later-other", 0).is_err());
+        assert!(encode_at("addiu $4, $4, label * 2", 0).is_err());
+        assert!(encode_at("j label+later", 0).is_err());
+        assert!(encode_at("lui $4, %gp_rel(0x1000)", 0).is_err());
+        assert!(encode_at("lui $4, %high(label)", 0).is_err());
+    }
+
+    #[test]
+    fn forward_constants() {
+        let text = |source: &str| -> Result<Vec<u32>, Vec<DiagnosticKind>> {
+            let object = assemble_source("forward.asm", source).map_err(|report| {
+                report
+                    .diagnostics()
+                    .iter()
+                    .map(|d| d.kind)
+                    .collect::<Vec<_>>()
+            })?;
+            let (_, data) = sections(&object)
+                .into_iter()
+                .find(|(name, _)| name == ".text")
+                .unwrap();
+            Ok(data
+                .chunks(4)
+                .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+                .collect())
+        };
+        assert_eq!(
+            text("start:\naddiu $t1, $t0, SIZE\naddiu $t1, $t1, end - start\nori $t1, $t1, MASK\nlw $t1, end - start + 4($sp)\nend:\n.equ SIZE, -0x1234\n.equ MASK, 0xFFFF\n"),
+            Ok(vec![0x2509EDCC, 0x25290010, 0x3529FFFF, 0x8FA90014])
+        );
+        assert_eq!(
+            text("addiu $t1, $t0, SIZE\n.equ SIZE, 0x12345\n"),
+            Err(vec![DiagnosticKind::OutOfRange])
+        );
+        assert_eq!(
+            text("addiu $t1, $t0, label\nlw $t1, label($0)\n.data\nlabel: .word 0\n"),
+            Err(vec![
+                DiagnosticKind::OperandType,
+                DiagnosticKind::OperandType
+            ])
+        );
+        assert_eq!(
+            text("addiu $t1, $t0, end - start\nstart:\n.data\nend:\n"),
+            Err(vec![DiagnosticKind::Expression])
+        );
+        assert_eq!(
+            text("addiu $t1, $t0, SIZE\n"),
+            Err(vec![DiagnosticKind::UndefinedSymbol])
+        );
+    }
+
+    #[test]
+    fn operand_errors() {
+        assert!(assemble("addi $4, $4, 32768").is_err());
+        assert!(assemble("andi $4, $4, -1").is_err());
+        assert!(assemble("sll $2, $3, 32").is_err());
+        assert!(assemble("add $3, $1").is_err());
+        assert!(assemble("add $3, $1, 5").is_err());
+        assert!(assemble("beq $1, $2, 6").is_err());
+        assert!(assemble_at("beq $1, $2, 0x40000", 0).is_err());
+        assert!(assemble_at("j 0x10000000", 0).is_err());
+        assert!(assemble("add $3, $1, $2,").is_err());
+        assert!(assemble("addi $4, $4, 1 << 15").is_err());
+        assert!(assemble("addi $4, $4, (1 + 2").is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use std::fmt::Write;
+
+use elf::internal::relocation::MipsRelocationType;
+
+use crate::{disassembler::simple::nammed_regs, lexer::tokenizer::TokenType};
+
+use super::{
+    operand::{integer_literal, register_from_name, OperandKind, Value},
+    preprocessor::{PPArea, PreProcessedLine},
+    pseudo::Expanded,
+    section::SectionKind,
+    symbol::{Symbol, SHN_ABS, SHN_UNDEF},
+    AssemblerState,
+};
+
+/// Bytes shown per row of the listing
+const ROW_BYTES: u32 = 4;
+/// Rows shown for a single line, the rest of a large `.space` or string is left out
+const MAX_ROWS: u32 = 4;
+
+//------------------------------------------------------------------------
+
+/// Bytes `start..end` of a section
+#[derive(Debug, Clone)]
+pub(crate) struct ListingRange {
+    pub text: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// A line after include and macro expansion with what it assembled to
+#[derive(Debug, Clone)]
+pub(crate) struct ListingEntry {
+    pub area: PPArea,
+    /// Index into `AssemblerState::sections`
+    pub section: usize,
+    pub line: ListingRange,
+    /// The real instructions of a pseudo instruction
+    pub expansion: Vec<ListingRange>,
+}
+
+//------------------------------------------------------------------------
+
+fn token_text(tok: &TokenType) -> String {
+    match tok {
+        // the tokenizer drops the `$` of named registers
+        TokenType::Identifier(ident) if register_from_name(ident).is_some() => {
+            format!("${}", ident)
+        }
+        TokenType::Identifier(ident) => ident.clone(),
+        TokenType::Label(label) => format!("{}:", label),
+        TokenType::Register(reg) => format!("${}", reg),
+        TokenType::StringLiteral(string) => format!("{:?}", string),
+        TokenType::CharLiteral(c) => format!("{:?}", c),
+        TokenType::F32Literal(val) => val.to_string(),
+        TokenType::F64Literal(val) => val.to_string(),
+        TokenType::U128Literal(val) => val.to_string(),
+        TokenType::I128Literal(val) => val.to_string(),
+        TokenType::LPar => "(".into(),
+        TokenType::RPar => ")".into(),
+        TokenType::Comma => ",".into(),
+        TokenType::Plus => "+".into(),
+        TokenType::Minus => "-".into(),
+        TokenType::Star => "*".into(),
+        TokenType::Slash => "/".into(),
+        TokenType::Percent => "%".into(),
+        TokenType::Ampersand => "&".into(),
+        TokenType::BitwiseOr => "|".into(),
+        TokenType::BitwiseXor => "^".into(),
+        TokenType::BitwiseNot => "~".into(),
+        TokenType::ShiftLeft => "<<".into(),
+        TokenType::ShiftRight => ">>".into(),
+        TokenType::LogicalAnd => "&&".into(),
+        TokenType::LogicalOr => "||".into(),
+        TokenType::LogicalNot => "!".into(),
+        TokenType::LessThan => "<".into(),
+        TokenType::LessThanEq => "<=".into(),
+        TokenType::GreaterThan => ">".into(),
+        TokenType::GreaterThanEq => ">=".into(),
+        TokenType::Equals => "==".into(),
+        TokenType::NotEquals => "!=".into(),
+        TokenType::Assignment => "=".into(),
+        TokenType::At => "@".into(),
+        other => match integer_literal(other) {
+            Some(value) => value.to_string(),
+            None => format!("{:?}", other),
+        },
+    }
+}
+
+/// Operators and `(`, what follows them starts an operand
+fn operator(tok: &TokenType) -> bool {
+    matches!(
+        tok,
+        TokenType::LPar
+            | TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Percent
+            | TokenType::Ampersand
+            | TokenType::BitwiseOr
+            | TokenType::BitwiseXor
+            | TokenType::BitwiseNot
+            | TokenType::ShiftLeft
+            | TokenType::ShiftRight
+            | TokenType::LogicalAnd
+            | TokenType::LogicalOr
+            | TokenType::LogicalNot
+            | TokenType::LessThan
+            | TokenType::LessThanEq
+            | TokenType::GreaterThan
+            | TokenType::GreaterThanEq
+            | TokenType::Equals
+            | TokenType::NotEquals
+    )
+}
+
+/// The text of a preprocessed line, made from its tokens so expanded macro arguments show up
+pub(crate) fn line_text(line: &PreProcessedLine) -> String {
+    match line {
+        PreProcessedLine::Label(label, _) => format!("{}:", label),
+        PreProcessedLine::Instruction(mnemonic, args, _) => {
+            let mut text = mnemonic.clone();
+            // the previous token and whether it was a prefix operator
+            let mut prev: Option<&TokenType> = None;
+            let mut prefix = false;
+            for arg in args {
+                let operand_start =
+                    prev.is_none_or(|tok| operator(tok) || matches!(tok, TokenType::Comma));
+                let glued = prev.is_some()
+                    && (prefix
+                        || matches!(prev, Some(TokenType::LPar))
+                        || matches!(arg.tok, TokenType::Comma | TokenType::RPar)
+                        || (matches!(arg.tok, TokenType::LPar) && !operand_start));
+                if !glued {
+                    text.push(' ');
+                }
+                text.push_str(&token_text(&arg.tok));
+                prefix = operand_start
+                    && matches!(
+                        arg.tok,
+                        TokenType::Minus
+                            | TokenType::Plus
+                            | TokenType::BitwiseNot
+                            | TokenType::LogicalNot
+                            | TokenType::Percent
+                    );
+                prev = Some(&arg.tok);
+            }
+            text
+        }
+    }
+}
+
+fn immediate(value: i64) -> String {
+    if (-0xFF..=0xFF).contains(&value) {
+        value.to_string()
+    } else if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Constant(value) => immediate(*value),
+        Value::Label(label) => label.to_string(),
+        Value::Relocation(r_type, label) => {
+            let operator = match r_type {
+                MipsRelocationType::R_MIPS_HI16 => "hi",
+                MipsRelocationType::R_MIPS_LO16 => "lo",
+                MipsRelocationType::R_MIPS_GPREL16 => "gp_rel",
+                other => return format!("{:?}({})", other, label),
+            };
+            format!("%{}({})", operator, label)
+        }
+    }
+}
+
+/// The text of a real instruction a pseudo instruction expanded to
+pub(crate) fn expanded_text(expanded: &Expanded) -> String {
+    let operands: Vec<_> = expanded
+        .operands
+        .iter()
+        .map(|operand| match &operand.kind {
+            OperandKind::Register(reg) => nammed_regs(*reg as usize).to_owned(),
+            OperandKind::Immediate(value) => immediate(*value),
+            OperandKind::Label(label) => label.to_string(),
+            OperandKind::Relocation(r_type, label) => {
+                value_text(&Value::Relocation(*r_type, label.clone()))
+            }
+            OperandKind::Memory { offset, base } => {
+                format!("{}({})", value_text(offset), nammed_regs(*base as usize))
+            }
+        })
+        .collect();
+    if operands.is_empty() {
+        expanded.mnemonic.clone()
+    } else {
+        format!("{} {}", expanded.mnemonic, operands.join(", "))
+    }
+}
+
+//------------------------------------------------------------------------
+
+/// `file:line` of where the text of a line comes from, the body of a macro for expanded lines
+fn source_location(state: &AssemblerState, area: &PPArea) -> String {
+    let area = area.source();
+    match area.file {
+        Some(file) => format!("{}:{}", state.get_file(file as usize).file, area.line + 1),
+        None => format!("<define>:{}", area.line + 1),
+    }
+}
+
+fn write_range(
+    out: &mut String,
+    state: &AssemblerState,
+    section: usize,
+    location: &str,
+    range: &ListingRange,
+) {
+    let section = &state.sections()[section];
+    let bytes = match section.kind {
+        SectionKind::Progbits => &section.data[range.start as usize..range.end as usize],
+        SectionKind::Nobits => &[],
+    };
+    let mut rows = bytes.chunks(ROW_BYTES as usize);
+    let hex = |row: Option<&[u8]>| {
+        row.unwrap_or_default()
+            .iter()
+            .fold(String::new(), |hex, byte| hex + &format!("{:02x}", byte))
+    };
+    let _ = writeln!(
+        out,
+        "{:<24} {}:{:08x}  {:<8}  {}",
+        location,
+        section.name,
+        range.start,
+        hex(rows.next()),
+        range.text
+    );
+    let indent = location.len().max(24) + section.name.len() + 2;
+    for (index, row) in (1..).zip(rows) {
+        if index == MAX_ROWS {
+            let _ = writeln!(out, "{:indent$}...", "", indent = indent + 10);
+            break;
+        }
+        let _ = writeln!(
+            out,
+            "{:indent$}{:08x}  {}",
+            "",
+            range.start + index * ROW_BYTES,
+            hex(Some(row)),
+            indent = indent
+        );
+    }
+}
+
+/// Every assembled line with its location, address and bytes, pseudo instructions are followed
+/// by the instructions they expanded to
+pub(crate) fn write_listing(state: &AssemblerState) -> String {
+    let mut out = String::new();
+    for entry in state.listing() {
+        let location = source_location(state, &entry.area);
+        if entry.expansion.is_empty() {
+            write_range(&mut out, state, entry.section, &location, &entry.line);
+            continue;
+        }
+        let header = ListingRange {
+            start: entry.expansion[0].start,
+            end: entry.expansion[0].start,
+            text: entry.line.text.clone(),
+        };
+        write_range(&mut out, state, entry.section, &location, &header);
+        for expanded in &entry.expansion {
+            let indented = ListingRange {
+                text: format!("    {}", expanded.text),
+                ..expanded.clone()
+            };
+            write_range(&mut out, state, entry.section, "", &indented);
+        }
+    }
+    out
+}
+
+//------------------------------------------------------------------------
+
+fn section_name(state: &AssemblerState, symbol: &Symbol) -> String {
+    match symbol.section_index {
+        SHN_UNDEF => "UND".into(),
+        SHN_ABS => "ABS".into(),
+        index => state.sections()[index as usize - 1].name.clone(),
+    }
+}
+
+/// The sections with their sizes and the symbol table sorted by section and address
+pub(crate) fn write_map(state: &AssemblerState) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Sections:");
+    let _ = writeln!(
+        out,
+        "  {:<3} {:<16} {:<8} {:<8} {:<5} {:<8} Flags",
+        "Idx", "Name", "Address", "Size", "Align", "Type"
+    );
+    for (index, section) in (1..).zip(state.sections()) {
+        let flags = [
+            (section.flags.alloc, 'A'),
+            (section.flags.write, 'W'),
+            (section.flags.execute, 'X'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect::<String>();
+        let kind = match section.kind {
+            SectionKind::Progbits => "PROGBITS",
+            SectionKind::Nobits => "NOBITS",
+        };
+        let _ = writeln!(
+            out,
+            "  {:<3} {:<16} {:08x} {:08x} {:<5} {:<8} {}",
+            index,
+            section.name,
+            0,
+            section.size(),
+            section.alignment,
+            kind,
+            flags
+        );
+    }
+
+    let mut symbols: Vec<&Symbol> = state.symbols().collect();
+    symbols.sort_by(|a, b| {
+        a.section_index
+            .cmp(&b.section_index)
+            .then(a.value.cmp(&b.value))
+            .then(a.name.cmp(&b.name))
+    });
+    let _ = writeln!(out, "\nSymbols:");
+    let _ = writeln!(
+        out,
+        "  {:<8} {:<8} {:<11} {:<10} {:<16} Name",
+        "Value", "Size", "Type", "Bind", "Section"
+    );
+    for symbol in symbols {
+        let _ = writeln!(
+            out,
+            "  {:08x} {:08x} {:<11} {:<10} {:<16} {}",
+            symbol.value,
+            symbol.size,
+            format!("{:?}", symbol.s_type),
+            format!("{:?}", symbol.binding),
+            section_name(state, symbol),
+            symbol.name
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{source::MemorySources, Assembler, AssemblerSettings};
+
+    /// Assembles `source` as `name.asm` with a listing, returns the listing and the map
+    fn listing(name: &str, source: &str) -> (String, String) {
+        let path = format!("{}.asm", name);
+        let sources = MemorySources::new().with(path.clone(), source);
+        let settings = AssemblerSettings {
+            listing: true,
+            ..Default::default()
+        };
+        let mut assembler = Assembler::with_sources(settings, sources);
+        if let Err(report) = assembler.assemble(path, &mut Vec::new()) {
+            panic!("{}", report);
+        }
+        (assembler.listing(), assembler.map())
+    }
+
+    #[test]
+    fn listing_and_map() {
+        let (listing, map) = listing(
+            "listing_and_map",
+            ".macro push reg\n  addiu $sp, $sp, -4\n  sw \\reg, 0($sp)\n.endm\n\
+             .globl main\nmain:\n  la $a0, msg\n  push $ra\n.data\nmsg: .asciiz \"hello world\"\n\
+             .bss\nbuf: .space 64\n",
+        );
+        let lines: Vec<_> = listing
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        let file = |line: usize| format!("listing_and_map.asm:{}", line);
+        let line = |index: usize| {
+            let line = &lines[index];
+            line[line.find(".asm:").map_or(0, |pos| pos + 5)..].to_owned()
+        };
+        assert!(lines[0].ends_with(&format!("{} .text:00000000 .globl main", file(5))));
+        assert_eq!(line(1), "6 .text:00000000 main:");
+        assert_eq!(line(2), "7 .text:00000000 la $a0, msg");
+        // the expansion, relocated fields are still zero
+        assert_eq!(lines[3], ".text:00000000 3c040000 lui $a0, %hi(msg)");
+        assert_eq!(lines[4], ".text:00000004 24840000 addiu $a0, $a0, %lo(msg)");
+        // macro lines point into the body of the macro
+        assert_eq!(line(5), "2 .text:00000008 27bdfffc addiu $sp, $sp, -4");
+        assert_eq!(line(6), "3 .text:0000000c afbf0000 sw $ra, 0($sp)");
+        assert_eq!(line(7), "9 .data:00000000 .data");
+        assert_eq!(line(8), "10 .data:00000000 msg:");
+        assert_eq!(
+            line(9),
+            "10 .data:00000000 68656c6c .asciiz \"hello world\""
+        );
+        assert_eq!(lines[10], "00000004 6f20776f");
+        assert_eq!(lines[11], "00000008 726c6400");
+        assert_eq!(line(13), "12 .bss:00000000 buf:");
+        assert_eq!(line(14), "12 .bss:00000000 .space 64");
+
+        assert!(map.contains("1   .text            00000000 00000010 4     PROGBITS AX"));
+        assert!(map.contains("3   .bss             00000000 00000040 1     NOBITS   AW"));
+        let main = map
+            .lines()
+            .position(|line| line.ends_with(" main"))
+            .unwrap();
+        let msg = map.lines().position(|line| line.ends_with(" msg")).unwrap();
+        assert!(main < msg);
+        assert!(map.contains("00000000 00000000 STT_NOTYPE  STB_GLOBAL .text            main"));
+    }
+}
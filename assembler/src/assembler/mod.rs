@@ -1,16 +1,51 @@
-use std::fs::File;
+use std::io::Write;
 
 #[allow(clippy::module_inception)]
 mod assembler;
 pub use self::assembler::*;
+pub mod debug;
+pub mod diagnostic;
+pub mod directive;
+pub mod expression;
+pub mod instruction;
+pub mod listing;
+pub mod object;
+pub mod operand;
 pub mod preprocessor;
+pub mod pseudo;
+pub mod relocation;
+pub mod section;
+pub mod source;
 pub mod symbol;
 
 #[allow(dead_code)]
 #[allow(unused)]
 pub fn assemble(
     input: impl Into<String>,
-    output: &mut File,
+    output: &mut impl Write,
 ) -> Result<AssemblerReport, AssemblerReport> {
     Assembler::new().assemble(input.into(), output)
 }
+
+/// Like `assemble` with settings other than the defaults (i.e. predefines)
+#[allow(dead_code)]
+pub fn assemble_with_settings(
+    input: impl Into<String>,
+    output: &mut impl Write,
+    settings: AssemblerSettings,
+) -> Result<AssemblerReport, AssemblerReport> {
+    Assembler::with_settings(settings).assemble(input.into(), output)
+}
+
+/// Assembles `source` without touching the file system, `path` is the name it is reported
+/// under. Returns the relocatable object
+pub fn assemble_source(
+    path: impl Into<String>,
+    source: impl Into<String>,
+) -> Result<Vec<u8>, AssemblerReport> {
+    let path = path.into();
+    let sources = source::MemorySources::new().with(path.clone(), source);
+    let mut object = Vec::new();
+    Assembler::with_sources(Default::default(), sources).assemble(path, &mut object)?;
+    Ok(object)
+}
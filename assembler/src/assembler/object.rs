@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use elf::{
+    internal::{
+        header::header_util::{ElfEndian, ElfMachine, ElfType, MipsMachineFlags},
+        section::section_util::{SectionFlags, SectionType},
+    },
+    writer::{Elf32Rel, Elf32Symbol, Elf32Writer, StringTable, WriterSection},
+};
+
+use super::{
+    relocation::RelocationTarget,
+    section::{Section, SectionKind},
+    symbol::{SymBind, SymType, Symbol, SHN_UNDEF},
+    AssemblerState,
+};
+
+fn section_flags(section: &Section) -> SectionFlags {
+    let mut flags = SectionFlags::empty();
+    flags.set(SectionFlags::SHF_ALLOC, section.flags.alloc);
+    flags.set(SectionFlags::SHF_WRITE, section.flags.write);
+    flags.set(SectionFlags::SHF_EXECINSTR, section.flags.execute);
+    flags
+}
+
+/// Symbols that are referenced but never defined are external, just like GNU as
+fn binding(symbol: &Symbol) -> SymBind {
+    match symbol.binding {
+        SymBind::STB_LOCAL if symbol.section_index == SHN_UNDEF => SymBind::STB_GLOBAL,
+        binding => binding,
+    }
+}
+
+/// Writes everything that was assembled as a big endian ELF32 MIPS relocatable object
+///
+/// Sections keep their `section_index` as their index in the file. The symbol table starts
+/// with a symbol for every section (what relocations against local labels refer to) followed
+/// by the local and then the global symbols
+pub(crate) fn write_object(state: &AssemblerState) -> Vec<u8> {
+    let mut writer = Elf32Writer::new(ElfEndian::BigEndian, ElfType::ET_REL, ElfMachine::EM_MIPS);
+    writer.set_flags((MipsMachineFlags::E_MIPS_ARCH_32 | MipsMachineFlags::E_MIPS_ABI_O32).bits());
+    let enc = writer.encoder();
+
+    for section in state.sections() {
+        let sh_type = match section.kind {
+            SectionKind::Progbits => SectionType::SHT_PROGBITS,
+            SectionKind::Nobits => SectionType::SHT_NOBITS,
+        };
+        let mut out = WriterSection::new(section.name.clone(), sh_type, section_flags(section));
+        out.addralign = section.alignment;
+        out.data = section.data.clone();
+        out.nobits_size = section.size();
+        writer.add_section(out);
+    }
+    let symtab_index = writer.next_section_index();
+    let strtab_index = symtab_index + 1;
+
+    // locals first, sorted so the output doesn't depend on hash map order
+    let mut symbols: Vec<&Symbol> = state.symbols().collect();
+    symbols.sort_by(|a, b| {
+        let local = |sym: &Symbol| binding(sym) == SymBind::STB_LOCAL;
+        local(b)
+            .cmp(&local(a))
+            .then(a.section_index.cmp(&b.section_index))
+            .then(a.value.cmp(&b.value))
+            .then(a.name.cmp(&b.name))
+    });
+
+    let mut strtab = StringTable::new();
+    let mut symtab = Vec::new();
+    Elf32Symbol::default().write(enc, &mut symtab);
+    for index in 1..=state.sections().len() as u16 {
+        Elf32Symbol {
+            info: Elf32Symbol::info(SymBind::STB_LOCAL.into(), SymType::STT_SECTION.into()),
+            shndx: index,
+            ..Default::default()
+        }
+        .write(enc, &mut symtab);
+    }
+    let mut first_global = Option::None;
+    let mut symbol_indices = HashMap::new();
+    for (index, symbol) in symbols.into_iter().enumerate() {
+        let index = (state.sections().len() + 1 + index) as u32;
+        let binding = binding(symbol);
+        if binding != SymBind::STB_LOCAL && first_global.is_none() {
+            first_global = Some(index);
+        }
+        Elf32Symbol {
+            name: strtab.add(&symbol.name),
+            value: symbol.value,
+            size: symbol.size,
+            info: Elf32Symbol::info(binding.into(), symbol.s_type.into()),
+            other: symbol.vis.into(),
+            shndx: symbol.section_index,
+        }
+        .write(enc, &mut symtab);
+        symbol_indices.insert(symbol.name.as_str(), index);
+    }
+    let symbol_count = symtab.len() as u32 / Elf32Symbol::SIZE;
+
+    let mut section = WriterSection::new(".symtab", SectionType::SHT_SYMTAB, SectionFlags::empty());
+    section.link = strtab_index as u32;
+    section.info = first_global.unwrap_or(symbol_count);
+    section.addralign = 4;
+    section.entsize = Elf32Symbol::SIZE;
+    section.data = symtab;
+    writer.add_section(section);
+
+    let mut section = WriterSection::new(".strtab", SectionType::SHT_STRTAB, SectionFlags::empty());
+    section.data = strtab.data().to_vec();
+    writer.add_section(section);
+
+    for (index, relocated) in state.sections().iter().enumerate() {
+        if relocated.relocations.is_empty() {
+            continue;
+        }
+        let mut data = Vec::new();
+        for relocation in &relocated.relocations {
+            let symbol = match &relocation.target {
+                RelocationTarget::Section(index) => *index as u32,
+                RelocationTarget::Symbol(name) => symbol_indices[name.as_str()],
+            };
+            Elf32Rel {
+                offset: relocation.offset,
+                symbol,
+                r_type: relocation.r_type,
+            }
+            .write(enc, &mut data);
+        }
+        let mut section = WriterSection::new(
+            format!(".rel{}", relocated.name),
+            SectionType::SHT_REL,
+            SectionFlags::SHF_INFO_LINK,
+        );
+        section.link = symtab_index as u32;
+        section.info = index as u32 + 1;
+        section.addralign = 4;
+        section.entsize = Elf32Rel::SIZE;
+        section.data = data;
+        writer.add_section(section);
+    }
+
+    writer.write()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use elf::external::{
+        from_bytes, header::ExternalElfHeaderTrait, section::ExternalSectionHeaderTrait,
+        TernaryResult,
+    };
+
+    use crate::assembler::{source::MemorySources, Assembler, AssemblerSettings};
+
+    /// Assembles `source` as `name.asm` from memory
+    pub(crate) fn assemble_object(name: &str, source: &str) -> Vec<u8> {
+        assemble_object_with(name, source, Default::default())
+    }
+
+    pub(crate) fn assemble_object_with(
+        name: &str,
+        source: &str,
+        settings: AssemblerSettings,
+    ) -> Vec<u8> {
+        let path = format!("{}.asm", name);
+        let sources = MemorySources::new().with(path.clone(), source);
+        let mut object = Vec::new();
+        let res = Assembler::with_sources(settings, sources).assemble(path, &mut object);
+        if let Err(report) = res {
+            panic!("{}", report);
+        }
+        object
+    }
+
+    #[test]
+    fn relocatable_object() {
+        let object = assemble_object(
+            "relocatable_object",
+            ".globl main\nmain:\n  la $a0, msg\n  jal puts\n  b main\n.data\nmsg: .word main\n.bss\nbuf: .space 4\n",
+        );
+        let elf = match from_bytes(&object) {
+            TernaryResult::Ok1(elf) => elf,
+            _ => panic!("not an ELF32 file"),
+        };
+        assert_eq!(elf.elf_header().elftype(), 1);
+        assert_eq!(elf.elf_header().machine(), 8);
+
+        let mut sections = Vec::new();
+        let mut index = 0;
+        while let Some(section) = elf.section_header(index) {
+            sections.push((section.get_name(), section.sh_type(), section.size()));
+            index += 1;
+        }
+        assert_eq!(
+            sections,
+            [
+                ("", 0, 0),
+                (".text", 1, 16),
+                (".data", 1, 4),
+                (".bss", 8, 4),
+                (".symtab", 2, 16 * 8),
+                (".strtab", 3, 19),
+                (".rel.text", 9, 8 * 4),
+                (".rel.data", 9, 8),
+                (".shstrtab", 3, 64),
+            ]
+        );
+
+        // (offset, symbol index, type)
+        let relocations = |index| -> Vec<(u32, u32, u8)> {
+            elf.section_header(index)
+                .unwrap()
+                .get_data()
+                .chunks(8)
+                .map(|rel| {
+                    let info = u32::from_be_bytes(rel[4..8].try_into().unwrap());
+                    let offset = u32::from_be_bytes(rel[0..4].try_into().unwrap());
+                    (offset, info >> 8, info as u8)
+                })
+                .collect()
+        };
+        // msg is local so it is relocated against .data even though it is defined after it is
+        // used, puts and main are global
+        assert_eq!(
+            relocations(6),
+            [(0, 2, 5), (4, 2, 6), (8, 6, 4), (12, 7, 10)]
+        );
+        assert_eq!(relocations(7), [(0, 7, 2)]);
+    }
+}
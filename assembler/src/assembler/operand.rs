@@ -0,0 +1,230 @@
+use elf::internal::relocation::MipsRelocationType;
+
+use crate::{disassembler::simple::nammed_regs, lexer::tokenizer::TokenType};
+
+use super::{
+    diagnostic::DiagnosticKind,
+    expression::{evaluate, evaluate_value},
+    preprocessor::{PPArea, PPToken},
+    relocation::SymbolRef,
+    AssemblerState,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperandKind {
+    Register(u32),
+    Immediate(i64),
+    /// A label, its address is only known after linking
+    Label(SymbolRef),
+    /// A 16 bit field holding part of the address of a label (`%hi`, `%lo` and `%gp_rel`)
+    Relocation(MipsRelocationType, SymbolRef),
+    /// `offset($base)`
+    Memory {
+        offset: Value,
+        base: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Constant(i64),
+    Label(SymbolRef),
+    Relocation(MipsRelocationType, SymbolRef),
+}
+
+impl From<Value> for OperandKind {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Constant(value) => OperandKind::Immediate(value),
+            Value::Label(label) => OperandKind::Label(label),
+            Value::Relocation(r_type, label) => OperandKind::Relocation(r_type, label),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Operand {
+    pub kind: OperandKind,
+    pub area: PPArea,
+}
+
+/// `$0`..`$31` are tokenized as registers, named registers (`$t0`, `$sp`) arrive as identifiers
+pub fn register_from_name(name: &str) -> Option<u32> {
+    match name {
+        "s8" => Some(30),
+        _ => (0..32).find(|reg| &nammed_regs(*reg as usize)[1..] == name),
+    }
+}
+
+pub(crate) fn integer_literal(tok: &TokenType) -> Option<i64> {
+    Some(match tok {
+        TokenType::I8Literal(val) => *val as i64,
+        TokenType::I16Literal(val) => *val as i64,
+        TokenType::I32Literal(val) => *val as i64,
+        TokenType::I64Literal(val) => *val,
+        TokenType::U8Literal(val) => *val as i64,
+        TokenType::U16Literal(val) => *val as i64,
+        TokenType::U32Literal(val) => *val as i64,
+        TokenType::U64Literal(val) => *val as i64,
+        TokenType::CharLiteral(val) => *val as i64,
+        _ => return None,
+    })
+}
+
+/// Splits the argument tokens of an instruction on commas and parses each operand
+///
+/// `area` is reported when an operand is missing entirely
+pub fn parse_operands(
+    state: &AssemblerState,
+    args: &[PPToken],
+    area: &PPArea,
+) -> Result<Vec<Operand>, (DiagnosticKind, String, PPArea)> {
+    if args.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut operands = Vec::new();
+    let mut last_area = area;
+    for group in args.split(|tok| matches!(tok.tok, TokenType::Comma)) {
+        match group.first() {
+            Some(first) => {
+                operands.push(parse_operand(state, group)?);
+                last_area = &first.location;
+            }
+            None => {
+                return Err((
+                    DiagnosticKind::OperandCount,
+                    "Expected operand".into(),
+                    last_area.clone(),
+                ))
+            }
+        }
+    }
+    Ok(operands)
+}
+
+fn parse_operand(
+    state: &AssemblerState,
+    group: &[PPToken],
+) -> Result<Operand, (DiagnosticKind, String, PPArea)> {
+    let area = group[0].location.clone();
+
+    // a register on its own
+    if let [tok] = group {
+        let reg = match &tok.tok {
+            TokenType::Register(reg) => Some(*reg),
+            TokenType::Identifier(ident) => register_from_name(ident),
+            _ => None,
+        };
+        if let Some(reg) = reg {
+            return Ok(Operand {
+                kind: OperandKind::Register(reg),
+                area,
+            });
+        }
+    }
+
+    // [value] [($base)], the value can have parentheses of its own (`%lo(label)($base)`)
+    let base_register = |tok: &PPToken| match &tok.tok {
+        TokenType::Register(reg) => Some(*reg),
+        TokenType::Identifier(ident) => register_from_name(ident),
+        _ => None,
+    };
+    match group {
+        [value @ .., open, base, close]
+            if matches!(open.tok, TokenType::LPar)
+                && matches!(close.tok, TokenType::RPar)
+                && base_register(base).is_some()
+                && !matches!(value, [.., percent, _] if matches!(percent.tok, TokenType::Percent)) =>
+        {
+            let offset = if value.is_empty() {
+                Value::Constant(0)
+            } else {
+                parse_value(state, value)?
+            };
+            let base = base_register(base).unwrap();
+            Ok(Operand {
+                kind: OperandKind::Memory { offset, base },
+                area,
+            })
+        }
+        _ => Ok(Operand {
+            kind: parse_value(state, group)?.into(),
+            area,
+        }),
+    }
+}
+
+/// An expression (see `evaluate_value`), or a relocation operator applied to one (`%hi(label)`,
+/// `%lo(label)` or `%gp_rel(label)`)
+pub(crate) fn parse_value(
+    state: &AssemblerState,
+    all: &[PPToken],
+) -> Result<Value, (DiagnosticKind, String, PPArea)> {
+    let [percent, operator, open, inner @ .., close] = all else {
+        return evaluate_value(state, all, &all[0].location);
+    };
+    if !matches!(percent.tok, TokenType::Percent) {
+        return evaluate_value(state, all, &all[0].location);
+    }
+    let TokenType::Identifier(operator_name) = &operator.tok else {
+        return Err((
+            DiagnosticKind::Relocation,
+            "Expected relocation operator (i.e. %hi, %lo or %gp_rel)".into(),
+            operator.location.clone(),
+        ));
+    };
+    if !matches!(open.tok, TokenType::LPar) || !matches!(close.tok, TokenType::RPar) {
+        return Err((
+            DiagnosticKind::Relocation,
+            format!("Expected %{}(value)", operator_name),
+            operator.location.clone(),
+        ));
+    }
+    if inner.is_empty() {
+        return Err((
+            DiagnosticKind::Expression,
+            "Expected value".into(),
+            close.location.clone(),
+        ));
+    }
+    let value = evaluate_value(state, inner, &inner[0].location)?;
+    match (operator_name.as_str(), value) {
+        // the low half is sign extended when it is used so the high half makes up for it
+        ("hi", Value::Constant(value)) => Ok(Value::Constant(((value + 0x8000) >> 16) & 0xFFFF)),
+        ("lo", Value::Constant(value)) => Ok(Value::Constant(value as i16 as i64)),
+        ("hi", Value::Label(label)) => {
+            Ok(Value::Relocation(MipsRelocationType::R_MIPS_HI16, label))
+        }
+        ("lo", Value::Label(label)) => {
+            Ok(Value::Relocation(MipsRelocationType::R_MIPS_LO16, label))
+        }
+        ("gp_rel", Value::Label(label)) => {
+            Ok(Value::Relocation(MipsRelocationType::R_MIPS_GPREL16, label))
+        }
+        ("gp_rel", _) => Err((
+            DiagnosticKind::Relocation,
+            "%gp_rel needs a label (the value of $gp is only known after linking)".into(),
+            inner[0].location.clone(),
+        )),
+        (other, _) => Err((
+            DiagnosticKind::Relocation,
+            format!("Unknown relocation operator: %{}", other),
+            operator.location.clone(),
+        )),
+    }
+}
+
+/// A value that has to be known while assembling, labels are only allowed if they are absolute
+pub(crate) fn parse_constant(
+    state: &AssemblerState,
+    all: &[PPToken],
+) -> Result<i64, (DiagnosticKind, String, PPArea)> {
+    if let Value::Relocation(..) = parse_value(state, all)? {
+        return Err((
+            DiagnosticKind::Relocation,
+            "Expected a constant but found a relocation operator".into(),
+            all[0].location.clone(),
+        ));
+    }
+    evaluate(state, all, &all[0].location)
+}
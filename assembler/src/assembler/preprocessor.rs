@@ -1,18 +1,29 @@
 use crate::lexer::tokenizer::{TokenType, Tokenizer};
 pub type Token = util::token::Token<TokenType>;
 
-use std::{cell::RefCell, collections::LinkedList, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, LinkedList},
+    ops::Range,
+    rc::Rc,
+};
 
 use util::token::TokenData;
 
-use super::{Assembler, AssemblerState, FileInfo};
+use super::{
+    diagnostic::DiagnosticKind,
+    expression::LOCATION_COUNTER,
+    operand::{integer_literal, parse_constant},
+    symbol::SHN_UNDEF,
+    Assembler, AssemblerState, FileInfo,
+};
 
 //-------------------------------------------------------------------------------------------------------------
 
 #[derive(Clone, Debug)]
 pub struct PPToken {
-    tok: TokenType,
-    location: PPArea,
+    pub(crate) tok: TokenType,
+    pub(crate) location: PPArea,
 }
 
 #[derive(Clone, Debug)]
@@ -50,6 +61,20 @@ impl PPArea {
         }
         *parent = Option::Some(Box::new(new_area));
     }
+
+    /// Where the text of an expanded token was written (the body of a macro or define), areas
+    /// without a file (predefines) are skipped
+    pub(crate) fn source(&self) -> &TokenData {
+        let mut source = &self.area;
+        let mut parent = &self.parent;
+        while let Option::Some(p) = parent {
+            if p.area.file.is_some() {
+                source = &p.area;
+            }
+            parent = &p.parent;
+        }
+        source
+    }
 }
 // impl Into<PPArea> for &PPToken{
 //     fn into(self) -> PPArea {
@@ -86,6 +111,8 @@ impl PPToken {
 #[derive(Debug)]
 pub enum PreProcessedLine {
     Label(String, PPArea),
+    /// The mnemonic and its argument tokens (defines already expanded, commas included)
+    Instruction(String, Vec<PPToken>, PPArea),
 }
 
 // impl PreProcessedLine{
@@ -98,10 +125,47 @@ pub enum PreProcessedLine {
 
 //-------------------------------------------------------------------------------------------------------------
 
+#[derive(Clone, Debug)]
+pub struct MacroParam {
+    pub(crate) name: String,
+    /// Used when the argument is left out or empty
+    pub(crate) default: Vec<PPToken>,
+    pub(crate) required: bool,
+    /// Takes every remaining argument, commas included
+    pub(crate) vararg: bool,
+}
+
+/// A `.macro` definition, the body is every token up to the matching `.endm`
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub(crate) params: Vec<MacroParam>,
+    pub(crate) body: Vec<PPToken>,
+}
+
+//-------------------------------------------------------------------------------------------------------------
+
+/// An `#if`/`#ifdef`/`#ifndef` block that hasn't reached its `#endif` yet
+struct Conditional {
+    /// Lines of the current branch are assembled
+    active: bool,
+    /// A branch was taken (or the enclosing block is skipped), the following ones are skipped
+    taken: bool,
+    /// `#else` was reached, there can't be another branch
+    in_else: bool,
+    area: PPArea,
+}
+
 pub struct PreProcessor {
     asm_state: Rc<RefCell<AssemblerState>>,
     token_strem: TokenStream,
     last_full_label: Option<String>,
+    conditions: Vec<Conditional>,
+    /// Number of macro expansions and loop iterations so far, what `\@` is replaced with
+    expansions: usize,
+    /// How many times each numeric local label (`1:`) has been defined so far
+    numeric_labels: HashMap<u32, usize>,
+    /// `1f` references and the definition they refer to, checked once the input ends
+    forward_references: Vec<(u32, usize, PPArea)>,
 }
 
 impl PreProcessor {
@@ -109,7 +173,7 @@ impl PreProcessor {
         assembler: &mut Assembler,
         input: String,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = assembler.asm_state().add_file(input.clone());
+        let file = assembler.asm_state().add_file(input.clone(), None);
         let file = match file {
             Result::Ok(val) => val,
             Result::Err(err) => {
@@ -123,14 +187,42 @@ impl PreProcessor {
         match token_strem.add_stream(FileStream::new(assembler.clone_asm_state(), file)) {
             Ok(_) => {}
             Err(err) => {
-                assembler.asm_state().report_preprocessor_error_no_area(err);
+                assembler
+                    .asm_state()
+                    .report_preprocessor_error_no_area(DiagnosticKind::ExpansionLimit, err);
+            }
+        }
+
+        let defines = assembler.asm_state().settings().defines.clone();
+        for (name, value) in defines {
+            let mut state = assembler.asm_state();
+            let mut tokens = Vec::new();
+            for tok in Tokenizer::new_from_str(&value)
+                .include_comments(false)
+                .include_documentation(false)
+                .include_whitespace(false)
+            {
+                match tok {
+                    Ok(tok) => tokens.push(PPToken::new(tok)),
+                    Err(err) => state.report_tokenizer_error(err),
+                }
             }
+            let define = if tokens.is_empty() {
+                super::assembler::Define::Nothing
+            } else {
+                super::assembler::Define::Replacement(tokens)
+            };
+            state.put_into_scope(name, define);
         }
 
         let new = Self {
             asm_state: assembler.clone_asm_state(),
             last_full_label: Option::None,
             token_strem,
+            conditions: Vec::new(),
+            expansions: 0,
+            numeric_labels: HashMap::new(),
+            forward_references: Vec::new(),
         };
 
         Result::Ok(new)
@@ -163,15 +255,22 @@ impl PreProcessor {
                                 }
                             }
                         }
-                        if let Option::Some(new_stream) = new_stream {
-                            let res = self.token_strem.add_stream(new_stream);
-                            match res {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    self.asm_state()
-                                        .report_preprocessor_error(err, tok.location);
+                        match new_stream {
+                            Option::Some(new_stream) => {
+                                let res = self.token_strem.add_stream(new_stream);
+                                match res {
+                                    Ok(_) => {}
+                                    Err(err) => {
+                                        self.asm_state().report_preprocessor_error(
+                                            DiagnosticKind::ExpansionLimit,
+                                            err,
+                                            tok.location,
+                                        );
+                                    }
                                 }
                             }
+                            // not a define so it is passed through as is
+                            Option::None => return Option::Some(tok),
                         }
                     }
                     _ => return Option::Some(tok),
@@ -186,7 +285,9 @@ impl PreProcessor {
             "include" => match self.argument_next() {
                 Some(arg1) => match &arg1.tok {
                     TokenType::StringLiteral(path) => {
-                        let file = self.asm_state().add_file(path.clone());
+                        let file = self
+                            .asm_state()
+                            .add_file(path.clone(), Some(arg1.location.clone()));
                         match file {
                             Ok(file) => {
                                 let mut stream = FileStream::new(self.asm_state.clone(), file);
@@ -194,13 +295,16 @@ impl PreProcessor {
                                 let res = self.token_strem.add_stream(stream);
                                 match res {
                                     Ok(_) => {}
-                                    Err(err) => {
-                                        self.asm_state().report_preprocessor_error(err, area)
-                                    }
+                                    Err(err) => self.asm_state().report_preprocessor_error(
+                                        DiagnosticKind::ExpansionLimit,
+                                        err,
+                                        area,
+                                    ),
                                 }
                             }
                             Err(err) => {
                                 self.asm_state().report_preprocessor_error(
+                                    DiagnosticKind::Include,
                                     format!("Failed to open file: {} ({})", path, err),
                                     arg1.location,
                                 );
@@ -209,26 +313,30 @@ impl PreProcessor {
                     }
                     _ => {
                         self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::Include,
                             "Invalid arguments expected file path (i.e. \"path/to/file.asm\")",
                             area,
                         );
                     }
                 },
                 None => {
-                    self.asm_state()
-                        .report_preprocessor_error("Expected filepath but found no argments", area);
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::Include,
+                        "Expected filepath but found no argments",
+                        area,
+                    );
                 }
             },
             "define" => match self.internal_next() {
                 Some(ident) => match ident.tok {
                     TokenType::Identifier(def_ident) => {
                         let mut values = Vec::new();
-                        loop {
-                            if let Option::Some(tok) = self.internal_next() {
+                        while let Option::Some(tok) = self.internal_next() {
+                            {
                                 match &tok.tok {
                                     TokenType::Identifier(iden) => {
                                         if iden.eq(&def_ident) {
-                                            self.asm_state().report_preprocessor_error("Cannot have identifiers with the same value as the defines identifier. This will create an infinite loop!", tok.location);
+                                            self.asm_state().report_preprocessor_error(DiagnosticKind::Define, "Cannot have identifiers with the same value as the defines identifier. This will create an infinite loop!", tok.location);
                                         } else {
                                             values.push(tok);
                                         }
@@ -254,6 +362,7 @@ impl PreProcessor {
                     }
                     _ => {
                         self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::Define,
                             "Invalid token type expected identifier",
                             area,
                         );
@@ -261,14 +370,48 @@ impl PreProcessor {
                 },
                 None => {
                     self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::Define,
                         "Expected identifier but found no arguments",
                         area,
                     );
                 }
             },
-            "undefine" => if let Some(_val) = self.internal_next() {},
+            "undef" | "undefine" => {
+                match self.internal_next() {
+                    Some(PPToken {
+                        tok: TokenType::Identifier(ident),
+                        ..
+                    }) => {
+                        self.asm_state().remove_from_scope(&ident);
+                    }
+                    Some(PPToken {
+                        tok: TokenType::NewLine,
+                        ..
+                    })
+                    | None => {
+                        self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::Define,
+                            "Expected identifier but found no arguments",
+                            area,
+                        );
+                        return;
+                    }
+                    Some(tok) => {
+                        self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::Define,
+                            "Invalid token type expected identifier",
+                            tok.location,
+                        );
+                    }
+                }
+                self.rest_of_line();
+            }
+            "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" => {
+                self.conditional(ident, area);
+            }
             _ => {
                 self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::UnexpectedToken,
                     format!("Unknown preprocessor statement: {}", ident),
                     area,
                 );
@@ -277,6 +420,801 @@ impl PreProcessor {
     }
 }
 
+impl PreProcessor {
+    /// Lines are skipped because a branch of an `#if` isn't taken
+    fn skipping(&self) -> bool {
+        self.conditions.last().is_some_and(|cond| !cond.active)
+    }
+
+    fn conditional(&mut self, statement: &str, area: PPArea) {
+        let skipping = self.skipping();
+        match statement {
+            "if" | "ifdef" | "ifndef" => {
+                let active = !skipping && self.condition(statement, &area);
+                self.conditions.push(Conditional {
+                    active,
+                    taken: active || skipping,
+                    in_else: false,
+                    area,
+                });
+            }
+            _ => {
+                let Some(cond) = self.conditions.last() else {
+                    self.rest_of_line();
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::UnbalancedBlock,
+                        format!("#{} without a matching #if", statement),
+                        area,
+                    );
+                    return;
+                };
+                if cond.in_else && statement != "endif" {
+                    self.rest_of_line();
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::UnbalancedBlock,
+                        format!("#{} after #else", statement),
+                        area,
+                    );
+                    return;
+                }
+                let taken = cond.taken;
+                match statement {
+                    "elif" => {
+                        // the condition isn't evaluated if it doesn't matter
+                        let active = !taken && self.condition("if", &area);
+                        if taken {
+                            self.rest_of_line();
+                        }
+                        let cond = self.conditions.last_mut().unwrap();
+                        cond.active = active;
+                        cond.taken |= active;
+                    }
+                    "else" => {
+                        self.end_of_statement(statement);
+                        let cond = self.conditions.last_mut().unwrap();
+                        cond.active = !taken;
+                        cond.taken = true;
+                        cond.in_else = true;
+                    }
+                    _ => {
+                        self.end_of_statement(statement);
+                        self.conditions.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Nothing may follow statements without arguments
+    fn end_of_statement(&mut self, statement: &str) {
+        if let Some(tok) = self.rest_of_line().into_iter().next() {
+            self.asm_state().report_preprocessor_error(
+                DiagnosticKind::Condition,
+                format!("Unexpected token after #{}", statement),
+                tok.location,
+            );
+        }
+    }
+
+    /// Evaluates the rest of the line as the condition of `#if`, `#ifdef` or `#ifndef`
+    fn condition(&mut self, statement: &str, area: &PPArea) -> bool {
+        let tokens = self.rest_of_line();
+        if statement != "if" {
+            let defined = match tokens.as_slice() {
+                [PPToken {
+                    tok: TokenType::Identifier(ident),
+                    ..
+                }] => self.asm_state().get_from_scope(ident).is_some(),
+                [] => {
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::Condition,
+                        "Expected identifier but found no arguments",
+                        area.clone(),
+                    );
+                    return false;
+                }
+                [tok, ..] => {
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::Condition,
+                        format!("#{} takes exactly one identifier", statement),
+                        tok.location.clone(),
+                    );
+                    return false;
+                }
+            };
+            return defined == (statement == "ifdef");
+        }
+
+        let res = self.expand_condition(&tokens, 0).and_then(|tokens| {
+            super::expression::evaluate(&self.asm_state.borrow(), &tokens, area)
+        });
+        match res {
+            Ok(value) => value != 0,
+            Err((kind, err, location)) => {
+                self.asm_state()
+                    .report_preprocessor_error(kind, err, location);
+                false
+            }
+        }
+    }
+
+    /// Replaces `defined(NAME)` and `defined NAME` with 1 or 0 and expands defines, identifiers
+    /// that are neither defines nor symbols are 0 like in C
+    fn expand_condition(
+        &mut self,
+        tokens: &[PPToken],
+        depth: usize,
+    ) -> Result<Vec<PPToken>, (DiagnosticKind, String, PPArea)> {
+        let mut expanded = Vec::with_capacity(tokens.len());
+        let mut iter = tokens.iter();
+        while let Some(tok) = iter.next() {
+            let TokenType::Identifier(ident) = &tok.tok else {
+                expanded.push(tok.clone());
+                continue;
+            };
+            if ident == "defined" {
+                let name = match (iter.next(), iter.clone().next()) {
+                    (
+                        Some(PPToken {
+                            tok: TokenType::Identifier(name),
+                            ..
+                        }),
+                        _,
+                    ) => name,
+                    (
+                        Some(PPToken {
+                            tok: TokenType::LPar,
+                            ..
+                        }),
+                        Some(PPToken {
+                            tok: TokenType::Identifier(name),
+                            ..
+                        }),
+                    ) => {
+                        iter.next();
+                        match iter.next() {
+                            Some(PPToken {
+                                tok: TokenType::RPar,
+                                ..
+                            }) => name,
+                            _ => {
+                                return Err((
+                                    DiagnosticKind::Condition,
+                                    "Expected ) after defined(".into(),
+                                    tok.location.clone(),
+                                ))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err((
+                            DiagnosticKind::Condition,
+                            "Expected identifier after defined".into(),
+                            tok.location.clone(),
+                        ))
+                    }
+                };
+                let defined = self.asm_state().get_from_scope(name).is_some();
+                expanded.push(PPToken {
+                    tok: TokenType::I32Literal(defined as i32),
+                    location: tok.location.clone(),
+                });
+                continue;
+            }
+            let mut state = self.asm_state();
+            let replacement = match state.get_from_scope(ident) {
+                Some(super::assembler::Define::Replacement(replacement)) => replacement.clone(),
+                Some(super::assembler::Define::Nothing) => Vec::new(),
+                _ => {
+                    let known = ident == LOCATION_COUNTER
+                        || state
+                            .get_symbol(ident)
+                            .is_some_and(|sym| sym.section_index != SHN_UNDEF);
+                    expanded.push(if known {
+                        tok.clone()
+                    } else {
+                        PPToken {
+                            tok: TokenType::I32Literal(0),
+                            location: tok.location.clone(),
+                        }
+                    });
+                    continue;
+                }
+            };
+            drop(state);
+            if depth >= self.asm_state().settings().max_token_iterators {
+                return Err((
+                    DiagnosticKind::ExpansionLimit,
+                    format!("Too many nested defines while expanding {}", ident),
+                    tok.location.clone(),
+                ));
+            }
+            // defines in the replacement are expanded as well, errors point at the use first
+            for mut replaced in self.expand_condition(&replacement, depth + 1)? {
+                replaced.location.add_pparea(tok.location.clone());
+                expanded.push(replaced);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// The remaining tokens of the current line without expanding defines, the newline is consumed
+    fn rest_of_line(&mut self) -> Vec<PPToken> {
+        let mut tokens = Vec::new();
+        while let Some(tok) = self.internal_next() {
+            if let TokenType::NewLine = tok.tok {
+                break;
+            }
+            tokens.push(tok);
+        }
+        tokens
+    }
+
+    /// Every token up to the `closing` directive that matches the block started at `area`,
+    /// blocks of the same kind can be nested
+    fn collect_body(
+        &mut self,
+        area: &PPArea,
+        opening: &[&str],
+        closing: &str,
+    ) -> Option<Vec<PPToken>> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        while let Some(tok) = self.internal_next() {
+            if let TokenType::Identifier(ident) = &tok.tok {
+                if opening.contains(&ident.as_str()) {
+                    depth += 1;
+                } else if ident == closing {
+                    if depth == 0 {
+                        self.rest_of_line();
+                        return Some(body);
+                    }
+                    depth -= 1;
+                }
+            }
+            body.push(tok);
+        }
+        self.asm_state().report_preprocessor_error(
+            DiagnosticKind::UnbalancedBlock,
+            format!("Reached the end of the input while looking for {}", closing),
+            area.clone(),
+        );
+        None
+    }
+
+    /// Expands `tokens` in place of the line at `area`, errors in them point at `area` first
+    fn push_expansion(&mut self, tokens: Vec<PPToken>, area: PPArea) {
+        let mut stream = TokenVecStream::new(&tokens);
+        stream.loc = Option::Some(area.clone());
+        if let Err(err) = self.token_strem.add_stream(stream) {
+            self.asm_state()
+                .report_preprocessor_error(DiagnosticKind::ExpansionLimit, err, area);
+        }
+    }
+
+    /// `.macro name param, param=default, param:req, param:vararg` ... `.endm`
+    fn define_macro(&mut self, area: PPArea) {
+        let header = self.rest_of_line();
+        let body = self.collect_body(&area, &[".macro"], ".endm");
+
+        let (name, params) = match header.split_first() {
+            Some((
+                PPToken {
+                    tok: TokenType::Identifier(name),
+                    ..
+                },
+                params,
+            )) => (name.clone(), params),
+            Some((tok, _)) => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::MacroDefinition,
+                    "Expected macro name",
+                    tok.location.clone(),
+                );
+                return;
+            }
+            None => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::MacroDefinition,
+                    "Expected macro name but found nothing",
+                    area,
+                );
+                return;
+            }
+        };
+        let params = match parse_params(params) {
+            Ok(params) => params,
+            Err((err, location)) => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::MacroDefinition,
+                    err,
+                    location,
+                );
+                return;
+            }
+        };
+        if let Some(body) = body {
+            self.asm_state().put_into_scope(
+                name,
+                super::assembler::Define::Macro(Macro { params, body }),
+            );
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str, mac: &Macro, area: PPArea) {
+        let args = self.rest_of_line();
+        let groups = split_arguments(&args);
+
+        let mut values: Vec<Option<Vec<PPToken>>> = vec![None; mac.params.len()];
+        let mut next = 0;
+        for group in &groups {
+            let (index, value) = match &args[group.clone()] {
+                [PPToken {
+                    tok: TokenType::Identifier(ident),
+                    ..
+                }, PPToken {
+                    tok: TokenType::Assignment,
+                    ..
+                }, value @ ..]
+                    if mac.params.iter().any(|p| &p.name == ident) =>
+                {
+                    let index = mac.params.iter().position(|p| &p.name == ident).unwrap();
+                    (index, value.to_vec())
+                }
+                value => match mac.params.get(next) {
+                    // the rest of the arguments, commas included
+                    Some(param) if param.vararg => (next, args[group.start..].to_vec()),
+                    Some(_) => (next, value.to_vec()),
+                    None => {
+                        let location = args[group.start.min(args.len() - 1)].location.clone();
+                        self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::MacroArguments,
+                            format!(
+                                "Too many arguments for macro {} (expected at most {})",
+                                name,
+                                mac.params.len()
+                            ),
+                            location,
+                        );
+                        return;
+                    }
+                },
+            };
+            let vararg = mac.params[index].vararg;
+            values[index] = Some(value);
+            next = index + 1;
+            if vararg {
+                break;
+            }
+        }
+
+        let mut bound = Vec::with_capacity(mac.params.len());
+        for (param, value) in mac.params.iter().zip(values) {
+            let value = match value {
+                Some(value) if !value.is_empty() => value,
+                _ if param.required => {
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::MacroArguments,
+                        format!(
+                            "Missing value for required parameter {} of macro {}",
+                            param.name, name
+                        ),
+                        area,
+                    );
+                    return;
+                }
+                _ => param.default.clone(),
+            };
+            bound.push((param.name.clone(), value));
+        }
+
+        self.expansions += 1;
+        match substitute(&mac.body, &bound, self.expansions - 1) {
+            Ok(tokens) => self.push_expansion(tokens, area),
+            Err((err, mut location)) => {
+                location.add_pparea(area);
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::MacroArguments,
+                    err,
+                    location,
+                );
+            }
+        }
+    }
+
+    /// The label the numeric local label reference `arg` (`1f` or `1b`) refers to
+    fn numeric_reference_label(&mut self, arg: &str, area: &PPArea) -> Option<String> {
+        let (number, forward) = numeric_reference(arg)?;
+        let defined = self.numeric_labels.get(&number).copied().unwrap_or(0);
+        let instance = if forward {
+            self.forward_references
+                .push((number, defined, area.clone()));
+            defined
+        } else if defined > 0 {
+            defined - 1
+        } else {
+            self.asm_state().report_preprocessor_error(
+                DiagnosticKind::LocalLabel,
+                format!("No {}: before {}", number, arg),
+                area.clone(),
+            );
+            return None;
+        };
+        Some(numeric_label(number, instance))
+    }
+
+    /// `.rept count` ... `.endr`
+    fn repeat(&mut self, area: PPArea) {
+        let mut count_tokens = Vec::new();
+        while let Some(tok) = self.argument_next() {
+            if let TokenType::NewLine = tok.tok {
+                break;
+            }
+            count_tokens.push(tok);
+        }
+        let body = self.collect_body(&area, &[".rept", ".irp"], ".endr");
+
+        if count_tokens.is_empty() {
+            self.asm_state().report_preprocessor_error(
+                DiagnosticKind::Repeat,
+                "Expected a repeat count but found nothing",
+                area,
+            );
+            return;
+        }
+        let res = parse_constant(&self.asm_state.borrow(), &count_tokens);
+        let count = match res {
+            Ok(count) if count >= 0 => count,
+            Ok(count) => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::Repeat,
+                    format!("Repeat count cannot be negative ({})", count),
+                    count_tokens[0].location.clone(),
+                );
+                return;
+            }
+            Err((kind, err, location)) => {
+                self.asm_state()
+                    .report_preprocessor_error(kind, err, location);
+                return;
+            }
+        };
+        let Some(body) = body else {
+            return;
+        };
+        let iterations = (0..count).map(|_| Vec::new()).collect();
+        self.loop_body(&body, iterations, area);
+    }
+
+    /// `.irp param, value, value` ... `.endr`, the body is repeated for every value
+    fn irp(&mut self, area: PPArea) {
+        let header = self.rest_of_line();
+        let body = self.collect_body(&area, &[".rept", ".irp"], ".endr");
+
+        let (name, values) = match header.split_first() {
+            Some((
+                PPToken {
+                    tok: TokenType::Identifier(name),
+                    ..
+                },
+                values,
+            )) => (name.clone(), values),
+            Some((tok, _)) => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::Repeat,
+                    "Expected parameter name",
+                    tok.location.clone(),
+                );
+                return;
+            }
+            None => {
+                self.asm_state().report_preprocessor_error(
+                    DiagnosticKind::Repeat,
+                    "Expected parameter name but found nothing",
+                    area,
+                );
+                return;
+            }
+        };
+        let values = match values.split_first() {
+            Some((
+                PPToken {
+                    tok: TokenType::Comma,
+                    ..
+                },
+                values,
+            )) => values,
+            _ => values,
+        };
+        let Some(body) = body else {
+            return;
+        };
+        // without values the body is expanded once with an empty argument
+        let iterations = if values.is_empty() {
+            vec![vec![(name, Vec::new())]]
+        } else {
+            split_arguments(values)
+                .into_iter()
+                .map(|group| vec![(name.clone(), values[group].to_vec())])
+                .collect()
+        };
+        self.loop_body(&body, iterations, area);
+    }
+
+    /// Expands `body` once for every set of arguments
+    fn loop_body(
+        &mut self,
+        body: &[PPToken],
+        iterations: Vec<Vec<(String, Vec<PPToken>)>>,
+        area: PPArea,
+    ) {
+        let mut tokens = Vec::new();
+        for args in iterations {
+            self.expansions += 1;
+            match substitute(body, &args, self.expansions - 1) {
+                Ok(expanded) => tokens.extend(expanded),
+                Err((err, mut location)) => {
+                    location.add_pparea(area);
+                    self.asm_state().report_preprocessor_error(
+                        DiagnosticKind::Repeat,
+                        err,
+                        location,
+                    );
+                    return;
+                }
+            }
+        }
+        self.push_expansion(tokens, area);
+    }
+}
+
+/// Splits the arguments of a macro invocation on commas that aren't inside parentheses
+fn split_arguments(args: &[PPToken]) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    if args.is_empty() {
+        return groups;
+    }
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, tok) in args.iter().enumerate() {
+        match tok.tok {
+            TokenType::LPar => depth += 1,
+            TokenType::RPar => depth -= 1,
+            TokenType::Comma if depth == 0 => {
+                groups.push(start..index);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    groups.push(start..args.len());
+    groups
+}
+
+fn parse_params(tokens: &[PPToken]) -> Result<Vec<MacroParam>, (String, PPArea)> {
+    // the first parameter can be separated from the name by a comma as well
+    let tokens = match tokens.first() {
+        Some(PPToken {
+            tok: TokenType::Comma,
+            ..
+        }) => &tokens[1..],
+        _ => tokens,
+    };
+    let mut params: Vec<MacroParam> = Vec::new();
+    for group in split_arguments(tokens) {
+        let group = &tokens[group];
+        let (name, rest) = match group {
+            [PPToken {
+                tok: TokenType::Identifier(name),
+                ..
+            }, rest @ ..] => (name, rest),
+            [PPToken {
+                tok: TokenType::Label(name),
+                ..
+            }, PPToken {
+                tok: TokenType::Identifier(qualifier),
+                location,
+            }, rest @ ..] => {
+                if qualifier != "req" && qualifier != "vararg" {
+                    return Err((
+                        format!(
+                            "Unknown parameter qualifier {} (expected req or vararg)",
+                            qualifier
+                        ),
+                        location.clone(),
+                    ));
+                }
+                (name, rest)
+            }
+            [tok, ..] => {
+                return Err(("Expected parameter name".into(), tok.location.clone()));
+            }
+            [] => {
+                let location = tokens.last().unwrap().location.clone();
+                return Err(("Expected parameter name but found nothing".into(), location));
+            }
+        };
+        let qualifier = match group.get(1) {
+            Some(PPToken {
+                tok: TokenType::Identifier(qualifier),
+                ..
+            }) if matches!(group[0].tok, TokenType::Label(_)) => Some(qualifier.as_str()),
+            _ => None,
+        };
+        let default = match rest {
+            [] => Vec::new(),
+            [PPToken {
+                tok: TokenType::Assignment,
+                ..
+            }, default @ ..] => default.to_vec(),
+            [tok, ..] => {
+                return Err((
+                    "Expected , or = after parameter name".into(),
+                    tok.location.clone(),
+                ))
+            }
+        };
+        if params.iter().any(|p| &p.name == name) {
+            return Err((
+                format!("Duplicate macro parameter {}", name),
+                group[0].location.clone(),
+            ));
+        }
+        if params.last().is_some_and(|p| p.vararg) {
+            return Err((
+                "Only the last macro parameter can be vararg".into(),
+                group[0].location.clone(),
+            ));
+        }
+        params.push(MacroParam {
+            name: name.clone(),
+            default,
+            required: qualifier == Some("req"),
+            vararg: qualifier == Some("vararg"),
+        });
+    }
+    Ok(params)
+}
+
+/// The text of an argument that is pasted into an identifier
+fn paste_text(value: &[PPToken]) -> Option<String> {
+    match value {
+        [] => Some(String::new()),
+        [tok] => match &tok.tok {
+            TokenType::Identifier(ident) => Some(ident.clone()),
+            other => integer_literal(other).map(|value| value.to_string()),
+        },
+        _ => None,
+    }
+}
+
+/// The number of a reference to a numeric local label and whether it refers forward (`1f`) or
+/// backward (`1b`)
+fn numeric_reference(ident: &str) -> Option<(u32, bool)> {
+    let (digits, forward) = match ident.strip_suffix('f') {
+        Some(digits) => (digits, true),
+        None => (ident.strip_suffix('b')?, false),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((digits.parse().ok()?, forward))
+}
+
+/// The symbol of the `instance`th definition of the numeric local label `number`, it starts with
+/// a `.` so it can't clash with the labels of the source
+fn numeric_label(number: u32, instance: usize) -> String {
+    format!(".L{}.{}", number, instance)
+}
+
+/// Replaces `\param` with the value of the parameter and `\@` with `counter`
+///
+/// A parameter that makes up an entire identifier is replaced by all of its tokens, otherwise
+/// it has to be a single identifier or number that is pasted into the identifier (`\name\@`).
+/// Unknown parameters are left as they are so definitions inside of the body keep theirs.
+/// Local labels (`.loop:`) defined in the body get `@counter` appended, together with the
+/// references to them in the body, so every expansion has its own
+fn substitute(
+    body: &[PPToken],
+    args: &[(String, Vec<PPToken>)],
+    counter: usize,
+) -> Result<Vec<PPToken>, (String, PPArea)> {
+    let locals: Vec<&str> = body
+        .iter()
+        .filter_map(|tok| match &tok.tok {
+            TokenType::Label(label) if label.starts_with('.') && !label.contains('\\') => {
+                Some(label.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    let mut tokens = Vec::with_capacity(body.len());
+    for tok in body {
+        let ident = match &tok.tok {
+            TokenType::Identifier(ident) | TokenType::Label(ident)
+                if locals.contains(&ident.as_str()) =>
+            {
+                let unique = format!("{}@{}", ident, counter);
+                let tok_type = match tok.tok {
+                    TokenType::Label(_) => TokenType::Label(unique),
+                    _ => TokenType::Identifier(unique),
+                };
+                tokens.push(PPToken {
+                    tok: tok_type,
+                    location: tok.location.clone(),
+                });
+                continue;
+            }
+            TokenType::Identifier(ident) | TokenType::Label(ident) if ident.contains('\\') => ident,
+            _ => {
+                tokens.push(tok.clone());
+                continue;
+            }
+        };
+        if let TokenType::Identifier(_) = tok.tok {
+            let whole = args
+                .iter()
+                .find(|(name, _)| ident.strip_prefix('\\') == Some(name));
+            if let Some((_, value)) = whole {
+                // errors in the argument point at where it is used in the body as well
+                tokens.extend(value.iter().map(|arg| {
+                    let mut arg = arg.clone();
+                    arg.location.add_pparea(tok.location.clone());
+                    arg
+                }));
+                continue;
+            }
+        }
+
+        let mut text = String::new();
+        let mut rest = ident.as_str();
+        while let Some(pos) = rest.find('\\') {
+            text.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(after) = rest.strip_prefix('@') {
+                text.push_str(&counter.to_string());
+                rest = after;
+                continue;
+            }
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let (name, after) = rest.split_at(len);
+            match args.iter().find(|(param, _)| param == name) {
+                Some((_, value)) => match paste_text(value) {
+                    Some(value) => text.push_str(&value),
+                    None => {
+                        return Err((
+                            format!(
+                                "Argument for \\{} cannot be pasted into {} (it has to be a single identifier or number)",
+                                name, ident
+                            ),
+                            tok.location.clone(),
+                        ))
+                    }
+                },
+                None => {
+                    text.push('\\');
+                    text.push_str(name);
+                }
+            }
+            rest = after;
+        }
+        text.push_str(rest);
+
+        let tok_type = match (&tok.tok, text.parse::<i32>()) {
+            (TokenType::Label(_), _) => TokenType::Label(text),
+            (_, Ok(value)) => TokenType::I32Literal(value),
+            _ => TokenType::Identifier(text),
+        };
+        tokens.push(PPToken {
+            tok: tok_type,
+            location: tok.location.clone(),
+        });
+    }
+    Ok(tokens)
+}
+
 impl Iterator for PreProcessor {
     type Item = PreProcessedLine;
 
@@ -284,34 +1222,136 @@ impl Iterator for PreProcessor {
         //let mut has_encountered_error = false;
         loop {
             match self.internal_next() {
+                Some(PPToken { tok, location }) if self.skipping() => match tok {
+                    TokenType::PreProcessorStatement(ident)
+                        if matches!(
+                            ident.as_str(),
+                            "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif"
+                        ) =>
+                    {
+                        self.conditional(&ident, location);
+                    }
+                    TokenType::NewLine => {}
+                    _ => {
+                        self.rest_of_line();
+                    }
+                },
                 Some(PPToken { tok, location }) => {
                     match tok {
-                        TokenType::Identifier(_ident) => {
+                        TokenType::Identifier(ident) => {
+                            match ident.as_str() {
+                                ".macro" => {
+                                    self.define_macro(location);
+                                    continue;
+                                }
+                                ".rept" => {
+                                    self.repeat(location);
+                                    continue;
+                                }
+                                ".irp" => {
+                                    self.irp(location);
+                                    continue;
+                                }
+                                ".endm" | ".endr" => {
+                                    self.rest_of_line();
+                                    self.asm_state().report_preprocessor_error(
+                                        DiagnosticKind::UnbalancedBlock,
+                                        format!(
+                                            "{} without a matching {}",
+                                            ident,
+                                            if ident == ".endm" {
+                                                ".macro"
+                                            } else {
+                                                ".rept or .irp"
+                                            }
+                                        ),
+                                        location,
+                                    );
+                                    continue;
+                                }
+                                _ => {}
+                            }
+                            let mac = match self.asm_state().get_from_scope(&ident) {
+                                Some(super::assembler::Define::Macro(mac)) => Some(mac.clone()),
+                                _ => None,
+                            };
+                            if let Some(mac) = mac {
+                                self.expand_macro(&ident, &mac, location);
+                                continue;
+                            }
+                            let mut args = Vec::new();
                             while let Option::Some(tok) = self.argument_next() {
                                 match tok.tok {
                                     TokenType::NewLine => {
                                         break;
                                     }
-                                    _ => self.asm_state().report_preprocessor_error(
-                                        format!("Unexpected token: {:?}", tok.tok),
-                                        tok.location,
-                                    ),
+                                    TokenType::Identifier(arg)
+                                        if numeric_reference(&arg).is_some() =>
+                                    {
+                                        if let Some(label) =
+                                            self.numeric_reference_label(&arg, &tok.location)
+                                        {
+                                            args.push(PPToken {
+                                                tok: TokenType::Identifier(label),
+                                                location: tok.location,
+                                            });
+                                        }
+                                    }
+                                    // references to local labels are relative to the last full label just like their definitions
+                                    // (except the location counter `.` and section names)
+                                    TokenType::Identifier(arg)
+                                        if arg.starts_with('.')
+                                            && arg != "."
+                                            && !(ident == ".section" && args.is_empty()) =>
+                                    {
+                                        match &self.last_full_label {
+                                            Some(last_full) => args.push(PPToken {
+                                                tok: TokenType::Identifier(format!(
+                                                    "{}{}",
+                                                    last_full, arg
+                                                )),
+                                                location: tok.location,
+                                            }),
+                                            None => {
+                                                self.asm_state().report_preprocessor_error(DiagnosticKind::LocalLabel, "Found local lable with no prior full lable before (hint add label without a leading '.' before this labels definition)", tok.location);
+                                            }
+                                        }
+                                    }
+                                    _ => args.push(tok),
                                 }
                             }
+                            return Option::Some(PreProcessedLine::Instruction(
+                                ident, args, location,
+                            ));
                         }
                         TokenType::PreProcessorStatement(ident) => {
                             self.accept_pre_processor_statement(&ident, location);
                         }
+                        TokenType::Label(ident) if ident.bytes().all(|b| b.is_ascii_digit()) => {
+                            let Ok(number) = ident.parse() else {
+                                self.asm_state().report_preprocessor_error(
+                                    DiagnosticKind::LocalLabel,
+                                    format!("Numeric label {} is too large", ident),
+                                    location,
+                                );
+                                continue;
+                            };
+                            let defined = self.numeric_labels.entry(number).or_insert(0);
+                            *defined += 1;
+                            let name = numeric_label(number, *defined - 1);
+                            return Option::Some(PreProcessedLine::Label(name, location));
+                        }
                         TokenType::Label(mut ident) => {
                             if let Option::Some('.') = ident.chars().next() {
                                 if let Option::Some(last_full) = &self.last_full_label {
                                     ident = format!("{}{}", last_full, ident);
                                     return Option::Some(PreProcessedLine::Label(ident, location));
                                 } else {
-                                    self.asm_state().report_preprocessor_error("Found local lable with no prior full lable before (hint add label without a leading '.' before this labels definition)", location);
+                                    self.asm_state().report_preprocessor_error(DiagnosticKind::LocalLabel, "Found local lable with no prior full lable before (hint add label without a leading '.' before this labels definition)", location);
                                 }
                             } else {
-                                self.last_full_label = Option::Some(ident.clone())
+                                self.last_full_label = Option::Some(ident.clone());
+                                return Option::Some(PreProcessedLine::Label(ident, location));
                             }
                         }
                         TokenType::NewLine
@@ -323,6 +1363,7 @@ impl Iterator for PreProcessor {
                         }
                         _ => {
                             self.asm_state().report_preprocessor_error(
+                                DiagnosticKind::UnexpectedToken,
                                 format!("Unexpected token: {:?}", tok),
                                 location,
                             );
@@ -330,7 +1371,25 @@ impl Iterator for PreProcessor {
                         }
                     }
                 }
-                None => return Option::None,
+                None => {
+                    for cond in std::mem::take(&mut self.conditions) {
+                        self.asm_state().report_preprocessor_error(
+                            DiagnosticKind::UnbalancedBlock,
+                            "Missing #endif for this #if",
+                            cond.area,
+                        );
+                    }
+                    for (number, instance, area) in std::mem::take(&mut self.forward_references) {
+                        if self.numeric_labels.get(&number).copied().unwrap_or(0) <= instance {
+                            self.asm_state().report_preprocessor_error(
+                                DiagnosticKind::LocalLabel,
+                                format!("No {}: after {}f", number, number),
+                                area,
+                            );
+                        }
+                    }
+                    return Option::None;
+                }
             }
         }
     }
@@ -481,3 +1540,267 @@ impl Iterator for FileStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::source::MemorySources;
+
+    fn token_text(tok: &TokenType) -> String {
+        match tok {
+            TokenType::Identifier(ident) => ident.clone(),
+            TokenType::Register(reg) => format!("${}", reg),
+            TokenType::Comma => ",".into(),
+            TokenType::Minus => "-".into(),
+            TokenType::LPar => "(".into(),
+            TokenType::RPar => ")".into(),
+            other => match integer_literal(other) {
+                Some(value) => value.to_string(),
+                None => format!("{:?}", other),
+            },
+        }
+    }
+
+    /// The preprocessed lines of `source` as text and whether any errors were reported
+    fn preprocess(name: &str, source: &str) -> (Vec<String>, bool) {
+        preprocess_with(name, source, Default::default())
+    }
+
+    fn preprocess_with(
+        name: &str,
+        source: &str,
+        settings: super::super::AssemblerSettings,
+    ) -> (Vec<String>, bool) {
+        let path = format!("{}.asm", name);
+        let sources = MemorySources::new().with(path.clone(), source);
+        let mut assembler = Assembler::with_sources(settings, sources);
+        let lines = PreProcessor::new(&mut assembler, path)
+            .unwrap()
+            .map(|line| match line {
+                PreProcessedLine::Label(label, _) => format!("{}:", label),
+                PreProcessedLine::Instruction(mnemonic, args, _) => args
+                    .iter()
+                    .fold(mnemonic, |line, arg| line + " " + &token_text(&arg.tok)),
+            })
+            .collect();
+        let failed = assembler.asm_state().has_encountered_error();
+        (lines, failed)
+    }
+
+    #[test]
+    fn macro_arguments() {
+        let (lines, failed) = preprocess(
+            "macro_arguments",
+            ".macro inc reg, amount=1\n  addi \\reg, \\reg, \\amount\n.endm\n\
+             inc $4\ninc $5, 8\ninc amount=2, reg=$6\ninc $7,\n\
+             .macro words first:req, rest:vararg\n  .word \\first\n  .word \\rest\n.endm\n\
+             words 1, 2, 3\nwords 4\n\
+             .macro getter name\nget_\\name:\n  lw $2, \\name\n.endm\ngetter count\n",
+        );
+        assert!(!failed);
+        assert_eq!(
+            lines,
+            [
+                "addi $4 , $4 , 1",
+                "addi $5 , $5 , 8",
+                "addi $6 , $6 , 2",
+                "addi $7 , $7 , 1",
+                ".word 1",
+                ".word 2 , 3",
+                ".word 4",
+                ".word",
+                "get_count:",
+                "lw $2 , count",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_macros_and_unique_labels() {
+        let (lines, failed) = preprocess(
+            "nested_macros",
+            ".macro wait count\n  li $8, \\count\nloop\\@:\n  bne $8, $0, loop\\@\n.endm\n\
+             .macro twice count\n  wait \\count\n  wait \\count\n.endm\n\
+             main:\ntwice 3\n",
+        );
+        assert!(!failed);
+        assert_eq!(
+            lines,
+            [
+                "main:",
+                "li $8 , 3",
+                "loop1:",
+                "bne $8 , $0 , loop1",
+                "li $8 , 3",
+                "loop2:",
+                "bne $8 , $0 , loop2",
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_and_scoped_labels() {
+        let (lines, failed) = preprocess(
+            "local_labels",
+            ".macro spin\n.wait:\n  bne $8, $0, .wait\n  b .done\n.endm\n\
+             main:\n1: beq $4, $0, 1f\nspin\n.rept 2\n  spin\n.endr\n.done:\n  b 1b\n\
+             1: .word 1b, 0x1f, 0b1\nother:\n.done: b .done\n",
+        );
+        assert!(!failed);
+        assert_eq!(
+            lines,
+            [
+                "main:",
+                ".L1.0:",
+                "beq $4 , $0 , .L1.1",
+                "main.wait@0:",
+                "bne $8 , $0 , main.wait@0",
+                "b main.done",
+                "main.wait@3:",
+                "bne $8 , $0 , main.wait@3",
+                "b main.done",
+                "main.wait@4:",
+                "bne $8 , $0 , main.wait@4",
+                "b main.done",
+                "main.done:",
+                "b .L1.0",
+                ".L1.1:",
+                ".word .L1.1 , 31 , 1",
+                "other:",
+                "other.done:",
+                "b other.done",
+            ]
+        );
+
+        let failed = |source: &str| preprocess("local_label_errors", source).1;
+        assert!(failed("b 1b\n1:\n"));
+        assert!(failed("1:\nb 1f\n"));
+        assert!(failed(".loop: nop\n"));
+        assert!(failed("99999999999: nop\n"));
+    }
+
+    #[test]
+    fn loops() {
+        let (lines, failed) = preprocess(
+            "loops",
+            "#define COUNT 2\n.rept COUNT\n  nop\n  .irp reg, $4, $5\n    sw \\reg, 0($sp)\n  .endr\n.endr\n\
+             .irp value\n  .word \\value\n.endr\n.rept 0\n  nop\n.endr\n",
+        );
+        assert!(!failed);
+        assert_eq!(
+            lines,
+            [
+                "nop",
+                "sw $4 , 0 ( sp )",
+                "sw $5 , 0 ( sp )",
+                "nop",
+                "sw $4 , 0 ( sp )",
+                "sw $5 , 0 ( sp )",
+                ".word",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let failed = |source: &str| preprocess("macro_errors", source).1;
+        assert!(failed(".macro m\nnop\n"));
+        assert!(failed(".macro m a\nnop\n.endm\nm 1, 2\n"));
+        assert!(failed(".macro m a:req\nnop\n.endm\nm\n"));
+        assert!(failed(".macro m a:optional\n.endm\n"));
+        assert!(failed(".macro m a, a\n.endm\n"));
+        assert!(failed(".macro m a:vararg, b\n.endm\n"));
+        assert!(failed(".endm\n"));
+        assert!(failed(".rept -1\n.endr\n"));
+        assert!(failed(".rept\n.endr\n"));
+        assert!(failed(".macro m a\nlabel\\a:\n.endm\nm 0($sp)\n"));
+        // recursion runs into the limit on nested token streams
+        assert!(failed(".macro m\nm\n.endm\nm\n"));
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let (lines, failed) = preprocess(
+            "conditional_assembly",
+            "#define DEBUG\n#define LEVEL 2\n\
+             #ifdef DEBUG\n  debug\n#else\n  release\n#endif\n\
+             #ifndef DEBUG\n  no_debug\n#endif\n\
+             #if LEVEL > 2\n  high\n#elif LEVEL == 2 && defined(DEBUG)\n  two\n#elif 1\n  never\n#else\n  low\n#endif\n\
+             #if 0\n  #if 1\n    nested\n  #else\n    nested_else\n  #endif\n  #include \"missing.asm\"\n#endif\n\
+             #undef DEBUG\n#if !defined DEBUG\n  undefined\n#endif\n",
+        );
+        assert!(!failed);
+        assert_eq!(lines, ["debug", "two", "undefined"]);
+
+        // identifiers that aren't defined are 0 and only what decides the result is evaluated
+        let (lines, failed) = preprocess(
+            "conditional_defined",
+            "#if defined(FOO) && FOO == 3\n  early\n#endif\n#define FOO 3\n\
+             #if defined(FOO) && FOO == 3\n  three\n#endif\n#if BAR || 0 && (1 / 0)\n  bar\n#endif\n",
+        );
+        assert!(!failed);
+        assert_eq!(lines, ["three"]);
+
+        // symbols are known as soon as the lines before have been assembled
+        crate::assembler::object::tests::assemble_object(
+            "conditional_symbols",
+            ".equ SIZE, 8\n#if SIZE != 8\n  not_an_instruction\n#endif\n",
+        );
+    }
+
+    #[test]
+    fn predefines() {
+        let mut settings = super::super::AssemblerSettings::default();
+        settings.define("TARGET=2");
+        settings.define("FAST");
+        settings.define("EMPTY=");
+        let (lines, failed) = preprocess_with(
+            "predefines",
+            "#if TARGET == 2 && FAST\n  li $4, TARGET\n#endif\n#ifdef EMPTY\n  empty\n#endif\n",
+            settings,
+        );
+        assert!(!failed);
+        assert_eq!(lines, ["li $4 , 2", "empty"]);
+    }
+
+    #[test]
+    fn conditional_errors() {
+        let failed = |source: &str| preprocess("conditional_errors", source).1;
+        assert!(failed("#else\n"));
+        assert!(failed("#endif\n"));
+        assert!(failed("#if 1\nnop\n"));
+        assert!(failed("#if 1\n#else\n#elif 1\n#endif\n"));
+        assert!(failed("#if 1\n#else\n#else\n#endif\n"));
+        assert!(failed("#if 1 +\n#endif\n"));
+        assert!(failed("#if 1 / 0\n#endif\n"));
+        assert!(failed("#ifdef\n#endif\n"));
+        assert!(failed("#ifdef A B\n#endif\n"));
+        assert!(failed("#if 1\n#endif extra\n"));
+        assert!(failed("#define A B\n#define B A\n#if A\n#endif\n"));
+        // conditions that don't matter aren't evaluated
+        assert!(!failed(
+            "#if 1\n#elif 1 / 0\n#endif\n#if 0\n#if undefined_symbol\n#endif\n#endif\n"
+        ));
+    }
+
+    #[test]
+    fn error_locations() {
+        let path = "macro_error_locations.asm";
+        let report = crate::assembler::assemble_source(
+            path,
+            ".macro load reg\n  lw \\reg, 0($4)\n.endm\nnop\nload $40\n",
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        // the invocation, where the argument is used in the body and then the argument itself
+        let invocation = report.find(&format!("--> {}:5:1", path));
+        let body = report.find(&format!("::: {}:2:6", path));
+        let argument = report.find(&format!("::: {}:5:6", path));
+        assert!(
+            invocation.is_some() && invocation < body && body < argument,
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,533 @@
+use elf::internal::relocation::MipsRelocationType;
+
+use super::{
+    diagnostic::DiagnosticKind,
+    operand::{Operand, OperandKind, Value},
+    preprocessor::PPArea,
+};
+
+/// A real instruction a pseudo instruction expanded to
+#[derive(Debug, Clone)]
+pub struct Expanded {
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    pub instructions: Vec<Expanded>,
+    /// The expansion clobbers `$at`
+    pub uses_at: bool,
+}
+
+const AT: u32 = 1;
+
+type ExpandResult = Result<Expansion, (DiagnosticKind, String, PPArea)>;
+
+fn reg(reg: u32, area: &PPArea) -> Operand {
+    Operand {
+        kind: OperandKind::Register(reg),
+        area: area.clone(),
+    }
+}
+
+fn imm(value: i64, area: &PPArea) -> Operand {
+    Operand {
+        kind: OperandKind::Immediate(value),
+        area: area.clone(),
+    }
+}
+
+fn ins(mnemonic: &str, operands: Vec<Operand>) -> Expanded {
+    Expanded {
+        mnemonic: mnemonic.into(),
+        operands,
+    }
+}
+
+fn expect<'a, const N: usize>(
+    operands: &'a [Operand],
+    area: &PPArea,
+) -> Result<&'a [Operand; N], (DiagnosticKind, String, PPArea)> {
+    operands.try_into().map_err(|_| {
+        (
+            DiagnosticKind::OperandCount,
+            format!("Expected {} operands but found {}", N, operands.len()),
+            operands.get(N).map_or(area, |op| &op.area).clone(),
+        )
+    })
+}
+
+fn immediate_value(operand: &Operand) -> Result<i64, (DiagnosticKind, String, PPArea)> {
+    match operand.kind {
+        OperandKind::Immediate(value) => Ok(value),
+        _ => Err((
+            DiagnosticKind::OperandType,
+            "Expected immediate".into(),
+            operand.area.clone(),
+        )),
+    }
+}
+
+/// The shortest sequence that loads `value` into `rt`
+fn load_immediate(
+    rt: &Operand,
+    value: i64,
+    area: &PPArea,
+) -> Result<Vec<Expanded>, (DiagnosticKind, String, PPArea)> {
+    if !(i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+        return Err((
+            DiagnosticKind::OutOfRange,
+            format!(
+                "immediate {} does not fit in 32 bits ({}..={})",
+                value,
+                i32::MIN,
+                u32::MAX
+            ),
+            area.clone(),
+        ));
+    }
+    let zero = reg(0, area);
+    let value = value as u32;
+    // 0xFFFFFFFF is the same register value as -1
+    let signed = value as i32 as i64;
+    Ok(if (i16::MIN as i64..=i16::MAX as i64).contains(&signed) {
+        vec![ins("addiu", vec![rt.clone(), zero, imm(signed, area)])]
+    } else if value <= u16::MAX as u32 {
+        vec![ins("ori", vec![rt.clone(), zero, imm(value as i64, area)])]
+    } else {
+        let mut sequence = vec![ins(
+            "lui",
+            vec![rt.clone(), imm((value >> 16) as i64, area)],
+        )];
+        if value & 0xFFFF != 0 {
+            sequence.push(ins(
+                "ori",
+                vec![rt.clone(), rt.clone(), imm((value & 0xFFFF) as i64, area)],
+            ));
+        }
+        sequence
+    })
+}
+
+/// `rt` or `$at` loaded with the immediate in `operand`
+fn register_or_at(
+    operand: &Operand,
+    sequence: &mut Vec<Expanded>,
+    uses_at: &mut bool,
+) -> Result<Operand, (DiagnosticKind, String, PPArea)> {
+    match operand.kind {
+        OperandKind::Immediate(value) => {
+            let at = reg(AT, &operand.area);
+            sequence.extend(load_immediate(&at, value, &operand.area)?);
+            *uses_at = true;
+            Ok(at)
+        }
+        _ => Ok(operand.clone()),
+    }
+}
+
+/// Expands `mnemonic` if it is a pseudo instruction, `None` means it should be encoded as is
+///
+/// `addr` is the address of the first instruction of the expansion
+pub fn expand(
+    mnemonic: &str,
+    operands: &[Operand],
+    addr: u32,
+    area: &PPArea,
+) -> Option<ExpandResult> {
+    let mut uses_at = false;
+    expand_sequence(mnemonic, operands, addr, area, &mut uses_at)
+        .transpose()
+        .map(|res| {
+            res.map(|instructions| Expansion {
+                instructions,
+                uses_at,
+            })
+        })
+}
+
+fn expand_sequence(
+    mnemonic: &str,
+    operands: &[Operand],
+    addr: u32,
+    area: &PPArea,
+    uses_at: &mut bool,
+) -> Result<Option<Vec<Expanded>>, (DiagnosticKind, String, PPArea)> {
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let zero = reg(0, area);
+    let mut sequence = Vec::new();
+
+    match mnemonic.as_str() {
+        "li" | "la" => {
+            // the address of a label is only known after linking so it is loaded in halves
+            let [rt, value] = expect(operands, area)?;
+            match &value.kind {
+                OperandKind::Label(label) => {
+                    let half = |r_type| Operand {
+                        kind: OperandKind::Relocation(r_type, label.clone()),
+                        area: value.area.clone(),
+                    };
+                    sequence = vec![
+                        ins(
+                            "lui",
+                            vec![rt.clone(), half(MipsRelocationType::R_MIPS_HI16)],
+                        ),
+                        ins(
+                            "addiu",
+                            vec![
+                                rt.clone(),
+                                rt.clone(),
+                                half(MipsRelocationType::R_MIPS_LO16),
+                            ],
+                        ),
+                    ];
+                }
+                _ if mnemonic == "li" => {
+                    sequence = load_immediate(rt, immediate_value(value)?, &value.area)?
+                }
+                _ => {
+                    // always two instructions so the size doesn't depend on the address
+                    let address = immediate_value(value)?;
+                    if !(0..=u32::MAX as i64).contains(&address) {
+                        return Err((
+                            DiagnosticKind::OutOfRange,
+                            format!("address {} is out of range", address),
+                            value.area.clone(),
+                        ));
+                    }
+                    let address = address as u32;
+                    let hi = (address.wrapping_add(0x8000) >> 16) as i64;
+                    let lo = address as i16 as i64;
+                    sequence = vec![
+                        ins("lui", vec![rt.clone(), imm(hi, &value.area)]),
+                        ins("addiu", vec![rt.clone(), rt.clone(), imm(lo, &value.area)]),
+                    ];
+                }
+            }
+        }
+        "move" => {
+            let [rd, rs] = expect(operands, area)?;
+            sequence = vec![ins("addu", vec![rd.clone(), zero.clone(), rs.clone()])];
+        }
+        "not" => {
+            let [rd, rs] = expect(operands, area)?;
+            sequence = vec![ins("nor", vec![rd.clone(), rs.clone(), zero.clone()])];
+        }
+        "neg" | "negu" => {
+            let [rd, rs] = expect(operands, area)?;
+            let sub = if mnemonic == "neg" { "sub" } else { "subu" };
+            sequence = vec![ins(sub, vec![rd.clone(), zero.clone(), rs.clone()])];
+        }
+        "abs" => {
+            // no $at needed, the negation is skipped for positive values
+            let [rd, rs] = expect(operands, area)?;
+            sequence = vec![
+                ins("addu", vec![rd.clone(), zero.clone(), rs.clone()]),
+                ins("bgez", vec![rs.clone(), imm(addr as i64 + 12, area)]),
+                ins("sub", vec![rd.clone(), zero.clone(), rs.clone()]),
+            ];
+        }
+        "b" => {
+            let [target] = expect(operands, area)?;
+            sequence = vec![ins("beq", vec![zero.clone(), zero.clone(), target.clone()])];
+        }
+        "beqz" | "bnez" => {
+            let [rs, target] = expect(operands, area)?;
+            let branch = if mnemonic == "beqz" { "beq" } else { "bne" };
+            sequence = vec![ins(branch, vec![rs.clone(), zero.clone(), target.clone()])];
+        }
+        "blt" | "bltu" | "bge" | "bgeu" | "bgt" | "bgtu" | "ble" | "bleu" => {
+            let [rs, rt, target] = expect(operands, area)?;
+            let unsigned = mnemonic.ends_with('u');
+            let at = reg(AT, area);
+            *uses_at = true;
+            let compare = match (&mnemonic[1..3], &rt.kind) {
+                // compares against small immediates don't need them loaded first
+                ("lt" | "ge", OperandKind::Immediate(value))
+                    if (i16::MIN as i64..=i16::MAX as i64).contains(value) =>
+                {
+                    let slti = if unsigned { "sltiu" } else { "slti" };
+                    ins(slti, vec![at.clone(), rs.clone(), rt.clone()])
+                }
+                (condition, _) => {
+                    let rt = register_or_at(rt, &mut sequence, uses_at)?;
+                    let slt = if unsigned { "sltu" } else { "slt" };
+                    // s > t and s <= t are t < s and !(t < s)
+                    if matches!(condition, "gt" | "le") {
+                        ins(slt, vec![at.clone(), rt, rs.clone()])
+                    } else {
+                        ins(slt, vec![at.clone(), rs.clone(), rt])
+                    }
+                }
+            };
+            sequence.push(compare);
+            let branch = if matches!(&mnemonic[1..3], "lt" | "gt") {
+                "bne"
+            } else {
+                "beq"
+            };
+            sequence.push(ins(branch, vec![at, zero.clone(), target.clone()]));
+        }
+        "mul" => {
+            let [rd, rs, rt] = expect(operands, area)?;
+            let rt = register_or_at(rt, &mut sequence, uses_at)?;
+            sequence.push(ins("mult", vec![rs.clone(), rt]));
+            sequence.push(ins("mflo", vec![rd.clone()]));
+        }
+        "div" | "divu" | "rem" | "remu" => {
+            if (mnemonic == "div" || mnemonic == "divu") && operands.len() == 2 {
+                // the real instruction
+                return Ok(None);
+            }
+            let [rd, rs, rt] = expect(operands, area)?;
+            let rt = register_or_at(rt, &mut sequence, uses_at)?;
+            let div = if mnemonic.ends_with('u') {
+                "divu"
+            } else {
+                "div"
+            };
+            let result = if mnemonic.starts_with("rem") {
+                "mfhi"
+            } else {
+                "mflo"
+            };
+            sequence.push(ins(div, vec![rs.clone(), rt]));
+            sequence.push(ins(result, vec![rd.clone()]));
+        }
+        "sgt" | "sgtu" => {
+            let [rd, rs, rt] = expect(operands, area)?;
+            let rt = register_or_at(rt, &mut sequence, uses_at)?;
+            let slt = if mnemonic.ends_with('u') {
+                "sltu"
+            } else {
+                "slt"
+            };
+            sequence.push(ins(slt, vec![rd.clone(), rt, rs.clone()]));
+        }
+        "sge" | "sgeu" | "sle" | "sleu" => {
+            let [rd, rs, rt] = expect(operands, area)?;
+            let rt = register_or_at(rt, &mut sequence, uses_at)?;
+            let slt = if mnemonic.ends_with('u') {
+                "sltu"
+            } else {
+                "slt"
+            };
+            // s >= t is !(s < t) and s <= t is !(t < s)
+            if mnemonic.starts_with("sge") {
+                sequence.push(ins(slt, vec![rd.clone(), rs.clone(), rt]));
+            } else {
+                sequence.push(ins(slt, vec![rd.clone(), rt, rs.clone()]));
+            }
+            sequence.push(ins("xori", vec![rd.clone(), rd.clone(), imm(1, area)]));
+        }
+        "seq" | "sne" => {
+            let [rd, rs, rt] = expect(operands, area)?;
+            let rt = register_or_at(rt, &mut sequence, uses_at)?;
+            sequence.push(ins("xor", vec![rd.clone(), rs.clone(), rt]));
+            if mnemonic == "seq" {
+                sequence.push(ins("sltiu", vec![rd.clone(), rd.clone(), imm(1, area)]));
+            } else {
+                sequence.push(ins("sltu", vec![rd.clone(), zero.clone(), rd.clone()]));
+            }
+        }
+        "ulw" | "usw" => {
+            let [rt, memory] = expect(operands, area)?;
+            let (offset, base) = match memory.kind {
+                OperandKind::Memory {
+                    offset: Value::Constant(offset),
+                    base,
+                } => (offset, base),
+                OperandKind::Immediate(offset) => (offset, 0),
+                OperandKind::Memory { .. } => {
+                    return Err((
+                        DiagnosticKind::OperandType,
+                        format!("{} needs a constant offset", mnemonic),
+                        memory.area.clone(),
+                    ))
+                }
+                _ => {
+                    return Err((
+                        DiagnosticKind::OperandType,
+                        "Expected memory operand (i.e. offset($base))".into(),
+                        memory.area.clone(),
+                    ))
+                }
+            };
+            // `lwl` would overwrite the base before `lwr` uses it, the address goes into $at
+            let (offset, base) = match rt.kind {
+                OperandKind::Register(rt) if mnemonic == "ulw" && rt == base => {
+                    *uses_at = true;
+                    sequence.push(ins(
+                        "addiu",
+                        vec![
+                            reg(AT, area),
+                            reg(base, &memory.area),
+                            imm(offset, &memory.area),
+                        ],
+                    ));
+                    (0, AT)
+                }
+                _ => (offset, base),
+            };
+            // big endian, the left part holds the most significant bytes
+            let part = |offset| Operand {
+                kind: OperandKind::Memory {
+                    offset: Value::Constant(offset),
+                    base,
+                },
+                area: memory.area.clone(),
+            };
+            let (left, right) = if mnemonic == "ulw" {
+                ("lwl", "lwr")
+            } else {
+                ("swl", "swr")
+            };
+            sequence.push(ins(left, vec![rt.clone(), part(offset)]));
+            sequence.push(ins(right, vec![rt.clone(), part(offset + 3)]));
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(sequence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{
+        instruction::{encode, lookup},
+        operand::parse_operands,
+        preprocessor::PPToken,
+        AssemblerState,
+    };
+    use crate::lexer::tokenizer::{TokenType, Tokenizer};
+
+    /// The encoded words and whether `$at` was used
+    fn expand_at(line: &str, addr: u32) -> Result<(Vec<u32>, bool), String> {
+        let mut state = AssemblerState::new(Default::default());
+        let section_index = state.current_section_index();
+        let label = state.symbol_mut("label");
+        label.value = 0x12348000;
+        label.section_index = section_index;
+        let mut tokens = Tokenizer::new_from_str(line).map(|tok| PPToken::new(tok.unwrap()));
+        let mnemonic = tokens.next().unwrap();
+        let args: Vec<_> = tokens.collect();
+        let mnemonic_str = match &mnemonic.tok {
+            TokenType::Identifier(ident) => ident.clone(),
+            other => panic!("{:?}", other),
+        };
+        let area = &mnemonic.location;
+        let operands = parse_operands(&state, &args, area).map_err(|(_, msg, _)| msg)?;
+        let expansion = match expand(&mnemonic_str, &operands, addr, area) {
+            Some(expansion) => expansion.map_err(|(_, msg, _)| msg)?,
+            None => Expansion {
+                instructions: vec![ins(&mnemonic_str, operands)],
+                uses_at: false,
+            },
+        };
+        let mut words = Vec::new();
+        for (i, Expanded { mnemonic, operands }) in expansion.instructions.iter().enumerate() {
+            let info = lookup(mnemonic).unwrap();
+            let encoded =
+                encode(info, operands, addr + i as u32 * 4, area).map_err(|(_, msg, _)| msg)?;
+            words.push(encoded.word);
+        }
+        Ok((words, expansion.uses_at))
+    }
+
+    fn expand_line(line: &str) -> Result<Vec<u32>, String> {
+        expand_at(line, 0).map(|(words, _)| words)
+    }
+
+    #[test]
+    fn load_immediate_picks_the_shortest_sequence() {
+        assert_eq!(expand_line("li $t0, 5"), Ok(vec![0x24080005]));
+        assert_eq!(expand_line("li $t0, -1"), Ok(vec![0x2408FFFF]));
+        assert_eq!(expand_line("li $t0, 0xFFFFFFFF"), Ok(vec![0x2408FFFF]));
+        assert_eq!(expand_line("li $t0, 0x8000"), Ok(vec![0x34088000]));
+        assert_eq!(expand_line("li $t0, 0x12340000"), Ok(vec![0x3C081234]));
+        assert_eq!(
+            expand_line("li $t0, 0x12345678"),
+            Ok(vec![0x3C081234, 0x35085678])
+        );
+        assert!(expand_line("li $t0, 0x100000000").is_err());
+        // the low half is sign extended by addiu so the high half is adjusted
+        assert_eq!(
+            expand_line("la $a0, 0x12348000"),
+            Ok(vec![0x3C041235, 0x24848000])
+        );
+        // labels are filled in by relocations
+        assert_eq!(
+            expand_line("la $a0, label"),
+            Ok(vec![0x3C040000, 0x24840000])
+        );
+        assert_eq!(
+            expand_line("li $a0, label"),
+            Ok(vec![0x3C040000, 0x24840000])
+        );
+    }
+
+    #[test]
+    fn register_pseudo_instructions() {
+        assert_eq!(expand_line("move $a0, $a1"), Ok(vec![0x00052021]));
+        assert_eq!(expand_line("not $t0, $t1"), Ok(vec![0x01204027]));
+        assert_eq!(expand_line("neg $t0, $t1"), Ok(vec![0x00094022]));
+        assert_eq!(
+            expand_line("abs $t0, $t1"),
+            Ok(vec![0x00094021, 0x05210001, 0x00094022])
+        );
+        assert_eq!(
+            expand_line("mul $t0, $t1, $t2"),
+            Ok(vec![0x012A0018, 0x00004012])
+        );
+        assert_eq!(
+            expand_line("rem $v0, $a0, $a1"),
+            Ok(vec![0x0085001A, 0x00001010])
+        );
+        assert_eq!(expand_line("div $a0, $a1"), Ok(vec![0x0085001A]));
+        assert_eq!(
+            expand_line("seq $t0, $t1, $t2"),
+            Ok(vec![0x012A4026, 0x2D080001])
+        );
+        assert_eq!(
+            expand_line("sne $t0, $t1, $t2"),
+            Ok(vec![0x012A4026, 0x0008402B])
+        );
+        assert_eq!(
+            expand_line("sge $t0, $t1, $t2"),
+            Ok(vec![0x012A402A, 0x39080001])
+        );
+        assert_eq!(
+            expand_line("ulw $t0, 4($sp)"),
+            Ok(vec![0x8BA80004, 0x9BA80007])
+        );
+        // the base is loaded into $at first so lwl doesn't overwrite it
+        assert_eq!(
+            expand_at("ulw $t0, 4($t0)", 0),
+            Ok((vec![0x25010004, 0x88280000, 0x98280003], true))
+        );
+        assert_eq!(
+            expand_at("usw $t0, 4($t0)", 0),
+            Ok((vec![0xA9080004, 0xB9080007], false))
+        );
+    }
+
+    #[test]
+    fn branch_pseudo_instructions() {
+        assert_eq!(expand_line("b 8"), Ok(vec![0x10000001]));
+        assert_eq!(expand_line("beqz $t0, 8"), Ok(vec![0x11000001]));
+        assert_eq!(
+            expand_at("blt $t0, $t1, 8", 0),
+            Ok((vec![0x0109082A, 0x14200000], true))
+        );
+        assert_eq!(
+            expand_line("bge $t0, 5, 8"),
+            Ok(vec![0x29010005, 0x10200000])
+        );
+        assert_eq!(
+            expand_line("bgt $t0, 100, 12"),
+            Ok(vec![0x24010064, 0x0028082A, 0x14200000])
+        );
+        assert_eq!(expand_at("move $a0, $a1", 0).map(|(_, at)| at), Ok(false));
+    }
+}
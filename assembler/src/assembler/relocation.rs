@@ -0,0 +1,167 @@
+use elf::internal::relocation::MipsRelocationType;
+
+use super::diagnostic::DiagnosticKind;
+
+//------------------------------------------------------------------------
+
+/// A label plus a constant offset (`label+8`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRef {
+    pub symbol: String,
+    pub addend: i64,
+    /// A label subtracted from `symbol` (`end - start`), only known once both are defined
+    pub minus: Option<String>,
+}
+
+impl SymbolRef {
+    pub fn new(symbol: impl Into<String>) -> Self {
+        Self {
+            symbol: symbol.into(),
+            addend: 0,
+            minus: None,
+        }
+    }
+}
+
+impl std::fmt::Display for SymbolRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol)?;
+        if let Some(minus) = &self.minus {
+            write!(f, "-{}", minus)?;
+        }
+        match self.addend {
+            0 => Ok(()),
+            addend if addend < 0 => write!(f, "{}", addend),
+            addend => write!(f, "+{}", addend),
+        }
+    }
+}
+
+/// What a fixup fills in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixupKind {
+    /// (Part of) the address of the label, relocated unless it is local
+    Relocation(MipsRelocationType),
+    /// A 16 bit immediate (`signed` or not) that has to be a constant, i.e. an `.equ` or the
+    /// distance between two labels that are defined later
+    Immediate { signed: bool },
+    /// The offset of a memory operand, a constant like a signed immediate
+    Offset,
+}
+
+/// A field of an encoded instruction or data word that holds the value of a label
+///
+/// It is resolved once the whole file is assembled, so the label can be defined after it is used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    pub kind: FixupKind,
+    pub target: SymbolRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocationTarget {
+    /// The start of a section (by `section_index`), the offset of the label is stored in the field
+    Section(u16),
+    /// A global, weak or undefined symbol, the field only holds the addend
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the relocated word from the start of its section
+    pub offset: u32,
+    pub r_type: MipsRelocationType,
+    pub target: RelocationTarget,
+}
+
+/// The bits of a word that store `addend` for `r_type`, relocations have no explicit addend
+pub fn addend_field(
+    r_type: MipsRelocationType,
+    addend: u32,
+) -> Result<u32, (DiagnosticKind, String)> {
+    use MipsRelocationType::*;
+    match r_type {
+        R_MIPS_32 => Ok(addend),
+        R_MIPS_26 if addend & 0b11 != 0 => Err((
+            DiagnosticKind::BranchTarget,
+            format!("jump target {:#x} is not word aligned", addend),
+        )),
+        R_MIPS_26 => Ok((addend >> 2) & 0x03FF_FFFF),
+        // the low half is sign extended when it is used so the high half makes up for it
+        R_MIPS_HI16 => Ok((addend.wrapping_add(0x8000) >> 16) & 0xFFFF),
+        R_MIPS_LO16 => Ok(addend & 0xFFFF),
+        R_MIPS_GPREL16 if (i16::MIN as i32..=i16::MAX as i32).contains(&(addend as i32)) => {
+            Ok(addend & 0xFFFF)
+        }
+        R_MIPS_GPREL16 => Err((
+            DiagnosticKind::OutOfRange,
+            format!(
+                "offset {} from the symbol is out of range for %gp_rel",
+                addend as i32
+            ),
+        )),
+        // the addend is the distance from the branch, not where it goes
+        R_MIPS_PC16 if addend & 0b11 != 0 => Err((
+            DiagnosticKind::BranchTarget,
+            format!("branch offset {} is not word aligned", addend as i32),
+        )),
+        R_MIPS_PC16 => {
+            // relative to the branch but the offset is from the instruction after it
+            let offset = (addend as i32 as i64 - 4) >> 2;
+            if (i16::MIN as i64..=i16::MAX as i64).contains(&offset) {
+                Ok(offset as u32 & 0xFFFF)
+            } else {
+                Err((
+                    DiagnosticKind::BranchTarget,
+                    format!(
+                        "branch offset {} is too far away ({} instructions, the limit is {}..={})",
+                        addend as i32,
+                        offset,
+                        i16::MIN,
+                        i16::MAX
+                    ),
+                ))
+            }
+        }
+        other => Err((
+            DiagnosticKind::Relocation,
+            format!("Unsupported relocation: {:?}", other),
+        )),
+    }
+}
+
+/// The bits of a word that store the `value` of an immediate or offset fixup, `None` if the
+/// value is the address of `target` which is only known after linking
+pub fn constant_field(
+    kind: FixupKind,
+    target: &SymbolRef,
+    value: Option<u32>,
+) -> Result<u32, (DiagnosticKind, String)> {
+    let (what, min, max) = match kind {
+        FixupKind::Immediate { signed: false } => ("immediate", 0, u16::MAX as i64),
+        FixupKind::Immediate { signed: true } => ("immediate", i16::MIN as i64, i16::MAX as i64),
+        FixupKind::Offset => ("offset", i16::MIN as i64, i16::MAX as i64),
+        FixupKind::Relocation(_) => unreachable!("relocations use addend_field"),
+    };
+    let Some(value) = value else {
+        let hint = match kind {
+            FixupKind::Offset => format!(
+                "use %lo({0}) with %hi({0}) in the base register, or %gp_rel({0})",
+                target
+            ),
+            _ => "use la to load its address".into(),
+        };
+        return Err((
+            DiagnosticKind::OperandType,
+            format!("Expected {} but found label {} ({})", what, target, hint),
+        ));
+    };
+    let value = value as i32 as i64;
+    if !(min..=max).contains(&value) {
+        return Err((
+            DiagnosticKind::OutOfRange,
+            format!("{} {} is out of range ({}..={})", what, value, min, max),
+        ));
+    }
+    Ok(value as u32 & 0xFFFF)
+}
@@ -0,0 +1,121 @@
+use super::relocation::Relocation;
+
+//------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Contents are stored in the output
+    Progbits,
+    /// Only the size is stored, the contents are zero when loaded (.bss)
+    Nobits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SectionFlags {
+    pub alloc: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl SectionFlags {
+    /// Parses the flag string of `.section name, "flags"` (i.e. "ax")
+    pub fn parse(flags: &str) -> Result<Self, char> {
+        let mut res = Self::default();
+        for c in flags.chars() {
+            match c {
+                'a' => res.alloc = true,
+                'w' => res.write = true,
+                'x' => res.execute = true,
+                c => return Err(c),
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub flags: SectionFlags,
+    /// The largest alignment requested by anything in the section
+    pub alignment: u32,
+    /// Always empty for `SectionKind::Nobits`
+    pub data: Vec<u8>,
+    /// Words in `data` that refer to labels, ordered by offset
+    pub relocations: Vec<Relocation>,
+    size: u32,
+}
+
+impl Section {
+    pub fn new(name: impl Into<String>, kind: SectionKind, flags: SectionFlags) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            flags,
+            alignment: 1,
+            data: Vec::new(),
+            relocations: Vec::new(),
+            size: 0,
+        }
+    }
+
+    /// The kind and flags of the standard sections, other sections default to allocated data
+    pub fn with_default_attributes(name: impl Into<String>) -> Self {
+        let name = name.into();
+        let flags = |flags| SectionFlags::parse(flags).unwrap();
+        let (kind, flags) = match name.as_str() {
+            ".text" => (SectionKind::Progbits, flags("ax")),
+            ".data" => (SectionKind::Progbits, flags("aw")),
+            ".rodata" => (SectionKind::Progbits, flags("a")),
+            ".bss" => (SectionKind::Nobits, flags("aw")),
+            _ if name.starts_with(".text.") => (SectionKind::Progbits, flags("ax")),
+            _ if name.starts_with(".bss.") => (SectionKind::Nobits, flags("aw")),
+            _ => (SectionKind::Progbits, flags("a")),
+        };
+        Self::new(name, kind, flags)
+    }
+
+    /// The location counter, the offset of the next byte from the start of the section
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.kind == SectionKind::Nobits && bytes.iter().any(|byte| *byte != 0) {
+            return Err(format!(
+                "Cannot store data in {}, only reserve space (i.e. .space)",
+                self.name
+            ));
+        }
+        if self.kind == SectionKind::Progbits {
+            self.data.extend_from_slice(bytes);
+        }
+        self.size += bytes.len() as u32;
+        Ok(())
+    }
+
+    /// Pads the section with `fill` until it is `size` bytes long
+    pub fn fill_to(&mut self, size: u32, fill: u8) -> Result<(), String> {
+        if size < self.size {
+            return Err(format!(
+                "Cannot move the location counter backwards (from {:#x} to {:#x})",
+                self.size, size
+            ));
+        }
+        if self.kind == SectionKind::Progbits {
+            self.data.resize(size as usize, fill);
+        } else if fill != 0 {
+            return Err(format!("Cannot store data in {}", self.name));
+        }
+        self.size = size;
+        Ok(())
+    }
+
+    /// Pads the section with `fill` to a multiple of `alignment`, a power of two
+    pub fn align(&mut self, alignment: u32, fill: u8) -> Result<(), String> {
+        self.alignment = self.alignment.max(alignment);
+        let aligned = (self.size + alignment - 1) & !(alignment - 1);
+        self.fill_to(aligned, fill)
+    }
+}
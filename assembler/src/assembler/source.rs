@@ -0,0 +1,148 @@
+//! Where the assembler reads its input and `#include`d files from
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Resolves the input path and `#include` paths to their contents
+pub trait SourceProvider {
+    /// The path that `path` refers to, what files are reported as and what is passed to `read`
+    fn resolve(&mut self, path: &str) -> String {
+        path.to_owned()
+    }
+
+    /// The contents of the file at `path`
+    fn read(&mut self, path: &str) -> Result<String, Box<dyn Error>>;
+}
+
+/// Reads files from disk, relative paths are relative to the working directory and then to
+/// the include directories in order (`-I`)
+#[derive(Debug, Default, Clone)]
+pub struct FileSystem {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl FileSystem {
+    pub fn new(include_dirs: Vec<PathBuf>) -> Self {
+        Self { include_dirs }
+    }
+}
+
+impl SourceProvider for FileSystem {
+    fn resolve(&mut self, path: &str) -> String {
+        if Path::new(path).is_absolute() || Path::new(path).exists() {
+            return path.to_owned();
+        }
+        self.include_dirs
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|path| path.exists())
+            .map_or_else(|| path.to_owned(), |path| path.display().to_string())
+    }
+
+    fn read(&mut self, path: &str) -> Result<String, Box<dyn Error>> {
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+/// Sources held in memory by path (editor buffers, embedded sources), nothing is read from disk
+#[derive(Debug, Default, Clone)]
+pub struct MemorySources {
+    files: HashMap<String, String>,
+}
+
+impl MemorySources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the file at `path`
+    pub fn add(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.files.insert(path.into(), source.into());
+    }
+
+    pub fn with(mut self, path: impl Into<String>, source: impl Into<String>) -> Self {
+        self.add(path, source);
+        self
+    }
+}
+
+impl SourceProvider for MemorySources {
+    fn read(&mut self, path: &str) -> Result<String, Box<dyn Error>> {
+        match self.files.get(path) {
+            Some(source) => Ok(source.clone()),
+            None => Err(format!("no source named {}", path).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_source, debug::tests::sections, Assembler, AssemblerSettings};
+
+    #[test]
+    fn in_memory() {
+        let sources = MemorySources::new()
+            .with(
+                "main.asm",
+                "#include \"defs.inc\"\nmain: li $v0, EXIT\nsyscall\n",
+            )
+            .with("defs.inc", "#define EXIT 10\n");
+        let mut assembler = Assembler::with_sources(AssemblerSettings::default(), sources);
+        let mut object = Vec::new();
+        assert!(assembler.assemble("main.asm".into(), &mut object).is_ok());
+        let text = sections(&object)
+            .into_iter()
+            .find(|(name, _)| name == ".text");
+        assert_eq!(text.unwrap().1, [0x24, 0x02, 0, 10, 0, 0, 0, 0x0C]);
+
+        let object = assemble_source("single.asm", "nop\n").ok().unwrap();
+        assert_eq!(&object[..4], b"\x7fELF");
+    }
+
+    #[test]
+    fn include_dirs() {
+        let dir = std::env::temp_dir().join(format!("include_dirs_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("inc")).unwrap();
+        std::fs::write(dir.join("inc/defs.inc"), "#define EXIT 10\n").unwrap();
+        let mut sources = FileSystem::new(vec![dir.join("missing"), dir.join("inc")]);
+        let resolved = sources.resolve("defs.inc");
+        let found = sources.read(&resolved).map_err(|err| err.to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(resolved, dir.join("inc/defs.inc").display().to_string());
+        assert_eq!(found.as_deref(), Ok("#define EXIT 10\n"));
+        assert_eq!(sources.resolve("nowhere.inc"), "nowhere.inc");
+    }
+
+    #[test]
+    fn missing_sources() {
+        let sources = MemorySources::new().with("main.asm", "#include \"missing.inc\"\nnop\n");
+        let mut assembler = Assembler::with_sources(AssemblerSettings::default(), sources);
+        let report = assembler
+            .assemble("main.asm".into(), &mut Vec::new())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            report.contains("Failed to open file: missing.inc (no source named missing.inc)"),
+            "{}",
+            report
+        );
+
+        let mut assembler =
+            Assembler::with_sources(AssemblerSettings::default(), MemorySources::new());
+        let report = assembler
+            .assemble("main.asm".into(), &mut Vec::new())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            report.contains("Failed to load file: main.asm"),
+            "{}",
+            report
+        );
+    }
+}
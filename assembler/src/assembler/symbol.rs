@@ -1,5 +1,11 @@
 //------------------------------------------------------------------------
 
+/// `section_index` of a symbol that is referenced but not defined in this file
+pub const SHN_UNDEF: u16 = 0;
+/// `section_index` of a symbol whose value is absolute (i.e. `.equ`)
+pub const SHN_ABS: u16 = 0xFFF1;
+
+#[derive(Debug, Clone)]
 pub struct Symbol {
     pub name: String,
     pub value: u32,
@@ -25,6 +31,7 @@ impl Default for Symbol {
 }
 
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymBind {
     STB_LOCAL,
     STB_GLOBAL,
@@ -35,7 +42,22 @@ pub enum SymBind {
     STB_HIPROC,
 }
 
+impl From<SymBind> for u8 {
+    fn from(val: SymBind) -> Self {
+        match val {
+            SymBind::STB_LOCAL => 0,
+            SymBind::STB_GLOBAL => 1,
+            SymBind::STB_WEAK => 2,
+            SymBind::STB_LOOS => 10,
+            SymBind::STB_HIOS => 12,
+            SymBind::STB_LOPROC => 13,
+            SymBind::STB_HIPROC => 15,
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymType {
     STT_NOTYPE,
     STT_OBJECT,
@@ -51,7 +73,27 @@ pub enum SymType {
     STT_HIPROC,
 }
 
+impl From<SymType> for u8 {
+    fn from(val: SymType) -> Self {
+        match val {
+            SymType::STT_NOTYPE => 0,
+            SymType::STT_OBJECT => 1,
+            SymType::STT_FUNC => 2,
+            SymType::STT_SECTION => 3,
+            SymType::STT_FILE => 4,
+            SymType::STT_COMMON => 5,
+            SymType::STT_TLS => 6,
+            SymType::STT_LOOS => 10,
+            SymType::STT_HIOS => 12,
+            SymType::STT_LOPROC => 13,
+            SymType::STT_SPARC_REGISTER => 13,
+            SymType::STT_HIPROC => 15,
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymVis {
     STV_DEFUALT,
     STV_INTERNAL,
@@ -61,3 +103,17 @@ pub enum SymVis {
     STV_SINGLETON,
     STV_ELIMINATE,
 }
+
+impl From<SymVis> for u8 {
+    fn from(val: SymVis) -> Self {
+        match val {
+            SymVis::STV_DEFUALT => 0,
+            SymVis::STV_INTERNAL => 1,
+            SymVis::STV_HIDDNE => 2,
+            SymVis::STV_PROTECTED => 3,
+            SymVis::STV_EXPORTED => 4,
+            SymVis::STV_SINGLETON => 5,
+            SymVis::STV_ELIMINATE => 6,
+        }
+    }
+}
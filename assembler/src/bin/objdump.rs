@@ -0,0 +1,112 @@
+use assembler::{
+    assembler::source::FileSystem,
+    disassembler::{
+        objdump::{objdump, ObjdumpSettings},
+        simple::Syntax,
+    },
+};
+
+const USAGE: &str = "\
+Usage: objdump [options] <file.o|file.elf>...
+
+Disassembles the code of MIPS ELF objects and executables
+
+Options:
+  -S, --source            Show the source lines above their code if the file has line info
+      --raw               Don't fold instructions into pseudo instructions (move, li, la, ...)
+      --numeric           Print registers by number ($4) instead of by name ($a0)
+      --syntax <mars|gnu> mars (the default) prints what the assembler accepts, gnu prints
+                          like GNU objdump
+  -h, --help              Print this help
+  -V, --version           Print the version
+
+Exit codes: 0 on success, 1 if a file couldn't be read or isn't a MIPS ELF file, 2 for invalid
+arguments";
+
+/// Exit code when a file can't be disassembled
+const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid arguments
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug)]
+enum Command {
+    Dump(ObjdumpSettings, Vec<String>),
+    Help,
+    Version,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut settings = ObjdumpSettings::default();
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-S" | "--source" => settings.source = true,
+            "--raw" => settings.disassembler.pseudo = false,
+            "--numeric" => settings.disassembler.register_names = false,
+            "--syntax" => {
+                let value = value
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value", flag))?;
+                settings.disassembler.syntax = match value.as_str() {
+                    "mars" => Syntax::Mars,
+                    "gnu" => Syntax::Gnu,
+                    other => return Err(format!("unknown syntax: {}", other)),
+                }
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option: {}", flag)),
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        return Err("no input files".into());
+    }
+    Ok(Command::Dump(settings, files))
+}
+
+fn run(settings: ObjdumpSettings, files: Vec<String>) -> i32 {
+    let mut code = 0;
+    for (index, file) in files.iter().enumerate() {
+        let res = std::fs::read(file)
+            .map_err(|err| err.to_string())
+            .and_then(|data| objdump(file, &data, &settings, &mut FileSystem::default()));
+        match res {
+            Ok(dump) => {
+                if index != 0 {
+                    println!();
+                }
+                print!("{}", dump);
+            }
+            Err(err) => {
+                eprintln!("error: {}: {}", file, err);
+                code = EXIT_FAILURE;
+            }
+        }
+    }
+    code
+}
+
+fn main() {
+    let code = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Dump(settings, files)) => run(settings, files),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            0
+        }
+        Ok(Command::Version) => {
+            println!("objdump {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            EXIT_USAGE
+        }
+    };
+    std::process::exit(code);
+}
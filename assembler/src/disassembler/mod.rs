@@ -1 +1,3 @@
+pub mod objdump;
+pub mod pseudo;
 pub mod simple;
@@ -0,0 +1,601 @@
+//! An objdump like listing of the code in a MIPS ELF file, objects and executables of either
+//! endianness
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+};
+
+use elf::{
+    external::{
+        from_bytes, header::ExternalElfHeaderTrait, section::ExternalSectionHeaderTrait,
+        TernaryResult,
+    },
+    internal::{
+        header::header_util::{ElfEndian, ElfMachine, ElfType},
+        relocation::MipsRelocationType,
+        section::section_util::{SectionFlags, SectionType},
+    },
+    writer::{Elf32Rel, Elf32Symbol, Encoder},
+};
+
+use super::{
+    pseudo::disassemble_block,
+    simple::{DisassemblerSettings, Symbols},
+};
+use crate::assembler::{
+    debug::read_line_table,
+    source::SourceProvider,
+    symbol::{SymType, SHN_UNDEF},
+};
+
+/// First reserved section index, symbols with an index past it aren't in a section
+const SHN_LORESERVE: u16 = 0xFF00;
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjdumpSettings {
+    /// Show the source lines above their code if the file has line info
+    pub source: bool,
+    pub disassembler: DisassemblerSettings,
+}
+
+struct ElfSection<'a> {
+    name: String,
+    sh_type: u32,
+    flags: SectionFlags,
+    addr: u32,
+    link: u32,
+    info: u32,
+    /// Empty for `SHT_NOBITS`
+    data: &'a [u8],
+}
+
+struct ElfSymbol {
+    name: String,
+    value: u32,
+    s_type: u8,
+    shndx: u16,
+}
+
+impl ElfSymbol {
+    /// Labels and functions, not the symbols of sections and files
+    fn is_label(&self) -> bool {
+        !self.name.is_empty()
+            && self.s_type != u8::from(SymType::STT_SECTION)
+            && self.s_type != u8::from(SymType::STT_FILE)
+            && self.shndx != SHN_UNDEF
+            && self.shndx < SHN_LORESERVE
+    }
+}
+
+/// `REL` entry, read without `Elf32Rel::read` so that unknown types can still be shown
+struct Rel {
+    offset: u32,
+    symbol: u32,
+    r_type: u8,
+}
+
+struct ElfFile<'a> {
+    enc: Encoder,
+    relocatable: bool,
+    sections: Vec<ElfSection<'a>>,
+    symbols: Vec<ElfSymbol>,
+}
+
+impl<'a> ElfFile<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, String> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err("not an ELF file".into());
+        }
+        let elf = match from_bytes(data) {
+            TernaryResult::Ok1(elf) => elf,
+            TernaryResult::Ok2(_) => return Err("64 bit ELF files are not supported".into()),
+            TernaryResult::Err(()) => return Err("not an ELF file".into()),
+        };
+        let header = elf.elf_header();
+        let endian = ElfEndian::try_from(header.endianness())
+            .map_err(|_| format!("invalid endianness {}", header.endianness()))?;
+        if header.machine() != u16::from(ElfMachine::EM_MIPS) {
+            return Err(format!("not a MIPS file (machine {})", header.machine()));
+        }
+        let headers_end = (header.section_header_offset() as usize)
+            .checked_add(header.section_header_entry_num() as usize * 40);
+        if headers_end.is_none_or(|end| end > data.len()) {
+            return Err("section headers are outside of the file".into());
+        }
+
+        let mut sections = Vec::new();
+        for index in 0..header.section_header_entry_num() as usize {
+            let section = elf.section_header(index).unwrap();
+            let sh_type = section.sh_type();
+            let contents = if sh_type == u32::from(SectionType::SHT_NOBITS) {
+                &data[..0]
+            } else {
+                let start = section.offset() as usize;
+                start
+                    .checked_add(section.size() as usize)
+                    .and_then(|end| data.get(start..end))
+                    .ok_or_else(|| format!("section {} is outside of the file", index))?
+            };
+            sections.push(ElfSection {
+                name: String::new(),
+                sh_type,
+                flags: SectionFlags::from_bits_truncate(section.flags() as u64),
+                addr: section.addr(),
+                link: section.link(),
+                info: section.info(),
+                data: contents,
+            });
+        }
+        let mut file = ElfFile {
+            enc: Encoder { endian },
+            relocatable: header.elftype() == u16::from(ElfType::ET_REL),
+            sections,
+            symbols: Vec::new(),
+        };
+        for index in 0..file.sections.len() {
+            let name = elf.section_header(index).unwrap().name_off();
+            file.sections[index].name = file.string(header.shstr_index() as u32, name);
+        }
+
+        if let Some(symtab) = file
+            .sections
+            .iter()
+            .find(|section| section.sh_type == u32::from(SectionType::SHT_SYMTAB))
+        {
+            for entry in symtab.data.chunks_exact(Elf32Symbol::SIZE as usize) {
+                let symbol = Elf32Symbol::read(file.enc, entry);
+                file.symbols.push(ElfSymbol {
+                    name: file.string(symtab.link, symbol.name),
+                    value: symbol.value,
+                    s_type: symbol.s_type(),
+                    shndx: symbol.shndx,
+                });
+            }
+        }
+        Ok(file)
+    }
+
+    /// The string at `offset` of the string table `table`, empty if there is none
+    fn string(&self, table: u32, offset: u32) -> String {
+        let bytes = self
+            .sections
+            .get(table as usize)
+            .and_then(|table| table.data.get(offset as usize..))
+            .unwrap_or_default();
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+
+    /// The relocations of section `index`
+    fn relocations(&self, index: usize) -> Vec<Rel> {
+        let mut relocations = Vec::new();
+        for section in &self.sections {
+            if section.sh_type != u32::from(SectionType::SHT_REL) || section.info as usize != index
+            {
+                continue;
+            }
+            for entry in section.data.chunks_exact(Elf32Rel::SIZE as usize) {
+                let info = self.enc.read_u32(&entry[4..]);
+                relocations.push(Rel {
+                    offset: self.enc.read_u32(entry),
+                    symbol: info >> 8,
+                    r_type: info as u8,
+                });
+            }
+        }
+        relocations
+    }
+
+    /// What a relocation refers to, the name of the section for section symbols
+    fn symbol_name(&self, index: u32) -> String {
+        match self.symbols.get(index as usize) {
+            Some(symbol) if symbol.s_type == u8::from(SymType::STT_SECTION) => self
+                .sections
+                .get(symbol.shndx as usize)
+                .map_or_else(String::new, |section| section.name.clone()),
+            Some(symbol) => symbol.name.clone(),
+            None => format!("<symbol {}>", index),
+        }
+    }
+
+    /// The `(file, line)` of every instruction of section `index` by address, empty if there is
+    /// no line info
+    fn lines(&self, index: usize) -> BTreeMap<u32, (usize, u32)> {
+        let mut lines = BTreeMap::new();
+        let Some((line_index, debug_line)) = self
+            .sections
+            .iter()
+            .enumerate()
+            .find(|(_, section)| section.name == ".debug_line")
+        else {
+            return lines;
+        };
+        let Some(table) = read_line_table(debug_line.data, self.enc) else {
+            return lines;
+        };
+        let section = &self.sections[index];
+        // in objects the start of every sequence is relocated against the section it covers
+        let sequences: HashMap<u32, u32> = self
+            .relocations(line_index)
+            .into_iter()
+            .map(|rel| (rel.offset, rel.symbol))
+            .collect();
+        for row in &table.rows {
+            let address = if self.relocatable {
+                let symbol = sequences
+                    .get(&(row.sequence as u32))
+                    .and_then(|symbol| self.symbols.get(*symbol as usize));
+                match symbol {
+                    Some(symbol) if symbol.shndx as usize == index => {
+                        row.address.wrapping_add(symbol.value)
+                    }
+                    _ => continue,
+                }
+            } else if (section.addr..section.addr + section.data.len() as u32)
+                .contains(&row.address)
+            {
+                row.address
+            } else {
+                continue;
+            };
+            lines.entry(address).or_insert((row.file, row.line));
+        }
+        lines
+    }
+}
+
+/// Lines of the source files, read when they are first shown
+struct SourceLines<'a> {
+    sources: &'a mut dyn SourceProvider,
+    files: Vec<String>,
+    lines: HashMap<usize, Option<Vec<String>>>,
+}
+
+impl SourceLines<'_> {
+    fn get(&mut self, file: usize, line: u32) -> Option<&str> {
+        let lines = self.lines.entry(file).or_insert_with(|| {
+            let path = self.sources.resolve(self.files.get(file)?);
+            let source = self.sources.read(&path).ok()?;
+            Some(source.lines().map(str::to_owned).collect())
+        });
+        let lines = lines.as_ref()?;
+        lines
+            .get((line as usize).checked_sub(1)?)
+            .map(String::as_str)
+    }
+}
+
+/// Disassembles the executable sections of the ELF file `data`, `name` is the file name shown in
+/// the header. `sources` provides the source files if `settings.source` is set
+pub fn objdump(
+    name: &str,
+    data: &[u8],
+    settings: &ObjdumpSettings,
+    sources: &mut dyn SourceProvider,
+) -> Result<String, String> {
+    let file = ElfFile::parse(data)?;
+    let mut out = String::new();
+    let endian = match file.enc.endian {
+        ElfEndian::BigEndian => "big",
+        ElfEndian::LittleEndian => "little",
+    };
+    let _ = writeln!(out, "{}:     file format elf32-{}mips", name, endian);
+
+    let mut source_lines = SourceLines {
+        sources,
+        files: Vec::new(),
+        lines: HashMap::new(),
+    };
+    if settings.source {
+        if let Some(table) = file
+            .sections
+            .iter()
+            .find(|section| section.name == ".debug_line")
+            .and_then(|section| read_line_table(section.data, file.enc))
+        {
+            source_lines.files = table.files;
+        }
+    }
+
+    for (index, section) in file.sections.iter().enumerate() {
+        if !section.flags.contains(SectionFlags::SHF_EXECINSTR) || section.data.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "\n\nDisassembly of section {}:\n", section.name);
+
+        // branch targets in objects are offsets into the section, so only its labels apply
+        let mut targets = Symbols::new();
+        let mut labels: Vec<&ElfSymbol> = Vec::new();
+        for symbol in file.symbols.iter().filter(|symbol| symbol.is_label()) {
+            if symbol.shndx as usize == index {
+                labels.push(symbol);
+            }
+            if symbol.shndx as usize == index || !file.relocatable {
+                targets
+                    .entry(symbol.value)
+                    .or_insert_with(|| symbol.name.clone());
+            }
+        }
+        labels.sort_by(|a, b| a.value.cmp(&b.value).then(a.name.cmp(&b.name)));
+        let mut relocations = file.relocations(index);
+        relocations.sort_by_key(|rel| rel.offset);
+        let lines = match settings.source {
+            true => file.lines(index),
+            false => BTreeMap::new(),
+        };
+
+        if labels
+            .first()
+            .is_none_or(|label| label.value != section.addr)
+        {
+            let _ = write!(out, "\n{:08x} <{}>:\n", section.addr, section.name);
+        }
+        // relocated words are disassembled on their own, what they refer to is only known
+        // after linking
+        let words: Vec<u32> = section
+            .data
+            .chunks_exact(4)
+            .map(|word| file.enc.read_u32(word))
+            .collect();
+        let relocated: HashSet<usize> = relocations
+            .iter()
+            .map(|rel| rel.offset as usize / 4)
+            .collect();
+        let mut texts = HashMap::new();
+        let mut start = 0;
+        for end in 1..=words.len() {
+            if end == words.len() || relocated.contains(&end) || relocated.contains(&(end - 1)) {
+                let add = section.addr.wrapping_add(start as u32 * 4);
+                let block = &words[start..end];
+                for line in disassemble_block(block, add, Some(&targets), &settings.disassembler) {
+                    texts.insert(line.address, line.text);
+                }
+                start = end;
+            }
+        }
+
+        let (mut labels, mut relocations) =
+            (labels.iter().peekable(), relocations.iter().peekable());
+        let mut last_line = None;
+        for (offset, bytes) in (0..).step_by(4).zip(section.data.chunks(4)) {
+            let address = section.addr.wrapping_add(offset);
+            while let Some(label) = labels.next_if(|label| label.value <= address) {
+                let _ = write!(out, "\n{:08x} <{}>:\n", label.value, label.name);
+            }
+            if let Some(&(file_index, line)) = lines.get(&address) {
+                if last_line != Some((file_index, line)) {
+                    let path = source_lines.files.get(file_index).cloned();
+                    let _ = writeln!(out, "{}:{}", path.unwrap_or_default(), line);
+                    if let Some(text) = source_lines.get(file_index, line) {
+                        let _ = writeln!(out, "{}", text);
+                    }
+                    last_line = Some((file_index, line));
+                }
+            }
+
+            if bytes.len() == 4 {
+                let word = file.enc.read_u32(bytes);
+                // the rest of a pseudo instruction has no text of its own
+                let _ = match texts.get(&address) {
+                    Some(text) => writeln!(out, "{:>8x}:\t{:08x}\t{}", address, word, text),
+                    None => writeln!(out, "{:>8x}:\t{:08x}", address, word),
+                };
+            } else {
+                let hex: Vec<_> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                let _ = writeln!(out, "{:>8x}:\t{}", address, hex.join(" "));
+            }
+            while let Some(rel) = relocations.next_if(|rel| rel.offset < offset + 4) {
+                let r_type = match MipsRelocationType::try_from(rel.r_type) {
+                    Ok(r_type) => format!("{:?}", r_type),
+                    Err(()) => format!("<type {}>", rel.r_type),
+                };
+                let _ = writeln!(
+                    out,
+                    "\t\t\t{:x}: {}\t{}",
+                    section.addr.wrapping_add(rel.offset),
+                    r_type,
+                    file.symbol_name(rel.symbol)
+                );
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use elf::writer::{Elf32Writer, StringTable, WriterSection};
+
+    use super::*;
+    use crate::{
+        assembler::{debug::tests::debug_settings, source::MemorySources, Assembler},
+        linker::{Linker, LinkerSettings},
+    };
+
+    const SOURCE: &str = "\
+.globl main
+main:
+    la $a0, message
+    jal print
+loop:
+    beq $a0, $zero, loop
+    jr $ra
+.data
+message: .asciiz \"hi\"
+";
+
+    fn object() -> (MemorySources, Vec<u8>) {
+        let sources = MemorySources::new().with("objdump.asm", SOURCE);
+        let mut object = Vec::new();
+        let res = Assembler::with_sources(debug_settings(), sources.clone())
+            .assemble("objdump.asm".into(), &mut object);
+        assert!(res.is_ok());
+        (sources, object)
+    }
+
+    #[test]
+    fn object_file() {
+        let (mut sources, object) = object();
+        let settings = ObjdumpSettings {
+            source: true,
+            ..Default::default()
+        };
+        let dump = objdump("objdump.o", &object, &settings, &mut sources).unwrap();
+        assert_eq!(
+            dump,
+            "\
+objdump.o:     file format elf32-bigmips
+
+
+Disassembly of section .text:
+
+00000000 <main>:
+objdump.asm:3
+    la $a0, message
+       0:\t3c040000\tlui   $a0, 0x0
+\t\t\t0: R_MIPS_HI16\t.data
+       4:\t24840000\taddiu $a0, $a0, 0
+\t\t\t4: R_MIPS_LO16\t.data
+objdump.asm:4
+    jal print
+       8:\t0c000000\tjal   main
+\t\t\t8: R_MIPS_26\tprint
+
+0000000c <loop>:
+objdump.asm:6
+    beq $a0, $zero, loop
+       c:\t1080ffff\tbeqz  $a0, loop
+objdump.asm:7
+    jr $ra
+      10:\t03e00008\tjr    $ra
+"
+        );
+
+        // without line info or sources
+        let dump = objdump(
+            "objdump.o",
+            &object,
+            &Default::default(),
+            &mut MemorySources::new(),
+        )
+        .unwrap();
+        assert!(!dump.contains("objdump.asm"));
+        assert!(dump.contains("\n       c:\t1080ffff\tbeqz  $a0, loop\n"));
+    }
+
+    #[test]
+    fn executable() {
+        let (mut sources, object) = object();
+        let start = crate::assembler::assemble_source(
+            "start.asm",
+            ".globl _start\n.globl print\n_start: jal main\nprint: jr $ra\n",
+        )
+        .ok()
+        .unwrap();
+        let mut linker = Linker::new(LinkerSettings {
+            text_base: 0x0040_0000,
+            ..Default::default()
+        });
+        linker.add_object("start.o", &start).unwrap();
+        linker.add_object("objdump.o", &object).unwrap();
+        let elf = linker.link().unwrap().to_elf();
+
+        let settings = ObjdumpSettings {
+            source: true,
+            ..Default::default()
+        };
+        let dump = objdump("a.elf", &elf, &settings, &mut sources).unwrap();
+        // the call is resolved and the source of the second object is found by address
+        assert!(
+            dump.contains("\n  400000:\t0c100002\tjal   main\n"),
+            "{}",
+            dump
+        );
+        // the halves of `la` are folded once they are relocated
+        assert!(dump.contains(
+            "\n00400008 <main>:\nobjdump.asm:3\n    la $a0, message\n  \
+             400008:\t3c040040\tla    $a0, message\n  40000c:\t24841000\n"
+        ));
+        assert!(dump.contains("\n  400010:\t0c100001\tjal   print\n"));
+        assert!(!dump.contains("R_MIPS"));
+    }
+
+    #[test]
+    fn little_endian() {
+        let mut writer = Elf32Writer::new(
+            ElfEndian::LittleEndian,
+            ElfType::ET_EXEC,
+            ElfMachine::EM_MIPS,
+        );
+        let enc = writer.encoder();
+        let mut text = WriterSection::new(
+            ".text",
+            SectionType::SHT_PROGBITS,
+            SectionFlags::SHF_ALLOC | SectionFlags::SHF_EXECINSTR,
+        );
+        text.addr = 0x1000;
+        for word in [0x0800_0401u32, 0x0000_000C, 0xAB] {
+            enc.u32(&mut text.data, word);
+        }
+        text.data.pop();
+        writer.add_section(text);
+
+        let mut strtab = StringTable::new();
+        let mut symtab = Vec::new();
+        Elf32Symbol::default().write(enc, &mut symtab);
+        Elf32Symbol {
+            name: strtab.add("start"),
+            value: 0x1004,
+            shndx: 1,
+            ..Default::default()
+        }
+        .write(enc, &mut symtab);
+        let mut section =
+            WriterSection::new(".symtab", SectionType::SHT_SYMTAB, SectionFlags::empty());
+        section.link = 3;
+        section.data = symtab;
+        writer.add_section(section);
+        let mut section =
+            WriterSection::new(".strtab", SectionType::SHT_STRTAB, SectionFlags::empty());
+        section.data = strtab.data().to_vec();
+        writer.add_section(section);
+
+        let dump = objdump(
+            "le.elf",
+            &writer.write(),
+            &Default::default(),
+            &mut MemorySources::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            dump,
+            "\
+le.elf:     file format elf32-littlemips
+
+
+Disassembly of section .text:
+
+00001000 <.text>:
+    1000:\t08000401\tj     start
+
+00001004 <start>:
+    1004:\t0000000c\tsyscall
+    1008:\tab 00 00
+"
+        );
+    }
+
+    #[test]
+    fn invalid_files() {
+        let mut sources = MemorySources::new();
+        let dump = |data: &[u8], sources: &mut MemorySources| {
+            objdump("x", data, &Default::default(), sources).unwrap_err()
+        };
+        assert_eq!(dump(b"not an elf", &mut sources), "not an ELF file");
+        let (_, mut object) = object();
+        // e_machine
+        object[19] = 3;
+        assert_eq!(dump(&object, &mut sources), "not a MIPS file (machine 3)");
+    }
+}
@@ -0,0 +1,230 @@
+//! Folds instructions back into the pseudo instructions they were most likely written as
+//!
+//! Only what assembles back into the same instructions is folded, i.e. `ori $t0, $zero, 5`
+//! stays as it is since `li $t0, 5` is assembled as `addiu`
+
+use super::simple::{
+    decode, format_word, DisassemblerSettings, Instruction,
+    Operand::{self, *},
+    Symbols,
+};
+
+/// A line of disassembly, one instruction or the pseudo instruction for several of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u32,
+    /// How many words the line stands for
+    pub words: usize,
+    pub text: String,
+}
+
+const ZERO: Operand = Register(0);
+
+/// The pseudo instruction `instruction` on its own was written as
+fn fold(instruction: Instruction) -> Instruction {
+    let pseudo = |mnemonic, operands: &[Operand]| Instruction {
+        mnemonic,
+        operands: operands.to_vec(),
+    };
+    match (instruction.mnemonic, instruction.operands.as_slice()) {
+        ("addu", [rd, ZERO, rs]) => pseudo("move", &[*rd, *rs]),
+        ("addiu", [rt, ZERO, imm]) => pseudo("li", &[*rt, *imm]),
+        // smaller values are loaded with addiu
+        ("ori", [rt, ZERO, Hex(imm)]) if *imm >= 0x8000 => pseudo("li", &[*rt, Hex(*imm)]),
+        ("nor", [rd, rs, ZERO]) => pseudo("not", &[*rd, *rs]),
+        ("sub", [rd, ZERO, rs]) => pseudo("neg", &[*rd, *rs]),
+        ("subu", [rd, ZERO, rs]) => pseudo("negu", &[*rd, *rs]),
+        ("beq", [ZERO, ZERO, target]) => pseudo("b", &[*target]),
+        ("beq", [rs, ZERO, target]) => pseudo("beqz", &[*rs, *target]),
+        ("bne", [rs, ZERO, target]) => pseudo("bnez", &[*rs, *target]),
+        _ => instruction,
+    }
+}
+
+/// The pseudo instruction that loads a register in halves with `first` and `second`
+fn fold_pair(first: &Instruction, second: &Instruction) -> Option<Instruction> {
+    let ("lui", [rt, Hex(hi)]) = (first.mnemonic, first.operands.as_slice()) else {
+        return None;
+    };
+    let (mnemonic, value) = match (second.mnemonic, second.operands.as_slice()) {
+        // the low half of `la` is sign extended
+        ("addiu", [rd, rs, Decimal(lo)]) if rd == rt && rs == rt => {
+            ("la", Target((hi << 16).wrapping_add(*lo as u32)))
+        }
+        // `li` of a value that doesn't fit in 16 bits and has a low half
+        ("ori", [rd, rs, Hex(lo)]) if rd == rt && rs == rt && *lo != 0 => {
+            let value = hi << 16 | lo;
+            if (i16::MIN as i32..=i16::MAX as i32).contains(&(value as i32)) {
+                return None;
+            }
+            ("li", Hex(value))
+        }
+        _ => return None,
+    };
+    Some(Instruction {
+        mnemonic,
+        operands: vec![*rt, value],
+    })
+}
+
+/// Disassembles the instructions `words` which start at `add`, targets found in `symbols` are
+/// printed by name
+///
+/// Instructions are only folded together if there is no symbol between them since something
+/// might jump there
+pub fn disassemble_block(
+    words: &[u32],
+    add: u32,
+    symbols: Option<&Symbols>,
+    settings: &DisassemblerSettings,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut index = 0;
+    while index < words.len() {
+        let address = add.wrapping_add(index as u32 * 4);
+        let Some(mut instruction) = decode(words[index], address) else {
+            lines.push(Line {
+                address,
+                words: 1,
+                text: format_word(words[index], settings),
+            });
+            index += 1;
+            continue;
+        };
+
+        let mut count = 1;
+        if settings.pseudo {
+            let next = address.wrapping_add(4);
+            let labeled = symbols.is_some_and(|symbols| symbols.contains_key(&next));
+            let pair = words
+                .get(index + 1)
+                .filter(|_| !labeled)
+                .and_then(|word| decode(*word, next))
+                .and_then(|second| fold_pair(&instruction, &second));
+            match pair {
+                Some(pair) => (instruction, count) = (pair, 2),
+                None => instruction = fold(instruction),
+            }
+        }
+        lines.push(Line {
+            address,
+            words: count,
+            text: instruction.format(symbols, settings),
+        });
+        index += count;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{assemble_source, debug::tests::sections},
+        disassembler::simple::Syntax,
+    };
+
+    fn text_words(source: &str) -> Vec<u32> {
+        let object = assemble_source("pseudo.asm", source).ok().unwrap();
+        let (_, text) = sections(&object)
+            .into_iter()
+            .find(|(name, _)| name == ".text")
+            .unwrap();
+        text.chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    const SOURCE: &str = "\
+move $a0, $a1
+li $t0, -5
+li $t0, 0x8000
+li $t0, 0x12345678
+li $t0, 0x12340000
+la $a0, 0x12348000
+not $t0, $t1
+neg $t0, $t1
+negu $t0, $t1
+loop: beqz $a0, loop
+bnez $a0, loop
+b loop
+nop
+ori $t0, $zero, 5
+addu $a0, $a1, $zero
+lui $t0, 0xFFFF
+ori $t0, $t0, 0x8000
+.word 0xFC000000
+";
+
+    #[test]
+    fn folding() {
+        let words = text_words(SOURCE);
+        let lines = disassemble_block(&words, 0, None, &Default::default());
+        let texts: Vec<_> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "move  $a0, $a1",
+                "li    $t0, -5",
+                "li    $t0, 0x8000",
+                "li    $t0, 0x12345678",
+                "lui   $t0, 0x1234",
+                "la    $a0, 0x12348000",
+                "not   $t0, $t1",
+                "neg   $t0, $t1",
+                "negu  $t0, $t1",
+                "beqz  $a0, 0x2c",
+                "bnez  $a0, 0x2c",
+                "b     0x2c",
+                "nop",
+                "ori   $t0, $zero, 0x5",
+                "addu  $a0, $a1, $zero",
+                "lui   $t0, 0xffff",
+                "ori   $t0, $t0, 0x8000",
+                ".word 0xfc000000",
+            ]
+        );
+        assert_eq!(
+            lines.iter().map(|line| line.words).sum::<usize>(),
+            words.len()
+        );
+        assert_eq!((lines[3].address, lines[3].words), (0xC, 2));
+
+        // assembles back into the same instructions
+        let mut source = texts.join("\n");
+        source.push('\n');
+        assert_eq!(text_words(&source), words);
+    }
+
+    #[test]
+    fn labels_and_syntax() {
+        let words = text_words("la $a0, 0x10010004\nloop: b loop\n");
+        let symbols = Symbols::from([(0x10010004, "message".to_owned()), (8, "loop".to_owned())]);
+        let text = |words: &[u32], settings| {
+            let lines = disassemble_block(words, 0, Some(&symbols), &settings);
+            lines.into_iter().map(|line| line.text).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            text(&words, DisassemblerSettings::default()),
+            ["la    $a0, message", "b     loop"]
+        );
+        let gnu = DisassemblerSettings {
+            syntax: Syntax::Gnu,
+            ..Default::default()
+        };
+        assert_eq!(
+            text(&words, gnu),
+            ["la\ta0,10010004 <message>", "b\t8 <loop>"]
+        );
+        assert_eq!(
+            text(&words, DisassemblerSettings::RAW),
+            ["lui   $4, 0x1001", "addiu $4, $4, 4", "beq   $0, $0, loop"]
+        );
+
+        // something could jump to the second half so it isn't folded
+        let symbols = Symbols::from([(4, "half".to_owned())]);
+        let lines = disassemble_block(&words[..2], 0, Some(&symbols), &Default::default());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].text, "addiu $a0, $a0, 4");
+    }
+}
@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+
+/// Names of addresses, branch and jump targets that have one are printed by name
+pub type Symbols = HashMap<u32, String>;
+
 pub fn fmt_reg(reg: usize, use_names: bool) -> &'static str {
     if use_names {
         nammed_regs(reg)
@@ -22,361 +27,446 @@ pub fn numbered_regs(reg: usize) -> &'static str {
     ][reg]
 }
 
+//------------------------------------------------------------------------
+
+/// How instructions are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Syntax {
+    /// `addu  $a0, $zero, $a1` and `j     main`, what the assembler (and MARS) accepts
+    #[default]
+    Mars,
+    /// `addu\ta0,zero,a1` and `j\t400010 <main>` like GNU objdump
+    Gnu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisassemblerSettings {
+    /// Fold instructions back into the pseudo instructions they were most likely written as
+    /// (`move`, `li`, `la`, `b`, ...)
+    pub pseudo: bool,
+    /// `$a0` instead of `$4`
+    pub register_names: bool,
+    pub syntax: Syntax,
+}
+
+impl DisassemblerSettings {
+    /// Every instruction as it is encoded with numbered registers
+    pub const RAW: Self = Self {
+        pseudo: false,
+        register_names: false,
+        syntax: Syntax::Mars,
+    };
+}
+
+impl Default for DisassemblerSettings {
+    fn default() -> Self {
+        Self {
+            pseudo: true,
+            register_names: true,
+            syntax: Syntax::Mars,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u32),
+    /// Printed in decimal
+    Decimal(i32),
+    /// Printed in hex
+    Hex(u32),
+    /// The absolute address of a branch or jump
+    Target(u32),
+    /// `offset($base)`
+    Memory(i32, u32),
+}
+
+/// A decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    fn new(mnemonic: &'static str, operands: impl Into<Vec<Operand>>) -> Self {
+        Self {
+            mnemonic,
+            operands: operands.into(),
+        }
+    }
+
+    /// The instruction as text, targets found in `symbols` are printed by name
+    pub fn format(&self, symbols: Option<&Symbols>, settings: &DisassemblerSettings) -> String {
+        let gnu = settings.syntax == Syntax::Gnu;
+        let reg = |reg: u32| {
+            let name = fmt_reg(reg as usize, settings.register_names);
+            // GNU objdump leaves out the `$`
+            if gnu {
+                &name[1..]
+            } else {
+                name
+            }
+        };
+        let operands: Vec<String> = self
+            .operands
+            .iter()
+            .map(|operand| match *operand {
+                Operand::Register(r) => reg(r).to_owned(),
+                Operand::Decimal(value) => value.to_string(),
+                Operand::Hex(value) => format!("{:#x}", value),
+                Operand::Target(target) => {
+                    match (symbols.and_then(|symbols| symbols.get(&target)), gnu) {
+                        (Some(name), false) => name.clone(),
+                        (None, false) => format!("{:#x}", target),
+                        (Some(name), true) => format!("{:x} <{}>", target, name),
+                        (None, true) => format!("{:x}", target),
+                    }
+                }
+                Operand::Memory(offset, base) => format!("{}({})", offset, reg(base)),
+            })
+            .collect();
+        match (operands.is_empty(), gnu) {
+            (true, _) => self.mnemonic.to_owned(),
+            (false, false) => format!("{:<5} {}", self.mnemonic, operands.join(", ")),
+            (false, true) => format!("{}\t{}", self.mnemonic, operands.join(",")),
+        }
+    }
+}
+
+/// A word that isn't an instruction, as data
+pub fn format_word(opcode: u32, settings: &DisassemblerSettings) -> String {
+    match settings.syntax {
+        Syntax::Mars => format!(".word {:#010x}", opcode),
+        Syntax::Gnu => format!(".word\t{:#010x}", opcode),
+    }
+}
+
+/// Disassembles the instruction `opcode` at `add`, branch and jump targets are printed as
+/// absolute addresses
 pub fn disassemble(opcode: u32, add: u32) -> String {
+    disassemble_with_symbols(opcode, add, None)
+}
+
+/// Like `disassemble` but targets found in `symbols` are printed by name. Words that aren't
+/// instructions are printed as `.word`, so the output can always be assembled again
+///
+/// Apart from `beq $0, $0` being printed as `b` nothing is folded, see `pseudo` for that
+pub fn disassemble_with_symbols(opcode: u32, add: u32, symbols: Option<&Symbols>) -> String {
+    let settings = DisassemblerSettings::RAW;
+    let Some(instruction) = decode(opcode, add) else {
+        return format_word(opcode, &settings);
+    };
+    let instruction = match instruction.operands.as_slice() {
+        [Operand::Register(0), Operand::Register(0), target] if instruction.mnemonic == "beq" => {
+            Instruction::new("b", [*target])
+        }
+        _ => instruction,
+    };
+    instruction.format(symbols, &settings)
+}
+
+/// The instruction `opcode` at `add` as it is encoded, `None` if the emulator can't execute it
+pub fn decode(opcode: u32, add: u32) -> Option<Instruction> {
     if opcode == 0 {
-        return "nop".into();
+        return Some(Instruction::new("nop", []));
     }
 
     match opcode >> 26 {
-        0b000000 => register_encoding(opcode, add),
+        0b000000 => register_encoding(opcode),
         _ => immediate_encoding(opcode, add),
     }
 }
 
-fn register_encoding(opcode: u32, _add: u32) -> String {
-    let s = (opcode >> 21) & 0b11111;
-    let t = (opcode >> 16) & 0b11111;
-    let d = (opcode >> 11) & 0b11111;
-    let a = (opcode >> 6) & 0b11111;
+fn register_encoding(opcode: u32) -> Option<Instruction> {
+    use Operand::*;
+    let s = Register((opcode >> 21) & 0b11111);
+    let t = Register((opcode >> 16) & 0b11111);
+    let d = Register((opcode >> 11) & 0b11111);
+    let a = Decimal(((opcode >> 6) & 0b11111) as i32);
     let f = opcode & 0b111111;
     let tc = (opcode >> 6) & 0b1111111111;
     let tce = (opcode >> 6) & 0b11111111111111111111;
 
-    match f {
+    let (mnemonic, operands) = match f {
+        0b001111 => ("sync", vec![]),
+
         //arithmatic
-        0b100000 =>
-        //add
-        {
-            format!("add   ${}, ${}, ${}", d, s, t)
-        }
-        0b100001 =>
-        //addu
-        {
-            format!("addu  ${}, ${}, ${}", d, s, t)
-        }
-        0b100100 =>
-        //and
-        {
-            format!("and   ${}, ${}, ${}", d, s, t)
-        }
-        0b011010 =>
-        //div
-        {
-            format!("div   ${}, ${}", s, t)
-        }
-        0b011011 =>
-        //divu
-        {
-            format!("divu  ${}, ${}", s, t)
-        }
-        0b011000 =>
-        //mult
-        {
-            format!("mult  ${}, ${}", s, t)
-        }
-        0b011001 =>
-        //multu
-        {
-            format!("multu ${}, ${}", s, t)
-        }
-        0b100111 =>
-        //nor
-        {
-            format!("nor   ${}, ${}, ${}", d, s, t)
-        }
-        0b100101 =>
-        //or
-        {
-            format!("or    ${}, ${}, ${}", d, s, t)
-        }
-        0b000000 =>
-        //sll
-        {
-            format!("sll   ${}, ${}, {}", d, t, a)
-        }
-        0b000100 =>
-        //sllv
-        {
-            format!("sllv  ${}, ${}, ${}", d, t, s)
-        }
-        0b000011 =>
-        //sra
-        {
-            format!("sra   ${}, ${}, {}", d, t, a)
-        }
-        0b000111 =>
-        //srav
-        {
-            format!("srav  ${}, ${}, ${}", d, t, s)
-        }
-        0b000010 =>
-        //srl
-        {
-            format!("srl   ${}, ${}, {}", d, t, a)
-        }
-        0b000110 =>
-        //srlv
-        {
-            format!("srlv  ${}, ${}, ${}", d, t, s)
-        }
-        0b100010 =>
-        //sub
-        {
-            format!("sub   ${}, ${}, ${}", d, s, t)
-        }
-        0b100011 =>
-        //subu
-        {
-            format!("subu  ${}, ${}, ${}", d, s, t)
-        }
-        0b100110 =>
-        //xor
-        {
-            format!("xor   ${}, ${}, ${}", d, s, t)
-        }
+        0b100000 => ("add", vec![d, s, t]),
+        0b100001 => ("addu", vec![d, s, t]),
+        0b100100 => ("and", vec![d, s, t]),
+        0b011010 => ("div", vec![s, t]),
+        0b011011 => ("divu", vec![s, t]),
+        0b011000 => ("mult", vec![s, t]),
+        0b011001 => ("multu", vec![s, t]),
+        0b100111 => ("nor", vec![d, s, t]),
+        0b100101 => ("or", vec![d, s, t]),
+        0b000000 => ("sll", vec![d, t, a]),
+        0b000100 => ("sllv", vec![d, t, s]),
+        0b000011 => ("sra", vec![d, t, a]),
+        0b000111 => ("srav", vec![d, t, s]),
+        0b000010 => ("srl", vec![d, t, a]),
+        0b000110 => ("srlv", vec![d, t, s]),
+        0b100010 => ("sub", vec![d, s, t]),
+        0b100011 => ("subu", vec![d, s, t]),
+        0b100110 => ("xor", vec![d, s, t]),
 
         //comparasin
-        0b101010 =>
-        //slt
-        {
-            format!("slt   ${}, ${}, ${}", d, s, t)
-        }
-        0b101011 =>
-        //sltu
-        {
-            format!("sltu  ${}, ${}, ${}", d, s, t)
-        }
+        0b101010 => ("slt", vec![d, s, t]),
+        0b101011 => ("sltu", vec![d, s, t]),
 
         //jump
-        0b001001 =>
-        //jalr
-        {
-            format!("jalr  ${}", s)
-        }
-        0b001000 =>
-        //jr
-        {
-            format!("jr    ${}", s)
-        }
+        // `$ra` is the default return register
+        0b001001 if d == Register(31) => ("jalr", vec![s]),
+        0b001001 => ("jalr", vec![d, s]),
+        0b001000 => ("jr", vec![s]),
 
         //system
-        0b001100 => {
-            if tce != 0 {
-                format!("syscall  {:#x}", tce)
-            } else {
-                "syscall".into()
-            }
-        }
-        0b001101 => {
-            if tce != 0 {
-                format!("break    {:#x}", tce)
+        0b001100 if tce != 0 => ("syscall", vec![Hex(tce)]),
+        0b001100 => ("syscall", vec![]),
+        0b001101 if tce != 0 => ("break", vec![Hex(tce)]),
+        0b001101 => ("break", vec![]),
+
+        //conditional traps
+        0b110100 | 0b110000 | 0b110001 | 0b110010 | 0b110011 | 0b110110 => {
+            let mnemonic = match f {
+                0b110100 => "teq",
+                0b110000 => "tge",
+                0b110001 => "tgeu",
+                0b110010 => "tlt",
+                0b110011 => "tltu",
+                _ => "tne",
+            };
+            if tc != 0 {
+                (mnemonic, vec![s, t, Hex(tc)])
             } else {
-                "break".into()
+                (mnemonic, vec![s, t])
             }
         }
-        //conditional traps
-        0b110100 =>
-        //TEQ
-        {
-            format!("teq   ${}, ${}, {:#X}", s, t, tc)
-        }
-        0b110000 =>
-        //TGE
-        {
-            format!("tge   ${}, ${}, {:#X}", s, t, tc)
-        }
-        0b110001 =>
-        //TGEU
-        {
-            format!("tgeu  ${}, ${}, {:#X}", s, t, tc)
-        }
-        0b110010 =>
-        //TIT
-        {
-            format!("tit   ${}, ${}, {:#X}", s, t, tc)
-        }
-        0b110011 =>
-        //TITU
-        {
-            format!("titu  ${}, ${}, {:#X}", s, t, tc)
-        }
-        0b110110 =>
-        //TNE
-        {
-            format!("tne  ${}, ${}, {:#X}", s, t, tc)
-        }
 
         //dataMovement
-        0b010000 =>
-        //mfhi
-        {
-            format!("mfhi  ${}", d)
-        }
-        0b010010 =>
-        //mflo
-        {
-            format!("mflo  ${}", d)
-        }
-        0b010001 =>
-        //mthi
-        {
-            format!("mthi  ${}", s)
-        }
-        0b010011 =>
-        //mtlo
-        {
-            format!("mtlo  ${}", s)
-        }
-        _ => format!("db {:#08x}", opcode),
-    }
+        0b010000 => ("mfhi", vec![d]),
+        0b010010 => ("mflo", vec![d]),
+        0b010001 => ("mthi", vec![s]),
+        0b010011 => ("mtlo", vec![s]),
+        _ => return None,
+    };
+    Some(Instruction::new(mnemonic, operands))
 }
-fn immediate_encoding(opcode: u32, add: u32) -> String {
+
+fn immediate_encoding(opcode: u32, add: u32) -> Option<Instruction> {
+    use Operand::*;
     let o = (opcode >> 26) & 0b111111;
-    let s = (opcode >> 21) & 0b11111;
-    let t = (opcode >> 16) & 0b11111;
+    let s = Register((opcode >> 21) & 0b11111);
+    let t = Register((opcode >> 16) & 0b11111);
     let sei = ((opcode as i32) << 16) >> 16;
-    let zei = opcode & 0xFFFF;
-    let b_arr = add.wrapping_add((sei as u32) << 2).wrapping_add(4);
+    let zei = Hex(opcode & 0xFFFF);
+    // branches are relative to the instruction after the branch
+    let b_arr = Target(add.wrapping_add((sei as u32) << 2).wrapping_add(4));
+    let mem = Memory(sei, (opcode >> 21) & 0b11111);
 
-    match o {
+    let (mnemonic, operands) = match o {
         //arthmetic
-        0b001000 =>
-        //addi
-        {
-            format!("addi  ${}, ${}, {}", t, s, sei)
-        }
-        0b001001 =>
-        //addiu
-        {
-            format!("addiu ${}, ${}, {}", t, s, zei)
-        }
-        0b001100 =>
-        //andi
-        {
-            format!("andi  ${}, ${}, {:#x}", t, s, zei)
-        }
-        0b001101 =>
-        //ori
-        {
-            format!("ori   ${}, ${}, {:#x}", t, s, zei)
-        }
-        0b001110 =>
-        //xori
-        {
-            format!("xori  ${}, ${}, {:#x}", t, s, zei)
-        }
+        0b001000 => ("addi", vec![t, s, Decimal(sei)]),
+        0b001001 => ("addiu", vec![t, s, Decimal(sei)]),
+        0b001100 => ("andi", vec![t, s, zei]),
+        0b001101 => ("ori", vec![t, s, zei]),
+        0b001110 => ("xori", vec![t, s, zei]),
 
         //constant manupulating inctructions
-        0b001111 =>
-        //lhi
-        {
-            format!("lui   ${}, {:#x}", t, zei)
-        }
+        0b001111 => ("lui", vec![t, zei]),
 
         //comparison instructions
-        0b001010 =>
-        //slti
-        {
-            format!("slti  ${}, ${}, {}", t, s, sei)
-        }
-        0b001011 =>
-        //sltu
-        {
-            format!("sltu  ${}, ${}, {}", t, s, sei)
-        }
+        0b001010 => ("slti", vec![t, s, Decimal(sei)]),
+        0b001011 => ("sltiu", vec![t, s, Decimal(sei)]),
 
         //branch instructions
-        0b000100 => {
-            //beq
-            if t == 0 && s == 0 {
-                format!("b     {:#x}", b_arr)
-            } else {
-                format!("beq   ${}, ${}, {:#x}", t, s, b_arr)
-            }
-        }
-        0b000001 => match t {
-            0b00001 => format!("bgez  ${}, ${}, {:#x}", t, s, b_arr),
-            0b00000 => format!("bltz  ${}, ${}, {:#x}", t, s, b_arr),
-            _ => format!("db {:#08x}", opcode),
+        0b000100 => ("beq", vec![s, t, b_arr]),
+        0b000001 => match (opcode >> 16) & 0b11111 {
+            0b00001 => ("bgez", vec![s, b_arr]),
+            0b00000 => ("bltz", vec![s, b_arr]),
+            _ => return None,
         },
-        0b000111 =>
-        //bgtz
-        {
-            format!("bgtz  ${}, ${}, {:#x}", t, s, b_arr)
-        }
-        0b000110 =>
-        //blez
-        {
-            format!("blez  ${}, ${}, {:#x}", t, s, b_arr)
-        }
-        0b000101 =>
-        //bne
-        {
-            format!("bne   ${}, ${}, {:#x}", t, s, b_arr)
-        }
+        0b000111 => ("bgtz", vec![s, b_arr]),
+        0b000110 => ("blez", vec![s, b_arr]),
+        0b000101 => ("bne", vec![s, t, b_arr]),
 
         //load unaliged instructions
-        0b100010 => format!("swl   ${}, {}(${})", t, sei, s),
-        0b100110 => format!("swr   ${}, {}(${})", t, sei, s),
+        0b100010 => ("lwl", vec![t, mem]),
+        0b100110 => ("lwr", vec![t, mem]),
 
         //save unaliged instructions
-        0b101010 => format!("swl   ${}, {}(${})", t, sei, s),
-        0b101110 => format!("swr   ${}, {}(${})", t, sei, s),
+        0b101010 => ("swl", vec![t, mem]),
+        0b101110 => ("swr", vec![t, mem]),
 
         //load instrictions
-        0b100000 =>
-        //lb
-        {
-            format!("lb    ${}, {}(${})", t, sei, s)
-        }
-        0b100100 =>
-        //lbu
-        {
-            format!("lbu   ${}, {}(${})", t, sei, s)
-        }
-        0b100001 =>
-        //lh
-        {
-            format!("lh    ${}, {}(${})", t, sei, s)
-        }
-        0b100101 =>
-        //lhu
-        {
-            format!("lhu   ${}, {}(${})", t, sei, s)
-        }
-        0b100011 =>
-        //lw
-        {
-            format!("lw    ${}, {}(${})", t, sei, s)
-        }
+        0b100000 => ("lb", vec![t, mem]),
+        0b100100 => ("lbu", vec![t, mem]),
+        0b100001 => ("lh", vec![t, mem]),
+        0b100101 => ("lhu", vec![t, mem]),
+        0b100011 => ("lw", vec![t, mem]),
+        0b110000 => ("ll", vec![t, mem]),
 
         //store instrictions
-        0b101000 =>
-        //sb
-        {
-            format!("sb    ${}, {}(${})", t, sei, s)
-        }
-        0b101001 =>
-        //sh
-        {
-            format!("sh    ${}, {}(${})", t, sei, s)
-        }
-        0b101011 =>
-        //sw
-        {
-            format!("sw    ${}, {}(${})", t, sei, s)
-        }
-        _ => jump_encoding(opcode, add),
-    }
+        0b101000 => ("sb", vec![t, mem]),
+        0b101001 => ("sh", vec![t, mem]),
+        0b101011 => ("sw", vec![t, mem]),
+        0b111000 => ("sc", vec![t, mem]),
+        _ => return jump_encoding(opcode, add),
+    };
+    Some(Instruction::new(mnemonic, operands))
 }
-fn jump_encoding(opcode: u32, add: u32) -> String {
+
+fn jump_encoding(opcode: u32, add: u32) -> Option<Instruction> {
     let o = (opcode >> 26) & 0b111111;
     let i = (opcode << 6) >> 6;
-    let j_add = add & 0b11110000000000000000000000000000 | (i << 2);
+    // the upper 4 bits come from the address of the instruction after the jump
+    let j_add =
+        Operand::Target(add.wrapping_add(4) & 0b11110000000000000000000000000000 | (i << 2));
 
     match o {
-        0b000010 => format!("j        {:#x}", j_add),
-        0b000011 => format!("jal      {:#x}", j_add),
-        _ => format!("db {:#08x}", opcode),
+        0b000010 => Some(Instruction::new("j", [j_add])),
+        0b000011 => Some(Instruction::new("jal", [j_add])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{
+            assemble_source,
+            debug::tests::sections,
+            instruction::{lookup, Format},
+        },
+        disassembler::pseudo::disassemble_block,
+    };
+
+    /// Every instruction the emulator executes
+    const MNEMONICS: &[&str] = &[
+        "nop", "sync", "add", "addu", "sub", "subu", "and", "or", "xor", "nor", "slt", "sltu",
+        "sll", "srl", "sra", "sllv", "srlv", "srav", "mult", "multu", "div", "divu", "mfhi",
+        "mthi", "mflo", "mtlo", "jr", "jalr", "j", "jal", "syscall", "break", "tge", "tgeu", "tlt",
+        "tltu", "teq", "tne", "addi", "addiu", "slti", "sltiu", "andi", "ori", "xori", "lui",
+        "bltz", "bgez", "beq", "bne", "blez", "bgtz", "lb", "lh", "lwl", "lw", "lbu", "lhu", "lwr",
+        "sb", "sh", "swl", "sw", "swr", "ll", "sc",
+    ];
+
+    /// xorshift, the same sequence every run so failures can be reproduced
+    struct Rng(u32);
+
+    impl Rng {
+        fn bits(&mut self, bits: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 & ((1u64 << bits) - 1) as u32
+        }
+    }
+
+    /// A random instruction at `addr` with every field the format doesn't use zeroed
+    fn random_instruction(rng: &mut Rng, mnemonic: &str, addr: u32) -> u32 {
+        use Format::*;
+        let info = lookup(mnemonic).unwrap();
+        let (s, t, d) = (rng.bits(5) << 21, rng.bits(5) << 16, rng.bits(5) << 11);
+        let imm = rng.bits(16);
+        // branches before address 0 can't be written as an address, they go forward instead
+        let branch = if (imm as i16 as i32) < -((addr as i32 + 4) >> 2) {
+            imm & 0x7FFF
+        } else {
+            imm
+        };
+        info.base
+            | match info.format {
+                None => 0,
+                RdRsRt | RdRtRs => d | s | t,
+                RdRtShamt => d | t | (rng.bits(5) << 6),
+                RsRt => s | t,
+                Rs => s,
+                Rd => d,
+                Jalr => d | s,
+                Code => rng.bits(20) << 6,
+                Trap => s | t | (rng.bits(10) << 6),
+                RtRsSigned | RtRsUnsigned | RtMemory => t | s | imm,
+                RtImm => t | imm,
+                RsRtBranch => s | t | branch,
+                RsBranch => s | branch,
+                Jump => rng.bits(26),
+            }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng(0x1234_5678);
+        let mut words = Vec::new();
+        for _ in 0..16 {
+            for mnemonic in MNEMONICS {
+                let addr = words.len() as u32 * 4;
+                words.push(random_instruction(&mut rng, mnemonic, addr));
+            }
+        }
+        // pseudo instructions and register names assemble back into the same words as well
+        for settings in [DisassemblerSettings::RAW, DisassemblerSettings::default()] {
+            let lines = disassemble_block(&words, 0, None, &settings);
+            let source: String = lines.iter().map(|line| line.text.clone() + "\n").collect();
+            let object = match assemble_source("round_trip.asm", source) {
+                Ok(object) => object,
+                Err(report) => panic!("{}", report),
+            };
+            let (_, text) = sections(&object)
+                .into_iter()
+                .find(|(name, _)| name == ".text")
+                .unwrap();
+            let assembled: Vec<_> = text
+                .chunks(4)
+                .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+                .collect();
+            for line in &lines {
+                let start = line.address as usize / 4;
+                let range = start..start + line.words;
+                assert_eq!(
+                    words.get(range.clone()),
+                    assembled.get(range),
+                    "{}",
+                    line.text
+                );
+            }
+            assert_eq!(assembled.len(), words.len());
+        }
+        // raw blocks are the same as every word on its own (none of them is `beq $0, $0`)
+        let raw = disassemble_block(&words, 0, None, &DisassemblerSettings::RAW);
+        for (index, line) in raw.iter().enumerate() {
+            assert_eq!(line.text, disassemble(words[index], index as u32 * 4));
+        }
+    }
+
+    #[test]
+    fn targets() {
+        // beq $4, $5, -2 instructions
+        assert_eq!(disassemble(0x1085FFFE, 0x400010), "beq   $4, $5, 0x40000c");
+        assert_eq!(disassemble(0x04810003, 0x400000), "bgez  $4, 0x400010");
+        assert_eq!(disassemble(0x1000FFFF, 0x400000), "b     0x400000");
+        // the region comes from PC + 4, PC is incremented before the jump is executed
+        assert_eq!(disassemble(0x0C000004, 0x0FFFFFFC), "jal   0x10000010");
+        assert_eq!(disassemble(0x08100004, 0x400000), "j     0x400010");
+
+        let symbols = Symbols::from([(0x400010, "main".to_owned())]);
+        let named = |opcode, add| disassemble_with_symbols(opcode, add, Some(&symbols));
+        assert_eq!(named(0x08100004, 0x400000), "j     main");
+        assert_eq!(named(0x04810003, 0x400000), "bgez  $4, main");
+        assert_eq!(named(0x1085FFFE, 0x400010), "beq   $4, $5, 0x40000c");
+    }
+
+    #[test]
+    fn other_words() {
+        assert_eq!(disassemble(0x0320F809, 0), "jalr  $25");
+        assert_eq!(disassemble(0x03201009, 0), "jalr  $2, $25");
+        assert_eq!(disassemble(0x2084FFFF, 0), "addi  $4, $4, -1");
+        assert_eq!(disassemble(0x2C84FFFF, 0), "sltiu $4, $4, -1");
+        assert_eq!(disassemble(0x00220034, 0), "teq   $1, $2");
+        assert_eq!(disassemble(0x0000000F, 0), "sync");
+        assert_eq!(disassemble(0xFC000000, 0), ".word 0xfc000000");
+        assert_eq!(disassemble(0x041F0000, 0), ".word 0x041f0000");
     }
 }
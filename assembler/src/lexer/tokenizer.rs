@@ -308,9 +308,13 @@ impl Iterator for Tokenizer<'_> {
                         '\n' => {
                             self.state = State::Default;
                         }
+                        // macro arguments (`\name`) and the expansion counter (`\@`)
+                        _ if self.is_curr_ident_continue() => {
+                            self.state = State::IdentifierContinue;
+                        }
                         _ => {
                             self.new_token = self.create_token(format!(
-                                "illegal character after \\ {:?} can only have \\n or \\r",
+                                "illegal character after \\ {:?} can only have \\n, \\r or a macro argument",
                                 self.c
                             ));
                             self.state = State::Default;
@@ -329,7 +333,8 @@ impl Iterator for Tokenizer<'_> {
                         }
                     },
                     State::IdentifierContinue => {
-                        if self.is_curr_ident_continue() {
+                        // macro arguments can be pasted into identifiers (`loop\@`)
+                        if self.is_curr_ident_continue() || self.c == '\\' {
                             self.state = State::IdentifierContinue;
                         } else {
                             self.matching = true;
@@ -337,7 +342,7 @@ impl Iterator for Tokenizer<'_> {
                             let start = ident.chars().next().unwrap();
                             match self.c {
                                 ':' => {
-                                    if self.is_ident_start(start) || start == '.' {
+                                    if self.is_ident_start(start) || start == '.' || start == '\\' {
                                         let ident = self.curr_str();
                                         self.matching = false;
                                         self.new_token = self.create_token(TokenType::Label(ident)); //self.create_token(TokenType::Identifier(ident.to_string()));
@@ -595,9 +600,9 @@ impl Iterator for Tokenizer<'_> {
                             self.state = State::LineComment(true);
                         }
                     },
-                    State::Colon => self.default_reset(true, TokenType::BitwiseXor),
-                    State::ShiftLeft => self.default_reset(true, TokenType::BitwiseXor),
-                    State::ShiftRight => self.default_reset(true, TokenType::BitwiseXor),
+                    State::Colon => self.default_reset(true, TokenType::Colon),
+                    State::ShiftLeft => self.default_reset(true, TokenType::ShiftLeft),
+                    State::ShiftRight => self.default_reset(true, TokenType::ShiftRight),
                     State::GreaterThan => match self.c {
                         '>' => self.state = State::ShiftRight,
                         '=' => self.default_reset(false, TokenType::GreaterThanEq),
@@ -612,15 +617,15 @@ impl Iterator for Tokenizer<'_> {
                         '=' => self.default_reset(false, TokenType::NotEquals),
                         _ => self.default_reset(true, TokenType::LogicalNot),
                     },
-                    State::Plus => self.default_reset(true, TokenType::BitwiseXor),
-                    State::Minus => self.default_reset(true, TokenType::BitwiseXor),
-                    State::Star => self.default_reset(true, TokenType::BitwiseXor),
+                    State::Plus => self.default_reset(true, TokenType::Plus),
+                    State::Minus => self.default_reset(true, TokenType::Minus),
+                    State::Star => self.default_reset(true, TokenType::Star),
                     State::Div => match self.c {
                         '/' => self.state = State::LineComment(false),
                         '*' => self.state = State::BlockComment(0, 0),
                         _ => self.default_reset(true, TokenType::Slash),
                     },
-                    State::Mod => self.default_reset(true, TokenType::BitwiseXor),
+                    State::Mod => self.default_reset(true, TokenType::Percent),
                     State::Xor => self.default_reset(true, TokenType::BitwiseXor),
                     State::Or => match self.c {
                         '|' => self.default_reset(false, TokenType::LogicalOr),
@@ -661,6 +666,10 @@ impl Iterator for Tokenizer<'_> {
                                 self.char_literal = '\r';
                                 self.state = state;
                             }
+                            ('t', 0) => {
+                                self.char_literal = '\t';
+                                self.state = state;
+                            }
                             ('"', 0) => {
                                 self.char_literal = '\"';
                                 self.state = state;
@@ -670,7 +679,7 @@ impl Iterator for Tokenizer<'_> {
                                 self.state = state;
                             }
                             ('0', 0) => {
-                                self.char_literal = '\\';
+                                self.char_literal = '\0';
                                 self.state = state;
                             }
                             ('u', 0) => {
@@ -847,6 +856,12 @@ impl Iterator for Tokenizer<'_> {
                         ('+' | '-', 1) => {
                             self.state = State::NumberLiteral(0);
                         }
+                        // numeric local label (`1:`)
+                        (':', _) if self.numeric_label().is_some() => {
+                            let label = self.numeric_label().unwrap();
+                            self.new_token = self.create_token(TokenType::Label(label));
+                            self.state = State::Default;
+                        }
                         _ => {
                             self.matching = true;
                             self.new_token = self.create_number_token(self.curr_str());
@@ -1069,6 +1084,12 @@ impl<'a> Tokenizer<'a> {
 
     fn create_number_token(&self, mut num: String) -> TokenizerItem {
         let original: String = num.to_string();
+        // references to numeric local labels (`1f` and `1b`)
+        if let Some(digits) = num.strip_suffix(['f', 'b']) {
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                return self.create_token(TokenType::Identifier(num));
+            }
+        }
         let suffixes = [
             "i8", "i16", "i32", "i64", "i128", "u8", "u16", "u32", "u64", "u128", "f32", "f64",
         ];
@@ -1136,14 +1157,20 @@ impl<'a> Tokenizer<'a> {
                 t_type = Result::Err("Cannot have non base 10 floating point literal".into())
             }
             (_, base) => {
-                if base == 10 {
-                    if num.contains('e') || num.contains('E') || num.contains('.') {
-                        parse_to_token!(f32, F32Literal)
-                    } else {
-                        parse_to_token_base!(i32, I32Literal, base)
-                    }
+                if base == 10 && (num.contains('e') || num.contains('E') || num.contains('.')) {
+                    // parsed at full precision so `.double` does not round through f32
+                    parse_to_token!(f64, F64Literal)
                 } else {
-                    parse_to_token_base!(i32, I32Literal, base)
+                    // unsuffixed literals are i32 unless they only fit in a wider type
+                    t_type = i32::from_str_radix(num.as_str(), base)
+                        .map(TokenType::I32Literal)
+                        .or_else(|_| {
+                            u32::from_str_radix(num.as_str(), base).map(TokenType::U32Literal)
+                        })
+                        .or_else(|_| {
+                            i64::from_str_radix(num.as_str(), base).map(TokenType::I64Literal)
+                        })
+                        .map_err(|err| err.to_string());
                 }
             }
         }
@@ -1238,6 +1265,17 @@ impl<'a> Tokenizer<'a> {
             .to_string()
         }
     }
+
+    /// The token so far if it is only digits, without the current character
+    fn numeric_label(&self) -> Option<String> {
+        let digits =
+            &self.bytes[self.start_curr.index_real..self.current.index_real - self.c.len_utf8()];
+        digits
+            .iter()
+            .all(u8::is_ascii_digit)
+            .then(|| String::from_utf8_lossy(digits).to_string())
+    }
+
     fn ntm(&mut self) {
         self.last = self.current;
 
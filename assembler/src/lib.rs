@@ -3,3 +3,4 @@
 pub mod assembler;
 pub mod disassembler;
 pub mod lexer;
+pub mod linker;
@@ -0,0 +1,172 @@
+use elf::{
+    external::{
+        from_bytes, header::ExternalElfHeaderTrait, section::ExternalSectionHeaderTrait,
+        TernaryResult,
+    },
+    internal::{
+        header::header_util::{ElfEndian, ElfMachine, ElfType},
+        section::section_util::{SectionFlags, SectionType},
+    },
+    writer::{Elf32Rel, Elf32Symbol, Encoder},
+};
+
+use super::LinkError;
+
+const ELF32_HEADER_SIZE: usize = 52;
+const ELF32_SECTION_HEADER_SIZE: usize = 40;
+
+//------------------------------------------------------------------------
+
+/// A section of an input object that ends up in the program, either loaded or debug info
+pub(crate) struct InputSection {
+    pub name: String,
+    pub flags: SectionFlags,
+    pub nobits: bool,
+    pub alignment: u32,
+    /// Contents of the section as they are in the object, empty for `SHT_NOBITS`
+    pub data: Vec<u8>,
+    pub size: u32,
+    pub relocations: Vec<Elf32Rel>,
+}
+
+pub(crate) struct InputSymbol {
+    pub name: String,
+    pub symbol: Elf32Symbol,
+}
+
+/// A relocatable object, only what the linker needs is kept
+pub(crate) struct ObjectFile {
+    pub name: String,
+    pub flags: u32,
+    /// Indexed by section header index, `None` for everything that isn't loaded or debug info
+    pub sections: Vec<Option<InputSection>>,
+    pub symbols: Vec<InputSymbol>,
+}
+
+impl ObjectFile {
+    pub fn parse(name: String, data: &[u8]) -> Result<Self, LinkError> {
+        let invalid = |reason: String| LinkError::InvalidObject {
+            object: name.clone(),
+            reason,
+        };
+
+        let elf = match from_bytes(data) {
+            TernaryResult::Ok1(elf) => elf,
+            TernaryResult::Ok2(_) => Err(invalid("64 bit objects are not supported".into()))?,
+            TernaryResult::Err(()) => Err(invalid("not an ELF file".into()))?,
+        };
+        let header = elf.elf_header();
+        if header.endianness() != u8::from(ElfEndian::BigEndian) {
+            Err(invalid("only big endian objects are supported".into()))?
+        }
+        if header.elftype() != u16::from(ElfType::ET_REL) {
+            Err(invalid("not a relocatable object".into()))?
+        }
+        if header.machine() != u16::from(ElfMachine::EM_MIPS) {
+            Err(invalid(format!(
+                "not a MIPS object (machine {})",
+                header.machine()
+            )))?
+        }
+        let headers_end = header.section_header_offset() as usize
+            + header.section_header_entry_num() as usize * ELF32_SECTION_HEADER_SIZE;
+        if data.len() < ELF32_HEADER_SIZE || headers_end > data.len() {
+            Err(invalid("section headers are outside of the file".into()))?
+        }
+        let enc = Encoder {
+            endian: ElfEndian::BigEndian,
+        };
+
+        let count = header.section_header_entry_num() as usize;
+        let mut contents = Vec::with_capacity(count);
+        for index in 0..count {
+            let section = elf.section_header(index).unwrap();
+            if section.sh_type() == u32::from(SectionType::SHT_NOBITS) {
+                contents.push(&data[..0]);
+                continue;
+            }
+            let start = section.offset() as usize;
+            let bytes = start
+                .checked_add(section.size() as usize)
+                .and_then(|end| data.get(start..end))
+                .ok_or_else(|| invalid(format!("section {} is outside of the file", index)))?;
+            contents.push(bytes);
+        }
+        let string = |table: usize, offset: u32| -> Result<String, LinkError> {
+            let bytes = contents
+                .get(table)
+                .and_then(|table| table.get(offset as usize..))
+                .ok_or_else(|| invalid(format!("string table {} is too small", table)))?;
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+        };
+
+        let mut object = ObjectFile {
+            name: name.clone(),
+            flags: header.flags(),
+            sections: Vec::with_capacity(count),
+            symbols: Vec::new(),
+        };
+        for (index, bytes) in contents.iter().enumerate() {
+            let section = elf.section_header(index).unwrap();
+            let flags = SectionFlags::from_bits_truncate(section.flags() as u64);
+            let sh_type = section.sh_type();
+            let name = string(header.shstr_index() as usize, section.name_off())?;
+            let loaded = flags.contains(SectionFlags::SHF_ALLOC)
+                && (sh_type == u32::from(SectionType::SHT_PROGBITS)
+                    || sh_type == u32::from(SectionType::SHT_NOBITS));
+            // debug info isn't loaded but is kept for debuggers
+            let debug = sh_type == u32::from(SectionType::SHT_PROGBITS)
+                && !flags.contains(SectionFlags::SHF_ALLOC)
+                && name.starts_with(".debug_");
+            if !loaded && !debug {
+                object.sections.push(None);
+                continue;
+            }
+            object.sections.push(Some(InputSection {
+                name,
+                flags,
+                nobits: sh_type == u32::from(SectionType::SHT_NOBITS),
+                alignment: section.addralign().max(1),
+                data: bytes.to_vec(),
+                size: section.size(),
+                relocations: Vec::new(),
+            }));
+        }
+
+        for (index, bytes) in contents.iter().enumerate() {
+            let section = elf.section_header(index).unwrap();
+            let sh_type = section.sh_type();
+            if sh_type == u32::from(SectionType::SHT_SYMTAB) {
+                for entry in bytes.chunks_exact(Elf32Symbol::SIZE as usize) {
+                    let symbol = Elf32Symbol::read(enc, entry);
+                    object.symbols.push(InputSymbol {
+                        name: string(section.link() as usize, symbol.name)?,
+                        symbol,
+                    });
+                }
+            } else if sh_type == u32::from(SectionType::SHT_REL) {
+                let target = section.info() as usize;
+                let Some(Some(target)) = object.sections.get_mut(target) else {
+                    // relocations of sections that aren't kept
+                    continue;
+                };
+                for entry in bytes.chunks_exact(Elf32Rel::SIZE as usize) {
+                    let relocation = Elf32Rel::read(enc, entry).map_err(|r_type| {
+                        invalid(format!("unsupported relocation type {}", r_type))
+                    })?;
+                    target.relocations.push(relocation);
+                }
+            } else if sh_type == u32::from(SectionType::SHT_RELA) {
+                Err(invalid("RELA relocations are not supported".into()))?
+            }
+        }
+
+        Ok(object)
+    }
+
+    /// The symbol a relocation refers to
+    pub fn symbol(&self, index: u32) -> Option<&InputSymbol> {
+        self.symbols.get(index as usize)
+    }
+}
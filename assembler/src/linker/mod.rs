@@ -0,0 +1,896 @@
+//! Links relocatable objects (from the assembler or GNU `as`) into a program for the emulator
+
+use std::collections::HashMap;
+
+use elf::{
+    internal::{relocation::MipsRelocationType, section::section_util::SectionFlags},
+    writer::Elf32Symbol,
+};
+
+use crate::assembler::{
+    diagnostic::DiagnosticKind,
+    symbol::{SymBind, SymType, SHN_ABS, SHN_UNDEF},
+};
+
+use self::{
+    input::{InputSection, ObjectFile},
+    output::{OutputSection, OutputSymbol},
+};
+
+mod input;
+mod output;
+mod relocate;
+
+pub use self::output::LinkedProgram;
+
+/// Alignment of the segments in the executable, the data segment starts on a new page by default
+pub const SEGMENT_ALIGN: u32 = 0x1000;
+
+//------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkerSettings {
+    /// Address of the text segment (code and read only data), 0 by default since that is where
+    /// the emulator loads flat images and starts executing
+    pub text_base: u32,
+    /// Address of the data segment, the next page after the text segment if `None`
+    pub data_base: Option<u32>,
+    /// Value of `$gp` (the `_gp` symbol), 0x8000 past the data segment if `None` so that
+    /// `%gp_rel` can reach its first 64KiB
+    pub gp: Option<u32>,
+    /// Symbol the program starts at, `_start` or the start of the text segment if `None`
+    pub entry: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    InvalidObject {
+        object: String,
+        reason: String,
+    },
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    UndefinedSymbol {
+        symbol: String,
+        object: String,
+    },
+    UndefinedEntry(String),
+    Relocation {
+        object: String,
+        section: String,
+        offset: u32,
+        reason: String,
+    },
+    Layout(String),
+}
+
+impl LinkError {
+    pub fn kind(&self) -> DiagnosticKind {
+        match self {
+            LinkError::InvalidObject { .. } => DiagnosticKind::InvalidObject,
+            LinkError::DuplicateSymbol { .. } => DiagnosticKind::LinkDuplicateSymbol,
+            LinkError::UndefinedSymbol { .. } => DiagnosticKind::LinkUndefinedSymbol,
+            LinkError::UndefinedEntry(_) => DiagnosticKind::UndefinedEntry,
+            LinkError::Relocation { .. } => DiagnosticKind::LinkRelocation,
+            LinkError::Layout(_) => DiagnosticKind::Layout,
+        }
+    }
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::InvalidObject { object, reason } => {
+                write!(f, "{}: invalid object: {}", object, reason)
+            }
+            LinkError::DuplicateSymbol {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "duplicate symbol `{}` (defined in {} and {})",
+                symbol, first, second
+            ),
+            LinkError::UndefinedSymbol { symbol, object } => {
+                write!(f, "{}: undefined reference to `{}`", object, symbol)
+            }
+            LinkError::UndefinedEntry(entry) => {
+                write!(f, "entry symbol `{}` is not defined", entry)
+            }
+            LinkError::Relocation {
+                object,
+                section,
+                offset,
+                reason,
+            } => write!(f, "{}:({}+{:#x}): {}", object, section, offset, reason),
+            LinkError::Layout(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+//------------------------------------------------------------------------
+
+/// Where an input section ended up
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    /// Index into the output sections
+    output: usize,
+    address: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GlobalSymbol {
+    value: u32,
+    size: u32,
+    s_type: u8,
+    weak: bool,
+    /// Object that defines the symbol, `None` for the ones provided by the linker
+    object: Option<usize>,
+    /// Index into the output sections, `None` for absolute symbols
+    output: Option<usize>,
+}
+
+fn align_up(value: u32, alignment: u32) -> Option<u32> {
+    let alignment = alignment.max(1);
+    value
+        .checked_add(alignment - 1)
+        .map(|v| v / alignment * alignment)
+}
+
+/// Order of the output sections: code, read only data, data, zero initialized data then
+/// debug info which isn't loaded
+fn section_class(section: &InputSection) -> u8 {
+    if !section.flags.contains(SectionFlags::SHF_ALLOC) {
+        4
+    } else if section.nobits {
+        3
+    } else if section.flags.contains(SectionFlags::SHF_WRITE) {
+        2
+    } else if section.flags.contains(SectionFlags::SHF_EXECINSTR) {
+        0
+    } else {
+        1
+    }
+}
+
+#[derive(Default)]
+pub struct Linker {
+    settings: LinkerSettings,
+    objects: Vec<ObjectFile>,
+}
+
+impl Linker {
+    pub fn new(settings: LinkerSettings) -> Self {
+        Self {
+            settings,
+            objects: Vec::new(),
+        }
+    }
+
+    /// Adds a relocatable object, `name` is only used in diagnostics
+    pub fn add_object(&mut self, name: impl Into<String>, data: &[u8]) -> Result<(), LinkError> {
+        self.objects.push(ObjectFile::parse(name.into(), data)?);
+        Ok(())
+    }
+
+    pub fn link(&self) -> Result<LinkedProgram, Vec<LinkError>> {
+        let (mut sections, placements, text_sections, loaded) =
+            self.layout().map_err(|e| vec![e])?;
+
+        let text_base = self.settings.text_base;
+        let text_end = sections[..text_sections]
+            .last()
+            .map_or(text_base, |s| s.address + s.size);
+        let data_base = sections[..loaded]
+            .get(text_sections)
+            .map_or(text_end, |s| s.address);
+        let data_end = sections[..loaded]
+            .last()
+            .map_or(data_base, |s| s.address + s.size);
+        let data_file_end = sections[..loaded]
+            .iter()
+            .rfind(|s| !s.nobits)
+            .map_or(data_base, |s| (s.address + s.size).max(data_base));
+        let gp = self
+            .settings
+            .gp
+            .unwrap_or_else(|| data_base.wrapping_add(0x8000));
+
+        let mut errors = Vec::new();
+        let mut globals: HashMap<&str, GlobalSymbol> = HashMap::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            for input in object.symbols.iter().skip(1) {
+                let symbol = &input.symbol;
+                if symbol.binding() == u8::from(SymBind::STB_LOCAL) || symbol.shndx == SHN_UNDEF {
+                    continue;
+                }
+                let (value, output) = match self.symbol_address(index, &placements, symbol) {
+                    Ok(val) => val,
+                    Err(err) => {
+                        errors.push(err);
+                        continue;
+                    }
+                };
+                let weak = symbol.binding() == u8::from(SymBind::STB_WEAK);
+                let definition = GlobalSymbol {
+                    value,
+                    size: symbol.size,
+                    s_type: symbol.s_type(),
+                    weak,
+                    object: Some(index),
+                    output,
+                };
+                match globals.get(input.name.as_str()) {
+                    Some(existing) if existing.weak && !weak => {}
+                    Some(existing) if !existing.weak && !weak => {
+                        errors.push(LinkError::DuplicateSymbol {
+                            symbol: input.name.clone(),
+                            first: self.objects[existing.object.unwrap()].name.clone(),
+                            second: object.name.clone(),
+                        });
+                        continue;
+                    }
+                    Some(_) => continue,
+                    None => {}
+                }
+                globals.insert(&input.name, definition);
+            }
+        }
+
+        let provided = [
+            ("_gp", gp),
+            ("_ftext", text_base),
+            ("_etext", text_end),
+            ("_fdata", data_base),
+            ("_edata", data_file_end),
+            ("_end", data_end),
+        ];
+        for (name, value) in provided {
+            globals.entry(name).or_insert(GlobalSymbol {
+                value,
+                size: 0,
+                s_type: SymType::STT_NOTYPE.into(),
+                weak: false,
+                object: None,
+                output: None,
+            });
+        }
+
+        // undefined weak symbols are 0, undefined strong ones are reported once per object
+        for (index, object) in self.objects.iter().enumerate() {
+            let mut reported = Vec::new();
+            for input in object.symbols.iter().skip(1) {
+                let symbol = &input.symbol;
+                if symbol.shndx != SHN_UNDEF || globals.contains_key(input.name.as_str()) {
+                    continue;
+                }
+                if symbol.binding() == u8::from(SymBind::STB_WEAK) {
+                    globals.insert(
+                        &input.name,
+                        GlobalSymbol {
+                            value: 0,
+                            size: 0,
+                            s_type: symbol.s_type(),
+                            weak: true,
+                            object: Some(index),
+                            output: None,
+                        },
+                    );
+                } else if !reported.contains(&input.name) {
+                    reported.push(input.name.clone());
+                    errors.push(LinkError::UndefinedSymbol {
+                        symbol: input.name.clone(),
+                        object: object.name.clone(),
+                    });
+                }
+            }
+        }
+
+        let entry = match &self.settings.entry {
+            Some(entry) => match globals.get(entry.as_str()) {
+                Some(symbol) => symbol.value,
+                None => {
+                    errors.push(LinkError::UndefinedEntry(entry.clone()));
+                    0
+                }
+            },
+            None => globals.get("_start").map_or(text_base, |s| s.value),
+        };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for (index, object) in self.objects.iter().enumerate() {
+            for (section_index, section) in object.sections.iter().enumerate() {
+                let (Some(section), Some(placement)) = (section, placements[index][section_index])
+                else {
+                    continue;
+                };
+                let output = &mut sections[placement.output];
+                let base = (placement.address - output.address) as usize;
+                let fail = |offset: u32, reason: String| LinkError::Relocation {
+                    object: object.name.clone(),
+                    section: section.name.clone(),
+                    offset,
+                    reason,
+                };
+                for (rel_index, relocation) in section.relocations.iter().enumerate() {
+                    let offset = relocation.offset;
+                    let Some(field) = section.data.get(offset as usize..offset as usize + 4) else {
+                        errors.push(fail(offset, "relocation is outside of the section".into()));
+                        continue;
+                    };
+                    let word = u32::from_be_bytes(field.try_into().unwrap());
+
+                    // the addend of a R_MIPS_HI16 is split between it and the next R_MIPS_LO16
+                    let low = section.relocations[rel_index + 1..]
+                        .iter()
+                        .filter(|_| relocation.r_type == MipsRelocationType::R_MIPS_HI16)
+                        .find(|rel| {
+                            rel.r_type == MipsRelocationType::R_MIPS_LO16
+                                && rel.symbol == relocation.symbol
+                        })
+                        .and_then(|rel| {
+                            let offset = rel.offset as usize;
+                            section.data.get(offset..offset + 4)
+                        })
+                        .map(|field| u32::from_be_bytes(field.try_into().unwrap()));
+
+                    let result = self
+                        .relocation_symbol(index, relocation.symbol, &placements, &globals)
+                        .and_then(|symbol| {
+                            let addend = relocate::addend(relocation.r_type, word, low)?;
+                            relocate::relocate(
+                                relocation.r_type,
+                                word,
+                                symbol,
+                                addend,
+                                placement.address + offset,
+                                gp,
+                            )
+                        });
+                    match result {
+                        Ok(word) => {
+                            let at = base + offset as usize;
+                            output.data[at..at + 4].copy_from_slice(&word.to_be_bytes());
+                        }
+                        Err(reason) => errors.push(fail(offset, reason)),
+                    }
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut symbols = Vec::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            for input in object.symbols.iter().skip(1) {
+                let symbol = &input.symbol;
+                if symbol.binding() != u8::from(SymBind::STB_LOCAL)
+                    || input.name.is_empty()
+                    || symbol.s_type() == u8::from(SymType::STT_SECTION)
+                    || symbol.s_type() == u8::from(SymType::STT_FILE)
+                {
+                    continue;
+                }
+                if let Ok((value, output)) = self.symbol_address(index, &placements, symbol) {
+                    symbols.push(OutputSymbol {
+                        name: input.name.clone(),
+                        value,
+                        size: symbol.size,
+                        info: symbol.info,
+                        output,
+                    });
+                }
+            }
+        }
+        let mut global_symbols: Vec<_> = globals.into_iter().collect();
+        global_symbols.sort_by(|a, b| a.1.value.cmp(&b.1.value).then(a.0.cmp(b.0)));
+        for (name, symbol) in global_symbols {
+            let binding = if symbol.weak {
+                SymBind::STB_WEAK
+            } else {
+                SymBind::STB_GLOBAL
+            };
+            symbols.push(OutputSymbol {
+                name: name.to_owned(),
+                value: symbol.value,
+                size: symbol.size,
+                info: Elf32Symbol::info(binding.into(), symbol.s_type),
+                output: symbol.output,
+            });
+        }
+
+        // symbols in debug info aren't useful in the program
+        symbols.retain(|symbol| symbol.output.is_none_or(|output| output < loaded));
+        let debug_sections = sections.split_off(loaded);
+        Ok(LinkedProgram {
+            entry,
+            gp,
+            flags: self.objects.first().map_or(0, |o| o.flags),
+            sections,
+            text_sections,
+            debug_sections,
+            symbols,
+        })
+    }
+
+    /// Merges sections with the same name and gives them addresses, the output sections of the
+    /// text segment come first and the debug sections last
+    ///
+    /// Also returns how many sections are in the text segment and how many are loaded, debug
+    /// sections start at address 0 so that their addresses are offsets into them
+    #[allow(clippy::type_complexity)]
+    fn layout(
+        &self,
+    ) -> Result<
+        (
+            Vec<OutputSection>,
+            Vec<Vec<Option<Placement>>>,
+            usize,
+            usize,
+        ),
+        LinkError,
+    > {
+        let mut names: Vec<(&str, u8)> = Vec::new();
+        for section in self
+            .objects
+            .iter()
+            .flat_map(|o| o.sections.iter().flatten())
+        {
+            if !names.iter().any(|(name, _)| *name == section.name) {
+                names.push((&section.name, section_class(section)));
+            }
+        }
+        names.sort_by_key(|(_, class)| *class);
+        let text_sections = names.iter().filter(|(_, class)| *class < 2).count();
+        let loaded = names.iter().filter(|(_, class)| *class < 4).count();
+
+        let overflow = || LinkError::Layout("the program does not fit in the address space".into());
+        let mut sections = Vec::new();
+        let mut placements: Vec<Vec<Option<Placement>>> = self
+            .objects
+            .iter()
+            .map(|o| vec![None; o.sections.len()])
+            .collect();
+        let mut address = self.settings.text_base;
+        for (output, (name, class)) in names.iter().enumerate() {
+            if output == text_sections {
+                address = match self.settings.data_base {
+                    Some(base) => base,
+                    None => align_up(address, SEGMENT_ALIGN).ok_or_else(overflow)?,
+                };
+            }
+            if output >= loaded {
+                address = 0;
+            }
+            let inputs: Vec<_> =
+                self.objects
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, object)| {
+                        object.sections.iter().enumerate().filter_map(
+                            move |(input_index, input)| Some((index, input_index, input.as_ref()?)),
+                        )
+                    })
+                    .filter(|(_, _, input)| input.name == *name)
+                    .collect();
+            // the start has to satisfy every input, each one is only aligned relative to it
+            let alignment = inputs.iter().map(|(_, _, i)| i.alignment).fold(1, u32::max);
+            address = align_up(address, alignment).ok_or_else(overflow)?;
+            let mut section = OutputSection {
+                name: name.to_string(),
+                flags: SectionFlags::empty(),
+                nobits: *class == 3,
+                alignment,
+                address,
+                data: Vec::new(),
+                size: 0,
+            };
+            for (index, input_index, input) in inputs {
+                section.flags |= input.flags;
+                let offset = align_up(section.size, input.alignment).ok_or_else(overflow)?;
+                placements[index][input_index] = Some(Placement {
+                    output,
+                    address: address.checked_add(offset).ok_or_else(overflow)?,
+                });
+                section.size = offset.checked_add(input.size).ok_or_else(overflow)?;
+                if !section.nobits {
+                    section.data.resize(offset as usize, 0);
+                    section.data.extend_from_slice(&input.data);
+                }
+            }
+            address = section
+                .address
+                .checked_add(section.size)
+                .ok_or_else(overflow)?;
+            sections.push(section);
+        }
+
+        let range = |sections: &[OutputSection]| {
+            let start = sections.first().map_or(0, |s| s.address);
+            start..sections.last().map_or(0, |s| s.address + s.size)
+        };
+        let (text, data) = sections[..loaded].split_at(text_sections);
+        let (text, data) = (range(text), range(data));
+        if text.start < data.end && data.start < text.end {
+            return Err(LinkError::Layout(format!(
+                "the data segment ({:#010x}..{:#010x}) overlaps the text segment ({:#010x}..{:#010x})",
+                data.start, data.end, text.start, text.end
+            )));
+        }
+        Ok((sections, placements, text_sections, loaded))
+    }
+
+    /// Address of a symbol defined in object `object` and the output section it is in
+    fn symbol_address(
+        &self,
+        object: usize,
+        placements: &[Vec<Option<Placement>>],
+        symbol: &Elf32Symbol,
+    ) -> Result<(u32, Option<usize>), LinkError> {
+        if symbol.shndx == SHN_ABS {
+            return Ok((symbol.value, None));
+        }
+        match placements[object]
+            .get(symbol.shndx as usize)
+            .copied()
+            .flatten()
+        {
+            Some(placement) => Ok((
+                placement.address.wrapping_add(symbol.value),
+                Some(placement.output),
+            )),
+            None => Err(LinkError::InvalidObject {
+                object: self.objects[object].name.clone(),
+                reason: format!(
+                    "symbol in section {} which is not loaded or not supported",
+                    symbol.shndx
+                ),
+            }),
+        }
+    }
+
+    /// Value of the symbol with index `symbol` in the symbol table of object `object`
+    fn relocation_symbol(
+        &self,
+        object: usize,
+        symbol: u32,
+        placements: &[Vec<Option<Placement>>],
+        globals: &HashMap<&str, GlobalSymbol>,
+    ) -> Result<u32, String> {
+        let input = self.objects[object]
+            .symbol(symbol)
+            .ok_or_else(|| format!("relocation against missing symbol {}", symbol))?;
+        if input.symbol.binding() == u8::from(SymBind::STB_LOCAL) && input.symbol.shndx != SHN_UNDEF
+        {
+            self.symbol_address(object, placements, &input.symbol)
+                .map(|(value, _)| value)
+                .map_err(|err| err.to_string())
+        } else {
+            globals
+                .get(input.name.as_str())
+                .map(|symbol| symbol.value)
+                .ok_or_else(|| format!("undefined reference to `{}`", input.name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elf::external::{
+        from_bytes, header::ExternalElfHeaderTrait, program::ExternalProgramHeaderTrait,
+        TernaryResult,
+    };
+
+    use super::*;
+    use crate::assembler::{
+        debug::tests::{debug_settings, decode_line_table, sections},
+        object::tests::{assemble_object, assemble_object_with},
+    };
+
+    fn link(settings: LinkerSettings, sources: &[&str]) -> Result<LinkedProgram, Vec<LinkError>> {
+        let mut linker = Linker::new(settings);
+        for (index, source) in sources.iter().enumerate() {
+            let name = format!("link_{}", index);
+            let object = assemble_object(&name, source);
+            linker.add_object(format!("{}.o", name), &object).unwrap();
+        }
+        linker.link()
+    }
+
+    const START: &str = "
+        .globl _start
+        _start:
+            la $a0, value
+            jal func
+            nop
+            b _start
+        .data
+        value: .word func
+        .bss
+        buf: .space 8
+    ";
+    const FUNC: &str = ".globl func\nfunc: jr $ra\n";
+
+    #[test]
+    fn program() {
+        let program = link(LinkerSettings::default(), &[START, FUNC]).unwrap();
+        assert_eq!(program.entry(), 0);
+        assert_eq!(program.symbol("func"), Some(0x14));
+        assert_eq!(program.symbol("value"), Some(0x1000));
+        assert_eq!(program.symbol("buf"), Some(0x1004));
+        assert_eq!(program.symbol("_end"), Some(0x100C));
+        assert_eq!(program.gp(), 0x9000);
+
+        let image = program.to_flat_binary();
+        let words: Vec<u32> = image
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            words[..6],
+            [0x3C040000, 0x24841000, 0x0C000005, 0, 0x1000FFFB, 0x03E00008]
+        );
+        assert!(words[6..0x400].iter().all(|word| *word == 0));
+        assert_eq!(words[0x400..], [0x14]);
+
+        let elf = program.to_elf();
+        let elf = match from_bytes(&elf) {
+            TernaryResult::Ok1(elf) => elf,
+            _ => panic!("not an ELF32 file"),
+        };
+        assert_eq!(elf.elf_header().elftype(), 2);
+        assert_eq!(elf.elf_header().entry_point(), 0);
+        let mut segments = Vec::new();
+        while let Some(segment) = elf.program_header(segments.len()) {
+            assert_eq!(
+                segment.offset() % SEGMENT_ALIGN,
+                segment.vaddr() % SEGMENT_ALIGN
+            );
+            segments.push((
+                segment.ph_type(),
+                segment.vaddr(),
+                segment.filesz(),
+                segment.memsz(),
+                segment.flags(),
+            ));
+        }
+        assert_eq!(segments, [(1, 0, 0x18, 0x18, 5), (1, 0x1000, 4, 0xC, 6)]);
+    }
+
+    #[test]
+    fn debug_info() {
+        let settings = LinkerSettings {
+            text_base: 0x0040_0000,
+            ..Default::default()
+        };
+        let mut linker = Linker::new(settings.clone());
+        for (name, source) in [("debug_start", START), ("debug_func", FUNC)] {
+            let object = assemble_object_with(name, source, debug_settings());
+            linker.add_object(name, &object).unwrap();
+        }
+        let program = linker.link().unwrap();
+        let plain = link(settings, &[START, FUNC]).unwrap();
+        assert_eq!(program.to_flat_binary(), plain.to_flat_binary());
+        assert_eq!(program.symbols.len(), plain.symbols.len());
+
+        let sections = sections(&program.to_elf());
+        let section = |name: &str| {
+            let found = sections.iter().find(|(found, _)| found == name);
+            found.map(|(_, data)| data.as_slice()).unwrap()
+        };
+        let word =
+            |data: &[u8], at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+
+        // the line tables of both objects are relocated to where their code ended up
+        let lines = section(".debug_line");
+        let second = 4 + word(lines, 0) as usize;
+        assert_eq!(
+            decode_line_table(lines),
+            [
+                (0x0040_0000, 1, 4),
+                (0x0040_0004, 1, 4),
+                (0x0040_0008, 1, 5),
+                (0x0040_000C, 1, 6),
+                (0x0040_0010, 1, 7),
+            ]
+        );
+        assert_eq!(decode_line_table(&lines[second..]), [(0x0040_0014, 1, 2)]);
+
+        // stmt_list of the second compile unit points at its line table
+        let info = section(".debug_info");
+        assert_eq!(word(info, 12), 0);
+        let unit = 4 + word(info, 0) as usize;
+        assert_eq!(word(info, unit + 12), second as u32);
+        // and its pc range at its code
+        assert_eq!(word(info, unit + 16), 0x0040_0014);
+        assert_eq!(word(info, unit + 20), 0x0040_0018);
+    }
+
+    #[test]
+    fn alignment() {
+        // the section starts where the most aligned input can be placed, not only the first
+        let sources = [
+            ".globl _start\n_start: nop\n.section .rodata\n.byte 1\n",
+            ".section .rodata\n.globl d\nd: .double 1.5\n",
+        ];
+        let program = link(LinkerSettings::default(), &sources).unwrap();
+        assert_eq!(program.symbol("d"), Some(0x10));
+        let image = program.to_flat_binary();
+        assert_eq!(image[8], 1);
+        assert_eq!(image[0x10..0x18], 1.5f64.to_be_bytes());
+
+        let elf = program.to_elf();
+        let TernaryResult::Ok1(elf) = from_bytes(&elf) else {
+            panic!("not an ELF32 file")
+        };
+        let segment = elf.program_header(0).unwrap();
+        assert_eq!((segment.filesz(), segment.memsz()), (0x18, 0x18));
+    }
+
+    #[test]
+    fn intel_hex() {
+        let settings = LinkerSettings {
+            text_base: 0x0040_FFF8,
+            data_base: Some(0x0042_0000),
+            ..Default::default()
+        };
+        let program = link(
+            settings,
+            &[".globl _start\n_start: .word 1, 2, 3, 4\n.data\n.byte 0xAB\n"],
+        );
+        let hex = program.unwrap().to_intel_hex();
+        assert_eq!(
+            hex.lines().collect::<Vec<_>>(),
+            [
+                ":020000040040BA",
+                ":08FFF8000000000100000002FE",
+                ":020000040041B9",
+                ":080000000000000300000004F1",
+                ":020000040042B8",
+                ":01000000AB54",
+                ":040000050040FFF8C0",
+                ":00000001FF",
+            ]
+        );
+    }
+
+    #[test]
+    fn settings() {
+        let settings = LinkerSettings {
+            text_base: 0x0040_0000,
+            data_base: Some(0x0041_0000),
+            gp: Some(0x0041_8000),
+            entry: Some("func".into()),
+        };
+        let program = link(settings, &[START, FUNC]).unwrap();
+        assert_eq!(program.entry(), 0x0040_0014);
+        assert_eq!(program.base_address(), 0x0040_0000);
+        assert_eq!(program.symbol("value"), Some(0x0041_0000));
+        assert_eq!(program.symbol("_gp"), Some(0x0041_8000));
+        let image = program.to_flat_binary();
+        assert_eq!(image.len(), 0x1_0004);
+        assert_eq!(image[..8], [0x3C, 0x04, 0x00, 0x41, 0x24, 0x84, 0x00, 0x00]);
+
+        let settings = LinkerSettings {
+            data_base: Some(0x10),
+            ..Default::default()
+        };
+        assert!(matches!(
+            link(settings, &[START, FUNC]).unwrap_err()[..],
+            [LinkError::Layout(_)]
+        ));
+    }
+
+    #[test]
+    fn symbols() {
+        // a weak definition is overridden by a global one wherever it comes from
+        let weak = ".weak func\nfunc: nop\n";
+        let program = link(LinkerSettings::default(), &[weak, START, FUNC]).unwrap();
+        assert_eq!(program.symbol("func"), Some(0x18));
+        let program = link(LinkerSettings::default(), &[START, weak]).unwrap();
+        assert_eq!(program.symbol("func"), Some(0x14));
+        // undefined weak symbols are 0
+        let program = link(LinkerSettings::default(), &[".weak none\nla $t0, none\n"]).unwrap();
+        assert_eq!(
+            program.to_flat_binary(),
+            [0x3C, 0x08, 0, 0, 0x25, 0x08, 0, 0]
+        );
+
+        let errors = link(LinkerSettings::default(), &[START, FUNC, FUNC]).unwrap_err();
+        assert_eq!(
+            errors,
+            [LinkError::DuplicateSymbol {
+                symbol: "func".into(),
+                first: "link_1.o".into(),
+                second: "link_2.o".into(),
+            }]
+        );
+        let errors = link(
+            LinkerSettings::default(),
+            &[START, "jal missing\nj missing\n"],
+        );
+        assert_eq!(
+            errors.unwrap_err(),
+            [
+                LinkError::UndefinedSymbol {
+                    symbol: "func".into(),
+                    object: "link_0.o".into(),
+                },
+                LinkError::UndefinedSymbol {
+                    symbol: "missing".into(),
+                    object: "link_1.o".into(),
+                }
+            ]
+        );
+        let settings = LinkerSettings {
+            entry: Some("main".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            link(settings, &[START, FUNC]).unwrap_err(),
+            [LinkError::UndefinedEntry("main".into())]
+        );
+    }
+
+    #[test]
+    fn relocation_operators() {
+        let source = "
+            .globl _start
+            _start:
+                lui $t0, %hi(value)
+                lw $t1, %lo(value)($t0)
+                addiu $t2, $t0, %lo(value+4)
+                lw $t3, %gp_rel(value+4)($gp)
+                b end
+                .word value+4
+            end:
+                jr $ra
+            .data
+            value: .word 1, 2
+        ";
+        let settings = LinkerSettings {
+            text_base: 0x1000_0000,
+            data_base: Some(0x1000_8000),
+            ..Default::default()
+        };
+        let program = link(settings, &[source]).unwrap();
+        assert_eq!(program.gp(), 0x1001_0000);
+        let words: Vec<u32> = program.to_flat_binary()[..0x1C]
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect();
+        // %lo(value) is negative so %hi(value) is rounded up
+        assert_eq!(
+            words,
+            [0x3C081001, 0x8D098000, 0x250A8004, 0x8F8B8004, 0x10000001, 0x10008004, 0x03E00008]
+        );
+    }
+
+    #[test]
+    fn relocation_errors() {
+        let settings = LinkerSettings {
+            data_base: Some(0x1000_0000),
+            ..Default::default()
+        };
+        let errors = link(settings, &["jal far\n", ".data\n.globl far\nfar: nop\n"]).unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [LinkError::Relocation { offset: 0, reason, .. }] if reason.contains("256MB")
+        ));
+
+        let mut linker = Linker::default();
+        let err = linker
+            .add_object("garbage.o", b"not an object")
+            .unwrap_err();
+        assert!(matches!(err, LinkError::InvalidObject { .. }));
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt::Write;
+
+use elf::{
+    internal::{
+        header::header_util::{ElfEndian, ElfMachine, ElfType},
+        program::{ProgramHeaderFlags, ProgramHeaderType},
+        section::section_util::{SectionFlags, SectionType},
+    },
+    writer::{Elf32Symbol, Elf32Writer, StringTable, WriterSection, WriterSegment},
+};
+
+use crate::assembler::symbol::{SymBind, SHN_ABS};
+
+use super::SEGMENT_ALIGN;
+
+//------------------------------------------------------------------------
+
+/// Input sections with the same name merged together
+#[derive(Debug, Clone)]
+pub(crate) struct OutputSection {
+    pub name: String,
+    pub flags: SectionFlags,
+    pub nobits: bool,
+    pub alignment: u32,
+    pub address: u32,
+    /// Empty for `SHT_NOBITS`
+    pub data: Vec<u8>,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct OutputSymbol {
+    pub name: String,
+    pub value: u32,
+    pub size: u32,
+    pub info: u8,
+    /// Index into the output sections, `None` for absolute symbols
+    pub output: Option<usize>,
+}
+
+/// The result of linking, which can be written as an executable or a flat image
+#[derive(Debug, Clone)]
+pub struct LinkedProgram {
+    pub(crate) entry: u32,
+    pub(crate) gp: u32,
+    pub(crate) flags: u32,
+    /// The first `text_sections` make up the text segment, the rest the data segment
+    pub(crate) sections: Vec<OutputSection>,
+    pub(crate) text_sections: usize,
+    /// Sections that aren't loaded, relocated as if they started at address 0
+    pub(crate) debug_sections: Vec<OutputSection>,
+    /// Locals first
+    pub(crate) symbols: Vec<OutputSymbol>,
+}
+
+impl LinkedProgram {
+    pub fn entry(&self) -> u32 {
+        self.entry
+    }
+
+    pub fn gp(&self) -> u32 {
+        self.gp
+    }
+
+    /// Value of a global (or if there is no global with that name, local) symbol
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        let mut symbols = self.symbols.iter().rev();
+        symbols.find(|s| s.name == name).map(|s| s.value)
+    }
+
+    /// Address of the first byte of `to_flat_binary`
+    pub fn base_address(&self) -> u32 {
+        self.sections.first().map_or(0, |s| s.address)
+    }
+
+    /// Memory from `base_address` up to the end of the initialized data, gaps are zero filled
+    ///
+    /// Zero initialized sections (.bss) at the end aren't included since memory starts out zeroed
+    pub fn to_flat_binary(&self) -> Vec<u8> {
+        let base = self.base_address();
+        let mut image = Vec::new();
+        for section in self.sections.iter().filter(|s| !s.nobits) {
+            let start = (section.address - base) as usize;
+            image.resize(start, 0);
+            image.extend_from_slice(&section.data);
+        }
+        image
+    }
+
+    /// The initialized sections as Intel HEX with 16 bytes per record, the start address
+    /// record holds the entry point
+    pub fn to_intel_hex(&self) -> String {
+        fn record(out: &mut String, address: u16, r_type: u8, data: &[u8]) {
+            let mut sum = data.len() as u8;
+            sum = sum.wrapping_add((address >> 8) as u8);
+            sum = sum.wrapping_add(address as u8);
+            sum = sum.wrapping_add(r_type);
+            let _ = write!(out, ":{:02X}{:04X}{:02X}", data.len(), address, r_type);
+            for byte in data {
+                sum = sum.wrapping_add(*byte);
+                let _ = write!(out, "{:02X}", byte);
+            }
+            let _ = writeln!(out, "{:02X}", sum.wrapping_neg());
+        }
+
+        let mut out = String::new();
+        // upper half of the address of the following data records
+        let mut upper = None;
+        for section in self.sections.iter().filter(|s| !s.nobits) {
+            for (index, chunk) in section.data.chunks(16).enumerate() {
+                let address = section.address + index as u32 * 16;
+                // records can't cross a 64KiB boundary
+                let split = (0x1_0000 - (address & 0xFFFF)) as usize;
+                let (first, second) = chunk.split_at(split.min(chunk.len()));
+                for (address, data) in [(address, first), (address + first.len() as u32, second)] {
+                    if data.is_empty() {
+                        continue;
+                    }
+                    if upper != Some(address >> 16) {
+                        upper = Some(address >> 16);
+                        record(&mut out, 0, 4, &((address >> 16) as u16).to_be_bytes());
+                    }
+                    record(&mut out, address as u16, 0, data);
+                }
+            }
+        }
+        record(&mut out, 0, 5, &self.entry.to_be_bytes());
+        record(&mut out, 0, 1, &[]);
+        out
+    }
+
+    /// A big endian ELF32 MIPS executable with a `PT_LOAD` segment for text and one for data,
+    /// followed by the debug sections
+    pub fn to_elf(&self) -> Vec<u8> {
+        let mut writer =
+            Elf32Writer::new(ElfEndian::BigEndian, ElfType::ET_EXEC, ElfMachine::EM_MIPS);
+        writer.set_flags(self.flags);
+        writer.set_entry(self.entry);
+        let enc = writer.encoder();
+
+        for section in &self.sections {
+            let sh_type = if section.nobits {
+                SectionType::SHT_NOBITS
+            } else {
+                SectionType::SHT_PROGBITS
+            };
+            let mut out = WriterSection::new(section.name.clone(), sh_type, section.flags);
+            out.addr = section.address;
+            out.addralign = section.alignment;
+            out.data = section.data.clone();
+            out.nobits_size = section.size;
+            writer.add_section(out);
+        }
+        let segments = [
+            (
+                0..self.text_sections,
+                ProgramHeaderFlags::PF_R | ProgramHeaderFlags::PF_X,
+            ),
+            (
+                self.text_sections..self.sections.len(),
+                ProgramHeaderFlags::PF_R | ProgramHeaderFlags::PF_W,
+            ),
+        ];
+        for (sections, flags) in segments {
+            if sections.is_empty() {
+                continue;
+            }
+            writer.add_segment(WriterSegment {
+                p_type: ProgramHeaderType::PT_LOAD,
+                flags,
+                align: SEGMENT_ALIGN,
+                sections: sections.start as u16 + 1..=sections.end as u16,
+            });
+        }
+        for section in &self.debug_sections {
+            let mut out = WriterSection::new(
+                section.name.clone(),
+                SectionType::SHT_PROGBITS,
+                section.flags,
+            );
+            out.addralign = section.alignment;
+            out.data = section.data.clone();
+            writer.add_section(out);
+        }
+
+        let symtab_index = writer.next_section_index();
+        let mut strtab = StringTable::new();
+        let mut symtab = Vec::new();
+        Elf32Symbol::default().write(enc, &mut symtab);
+        let mut first_global = None;
+        for (index, symbol) in (1..).zip(&self.symbols) {
+            if symbol.info >> 4 != u8::from(SymBind::STB_LOCAL) && first_global.is_none() {
+                first_global = Some(index);
+            }
+            Elf32Symbol {
+                name: strtab.add(&symbol.name),
+                value: symbol.value,
+                size: symbol.size,
+                info: symbol.info,
+                other: 0,
+                shndx: symbol.output.map_or(SHN_ABS, |output| output as u16 + 1),
+            }
+            .write(enc, &mut symtab);
+        }
+
+        let mut section =
+            WriterSection::new(".symtab", SectionType::SHT_SYMTAB, SectionFlags::empty());
+        section.link = symtab_index as u32 + 1;
+        section.info = first_global.unwrap_or(self.symbols.len() as u32 + 1);
+        section.addralign = 4;
+        section.entsize = Elf32Symbol::SIZE;
+        section.data = symtab;
+        writer.add_section(section);
+
+        let mut section =
+            WriterSection::new(".strtab", SectionType::SHT_STRTAB, SectionFlags::empty());
+        section.data = strtab.data().to_vec();
+        writer.add_section(section);
+
+        writer.write()
+    }
+}
@@ -0,0 +1,78 @@
+use elf::internal::relocation::MipsRelocationType;
+
+//------------------------------------------------------------------------
+
+fn sign_extend_16(value: u32) -> u32 {
+    value as u16 as i16 as i32 as u32
+}
+
+/// The addend stored in the relocated `word`
+///
+/// `R_MIPS_HI16` only stores the upper half, `low` is the field of the `R_MIPS_LO16` that
+/// goes with it
+pub(super) fn addend(
+    r_type: MipsRelocationType,
+    word: u32,
+    low: Option<u32>,
+) -> Result<u32, String> {
+    use MipsRelocationType::*;
+    match r_type {
+        R_MIPS_32 => Ok(word),
+        R_MIPS_26 => Ok((word & 0x03FF_FFFF) << 2),
+        R_MIPS_HI16 => match low {
+            Some(low) => Ok((word << 16).wrapping_add(sign_extend_16(low))),
+            None => Err("R_MIPS_HI16 without a matching R_MIPS_LO16".into()),
+        },
+        R_MIPS_LO16 | R_MIPS_GPREL16 => Ok(sign_extend_16(word)),
+        R_MIPS_PC16 => Ok(sign_extend_16(word) << 2),
+        other => Err(format!("unsupported relocation {:?}", other)),
+    }
+}
+
+/// Stores `symbol + addend` in `word` as `r_type` for a field at address `place`
+pub(super) fn relocate(
+    r_type: MipsRelocationType,
+    word: u32,
+    symbol: u32,
+    addend: u32,
+    place: u32,
+    gp: u32,
+) -> Result<u32, String> {
+    use MipsRelocationType::*;
+    let value = symbol.wrapping_add(addend);
+    let relative = |value: u32| -> Result<u32, String> {
+        let offset = value as i32;
+        if (i16::MIN as i32..=i16::MAX as i32).contains(&offset) {
+            Ok((word & 0xFFFF_0000) | (offset as u32 & 0xFFFF))
+        } else {
+            Err(format!(
+                "offset {} is out of range for {:?}",
+                offset, r_type
+            ))
+        }
+    };
+    match r_type {
+        R_MIPS_32 => Ok(value),
+        R_MIPS_26 if value & 0b11 != 0 => {
+            Err(format!("jump target {:#010x} is not word aligned", value))
+        }
+        R_MIPS_26 if value >> 28 != place.wrapping_add(4) >> 28 => Err(format!(
+            "jump target {:#010x} is not in the same 256MB region as the jump at {:#010x}",
+            value, place
+        )),
+        R_MIPS_26 => Ok((word & 0xFC00_0000) | ((value >> 2) & 0x03FF_FFFF)),
+        // the low half is sign extended when it is used so the high half makes up for it
+        R_MIPS_HI16 => Ok((word & 0xFFFF_0000) | (value.wrapping_add(0x8000) >> 16)),
+        R_MIPS_LO16 => Ok((word & 0xFFFF_0000) | (value & 0xFFFF)),
+        R_MIPS_PC16 => {
+            let offset = value.wrapping_sub(place);
+            if offset & 0b11 != 0 {
+                Err(format!("branch target {:#010x} is not word aligned", value))
+            } else {
+                relative(((offset as i32) >> 2) as u32)
+            }
+        }
+        R_MIPS_GPREL16 => relative(value.wrapping_sub(gp)),
+        other => Err(format!("unsupported relocation {:?}", other)),
+    }
+}
@@ -1,19 +1,427 @@
-use assembler::assembler::assemble;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-fn main() {
-    let input = "./assembler/res/test.asm";
-    let mut output = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open("./assembler/res/snake.mxn")
-        .unwrap();
+use assembler::{
+    assembler::{
+        diagnostic::{self, Diagnostic, DiagnosticKind, Severity},
+        source::FileSystem,
+        Assembler, AssemblerSettings,
+    },
+    linker::{Linker, LinkerSettings},
+};
+
+const USAGE: &str = "\
+Usage: assembler [options] <input.asm>...
+
+Options:
+  -o <file>             Write the output to <file> ('-' for stdout)
+  -f, --format <format> obj (relocatable ELF, the default), elf (linked executable),
+                        bin (flat binary) or hex (Intel HEX)
+  -I <dir>              Search <dir> for #include files
+  -D <name>[=<value>]   Define <name> as <value> (1 if there is none)
+  -g                    Add DWARF line info for debuggers
+  -l, --listing <file>  Write a listing of the assembled source to <file> ('-' for stdout)
+  -m, --map <file>      Write the sections and symbols to <file> ('-' for stdout)
+      --entry <symbol>  Symbol the program starts at (linked formats)
+      --text-base <addr> Address of the text segment (linked formats)
+      --data-base <addr> Address of the data segment (linked formats)
+      --werror          Treat warnings as errors
+      --message-format <human|json>
+                        How errors and warnings are printed on stderr
+  -h, --help            Print this help
+  -V, --version         Print the version
+
+Exit codes: 0 on success, 1 if assembling or linking failed, 2 for invalid arguments";
+
+/// Exit code when assembling or linking fails
+const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid arguments
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Object,
+    Elf,
+    Binary,
+    Hex,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Object => "o",
+            Format::Elf => "elf",
+            Format::Binary => "bin",
+            Format::Hex => "hex",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    inputs: Vec<String>,
+    output: Option<String>,
+    format: Option<Format>,
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<String>,
+    debug_info: bool,
+    listing: Option<String>,
+    map: Option<String>,
+    linker: LinkerSettings,
+    werror: bool,
+    json: bool,
+}
+
+#[derive(Debug)]
+enum Command {
+    Assemble(Options),
+    Help,
+    Version,
+}
+
+fn parse_address(value: &str) -> Result<u32, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => value.replace('_', "").parse(),
+    };
+    parsed.map_err(|_| format!("invalid address: {}", value))
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // `-Ipath` and `-DNAME` are the same as `-I path` and `-D NAME`
+        let (flag, attached) = match arg.as_str() {
+            arg if arg.len() > 2 && (arg.starts_with("-I") || arg.starts_with("-D")) => {
+                (&arg[..2], Some(arg[2..].to_owned()))
+            }
+            arg => match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+                _ => (arg, None),
+            },
+        };
+        let mut value = || {
+            attached
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-o" => options.output = Some(value()?),
+            "-f" | "--format" => {
+                options.format = Some(match value()?.as_str() {
+                    "obj" => Format::Object,
+                    "elf" => Format::Elf,
+                    "bin" => Format::Binary,
+                    "hex" => Format::Hex,
+                    other => return Err(format!("unknown output format: {}", other)),
+                })
+            }
+            "-I" => options.include_dirs.push(value()?.into()),
+            "-D" => options.defines.push(value()?),
+            "-g" => options.debug_info = true,
+            "-l" | "--listing" => options.listing = Some(value()?),
+            "-m" | "--map" => options.map = Some(value()?),
+            "--entry" => options.linker.entry = Some(value()?),
+            "--text-base" => options.linker.text_base = parse_address(&value()?)?,
+            "--data-base" => options.linker.data_base = Some(parse_address(&value()?)?),
+            "--werror" => options.werror = true,
+            "--message-format" => {
+                options.json = match value()?.as_str() {
+                    "human" => false,
+                    "json" => true,
+                    other => return Err(format!("unknown message format: {}", other)),
+                }
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option: {}", flag))
+            }
+            _ => options.inputs.push(arg),
+        }
+    }
+
+    if options.inputs.is_empty() {
+        return Err("no input files".into());
+    }
+    let format = options.format.unwrap_or(Format::Object);
+    if format == Format::Object && options.inputs.len() > 1 && options.output.is_some() {
+        return Err(
+            "-o with more than one input needs a linked format (-f elf, bin or hex)".into(),
+        );
+    }
+    let stdout = [&options.output, &options.listing, &options.map]
+        .into_iter()
+        .filter(|path| path.as_deref() == Some("-"))
+        .count();
+    if stdout > 1 {
+        return Err("only one of -o, -l and -m can write to stdout ('-')".into());
+    }
+    Ok(Command::Assemble(options))
+}
+
+//------------------------------------------------------------------------
+
+/// Where the output for `input` goes when there is no `-o`
+fn default_output(input: &str, format: Format) -> String {
+    Path::new(input)
+        .with_extension(format.extension())
+        .display()
+        .to_string()
+}
+
+fn write_file(path: &str, data: &[u8]) -> Result<(), String> {
+    let res = match path {
+        "-" => std::io::stdout().write_all(data),
+        path => std::fs::write(path, data),
+    };
+    res.map_err(|err| format!("failed to write {}: {}", path, err))
+}
+
+/// Errors and warnings of every input, printed once everything is done
+#[derive(Default)]
+struct Diagnostics {
+    human: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    fn error(&mut self, kind: DiagnosticKind, message: String) {
+        self.human.push_str(&format!("error: {}\n", message));
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            kind,
+            message,
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        });
+    }
+
+    fn print(&self, json: bool) {
+        if json {
+            eprintln!("{}", diagnostic::to_json(&self.diagnostics));
+        } else {
+            eprint!("{}", self.human);
+        }
+    }
+}
+
+/// Assembles `input`, returns the object if there were no errors (or warnings with `--werror`)
+fn assemble(options: &Options, input: &str, diagnostics: &mut Diagnostics) -> Option<Vec<u8>> {
+    let mut settings = AssemblerSettings {
+        listing: options.listing.is_some(),
+        debug_info: options.debug_info,
+        ..Default::default()
+    };
+    for define in &options.defines {
+        settings.define(define);
+    }
+    let sources = FileSystem::new(options.include_dirs.clone());
+    let mut assembler = Assembler::with_sources(settings, sources);
+    let mut object = Vec::new();
+    let (report, ok) = match assembler.assemble(input.to_owned(), &mut object) {
+        Ok(report) => (report, true),
+        Err(report) => (report, false),
+    };
+
+    let found = report.diagnostics();
+    if !found.is_empty() {
+        diagnostics.human.push_str(&format!("{}\n", report));
+    }
+    let warnings = found
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Warning);
+    diagnostics.diagnostics.extend(found);
+    if ok && warnings && options.werror {
+        diagnostics.error(
+            DiagnosticKind::WarningsAsErrors,
+            format!("{}: warnings are treated as errors", input),
+        );
+    }
 
-    match assemble(input, &mut output) {
-        Ok(report) => {
-            println!("Assembled\n{}", report);
+    // listings and maps are useful to find out what went wrong so they are always written
+    let extras = [
+        (&options.listing, assembler.listing()),
+        (&options.map, assembler.map()),
+    ];
+    for (path, contents) in extras {
+        if let Some(path) = path {
+            // the listings of every input are appended to each other
+            let res = match path.as_str() {
+                "-" => std::io::stdout().write_all(contents.as_bytes()),
+                path => std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(contents.as_bytes())),
+            };
+            if let Err(err) = res {
+                diagnostics.error(
+                    DiagnosticKind::Io,
+                    format!("failed to write {}: {}", path, err),
+                );
+            }
         }
-        Err(report) => {
-            println!("Failed to Assembled\n{}", report);
+    }
+
+    (ok && !(warnings && options.werror)).then_some(object)
+}
+
+fn run(options: Options) -> i32 {
+    for path in [&options.listing, &options.map].into_iter().flatten() {
+        // appended to for every input
+        if path != "-" {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    let format = options.format.unwrap_or(Format::Object);
+    let mut diagnostics = Diagnostics::default();
+    let mut objects = Vec::new();
+    for input in &options.inputs {
+        if let Some(object) = assemble(&options, input, &mut diagnostics) {
+            objects.push((input, object));
         }
     }
+    let mut failed = objects.len() != options.inputs.len();
+
+    if !failed && format == Format::Object {
+        for (input, object) in &objects {
+            let output = options
+                .output
+                .clone()
+                .unwrap_or_else(|| default_output(input, format));
+            if let Err(err) = write_file(&output, object) {
+                diagnostics.error(DiagnosticKind::Io, err);
+                failed = true;
+            }
+        }
+    } else if !failed {
+        let mut linker = Linker::new(options.linker.clone());
+        for (input, object) in &objects {
+            let name = default_output(input, Format::Object);
+            if let Err(err) = linker.add_object(name, object) {
+                diagnostics.error(err.kind(), err.to_string());
+                failed = true;
+            }
+        }
+        match linker.link() {
+            Ok(program) if !failed => {
+                let data = match format {
+                    Format::Elf => program.to_elf(),
+                    Format::Binary => program.to_flat_binary(),
+                    _ => program.to_intel_hex().into_bytes(),
+                };
+                let output = options
+                    .output
+                    .clone()
+                    .unwrap_or_else(|| default_output(&options.inputs[0], format));
+                if let Err(err) = write_file(&output, &data) {
+                    diagnostics.error(DiagnosticKind::Io, err);
+                    failed = true;
+                }
+            }
+            Ok(_) => {}
+            Err(errors) => {
+                for err in errors {
+                    diagnostics.error(err.kind(), err.to_string());
+                }
+                failed = true;
+            }
+        }
+    }
+
+    diagnostics.print(options.json);
+    if failed {
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
+
+fn main() {
+    let code = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Assemble(options)) => run(options),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            0
+        }
+        Ok(Command::Version) => {
+            println!("assembler {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            EXIT_USAGE
+        }
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        match parse_args(args.split_whitespace().map(String::from))? {
+            Command::Assemble(options) => Ok(options),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn arguments() {
+        let options = parse(
+            "-o out.hex a.asm -f hex -Iinc -I lib -DDEBUG -D SIZE=4 -g --listing=a.lst -m a.map \
+             --entry main --text-base 0x400000 --data-base=4096 --werror --message-format json b.asm",
+        )
+        .unwrap();
+        assert_eq!(options.inputs, ["a.asm", "b.asm"]);
+        assert_eq!(options.output.as_deref(), Some("out.hex"));
+        assert_eq!(options.format, Some(Format::Hex));
+        assert_eq!(options.include_dirs, [PathBuf::from("inc"), "lib".into()]);
+        assert_eq!(options.defines, ["DEBUG", "SIZE=4"]);
+        assert!(options.debug_info && options.werror && options.json);
+        assert_eq!(options.listing.as_deref(), Some("a.lst"));
+        assert_eq!(options.map.as_deref(), Some("a.map"));
+        assert_eq!(options.linker.entry.as_deref(), Some("main"));
+        assert_eq!(options.linker.text_base, 0x0040_0000);
+        assert_eq!(options.linker.data_base, Some(4096));
+
+        assert!(matches!(parse_args(["-h".into()]), Ok(Command::Help)));
+        assert!(matches!(
+            parse_args(["--version".into()]),
+            Ok(Command::Version)
+        ));
+        assert_eq!(default_output("dir/a.asm", Format::Object), "dir/a.o");
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(parse("").unwrap_err(), "no input files");
+        assert_eq!(parse("a.asm -o").unwrap_err(), "-o needs a value");
+        assert_eq!(parse("a.asm -x").unwrap_err(), "unknown option: -x");
+        assert_eq!(
+            parse("a.asm -f exe").unwrap_err(),
+            "unknown output format: exe"
+        );
+        assert_eq!(
+            parse("a.asm --text-base 0xZZ").unwrap_err(),
+            "invalid address: 0xZZ"
+        );
+        assert!(parse("a.asm b.asm -o out.o").is_err());
+        assert!(parse("a.asm b.asm -o out.elf -f elf").is_ok());
+        assert!(parse("a.asm -l - -m a.map").is_ok());
+        assert_eq!(
+            parse("a.asm -o - -m -").unwrap_err(),
+            "only one of -o, -l and -m can write to stdout ('-')"
+        );
+    }
 }
@@ -2,6 +2,7 @@ use self::header::ElfHeader;
 
 pub mod header;
 pub mod program;
+pub mod relocation;
 pub mod section;
 
 pub struct InternalElf {
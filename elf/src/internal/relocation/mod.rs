@@ -0,0 +1,49 @@
+//----------------------------------------------------------------------------
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+/** Relocation types of the MIPS o32 ABI, the addend is stored in the relocated field */
+pub enum MipsRelocationType {
+    /** No reloc */
+    R_MIPS_NONE = 0,
+    /** Direct 16 bit */
+    R_MIPS_16 = 1,
+    /** Direct 32 bit */
+    R_MIPS_32 = 2,
+    /** PC relative 32 bit */
+    R_MIPS_REL32 = 3,
+    /** Direct 26 bit shifted (j, jal) */
+    R_MIPS_26 = 4,
+    /** High 16 bit, adjusted for the sign of the following R_MIPS_LO16 */
+    R_MIPS_HI16 = 5,
+    /** Low 16 bit */
+    R_MIPS_LO16 = 6,
+    /** GP relative 16 bit */
+    R_MIPS_GPREL16 = 7,
+    /** 16 bit literal entry */
+    R_MIPS_LITERAL = 8,
+    /** 16 bit GOT entry */
+    R_MIPS_GOT16 = 9,
+    /** PC relative 16 bit shifted (branches) */
+    R_MIPS_PC16 = 10,
+    /** 16 bit GOT entry for function */
+    R_MIPS_CALL16 = 11,
+    /** GP relative 32 bit */
+    R_MIPS_GPREL32 = 12,
+}
+
+impl TryFrom<u8> for MipsRelocationType {
+    type Error = ();
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        match num_traits::FromPrimitive::from_u8(n) {
+            Option::Some(val) => Result::Ok(val),
+            Option::None => Result::Err(()),
+        }
+    }
+}
+
+impl From<MipsRelocationType> for u8 {
+    fn from(val: MipsRelocationType) -> Self {
+        num_traits::ToPrimitive::to_u8(&val).unwrap()
+    }
+}
@@ -1,2 +1,3 @@
 pub mod external;
 pub mod internal;
+pub mod writer;
@@ -0,0 +1,297 @@
+use std::ops::RangeInclusive;
+
+use crate::internal::{
+    header::header_util::{ElfClass, ElfEndian, ElfMachine, ElfType},
+    program::{ProgramHeaderFlags, ProgramHeaderType},
+    section::section_util::{SectionFlags, SectionType},
+};
+
+pub mod table;
+
+pub use self::table::*;
+
+const ELF32_HEADER_SIZE: u32 = 52;
+const ELF32_SECTION_HEADER_SIZE: u32 = 40;
+const ELF32_PROGRAM_HEADER_SIZE: u32 = 32;
+
+//---------------------------------------------------------------------------------------------------------
+
+/// Reads and writes multi byte values in the byte order of an ELF file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoder {
+    pub endian: ElfEndian,
+}
+
+impl Encoder {
+    /// Reads the `u16` at the start of `data`, which must be at least 2 bytes long
+    pub fn read_u16(&self, data: &[u8]) -> u16 {
+        let bytes = [data[0], data[1]];
+        match self.endian {
+            ElfEndian::LittleEndian => u16::from_le_bytes(bytes),
+            ElfEndian::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    /// Reads the `u32` at the start of `data`, which must be at least 4 bytes long
+    pub fn read_u32(&self, data: &[u8]) -> u32 {
+        let bytes = [data[0], data[1], data[2], data[3]];
+        match self.endian {
+            ElfEndian::LittleEndian => u32::from_le_bytes(bytes),
+            ElfEndian::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn u16(&self, out: &mut Vec<u8>, val: u16) {
+        match self.endian {
+            ElfEndian::LittleEndian => out.extend_from_slice(&val.to_le_bytes()),
+            ElfEndian::BigEndian => out.extend_from_slice(&val.to_be_bytes()),
+        }
+    }
+
+    pub fn u32(&self, out: &mut Vec<u8>, val: u32) {
+        match self.endian {
+            ElfEndian::LittleEndian => out.extend_from_slice(&val.to_le_bytes()),
+            ElfEndian::BigEndian => out.extend_from_slice(&val.to_be_bytes()),
+        }
+    }
+}
+
+//---------------------------------------------------------------------------------------------------------
+
+pub struct WriterSection {
+    pub name: String,
+    pub sh_type: SectionType,
+    pub flags: SectionFlags,
+    pub addr: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u32,
+    pub entsize: u32,
+    /// Contents of the section, empty for `SHT_NOBITS`
+    pub data: Vec<u8>,
+    /// Only used for `SHT_NOBITS` sections, the size of everything else is the size of `data`
+    pub nobits_size: u32,
+}
+
+impl WriterSection {
+    pub fn new(name: impl Into<String>, sh_type: SectionType, flags: SectionFlags) -> Self {
+        Self {
+            name: name.into(),
+            sh_type,
+            flags,
+            addr: 0,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+            data: Vec::new(),
+            nobits_size: 0,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        if self.sh_type == SectionType::SHT_NOBITS {
+            self.nobits_size
+        } else {
+            self.data.len() as u32
+        }
+    }
+}
+
+//---------------------------------------------------------------------------------------------------------
+
+/// A program header covering a run of consecutive sections
+///
+/// The sections must already have their addresses assigned and be in address order, their file
+/// offsets are padded so that they are congruent to their addresses modulo `align`
+pub struct WriterSegment {
+    pub p_type: ProgramHeaderType,
+    pub flags: ProgramHeaderFlags,
+    pub align: u32,
+    pub sections: RangeInclusive<u16>,
+}
+
+//---------------------------------------------------------------------------------------------------------
+
+/// Builds an ELF32 file in memory
+///
+/// Sections are numbered in the order they are added starting at 1, the null section and the
+/// section name table (added last) are created by the writer. The program header table, if there
+/// are any segments, directly follows the ELF header
+pub struct Elf32Writer {
+    encoder: Encoder,
+    elf_type: ElfType,
+    machine: ElfMachine,
+    flags: u32,
+    entry: u32,
+    sections: Vec<WriterSection>,
+    segments: Vec<WriterSegment>,
+}
+
+impl Elf32Writer {
+    pub fn new(endian: ElfEndian, elf_type: ElfType, machine: ElfMachine) -> Self {
+        Self {
+            encoder: Encoder { endian },
+            elf_type,
+            machine,
+            flags: 0,
+            entry: 0,
+            sections: Vec::new(),
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn encoder(&self) -> Encoder {
+        self.encoder
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.flags = flags;
+    }
+
+    pub fn set_entry(&mut self, entry: u32) {
+        self.entry = entry;
+    }
+
+    /// Adds a section and returns its index
+    pub fn add_section(&mut self, section: WriterSection) -> u16 {
+        self.sections.push(section);
+        self.sections.len() as u16
+    }
+
+    pub fn add_segment(&mut self, segment: WriterSegment) {
+        self.segments.push(segment);
+    }
+
+    pub fn section_mut(&mut self, index: u16) -> &mut WriterSection {
+        &mut self.sections[index as usize - 1]
+    }
+
+    /// The index the next section added will have
+    pub fn next_section_index(&self) -> u16 {
+        self.sections.len() as u16 + 1
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let enc = self.encoder;
+
+        let mut shstrtab = StringTable::new();
+        let names: Vec<u32> = self
+            .sections
+            .iter()
+            .map(|section| shstrtab.add(&section.name))
+            .collect();
+        let shstrtab_name = shstrtab.add(".shstrtab");
+        let shstrtab_index = self.sections.len() as u16 + 1;
+
+        // section contents follow the headers in order, then the section header table
+        let phoff = if self.segments.is_empty() {
+            0
+        } else {
+            ELF32_HEADER_SIZE
+        };
+        let headers_size =
+            ELF32_HEADER_SIZE + self.segments.len() as u32 * ELF32_PROGRAM_HEADER_SIZE;
+        let mut out = vec![0u8; headers_size as usize];
+        let mut offsets = Vec::new();
+        for (index, section) in (1..).zip(&self.sections) {
+            let align = section.addralign.max(1) as usize;
+            out.resize(out.len().div_ceil(align) * align, 0);
+            let segment = self
+                .segments
+                .iter()
+                .find(|segment| segment.sections.contains(&index));
+            if let Some(segment) = segment {
+                let align = segment.align.max(1);
+                let padding = section.addr.wrapping_sub(out.len() as u32) % align;
+                out.resize(out.len() + padding as usize, 0);
+            }
+            offsets.push(out.len() as u32);
+            if section.sh_type != SectionType::SHT_NOBITS {
+                out.extend_from_slice(&section.data);
+            }
+        }
+        let shstrtab_offset = out.len() as u32;
+        out.extend_from_slice(shstrtab.data());
+        out.resize(out.len().div_ceil(4) * 4, 0);
+        let shoff = out.len() as u32;
+
+        let mut program_headers = Vec::new();
+        for segment in &self.segments {
+            let first = *segment.sections.start() as usize - 1;
+            let last = *segment.sections.end() as usize - 1;
+            let sections = &self.sections[first..=last];
+            let start = sections[0].addr;
+            let end = sections
+                .iter()
+                .map(|s| s.addr + s.size())
+                .max()
+                .unwrap_or(start);
+            let file_end = (first..=last)
+                .filter(|index| self.sections[*index].sh_type != SectionType::SHT_NOBITS)
+                .map(|index| offsets[index] + self.sections[index].size())
+                .max()
+                .unwrap_or(offsets[first]);
+            enc.u32(&mut program_headers, segment.p_type.into());
+            enc.u32(&mut program_headers, offsets[first]);
+            enc.u32(&mut program_headers, start);
+            enc.u32(&mut program_headers, start);
+            enc.u32(
+                &mut program_headers,
+                file_end.saturating_sub(offsets[first]),
+            );
+            enc.u32(&mut program_headers, end - start);
+            enc.u32(&mut program_headers, segment.flags.bits() as u32);
+            enc.u32(&mut program_headers, segment.align);
+        }
+        out[ELF32_HEADER_SIZE as usize..headers_size as usize].copy_from_slice(&program_headers);
+
+        // null section
+        out.extend_from_slice(&[0; ELF32_SECTION_HEADER_SIZE as usize]);
+        for ((section, name), offset) in self.sections.iter().zip(names).zip(offsets) {
+            enc.u32(&mut out, name);
+            enc.u32(&mut out, section.sh_type.into());
+            enc.u32(&mut out, section.flags.bits() as u32);
+            enc.u32(&mut out, section.addr);
+            enc.u32(&mut out, offset);
+            enc.u32(&mut out, section.size());
+            enc.u32(&mut out, section.link);
+            enc.u32(&mut out, section.info);
+            enc.u32(&mut out, section.addralign);
+            enc.u32(&mut out, section.entsize);
+        }
+        enc.u32(&mut out, shstrtab_name);
+        enc.u32(&mut out, SectionType::SHT_STRTAB.into());
+        enc.u32(&mut out, 0);
+        enc.u32(&mut out, 0);
+        enc.u32(&mut out, shstrtab_offset);
+        enc.u32(&mut out, shstrtab.data().len() as u32);
+        enc.u32(&mut out, 0);
+        enc.u32(&mut out, 0);
+        enc.u32(&mut out, 1);
+        enc.u32(&mut out, 0);
+
+        let mut header = Vec::with_capacity(ELF32_HEADER_SIZE as usize);
+        header.extend_from_slice(b"\x7fELF");
+        header.push(ElfClass::Elf32.into());
+        header.push(enc.endian.into());
+        // version, System V ABI, ABI version and padding
+        header.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        enc.u16(&mut header, self.elf_type.into());
+        enc.u16(&mut header, self.machine.into());
+        enc.u32(&mut header, 1);
+        enc.u32(&mut header, self.entry);
+        enc.u32(&mut header, phoff);
+        enc.u32(&mut header, shoff);
+        enc.u32(&mut header, self.flags);
+        enc.u16(&mut header, ELF32_HEADER_SIZE as u16);
+        enc.u16(&mut header, ELF32_PROGRAM_HEADER_SIZE as u16);
+        enc.u16(&mut header, self.segments.len() as u16);
+        enc.u16(&mut header, ELF32_SECTION_HEADER_SIZE as u16);
+        enc.u16(&mut header, self.sections.len() as u16 + 2);
+        enc.u16(&mut header, shstrtab_index);
+        out[..ELF32_HEADER_SIZE as usize].copy_from_slice(&header);
+
+        out
+    }
+}
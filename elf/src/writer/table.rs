@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use crate::internal::relocation::MipsRelocationType;
+
+use super::Encoder;
+
+//---------------------------------------------------------------------------------------------------------
+
+/// A `SHT_STRTAB` section, identical strings share an offset
+pub struct StringTable {
+    data: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Self {
+            // offset 0 is always the empty string
+            data: vec![0],
+            offsets: HashMap::new(),
+        }
+    }
+
+    /// Adds `string` if it isn't already in the table and returns its offset
+    pub fn add(&mut self, string: &str) -> u32 {
+        if string.is_empty() {
+            return 0;
+        }
+        if let Some(offset) = self.offsets.get(string) {
+            return *offset;
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(string.as_bytes());
+        self.data.push(0);
+        self.offsets.insert(string.to_owned(), offset);
+        offset
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+//---------------------------------------------------------------------------------------------------------
+
+/// An entry of a `SHT_SYMTAB` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Elf32Symbol {
+    pub name: u32,
+    pub value: u32,
+    pub size: u32,
+    /// Binding in the upper 4 bits, type in the lower 4
+    pub info: u8,
+    /// Visibility
+    pub other: u8,
+    pub shndx: u16,
+}
+
+impl Elf32Symbol {
+    pub const SIZE: u32 = 16;
+
+    pub fn info(binding: u8, s_type: u8) -> u8 {
+        (binding << 4) | (s_type & 0xF)
+    }
+
+    /// Reads the symbol at the start of `data`, which must be at least `SIZE` bytes long
+    pub fn read(enc: Encoder, data: &[u8]) -> Self {
+        Self {
+            name: enc.read_u32(&data[0..]),
+            value: enc.read_u32(&data[4..]),
+            size: enc.read_u32(&data[8..]),
+            info: data[12],
+            other: data[13],
+            shndx: enc.read_u16(&data[14..]),
+        }
+    }
+
+    pub fn binding(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn s_type(&self) -> u8 {
+        self.info & 0xF
+    }
+
+    pub fn write(&self, enc: Encoder, out: &mut Vec<u8>) {
+        enc.u32(out, self.name);
+        enc.u32(out, self.value);
+        enc.u32(out, self.size);
+        out.push(self.info);
+        out.push(self.other);
+        enc.u16(out, self.shndx);
+    }
+}
+
+//---------------------------------------------------------------------------------------------------------
+
+/// An entry of a MIPS `SHT_REL` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elf32Rel {
+    pub offset: u32,
+    /// Index into the symbol table the section is linked to
+    pub symbol: u32,
+    pub r_type: MipsRelocationType,
+}
+
+impl Elf32Rel {
+    pub const SIZE: u32 = 8;
+
+    /// Reads the entry at the start of `data`, which must be at least `SIZE` bytes long
+    ///
+    /// Fails with the raw type if it isn't a known MIPS relocation
+    pub fn read(enc: Encoder, data: &[u8]) -> Result<Self, u8> {
+        let info = enc.read_u32(&data[4..]);
+        Ok(Self {
+            offset: enc.read_u32(&data[0..]),
+            symbol: info >> 8,
+            r_type: MipsRelocationType::try_from(info as u8).map_err(|_| info as u8)?,
+        })
+    }
+
+    pub fn write(&self, enc: Encoder, out: &mut Vec<u8>) {
+        enc.u32(out, self.offset);
+        enc.u32(out, (self.symbol << 8) | u8::from(self.r_type) as u32);
+    }
+}
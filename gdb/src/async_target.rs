@@ -1,5 +1,7 @@
 use std::sync::{Arc, Mutex};
 
+use mips_emulator::semihosting::SemihostingCall;
+
 use crate::{
     connection::Connection,
     signal::Signal,
@@ -20,6 +22,27 @@ impl<C: Connection, T: Target> GDBAsyncNotifier<C, T> {
         self.send_stub_stop_signal(reason);
     }
 
+    pub fn target_thread_stop_signal(&self, thread: u32, reason: StopReason) {
+        let mut stub = self.gdb.lock().unwrap();
+        if stub.is_target_running_or_inturrupt() && stub.target_thread_stop(thread, reason).is_err()
+        {
+            stub.detach_target_and_disconnect(DisconnectReason::Error);
+        }
+    }
+
+    /// Forwards a File-I/O request to the client, returns false if the client couldnt take it
+    pub fn target_file_io(&self, request: SemihostingCall) -> bool {
+        let mut stub = self.gdb.lock().unwrap();
+        if !stub.is_target_running_or_inturrupt() {
+            return false;
+        }
+        if stub.target_file_io(request).is_err() {
+            stub.detach_target_and_disconnect(DisconnectReason::Error);
+            return false;
+        }
+        true
+    }
+
     pub fn on_target_detach(&self) {
         self.gdb
             .lock()
@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+
+use crate::connection::Connection;
+
+#[derive(Debug)]
+pub enum ClientError<C: Connection> {
+    Connection(C::Error),
+    /// The stub answered with `Exx`
+    ErrorReply(u8),
+    UnexpectedReply(String),
+    InvalidCheckSum(u8, u8),
+    ServerSentNack,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReply {
+    Stopped {
+        signal: u8,
+        thread: Option<u32>,
+        /// The remaining `name:value;` pairs of a `T` reply
+        fields: Vec<(String, String)>,
+    },
+    Exited(u8),
+    Terminated(u8),
+}
+
+impl StopReply {
+    pub fn parse(reply: &str) -> Option<Self> {
+        let code = u8::from_str_radix(reply.get(1..3)?, 16).ok()?;
+        match reply.as_bytes()[0] {
+            b'S' => Some(StopReply::Stopped {
+                signal: code,
+                thread: None,
+                fields: Vec::new(),
+            }),
+            b'T' => {
+                let mut thread = None;
+                let mut fields = Vec::new();
+                for field in reply[3..].split(';').filter(|field| !field.is_empty()) {
+                    let (name, value) = field.split_once(':')?;
+                    if name == "thread" {
+                        thread = Some(u32::from_str_radix(value, 16).ok()?);
+                    } else {
+                        fields.push((name.into(), value.into()));
+                    }
+                }
+                Some(StopReply::Stopped {
+                    signal: code,
+                    thread,
+                    fields,
+                })
+            }
+            b'W' => Some(StopReply::Exited(code)),
+            b'X' => Some(StopReply::Terminated(code)),
+            _ => None,
+        }
+    }
+}
+
+/// The client side of the remote protocol, this talks to a `GDBStub` (or any other gdbserver) over a `Connection`
+pub struct GdbClient<C: Connection> {
+    connection: C,
+    no_ack_mode: bool,
+    /// Notifications (`%name:data`) received while waiting for a reply
+    notifications: VecDeque<String>,
+}
+
+impl<C: Connection> GdbClient<C> {
+    /// Starts a session and negotiates the features the stub supports
+    pub fn connect(mut connection: C) -> Result<Self, ClientError<C>> {
+        connection
+            .on_session_start()
+            .map_err(ClientError::Connection)?;
+        let mut client = Self {
+            connection,
+            no_ack_mode: false,
+            notifications: VecDeque::new(),
+        };
+
+        let supported = client.request("qSupported:swbreak+;vContSupported+")?;
+        if supported
+            .split(';')
+            .any(|feature| feature == "QStartNoAckMode+")
+        {
+            client.request_ok("QStartNoAckMode")?;
+            client.no_ack_mode = true;
+        }
+        Ok(client)
+    }
+
+    pub fn connection(&mut self) -> &mut C {
+        &mut self.connection
+    }
+
+    /// Sends a packet and waits for the stubs reply
+    pub fn request(&mut self, packet: &str) -> Result<String, ClientError<C>> {
+        self.send(packet)?;
+        self.receive()
+    }
+
+    /// Sends a packet without waiting for a reply, used for packets that are answered later (e.g. continue)
+    pub fn send(&mut self, packet: &str) -> Result<(), ClientError<C>> {
+        let check_sum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let msg = format!("${packet}#{check_sum:02x}");
+        log::trace!("--> {}", msg);
+        self.connection
+            .write_all(msg.as_bytes())
+            .map_err(ClientError::Connection)?;
+        self.connection.flush().map_err(ClientError::Connection)?;
+
+        if !self.no_ack_mode {
+            match self.read_byte()? {
+                b'+' => {}
+                b'-' => Err(ClientError::ServerSentNack)?,
+                byte => Err(ClientError::UnexpectedReply((byte as char).into()))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the next packet from the stub, notifications are queued and skipped
+    pub fn receive(&mut self) -> Result<String, ClientError<C>> {
+        loop {
+            let (header, body) = self.read_packet()?;
+            if header == b'%' {
+                self.notifications.push_back(body);
+            } else {
+                return Ok(body);
+            }
+        }
+    }
+
+    /// Returns the next notification, waiting for one if none have been received yet
+    pub fn receive_notification(&mut self) -> Result<String, ClientError<C>> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(notification);
+        }
+        loop {
+            let (header, body) = self.read_packet()?;
+            if header == b'%' {
+                return Ok(body);
+            }
+            Err(ClientError::UnexpectedReply(body))?
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, ClientError<C>> {
+        self.connection.read().map_err(ClientError::Connection)
+    }
+
+    fn read_packet(&mut self) -> Result<(u8, String), ClientError<C>> {
+        let header = loop {
+            match self.read_byte()? {
+                // stray acks (or acks we dont wait for) are skipped
+                b'+' => {}
+                header @ (b'$' | b'%') => break header,
+                byte => Err(ClientError::UnexpectedReply((byte as char).into()))?,
+            }
+        };
+
+        let mut body = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => body.push(byte),
+            }
+        }
+        let check_sum = [self.read_byte()?, self.read_byte()?];
+        let check_sum = std::str::from_utf8(&check_sum)
+            .ok()
+            .and_then(|check_sum| u8::from_str_radix(check_sum, 16).ok())
+            .ok_or(ClientError::UnexpectedReply(
+                String::from_utf8_lossy(&body).into(),
+            ))?;
+        let calculated = body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if check_sum != calculated {
+            if !self.no_ack_mode {
+                self.connection
+                    .write(b'-')
+                    .map_err(ClientError::Connection)?;
+            }
+            Err(ClientError::InvalidCheckSum(check_sum, calculated))?
+        }
+        // notifications are never acknowledged
+        if !self.no_ack_mode && header == b'$' {
+            self.connection
+                .write(b'+')
+                .map_err(ClientError::Connection)?;
+            self.connection.flush().map_err(ClientError::Connection)?;
+        }
+
+        let body = String::from_utf8_lossy(&body).into_owned();
+        log::trace!("<-- {}{}", header as char, body);
+        Ok((header, body))
+    }
+
+    fn request_ok(&mut self, packet: &str) -> Result<(), ClientError<C>> {
+        let reply = self.request(packet)?;
+        Self::check_error(&reply)?;
+        if reply == "OK" {
+            Ok(())
+        } else {
+            Err(ClientError::UnexpectedReply(reply))
+        }
+    }
+
+    fn check_error(reply: &str) -> Result<(), ClientError<C>> {
+        match reply
+            .strip_prefix('E')
+            .map(|code| u8::from_str_radix(code, 16))
+        {
+            Some(Ok(code)) if reply.len() == 3 => Err(ClientError::ErrorReply(code)),
+            _ => Ok(()),
+        }
+    }
+
+    fn decode_hex(reply: &str) -> Result<Vec<u8>, ClientError<C>> {
+        if !reply.len().is_multiple_of(2) {
+            Err(ClientError::UnexpectedReply(reply.into()))?
+        }
+        (0..reply.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&reply[i..i + 2], 16)
+                    .map_err(|_| ClientError::UnexpectedReply(reply.into()))
+            })
+            .collect()
+    }
+
+    fn stop_reply(reply: String) -> Result<StopReply, ClientError<C>> {
+        Self::check_error(&reply)?;
+        StopReply::parse(&reply).ok_or(ClientError::UnexpectedReply(reply))
+    }
+
+    /// Asks why the target is stopped (`?`)
+    pub fn stop_reason(&mut self) -> Result<StopReply, ClientError<C>> {
+        let reply = self.request("?")?;
+        Self::stop_reply(reply)
+    }
+
+    pub fn read_registers(&mut self) -> Result<Vec<u32>, ClientError<C>> {
+        let reply = self.request("g")?;
+        Self::check_error(&reply)?;
+        Ok(Self::decode_hex(&reply)?
+            .chunks_exact(4)
+            .map(|reg| u32::from_be_bytes([reg[0], reg[1], reg[2], reg[3]]))
+            .collect())
+    }
+
+    pub fn read_register(&mut self, reg: u8) -> Result<u32, ClientError<C>> {
+        let reply = self.request(&format!("p{reg:x}"))?;
+        Self::check_error(&reply)?;
+        let bytes = Self::decode_hex(&reply)?;
+        let bytes: [u8; 4] = bytes
+            .try_into()
+            .map_err(|_| ClientError::UnexpectedReply(reply))?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn write_register(&mut self, reg: u8, value: u32) -> Result<(), ClientError<C>> {
+        self.request_ok(&format!("P{reg:x}={value:08x}"))
+    }
+
+    pub fn read_memory(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, ClientError<C>> {
+        let reply = self.request(&format!("m{addr:x},{len:x}"))?;
+        Self::check_error(&reply)?;
+        Self::decode_hex(&reply)
+    }
+
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), ClientError<C>> {
+        let data: String = data.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.request_ok(&format!("M{addr:x},{:x}:{data}", data.len() / 2))
+    }
+
+    pub fn insert_breakpoint(&mut self, addr: u32) -> Result<(), ClientError<C>> {
+        self.request_ok(&format!("Z0,{addr:x},4"))
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u32) -> Result<(), ClientError<C>> {
+        self.request_ok(&format!("z0,{addr:x},4"))
+    }
+
+    /// Resumes every thread without waiting for it to stop, see `wait_for_stop`
+    pub fn resume(&mut self) -> Result<(), ClientError<C>> {
+        self.send("vCont;c")
+    }
+
+    /// Steps `thread` without waiting for it to stop, see `wait_for_stop`
+    pub fn resume_step(&mut self, thread: u32) -> Result<(), ClientError<C>> {
+        self.send(&format!("vCont;s:{thread:x}"))
+    }
+
+    pub fn wait_for_stop(&mut self) -> Result<StopReply, ClientError<C>> {
+        let reply = self.receive()?;
+        Self::stop_reply(reply)
+    }
+
+    /// Resumes every thread and waits until the target stops again
+    pub fn continue_execution(&mut self) -> Result<StopReply, ClientError<C>> {
+        self.resume()?;
+        self.wait_for_stop()
+    }
+
+    /// Steps `thread` and waits until it stops again
+    pub fn step(&mut self, thread: u32) -> Result<StopReply, ClientError<C>> {
+        self.resume_step(thread)?;
+        self.wait_for_stop()
+    }
+
+    /// Sends a Ctrl-C to the stub, the stop is received with `wait_for_stop`
+    pub fn interrupt(&mut self) -> Result<(), ClientError<C>> {
+        self.connection
+            .write(0x03)
+            .map_err(ClientError::Connection)?;
+        self.connection.flush().map_err(ClientError::Connection)
+    }
+
+    pub fn threads(&mut self) -> Result<Vec<u32>, ClientError<C>> {
+        let mut threads = Vec::new();
+        let mut reply = self.request("qfThreadInfo")?;
+        while let Some(ids) = reply.strip_prefix('m') {
+            for id in ids.split(',') {
+                threads.push(
+                    u32::from_str_radix(id, 16)
+                        .map_err(|_| ClientError::UnexpectedReply(reply.clone()))?,
+                );
+            }
+            reply = self.request("qsThreadInfo")?;
+        }
+        Ok(threads)
+    }
+
+    /// Ends the session and kills the target
+    pub fn kill(mut self) -> Result<(), ClientError<C>> {
+        self.send("k")?;
+        // the stub may already have closed its end
+        let _ = self.connection.on_session_end();
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use std::net::TcpStream;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{
+        mpsc::{Receiver, TryRecvError},
+        Mutex,
+    },
+};
 
 pub trait Connection {
     type Error: std::fmt::Debug;
@@ -83,3 +91,167 @@ impl Connection for TcpStream {
         Some(format!("{{\n\tlocal: {}\n\tpeer:  {}\n}}", local, peer))
     }
 }
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        Write::write_all(self, &[byte])
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        Write::write_all(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Write::flush(self)
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        self.set_nonblocking(false)?;
+
+        let mut buf = [0u8];
+        Read::read_exact(self, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        self.set_nonblocking(true)?;
+
+        let mut buf = [0u8];
+        match Self::peek(self, &mut buf) {
+            Ok(_) => Ok(Some(buf[0])),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn on_session_end(&mut self) -> Result<(), Self::Error> {
+        self.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn string_repr(&self) -> Option<String> {
+        let local = self.local_addr().ok()?;
+        let peer = self.peer_addr().ok()?;
+        Some(format!("{{\n\tlocal: {:?}\n\tpeer:  {:?}\n}}", local, peer))
+    }
+}
+
+/// A connection over a pair of byte streams, e.g. the stdin/stdout of the emulator when gdb launches it with `target remote | ...`.
+///
+/// The reading end is drained by a background thread so `peek` never blocks
+#[derive(Debug)]
+pub struct PipeConnection<W: Write> {
+    /// Only in a mutex so the connection is `Sync`, it is never actually shared
+    incoming: Mutex<Receiver<std::io::Result<Vec<u8>>>>,
+    buffer: VecDeque<u8>,
+    output: W,
+    name: String,
+}
+
+pub type StdioConnection = PipeConnection<std::io::Stdout>;
+
+impl StdioConnection {
+    /// Nothing else may write to stdout while this connection is in use (including loggers)
+    pub fn stdio() -> Self {
+        Self::new(std::io::stdin(), std::io::stdout(), "stdio")
+    }
+}
+
+impl<W: Write> PipeConnection<W> {
+    pub fn new(mut input: impl Read + Send + 'static, output: W, name: impl Into<String>) -> Self {
+        let (sender, incoming) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            loop {
+                let res = match input.read(&mut buf) {
+                    // the sender is dropped on eof, which the connection reports as an error
+                    Ok(0) => break,
+                    Ok(len) => Ok(buf[..len].to_vec()),
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = res.is_err();
+                if sender.send(res).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            incoming: Mutex::new(incoming),
+            buffer: VecDeque::new(),
+            output,
+            name: name.into(),
+        }
+    }
+
+    fn fill_buffer(&mut self, blocking: bool) -> Result<(), std::io::Error> {
+        if !self.buffer.is_empty() {
+            return Ok(());
+        }
+        let incoming = self
+            .incoming
+            .get_mut()
+            .map_err(|_| std::io::Error::other("pipe reader poisoned"))?;
+        let data = if blocking {
+            incoming
+                .recv()
+                .map_err(|_| std::io::ErrorKind::UnexpectedEof)?
+        } else {
+            match incoming.try_recv() {
+                Ok(data) => data,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => Err(std::io::ErrorKind::UnexpectedEof)?,
+            }
+        };
+        self.buffer.extend(data?);
+        Ok(())
+    }
+}
+
+impl<W: Write> Connection for PipeConnection<W> {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.output.write_all(&[byte])
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.output.write_all(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.output.flush()
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_session_end(&mut self) -> Result<(), Self::Error> {
+        self.output.flush()
+    }
+
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        loop {
+            self.fill_buffer(true)?;
+            if let Some(byte) = self.buffer.pop_front() {
+                return Ok(byte);
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        self.fill_buffer(false)?;
+        Ok(self.buffer.front().copied())
+    }
+
+    fn string_repr(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+}
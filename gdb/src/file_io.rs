@@ -0,0 +1,74 @@
+use mips_emulator::semihosting::SemihostingCall;
+
+use crate::packets::incoming::CommandParseError;
+
+/// Formats a File-I/O request the target makes of the gdb client as the body of an `F` packet
+/// (without the leading `F`).
+///
+/// Pointers and lengths refer to target memory, the client reads and writes it with the regular memory packets
+pub fn request_packet(call: &SemihostingCall) -> String {
+    match *call {
+        SemihostingCall::Open {
+            path,
+            path_len,
+            flags,
+            mode,
+        } => format!("open,{path:x}/{path_len:x},{flags:x},{mode:x}"),
+        SemihostingCall::Close { fd } => format!("close,{fd:x}"),
+        SemihostingCall::Read { fd, buf, count } => format!("read,{fd:x},{buf:x},{count:x}"),
+        SemihostingCall::Write { fd, buf, count } => format!("write,{fd:x},{buf:x},{count:x}"),
+        SemihostingCall::Lseek { fd, offset, whence } => {
+            let sign = if offset < 0 { "-" } else { "" };
+            let offset = offset.unsigned_abs();
+            format!("lseek,{fd:x},{sign}{offset:x},{whence:x}")
+        }
+        SemihostingCall::GetTimeOfDay { tv, tz } => format!("gettimeofday,{tv:x},{tz:x}"),
+        SemihostingCall::IsATty { fd } => format!("isatty,{fd:x}"),
+        SemihostingCall::System {
+            command,
+            command_len,
+        } => format!("system,{command:x}/{command_len:x}"),
+    }
+}
+
+/// The clients answer to a File-I/O request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileIoReply {
+    /// The return value of the call, `-1` on failure
+    pub retcode: i64,
+    /// Only present when the call failed
+    pub errno: Option<u32>,
+    /// The user pressed Ctrl-C while the call was being serviced, the target should stop with SIGINT
+    pub ctrl_c: bool,
+}
+
+impl FileIoReply {
+    /// Parses the body of an `F` reply (without the leading `F`), `retcode[,errno[,C]][;attachment]`
+    pub fn parse(args: &str) -> Result<Self, CommandParseError> {
+        let args = args.split_once(';').map_or(args, |(args, _)| args);
+        let mut parts = args.split(',');
+
+        let retcode = parts.next().ok_or(CommandParseError::MalformedCommand)?;
+        let retcode = if let Some(retcode) = retcode.strip_prefix('-') {
+            -i64::from_str_radix(retcode, 16).map_err(CommandParseError::ParseIntError)?
+        } else {
+            i64::from_str_radix(retcode, 16).map_err(CommandParseError::ParseIntError)?
+        };
+        let errno = parts
+            .next()
+            .map(|errno| u32::from_str_radix(errno, 16))
+            .transpose()
+            .map_err(CommandParseError::ParseIntError)?;
+        let ctrl_c = match parts.next() {
+            Some("C") => true,
+            Some(_) => Err(CommandParseError::MalformedCommand)?,
+            None => false,
+        };
+
+        Ok(Self {
+            retcode,
+            errno,
+            ctrl_c,
+        })
+    }
+}
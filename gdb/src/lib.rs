@@ -1,6 +1,11 @@
+#![cfg_attr(unix, feature(unix_socket_peek))]
+
 pub mod async_target;
+pub mod client;
 pub mod connection;
+pub mod file_io;
 pub mod packets;
 pub mod signal;
 pub mod stub;
 pub mod target;
+pub mod transport;
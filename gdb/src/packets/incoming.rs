@@ -1,6 +1,6 @@
 use std::{num::ParseIntError, str::Utf8Error};
 
-use crate::signal::Signal;
+use crate::{file_io::FileIoReply, signal::Signal};
 
 #[derive(Debug)]
 pub enum Packet {
@@ -72,12 +72,85 @@ pub enum Command {
     qProcessInfo,
     qRegisterInfo(u8),
     qMemoryRegionInfo(u32),
+    /// `offset`, `length` of the memory map xml to send
+    qXferMemoryMapRead(usize, usize),
 
-    SelectExecutionThread(u8),
-    SelectRegisterThread(u8),
-    SelectMemoryThread(u8),
+    SelectExecutionThread(ThreadId),
+    SelectRegisterThread(ThreadId),
+    SelectMemoryThread(ThreadId),
+    ThreadAlive(u32),
+
+    vContQuery,
+    vCont(Vec<(VContAction, ThreadId)>),
+    vStopped,
+    QNonStop(bool),
 
     QStartNoAckMode,
+    QCatchSyscalls(CatchSyscalls),
+
+    FileIoReply(FileIoReply),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadId {
+    /// `-1`
+    All,
+    /// `0`
+    Any,
+    Id(u32),
+}
+
+impl ThreadId {
+    fn parse(arg: &str) -> Result<Self, CommandParseError> {
+        match arg {
+            "-1" => Ok(ThreadId::All),
+            "0" => Ok(ThreadId::Any),
+            arg => u32::from_str_radix(arg, 16)
+                .map(ThreadId::Id)
+                .map_err(CommandParseError::ParseIntError),
+        }
+    }
+
+    pub fn matches(&self, thread: u32) -> bool {
+        match self {
+            ThreadId::All | ThreadId::Any => true,
+            ThreadId::Id(id) => *id == thread,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum VContAction {
+    Continue,
+    ContinueSignal(Signal),
+    Step,
+    StepSignal(Signal),
+    Stop,
+}
+
+impl VContAction {
+    fn parse(action: &str) -> Result<Self, CommandParseError> {
+        let signal = |sig: &str| {
+            u8::from_str_radix(sig, 16)
+                .map(Signal::from_protocol_u8)
+                .map_err(CommandParseError::ParseIntError)
+        };
+        Ok(match action.split_at(action.len().min(1)) {
+            ("c", "") => VContAction::Continue,
+            ("s", "") => VContAction::Step,
+            ("t", "") => VContAction::Stop,
+            ("C", sig) => VContAction::ContinueSignal(signal(sig)?),
+            ("S", sig) => VContAction::StepSignal(signal(sig)?),
+            _ => Err(CommandParseError::MalformedCommand)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum CatchSyscalls {
+    Disabled,
+    All,
+    Only(Vec<u32>),
 }
 
 #[derive(Debug)]
@@ -87,6 +160,25 @@ pub enum CommandParseError {
     MalformedCommand,
 }
 
+/// The `kind` and address of a `Z`/`z` packet (`type,addr,kind`), `None` for watchpoints
+///
+/// Hardware breakpoints (type 1, which gdb uses for rom) are set the same way as software ones
+/// since no memory is actually read only
+fn parse_breakpoint(args: &str) -> Result<Option<(u8, u32)>, CommandParseError> {
+    let (ty, args) = args
+        .split_once(',')
+        .ok_or(CommandParseError::MalformedCommand)?;
+    if ty != "0" && ty != "1" {
+        return Ok(None);
+    }
+    let (addr, kind) = args
+        .split_once(',')
+        .ok_or(CommandParseError::MalformedCommand)?;
+    let addr = u32::from_str_radix(addr, 16).map_err(CommandParseError::ParseIntError)?;
+    let kind = u8::from_str_radix(kind, 16).map_err(CommandParseError::ParseIntError)?;
+    Ok(Some((kind, addr)))
+}
+
 impl Command {
     pub fn from_buf(buf: &[u8]) -> Result<Self, CommandParseError> {
         macro_rules! create_command {
@@ -163,6 +255,12 @@ impl Command {
             "g" => Command::ReadRegisters,
             'G' = _args => {panic!()},
             'p' = args => u8::from_str_radix(args, 16).map(Command::ReadRegister).map_err(CommandParseError::ParseIntError)?,
+            'P' = args => {
+                let (reg, value) = args.split_once('=').ok_or(CommandParseError::MalformedCommand)?;
+                let reg = u8::from_str_radix(reg, 16).map_err(CommandParseError::ParseIntError)?;
+                let value = u32::from_str_radix(value, 16).map_err(CommandParseError::ParseIntError)?;
+                Command::WriteRegister(reg, value)
+            },
 
             'm' = args => {
                 let (add, len) = args.split_once(',').map_or(Err(CommandParseError::MalformedCommand), Ok)?;
@@ -190,20 +288,18 @@ impl Command {
                 }
             },
 
+            'F' = args => Command::FileIoReply(FileIoReply::parse(args)?),
+
             "r" => Command::Reset,
             "k" => Command::Kill,
 
-            "z0," = args => {
-                let (addr, kind) = args.split_once(',').ok_or(CommandParseError::MalformedCommand)?;
-                let addr = u32::from_str_radix(addr, 16).map_err(CommandParseError::ParseIntError)?;
-                let kind = u8::from_str_radix(kind, 16).map_err(CommandParseError::ParseIntError)?;
-                Command::RemoveSoftwareBreakpoint(kind, addr)
+            'z' = args => match parse_breakpoint(args)? {
+                Some((kind, addr)) => Command::RemoveSoftwareBreakpoint(kind, addr),
+                None => Command::Unreconized,
             },
-            "Z0," = args => {
-                let (addr, kind) = args.split_once(',').ok_or(CommandParseError::MalformedCommand)?;
-                let addr = u32::from_str_radix(addr, 16).map_err(CommandParseError::ParseIntError)?;
-                let kind = u8::from_str_radix(kind, 16).map_err(CommandParseError::ParseIntError)?;
-                Command::InsertSoftwareBreakpoint(kind, addr)
+            'Z' = args => match parse_breakpoint(args)? {
+                Some((kind, addr)) => Command::InsertSoftwareBreakpoint(kind, addr),
+                None => Command::Unreconized,
             },
 
 
@@ -240,9 +336,29 @@ impl Command {
 
             "vMustReplyEmpty" => Command::MustReplayEmpty,
 
-            "Hc" = arg => u8::from_str_radix(arg.trim_start_matches('-'), 16).map(Command::SelectExecutionThread).map_err(CommandParseError::ParseIntError)?,
-            "Hg" = arg => u8::from_str_radix(arg.trim_start_matches('-'), 16).map(Command::SelectRegisterThread).map_err(CommandParseError::ParseIntError)?,
-            "Hm" = arg => u8::from_str_radix(arg.trim_start_matches('-'), 16).map(Command::SelectMemoryThread).map_err(CommandParseError::ParseIntError)?,
+            "Hc" = arg => Command::SelectExecutionThread(ThreadId::parse(arg)?),
+            "Hg" = arg => Command::SelectRegisterThread(ThreadId::parse(arg)?),
+            "Hm" = arg => Command::SelectMemoryThread(ThreadId::parse(arg)?),
+            'T' = arg => u32::from_str_radix(arg, 16).map(Command::ThreadAlive).map_err(CommandParseError::ParseIntError)?,
+
+            "vCont?" => Command::vContQuery,
+            "vCont;" = args => {
+                let mut actions = Vec::new();
+                for action in args.split(';') {
+                    let (action, thread) = match action.split_once(':') {
+                        Some((action, thread)) => (action, ThreadId::parse(thread)?),
+                        None => (action, ThreadId::All),
+                    };
+                    actions.push((VContAction::parse(action)?, thread));
+                }
+                Command::vCont(actions)
+            },
+            "vStopped" => Command::vStopped,
+            "QNonStop:" = arg => match arg {
+                "0" => Command::QNonStop(false),
+                "1" => Command::QNonStop(true),
+                _ => Err(CommandParseError::MalformedCommand)?,
+            },
 
             "qSupported:" = raw_args => {
                 let mut args: Vec<(String, bool, Option<String>)> = Vec::new();
@@ -272,12 +388,34 @@ impl Command {
             "qHostInfo" => Command::qHostInfo,
             "qProcessInfo" => Command::qProcessInfo,
             "qRegisterInfo" = arg => u8::from_str_radix(arg, 16).map(Command::qRegisterInfo).map_err(CommandParseError::ParseIntError)?,
-            "qMemoryRegionInfo:" = arg => {
-                let page = u32::from_str_radix(arg, 16).map_err(CommandParseError::ParseIntError)?;
-                Command::qMemoryRegionInfo(page & !0xFFFF)
+            "qMemoryRegionInfo:" = arg => u32::from_str_radix(arg, 16).map(Command::qMemoryRegionInfo).map_err(CommandParseError::ParseIntError)?,
+            "qXfer:memory-map:read::" = args => {
+                let (offset, length) = args.split_once(',').ok_or(CommandParseError::MalformedCommand)?;
+                Command::qXferMemoryMapRead(
+                    usize::from_str_radix(offset, 16).map_err(CommandParseError::ParseIntError)?,
+                    usize::from_str_radix(length, 16).map_err(CommandParseError::ParseIntError)?,
+                )
             },
 
-            "QStartNoAckMode" => Command::QStartNoAckMode
+            "QStartNoAckMode" => Command::QStartNoAckMode,
+            "QCatchSyscalls:" = args => {
+                let mut args = args.split(';');
+                match args.next() {
+                    Some("0") => Command::QCatchSyscalls(CatchSyscalls::Disabled),
+                    Some("1") => {
+                        let ids = args
+                            .map(|id| u32::from_str_radix(id, 16))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(CommandParseError::ParseIntError)?;
+                        if ids.is_empty() {
+                            Command::QCatchSyscalls(CatchSyscalls::All)
+                        } else {
+                            Command::QCatchSyscalls(CatchSyscalls::Only(ids))
+                        }
+                    }
+                    _ => Err(CommandParseError::MalformedCommand)?,
+                }
+            }
         ))
     }
 }
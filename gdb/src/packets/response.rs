@@ -2,6 +2,7 @@ use crate::connection::Connection;
 
 pub struct ResponseWritter<'a, C: Connection> {
     conn: &'a mut C,
+    header: u8,
 
     started: bool,
     check_sum: u8,
@@ -22,7 +23,7 @@ impl<'a, C: Connection> ResponseWritter<'a, C> {
     fn inner_write(&mut self, byte: u8) -> Result<(), C::Error> {
         if !self.started {
             self.started = true;
-            self.msg.push(b'$');
+            self.msg.push(self.header);
             //self.conn.write(b'$')?;
         }
         self.msg.push(byte);
@@ -57,6 +58,19 @@ impl<'a, C: Connection> ResponseWritter<'a, C> {
         Ok(())
     }
 
+    /// Writes binary data, escaping the bytes that have a meaning in the protocol
+    pub fn write_binary(&mut self, data: &[u8]) -> Result<(), C::Error> {
+        for &b in data {
+            if matches!(b, b'#' | b'$' | b'}' | b'*') {
+                self.write(b'}')?;
+                self.write(b ^ 0x20)?;
+            } else {
+                self.write(b)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn write(&mut self, byte: u8) -> Result<(), C::Error> {
         self.inner_write(byte)
     }
@@ -64,9 +78,18 @@ impl<'a, C: Connection> ResponseWritter<'a, C> {
     pub fn new(conn: &'a mut C) -> Self {
         Self {
             conn,
+            header: b'$',
             started: false,
             check_sum: 0,
             msg: Default::default(),
         }
     }
+
+    /// Creates a writter for an asynchronous notification (`%name:data#cs`)
+    pub fn new_notification(conn: &'a mut C) -> Self {
+        Self {
+            header: b'%',
+            ..Self::new(conn)
+        }
+    }
 }
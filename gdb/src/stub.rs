@@ -1,12 +1,17 @@
+use std::collections::{HashSet, VecDeque};
+
+use mips_emulator::semihosting::SemihostingCall;
+
 use crate::{
     connection::Connection,
+    file_io,
     packets::{
-        incoming::{Command, Packet, PacketParseError},
+        incoming::{CatchSyscalls, Command, Packet, PacketParseError, ThreadId, VContAction},
         psm::PacketStateMachine,
         response::ResponseWritter,
     },
     signal::Signal,
-    target::Target,
+    target::{MemoryKind, MemoryRegion, ResumeAction, Target},
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -41,12 +46,18 @@ pub enum DisconnectReason {
 
 struct GDBStubCfg {
     no_ack_mode: bool,
+    non_stop: bool,
+    catch_syscalls: CatchSyscalls,
 }
 
 #[allow(clippy::derivable_impls)]
 impl Default for GDBStubCfg {
     fn default() -> Self {
-        Self { no_ack_mode: false }
+        Self {
+            no_ack_mode: false,
+            non_stop: false,
+            catch_syscalls: CatchSyscalls::Disabled,
+        }
     }
 }
 
@@ -57,6 +68,14 @@ pub struct GDBStub<C: Connection, T: Target> {
     ptm: PacketStateMachine,
     cfg: GDBStubCfg,
     async_data: Vec<String>,
+    file_io_pending: bool,
+    current_thread: u32,
+    /// Threads resumed by the client that havnt reported a stop yet (non-stop mode)
+    running_threads: HashSet<u32>,
+    /// Threads the client asked to stop with `vCont;t`
+    stop_requested: HashSet<u32>,
+    /// Stop replies waiting to be fetched with `vStopped`, the first one has already been notified
+    stop_queue: VecDeque<String>,
     packets_sent: usize,
     packets_receved: usize,
     bytes_sent: usize,
@@ -76,6 +95,12 @@ pub enum StopReason {
     Terminated(Signal),
     SwBreak,
     HwBreak,
+    /// The target is about to execute the syscall with this id
+    SyscallEntry(u32),
+    /// The target has just finished the syscall with this id
+    SyscallReturn(u32),
+    /// The target faulted, the faulting address is reported through the `bad` register
+    Fault(Signal, u32),
 }
 
 impl<C: Connection, T: Target> GDBStub<C, T> {
@@ -87,6 +112,11 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
             ptm: PacketStateMachine::new(),
             cfg: Default::default(),
             async_data: Vec::new(),
+            file_io_pending: false,
+            current_thread: 1,
+            running_threads: HashSet::new(),
+            stop_requested: HashSet::new(),
+            stop_queue: VecDeque::new(),
             packets_sent: 0,
             packets_receved: 0,
             bytes_sent: 0,
@@ -122,6 +152,18 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
         self.state
     }
 
+    pub fn is_catching_syscalls(&self) -> bool {
+        !matches!(self.cfg.catch_syscalls, CatchSyscalls::Disabled)
+    }
+
+    pub fn catches_syscall(&self, id: u32) -> bool {
+        match &self.cfg.catch_syscalls {
+            CatchSyscalls::Disabled => false,
+            CatchSyscalls::All => true,
+            CatchSyscalls::Only(ids) => ids.contains(&id),
+        }
+    }
+
     pub fn check_non_blocking(&mut self) -> Result<Option<DisconnectReason>, GDBError<C, T>> {
         let res = if self.has_data_to_read() {
             match self.state {
@@ -152,56 +194,162 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
     }
 
     pub fn target_stop(&mut self, reason: StopReason) -> Result<(), GDBError<C, T>> {
+        self.target_thread_stop(self.current_thread, reason)
+    }
+
+    /// Reports that `thread` stopped.
+    ///
+    /// In non-stop mode the reply is sent as a `%Stop` notification (or queued until the client asks for it with `vStopped`),
+    /// stops of threads the client didnt resume are ignored
+    pub fn target_thread_stop(
+        &mut self,
+        thread: u32,
+        reason: StopReason,
+    ) -> Result<(), GDBError<C, T>> {
         if let GDBState::Disconnected(reason) = self.state {
             return Err(GDBError::NotConnected(reason));
         }
-        let mut res = ResponseWritter::new(&mut self.connection);
-        match reason {
-            StopReason::DoneStep => {
-                res.write(b'S').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(Signal::SIGTRAP as u8)
-                    .map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Idle;
-            }
-            StopReason::Signal(sig) => {
-                res.write(b'S').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(sig as u8)
-                    .map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Idle;
+        let reason = match reason {
+            // threads stopped with vCont;t report signal 0
+            StopReason::Signal(_) | StopReason::DoneStep if self.stop_requested.remove(&thread) => {
+                StopReason::Signal(Signal::SIGZERO)
             }
-            StopReason::Exited(code) => {
-                res.write(b'W').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(code).map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Disconnected(DisconnectReason::TargetExited(code));
+            reason => reason,
+        };
+        let (reply, state) = Self::stop_reply(thread, reason);
+
+        if self.cfg.non_stop {
+            if !self.running_threads.remove(&thread) {
+                return Ok(());
             }
-            StopReason::Terminated(sig) => {
-                res.write(b'X').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(sig as u8)
+            self.state = match state {
+                GDBState::Disconnected(_) => state,
+                _ if self.running_threads.is_empty() => GDBState::Idle,
+                _ => GDBState::Running,
+            };
+            self.stop_queue.push_back(reply);
+            if self.stop_queue.len() == 1 {
+                let mut res = ResponseWritter::new_notification(&mut self.connection);
+                res.write_str("Stop:").map_err(GDBError::ConnectionWrite)?;
+                res.write_str(&self.stop_queue[0])
                     .map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Disconnected(DisconnectReason::TargetTerminated(sig));
+                let len = res.flush().map_err(GDBError::ConnectionWrite)?;
+                self.bytes_sent += len;
+                self.packets_sent += 1;
             }
-            StopReason::SwBreak => {
-                //self.target.sw_breakpoint_hit();
-                res.write(b'S').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(Signal::SIGTRAP as u8)
-                    .map_err(GDBError::ConnectionWrite)?;
-                // res.write_str("swbreak:;")
-                //     .map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Idle;
+            return Ok(());
+        }
+
+        self.running_threads.clear();
+        self.stop_requested.clear();
+        let mut res = ResponseWritter::new(&mut self.connection);
+        res.write_str(&reply).map_err(GDBError::ConnectionWrite)?;
+        self.state = state;
+        let len = res.flush().map_err(GDBError::ConnectionWrite)?;
+        self.bytes_sent += len;
+        self.packets_sent += 1;
+        //self.async_data.push(buff);
+        Ok(())
+    }
+
+    /// Describes the region `addr` is in, or the unmapped gap around it, as a `qMemoryRegionInfo` reply
+    fn memory_region_info(map: &[MemoryRegion], addr: u32) -> String {
+        if let Some(region) = map.iter().find(|region| region.contains(addr)) {
+            let permissions: String = [
+                (region.read, 'r'),
+                (region.write, 'w'),
+                (region.execute, 'x'),
+            ]
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .map(|(_, c)| c)
+            .collect();
+            return format!(
+                "start:{:x};size:{:x};permissions:{permissions};",
+                region.start, region.size
+            );
+        }
+        let start = map
+            .iter()
+            .map(MemoryRegion::end)
+            .filter(|end| *end <= addr as u64)
+            .max()
+            .unwrap_or(0);
+        let end = map
+            .iter()
+            .map(|region| region.start as u64)
+            .filter(|start| *start > addr as u64)
+            .min()
+            .unwrap_or(1 << 32);
+        format!("start:{:x};size:{:x};", start, end - start)
+    }
+
+    fn memory_map_xml(map: &[MemoryRegion]) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" \"http://sourceware.org/gdb/gdb-memory-map.dtd\">\n<memory-map>\n",
+        );
+        for region in map {
+            let kind = match region.kind {
+                MemoryKind::Ram => "ram",
+                MemoryKind::Rom => "rom",
+            };
+            xml.push_str(&format!(
+                "  <memory type=\"{kind}\" start=\"{:#x}\" length=\"{:#x}\"/>\n",
+                region.start, region.size
+            ));
+        }
+        xml.push_str("</memory-map>\n");
+        xml
+    }
+
+    /// Formats the stop reply packet for `reason` and the state the stub is in afterwards
+    fn stop_reply(thread: u32, reason: StopReason) -> (String, GDBState) {
+        let stopped = |sig: Signal, info: String| {
+            (
+                format!("T{:02x}{info}thread:{thread:x};", sig as u8),
+                GDBState::Idle,
+            )
+        };
+        match reason {
+            StopReason::DoneStep => stopped(Signal::SIGTRAP, String::new()),
+            StopReason::Signal(sig) => stopped(sig, String::new()),
+            StopReason::Exited(code) => (
+                format!("W{code:02x}"),
+                GDBState::Disconnected(DisconnectReason::TargetExited(code)),
+            ),
+            StopReason::Terminated(sig) => (
+                format!("X{:02x}", sig as u8),
+                GDBState::Disconnected(DisconnectReason::TargetTerminated(sig)),
+            ),
+            // "swbreak:;" / "hwbreak:;" are only allowed once the client says it supports them
+            StopReason::SwBreak => stopped(Signal::SIGTRAP, String::new()),
+            StopReason::HwBreak => stopped(Signal::SIGTRAP, String::new()),
+            StopReason::SyscallEntry(id) => {
+                stopped(Signal::SIGTRAP, format!("syscall_entry:{id:x};"))
             }
-            StopReason::HwBreak => {
-                res.write(b'S').map_err(GDBError::ConnectionWrite)?;
-                res.write_hex(Signal::SIGTRAP as u8)
-                    .map_err(GDBError::ConnectionWrite)?;
-                // res.write_str("hwbreak:;")
-                //     .map_err(GDBError::ConnectionWrite)?;
-                self.state = GDBState::Idle;
+            StopReason::SyscallReturn(id) => {
+                stopped(Signal::SIGTRAP, format!("syscall_return:{id:x};"))
             }
+            StopReason::Fault(sig, addr) => stopped(sig, format!("{BAD_REGISTER:02x}:{addr:08x};")),
         }
+    }
+
+    /// Asks the client to perform a File-I/O call on behalf of the (now stopped) target.
+    ///
+    /// The target is resumed through `Target::file_io_reply` once the client answers
+    pub fn target_file_io(&mut self, request: SemihostingCall) -> Result<(), GDBError<C, T>> {
+        if let GDBState::Disconnected(reason) = self.state {
+            return Err(GDBError::NotConnected(reason));
+        }
+        let mut res = ResponseWritter::new(&mut self.connection);
+        res.write(b'F').map_err(GDBError::ConnectionWrite)?;
+        res.write_str(&file_io::request_packet(&request))
+            .map_err(GDBError::ConnectionWrite)?;
         let len = res.flush().map_err(GDBError::ConnectionWrite)?;
         self.bytes_sent += len;
         self.packets_sent += 1;
-        //self.async_data.push(buff);
+        self.file_io_pending = true;
+        self.state = GDBState::Idle;
         Ok(())
     }
 
@@ -252,9 +400,9 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                     log::trace!("--> +");
                 }
 
-                let (state, response) = self.handle_command(command)?;
+                let response = self.handle_command(command)?;
 
-                if !matches!(state, GDBState::Disconnected(DisconnectReason::Kill)) {
+                if let Some(response) = response {
                     let len = response.flush().map_err(GDBError::ConnectionFlush)?;
                     self.bytes_sent += len;
                     self.packets_sent += 1;
@@ -267,8 +415,9 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
     fn handle_command(
         &mut self,
         command: Command,
-    ) -> Result<(&GDBState, ResponseWritter<C>), GDBError<C, T>> {
+    ) -> Result<Option<ResponseWritter<C>>, GDBError<C, T>> {
         let mut response = ResponseWritter::new(&mut self.connection);
+        let mut send_response = true;
 
         match command {
             Command::ContinueAt(addr) => {
@@ -325,7 +474,17 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                         .map_err(GDBError::ConnectionWrite)?;
                 }
             }
-            Command::WriteRegister(_, _) => {}
+            Command::WriteRegister(reg, value) => {
+                if self.target.write_register(reg, value).is_ok() {
+                    response
+                        .write_str("OK")
+                        .map_err(GDBError::ConnectionWrite)?;
+                } else {
+                    response
+                        .write_str("E01")
+                        .map_err(GDBError::ConnectionWrite)?;
+                }
+            }
             Command::WriteRegisters() => {}
 
             Command::ReadMemory(addr, len) => {
@@ -336,11 +495,9 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                             .map_err(GDBError::ConnectionWrite)?;
                     }
                 } else {
-                    for _ in 0..len {
-                        response
-                            .write_hex(0xDF)
-                            .map_err(GDBError::ConnectionWrite)?;
-                    }
+                    response
+                        .write_str("E01")
+                        .map_err(GDBError::ConnectionWrite)?;
                 }
             }
             Command::WriteMemory(addr, data) => {
@@ -355,25 +512,54 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
             Command::MustReplayEmpty => {}
 
             Command::ExceptionReason => {
-                response.write_str("S").map_err(GDBError::ConnectionWrite)?;
-                response
-                    .write_hex(Signal::SIGTRAP as u8)
+                if self.cfg.non_stop {
+                    // every stopped thread is reported, the rest are fetched with vStopped
+                    self.stop_queue.clear();
+                    for thread in self.target.threads() {
+                        if !self.running_threads.contains(&thread) {
+                            let (reply, _) =
+                                Self::stop_reply(thread, StopReason::Signal(Signal::SIGZERO));
+                            self.stop_queue.push_back(reply);
+                        }
+                    }
+                    match self.stop_queue.front() {
+                        Some(reply) => response.write_str(reply),
+                        None => response.write_str("OK"),
+                    }
                     .map_err(GDBError::ConnectionWrite)?;
+                } else {
+                    let (reply, _) =
+                        Self::stop_reply(self.current_thread, StopReason::Signal(Signal::SIGTRAP));
+                    response
+                        .write_str(&reply)
+                        .map_err(GDBError::ConnectionWrite)?;
+                }
             }
 
             Command::Unreconized => {}
 
-            Command::Kill => self.state = GDBState::Disconnected(DisconnectReason::Kill),
+            Command::Kill => {
+                self.state = GDBState::Disconnected(DisconnectReason::Kill);
+                send_response = false;
+            }
             Command::qSupported(_) => response
-                .write_str("QStartNoAckMode+")
+                .write_str("QStartNoAckMode+;QCatchSyscalls+;qXfer:memory-map:read+")
                 .map_err(GDBError::ConnectionWrite)?,
             Command::qTStatus => {}
-            Command::qfThreadInfo => response
-                .write_str("m11")
-                .map_err(GDBError::ConnectionWrite)?,
+            Command::qfThreadInfo => {
+                let threads: Vec<String> = self
+                    .target
+                    .threads()
+                    .iter()
+                    .map(|thread| format!("{thread:x}"))
+                    .collect();
+                response
+                    .write_str(&format!("m{}", threads.join(",")))
+                    .map_err(GDBError::ConnectionWrite)?
+            }
             Command::qsThreadInfo => response.write(b'l').map_err(GDBError::ConnectionWrite)?,
             Command::qC => response
-                .write_str("QC11")
+                .write_str(&format!("QC{:x}", self.current_thread))
                 .map_err(GDBError::ConnectionWrite)?,
             Command::qAttached => {}
             Command::qOffsets => {}
@@ -399,30 +585,92 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                 }
             }
 
-            //TODO!()
-            Command::qMemoryRegionInfo(region) => {
-                response
-                    .write_str("start:")
-                    .map_err(GDBError::ConnectionWrite)?;
+            Command::qMemoryRegionInfo(addr) => {
+                let map = self.target.memory_map();
                 response
-                    .write_hex_buff(&region.to_be_bytes())
-                    .map_err(GDBError::ConnectionWrite)?;
+                    .write_str(&Self::memory_region_info(&map, addr))
+                    .map_err(GDBError::ConnectionWrite)?
+            }
+            Command::qXferMemoryMapRead(offset, length) => {
+                let map = Self::memory_map_xml(&self.target.memory_map());
+                let data = map.as_bytes().get(offset..).unwrap_or_default();
+                let (prefix, data) = if data.len() > length {
+                    (b'm', &data[..length])
+                } else {
+                    (b'l', data)
+                };
+                response.write(prefix).map_err(GDBError::ConnectionWrite)?;
                 response
-                    .write_str(";size:ffffffff;permissions:rwx;")
+                    .write_binary(data)
                     .map_err(GDBError::ConnectionWrite)?
             }
-            //TODO!()
-            Command::SelectExecutionThread(_) => {
+            Command::SelectExecutionThread(thread)
+            | Command::SelectRegisterThread(thread)
+            | Command::SelectMemoryThread(thread) => {
+                let selected = match thread {
+                    ThreadId::All | ThreadId::Any => Ok(()),
+                    ThreadId::Id(id) => self.target.select_thread(id).map(|_| {
+                        self.current_thread = id;
+                    }),
+                };
                 response
-                    .write_str("OK")
+                    .write_str(if selected.is_ok() { "OK" } else { "E01" })
                     .map_err(GDBError::ConnectionWrite)?;
             }
-            Command::SelectRegisterThread(_) => {
+            Command::ThreadAlive(thread) => {
+                let alive = self.target.threads().contains(&thread);
                 response
-                    .write_str("OK")
+                    .write_str(if alive { "OK" } else { "E01" })
                     .map_err(GDBError::ConnectionWrite)?;
             }
-            Command::SelectMemoryThread(_) => {
+            Command::vContQuery => response
+                .write_str("vCont;c;C;s;S;t")
+                .map_err(GDBError::ConnectionWrite)?,
+            Command::vCont(actions) => {
+                for thread in self.target.threads() {
+                    // the first action that applies to a thread wins
+                    let Some((action, _)) = actions.iter().find(|(_, id)| id.matches(thread))
+                    else {
+                        continue;
+                    };
+                    let action = match action {
+                        VContAction::Continue | VContAction::ContinueSignal(_) => {
+                            ResumeAction::Continue
+                        }
+                        VContAction::Step | VContAction::StepSignal(_) => ResumeAction::Step,
+                        VContAction::Stop => {
+                            if !self.running_threads.contains(&thread) {
+                                continue;
+                            }
+                            self.stop_requested.insert(thread);
+                            ResumeAction::Stop
+                        }
+                    };
+                    if action != ResumeAction::Stop {
+                        self.running_threads.insert(thread);
+                        self.state = GDBState::Running;
+                    }
+                    self.target.resume_thread(thread, action);
+                }
+                if self.cfg.non_stop {
+                    response
+                        .write_str("OK")
+                        .map_err(GDBError::ConnectionWrite)?;
+                } else {
+                    // in all-stop mode the stop reply answers this packet
+                    send_response = false;
+                }
+            }
+            Command::vStopped => {
+                self.stop_queue.pop_front();
+                match self.stop_queue.front() {
+                    Some(reply) => response.write_str(reply),
+                    None => response.write_str("OK"),
+                }
+                .map_err(GDBError::ConnectionWrite)?;
+            }
+            Command::QNonStop(non_stop) => {
+                self.cfg.non_stop = non_stop;
                 response
                     .write_str("OK")
                     .map_err(GDBError::ConnectionWrite)?;
@@ -433,6 +681,12 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                     .write_str("OK")
                     .map_err(GDBError::ConnectionWrite)?;
             }
+            Command::QCatchSyscalls(catch) => {
+                self.cfg.catch_syscalls = catch;
+                response
+                    .write_str("OK")
+                    .map_err(GDBError::ConnectionWrite)?;
+            }
             Command::InsertSoftwareBreakpoint(kind, addr) => {
                 if let Err(err) = self.target.insert_software_breakpoint(kind, addr) {
                     response
@@ -469,11 +723,38 @@ impl<C: Connection, T: Target> GDBStub<C, T> {
                     .write_str("port:1234;")
                     .map_err(GDBError::ConnectionWrite)?;
             } //TODO!()
+
+            Command::FileIoReply(reply) => {
+                if !self.file_io_pending {
+                    response
+                        .write_str("E01")
+                        .map_err(GDBError::ConnectionWrite)?;
+                } else {
+                    self.file_io_pending = false;
+                    self.target
+                        .file_io_reply(reply)
+                        .map_err(GDBError::TargetError)?;
+                    if reply.ctrl_c {
+                        response.write(b'S').map_err(GDBError::ConnectionWrite)?;
+                        response
+                            .write_hex(Signal::SIGINT as u8)
+                            .map_err(GDBError::ConnectionWrite)?;
+                    } else {
+                        // the client waits for the next stop reply
+                        self.state = GDBState::Running;
+                        self.target.continue_at(None);
+                        send_response = false;
+                    }
+                }
+            }
         }
-        Ok((&self.state, response))
+        Ok(send_response.then_some(response))
     }
 }
 
+/// Index of the `bad` (bad virtual address) register in `REGISTER_INFO`
+const BAD_REGISTER: u8 = 35;
+
 const REGISTER_INFO: [&str; 38] = [
   "name:r0;alt-name:zero;bitsize:32;offset:0;encoding:uint;format:hex;set:General Purpose Registers;",
   "name:r1;alt-name:at;bitsize:32;offset:4;encoding:uint;format:hex;set:General Purpose Registers;",
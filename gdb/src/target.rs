@@ -1,9 +1,48 @@
 use std::fmt::Debug;
 
+use crate::file_io::FileIoReply;
+
 pub enum InturruptType {
     Async,
     Sync,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeAction {
+    Continue,
+    Step,
+    /// Stop the thread, its stop is reported like any other
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Ram,
+    Rom,
+}
+
+/// A mapped range of target memory, every address outside of a region is unmapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    /// A u64 so a single region can cover the whole address space
+    pub size: u64,
+    pub kind: MemoryKind,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        self.start <= addr && (addr as u64) < self.end()
+    }
+}
+
 pub trait Target {
     type Error: Debug;
     fn detach(&mut self);
@@ -11,8 +50,18 @@ pub trait Target {
     fn inturrupt(&mut self) -> Result<InturruptType, Self::Error>;
     fn step_at(&mut self, addr: Option<u32>);
     fn continue_at(&mut self, addr: Option<u32>);
+
+    /// Ids of every thread (core) of the target, ids start at 1
+    fn threads(&mut self) -> Vec<u32>;
+    /// Selects the thread register accesses apply to
+    fn select_thread(&mut self, thread: u32) -> Result<(), Self::Error>;
+    fn resume_thread(&mut self, thread: u32, action: ResumeAction);
+
     fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error>;
+    /// Reading unmapped memory is an error
     fn read_memory(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, Self::Error>;
+    /// Every mapped region, sorted by address and not overlapping
+    fn memory_map(&mut self) -> Vec<MemoryRegion>;
     fn read_registers(&mut self) -> Result<[u32; 38], Self::Error>;
     fn read_register(&mut self, reg: u8) -> Result<u32, Self::Error>;
     fn write_register(&mut self, reg: u8, data: u32) -> Result<(), Self::Error>;
@@ -21,4 +70,7 @@ pub trait Target {
     fn sw_breakpoint_hit(&mut self);
     fn insert_software_breakpoint(&mut self, kind: u8, addr: u32) -> Result<(), Self::Error>;
     fn remove_software_breakpoint(&mut self, kind: u8, addr: u32) -> Result<(), Self::Error>;
+
+    /// Hands the result of a File-I/O request back to the target, this is called before the target is resumed
+    fn file_io_reply(&mut self, reply: FileIoReply) -> Result<(), Self::Error>;
 }
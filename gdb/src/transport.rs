@@ -0,0 +1,181 @@
+use std::{
+    fmt::Display,
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    str::FromStr,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use crate::connection::{Connection, StdioConnection};
+
+/// Where a stub waits for its client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// A tcp address, e.g. `localhost:1234`
+    Tcp(String),
+    /// A unix domain socket, a stale socket file at the path is replaced
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// The stdin/stdout of this process, for `target remote | <command>`
+    Stdio,
+}
+
+impl Transport {
+    pub fn listen(&self) -> std::io::Result<TransportListener> {
+        match self {
+            Transport::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(TransportListener::Tcp(listener))
+            }
+            #[cfg(unix)]
+            Transport::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                if let Ok(meta) = std::fs::symlink_metadata(path) {
+                    if meta.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                Ok(TransportListener::Unix(listener))
+            }
+            Transport::Stdio => Ok(TransportListener::Stdio(Some(StdioConnection::stdio()))),
+        }
+    }
+}
+
+/// `tcp:<addr>`, `unix:<path>`, `stdio` or a bare tcp address
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdio" || s == "-" {
+            Ok(Transport::Stdio)
+        } else if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(Transport::Tcp(addr.into()))
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Transport::Unix(path.into()));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix sockets are not supported on this platform: {path}"
+            ));
+        } else if s.contains(':') {
+            Ok(Transport::Tcp(s.into()))
+        } else {
+            Err(format!("invalid transport: {s}"))
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp(addr) => write!(f, "tcp:{addr}"),
+            #[cfg(unix)]
+            Transport::Unix(path) => write!(f, "unix:{}", path.display()),
+            Transport::Stdio => write!(f, "stdio"),
+        }
+    }
+}
+
+pub enum TransportListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// stdio only ever has one client
+    Stdio(Option<StdioConnection>),
+}
+
+impl TransportListener {
+    /// Returns `None` if no client is waiting to connect
+    pub fn accept(&mut self) -> std::io::Result<Option<TransportConnection>> {
+        fn non_blocking<T>(res: std::io::Result<T>) -> std::io::Result<Option<T>> {
+            match res {
+                Ok(ok) => Ok(Some(ok)),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        match self {
+            TransportListener::Tcp(listener) => Ok(non_blocking(listener.accept())?
+                .map(|(stream, _)| TransportConnection::Tcp(stream))),
+            #[cfg(unix)]
+            TransportListener::Unix(listener) => Ok(non_blocking(listener.accept())?
+                .map(|(stream, _)| TransportConnection::Unix(stream))),
+            TransportListener::Stdio(stdio) => Ok(stdio.take().map(TransportConnection::Stdio)),
+        }
+    }
+
+    /// Blocks until a client connects
+    pub fn accept_blocking(&mut self) -> std::io::Result<TransportConnection> {
+        loop {
+            if let Some(connection) = self.accept()? {
+                return Ok(connection);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
+
+/// A connection over any `Transport`, so the transport can be picked at runtime
+#[derive(Debug)]
+pub enum TransportConnection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Stdio(StdioConnection),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $con:ident => $expr:expr) => {
+        match $self {
+            TransportConnection::Tcp($con) => $expr,
+            #[cfg(unix)]
+            TransportConnection::Unix($con) => $expr,
+            TransportConnection::Stdio($con) => $expr,
+        }
+    };
+}
+
+impl Connection for TransportConnection {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        dispatch!(self, con => con.write(byte))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        dispatch!(self, con => Connection::write_all(con, buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        dispatch!(self, con => Connection::flush(con))
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        dispatch!(self, con => con.on_session_start())
+    }
+
+    fn on_session_end(&mut self) -> Result<(), Self::Error> {
+        dispatch!(self, con => con.on_session_end())
+    }
+
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        dispatch!(self, con => Connection::read(con))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        dispatch!(self, con => Connection::peek(con))
+    }
+
+    fn string_repr(&self) -> Option<String> {
+        dispatch!(self, con => con.string_repr())
+    }
+}
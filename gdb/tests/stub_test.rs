@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use gdb::{
+    async_target::{create_async_stub, GDBAsyncNotifier},
+    client::{GdbClient, StopReply},
+    connection::Connection,
+    file_io::FileIoReply,
+    stub::{DisconnectReason, GDBError, GDBStub, StopReason},
+    target::{InturruptType, MemoryKind, MemoryRegion, ResumeAction, Target},
+};
+use mips_emulator::semihosting::SemihostingCall;
+
+#[derive(Debug)]
+struct MockState {
+    regs: [u32; 38],
+    memory: HashMap<u32, u8>,
+    breakpoints: Vec<u32>,
+    resumed: Vec<ResumeAction>,
+    file_io_replies: Vec<FileIoReply>,
+    detached: bool,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            regs: [0; 38],
+            memory: HashMap::new(),
+            breakpoints: Vec::new(),
+            resumed: Vec::new(),
+            file_io_replies: Vec::new(),
+            detached: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum MockError {
+    InvalidRegister,
+    BreakpointDoesntExist,
+    UnmappedMemory,
+}
+
+const MOCK_MEMORY_MAP: [MemoryRegion; 2] = [
+    MemoryRegion {
+        start: 0,
+        size: 0x2000,
+        kind: MemoryKind::Ram,
+        read: true,
+        write: true,
+        execute: false,
+    },
+    MemoryRegion {
+        start: 0x0040_0000,
+        size: 0x1_0000,
+        kind: MemoryKind::Rom,
+        read: true,
+        write: false,
+        execute: true,
+    },
+];
+
+/// A target that just records what the stub asks of it
+#[derive(Debug)]
+struct MockTarget {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Target for MockTarget {
+    type Error = MockError;
+
+    fn detach(&mut self) {
+        self.state.lock().unwrap().detached = true;
+    }
+
+    fn inturrupt(&mut self) -> Result<InturruptType, Self::Error> {
+        Ok(InturruptType::Sync)
+    }
+
+    fn step_at(&mut self, _addr: Option<u32>) {
+        self.resume_thread(1, ResumeAction::Step);
+    }
+
+    fn continue_at(&mut self, _addr: Option<u32>) {
+        self.resume_thread(1, ResumeAction::Continue);
+    }
+
+    fn threads(&mut self) -> Vec<u32> {
+        vec![1, 2]
+    }
+
+    fn select_thread(&mut self, _thread: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn resume_thread(&mut self, _thread: u32, action: ResumeAction) {
+        self.state.lock().unwrap().resumed.push(action);
+    }
+
+    fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        for (i, byte) in data.iter().enumerate() {
+            state.memory.insert(addr + i as u32, *byte);
+        }
+        Ok(())
+    }
+
+    fn read_memory(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, Self::Error> {
+        let state = self.state.lock().unwrap();
+        (addr..addr + len)
+            .map(|addr| {
+                if MOCK_MEMORY_MAP.iter().any(|region| region.contains(addr)) {
+                    Ok(state.memory.get(&addr).copied().unwrap_or(0))
+                } else {
+                    Err(MockError::UnmappedMemory)
+                }
+            })
+            .collect()
+    }
+
+    fn memory_map(&mut self) -> Vec<MemoryRegion> {
+        MOCK_MEMORY_MAP.to_vec()
+    }
+
+    fn read_registers(&mut self) -> Result<[u32; 38], Self::Error> {
+        Ok(self.state.lock().unwrap().regs)
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u32, Self::Error> {
+        let state = self.state.lock().unwrap();
+        state
+            .regs
+            .get(reg as usize)
+            .copied()
+            .ok_or(MockError::InvalidRegister)
+    }
+
+    fn write_register(&mut self, reg: u8, data: u32) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        *state
+            .regs
+            .get_mut(reg as usize)
+            .ok_or(MockError::InvalidRegister)? = data;
+        Ok(())
+    }
+
+    fn write_registers(&mut self, data: [u32; 38]) -> Result<(), Self::Error> {
+        self.state.lock().unwrap().regs = data;
+        Ok(())
+    }
+
+    fn sw_breakpoint_hit(&mut self) {}
+
+    fn insert_software_breakpoint(&mut self, _kind: u8, addr: u32) -> Result<(), Self::Error> {
+        self.state.lock().unwrap().breakpoints.push(addr);
+        Ok(())
+    }
+
+    fn remove_software_breakpoint(&mut self, _kind: u8, addr: u32) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        let index = state
+            .breakpoints
+            .iter()
+            .position(|bp| *bp == addr)
+            .ok_or(MockError::BreakpointDoesntExist)?;
+        state.breakpoints.remove(index);
+        Ok(())
+    }
+
+    fn file_io_reply(&mut self, reply: FileIoReply) -> Result<(), Self::Error> {
+        self.state.lock().unwrap().file_io_replies.push(reply);
+        Ok(())
+    }
+}
+
+type StubResult<S> = Result<DisconnectReason, GDBError<S, MockTarget>>;
+
+struct Session<S: Connection = TcpStream, C: Connection = TcpStream> {
+    client: GdbClient<C>,
+    notifier: GDBAsyncNotifier<S, MockTarget>,
+    state: Arc<Mutex<MockState>>,
+    stub: JoinHandle<StubResult<S>>,
+}
+
+/// Runs a stub against a `MockTarget` over a loopback socket and connects a client to it
+fn start_session() -> Session {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client_stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    // a broken stub fails the test instead of hanging it
+    client_stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let (stub_stream, _) = listener.accept().unwrap();
+    start_session_over(stub_stream, client_stream)
+}
+
+fn start_session_over<S, C>(stub_connection: S, client_connection: C) -> Session<S, C>
+where
+    S: Connection + Send + 'static,
+    S::Error: Send,
+    C: Connection + std::fmt::Debug,
+{
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let target = MockTarget {
+        state: state.clone(),
+    };
+    let (stub, notifier) = create_async_stub(GDBStub::new(target, stub_connection));
+    let stub = std::thread::spawn(move || stub.run_blocking());
+
+    Session {
+        client: GdbClient::connect(client_connection).unwrap(),
+        notifier,
+        state,
+        stub,
+    }
+}
+
+impl<S: Connection + std::fmt::Debug, C: Connection + std::fmt::Debug> Session<S, C> {
+    /// Waits until the stub has handed `count` resume actions to the target
+    fn wait_for_resumes(&self, count: usize) {
+        for _ in 0..5000 {
+            if self.state.lock().unwrap().resumed.len() >= count {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("target was never resumed");
+    }
+
+    fn finish(self) {
+        self.client.kill().unwrap();
+        let reason = self.stub.join().unwrap().unwrap();
+        assert!(matches!(reason, DisconnectReason::Kill));
+        assert!(self.state.lock().unwrap().detached);
+    }
+}
+
+#[test]
+fn registers() {
+    let mut session = start_session();
+    session.state.lock().unwrap().regs[4] = 0xDEADBEEF;
+
+    let regs = session.client.read_registers().unwrap();
+    assert_eq!(regs.len(), 38);
+    assert_eq!(regs[4], 0xDEADBEEF);
+    assert_eq!(session.client.read_register(4).unwrap(), 0xDEADBEEF);
+
+    session.client.write_register(37, 0x0040_0000).unwrap();
+    assert_eq!(session.client.read_register(37).unwrap(), 0x0040_0000);
+    assert_eq!(session.state.lock().unwrap().regs[37], 0x0040_0000);
+
+    session.finish();
+}
+
+#[test]
+fn memory() {
+    let mut session = start_session();
+
+    session
+        .client
+        .write_memory(0x1000, &[1, 2, 3, 0xFF])
+        .unwrap();
+    assert_eq!(
+        session.client.read_memory(0x1000, 4).unwrap(),
+        [1, 2, 3, 0xFF]
+    );
+    assert_eq!(session.state.lock().unwrap().memory[&0x1003], 0xFF);
+
+    session.finish();
+}
+
+#[test]
+fn unmapped_memory() {
+    let mut session = start_session();
+
+    assert!(session.client.read_memory(0x1FFE, 2).is_ok());
+    assert!(session.client.read_memory(0x1FFE, 4).is_err());
+    assert!(session.client.read_memory(0x8000_0000, 1).is_err());
+
+    session.finish();
+}
+
+#[test]
+fn memory_region_info() {
+    let mut session = start_session();
+
+    assert_eq!(
+        session.client.request("qMemoryRegionInfo:1234").unwrap(),
+        "start:0;size:2000;permissions:rw;"
+    );
+    assert_eq!(
+        session.client.request("qMemoryRegionInfo:40fffc").unwrap(),
+        "start:400000;size:10000;permissions:rx;"
+    );
+    // the gaps between regions are reported without permissions
+    assert_eq!(
+        session.client.request("qMemoryRegionInfo:3000").unwrap(),
+        "start:2000;size:3fe000;"
+    );
+    assert_eq!(
+        session
+            .client
+            .request("qMemoryRegionInfo:80000000")
+            .unwrap(),
+        "start:410000;size:ffbf0000;"
+    );
+
+    session.finish();
+}
+
+#[test]
+fn memory_map() {
+    let mut session = start_session();
+
+    let map = session
+        .client
+        .request("qXfer:memory-map:read::0,1000")
+        .unwrap();
+    let map = map.strip_prefix('l').unwrap();
+    assert!(map.starts_with("<?xml"));
+    assert!(map.contains(r#"<memory type="ram" start="0x0" length="0x2000"/>"#));
+    assert!(map.contains(r#"<memory type="rom" start="0x400000" length="0x10000"/>"#));
+
+    // gdb reads the map in chunks when it doesn't fit in one packet
+    let mut chunked = String::new();
+    loop {
+        let chunk = session
+            .client
+            .request(&format!("qXfer:memory-map:read::{:x},20", chunked.len()))
+            .unwrap();
+        let (more, data) = chunk.split_at(1);
+        chunked.push_str(data);
+        if more == "l" {
+            break;
+        }
+        assert_eq!(more, "m");
+        assert_eq!(data.len(), 0x20);
+    }
+    assert_eq!(chunked, map);
+
+    session.finish();
+}
+
+#[test]
+fn breakpoints_and_continue() {
+    let mut session = start_session();
+
+    session.client.insert_breakpoint(0x400010).unwrap();
+    assert_eq!(session.state.lock().unwrap().breakpoints, [0x400010]);
+
+    session.client.resume().unwrap();
+    session.wait_for_resumes(2);
+    session.notifier.target_stop_signal(StopReason::SwBreak);
+    assert_eq!(
+        session.client.wait_for_stop().unwrap(),
+        StopReply::Stopped {
+            signal: 5,
+            thread: Some(1),
+            fields: Vec::new()
+        }
+    );
+    assert_eq!(
+        session.state.lock().unwrap().resumed,
+        [ResumeAction::Continue, ResumeAction::Continue]
+    );
+
+    session.client.remove_breakpoint(0x400010).unwrap();
+    assert!(session.state.lock().unwrap().breakpoints.is_empty());
+
+    // hardware breakpoints are set like software ones, watchpoints aren't supported
+    assert_eq!(session.client.request("Z1,400020,4").unwrap(), "OK");
+    assert_eq!(session.state.lock().unwrap().breakpoints, [0x400020]);
+    assert_eq!(session.client.request("z1,400020,4").unwrap(), "OK");
+    assert!(session.state.lock().unwrap().breakpoints.is_empty());
+    assert_eq!(session.client.request("Z2,400020,4").unwrap(), "");
+
+    session.finish();
+}
+
+#[test]
+fn step_single_thread() {
+    let mut session = start_session();
+
+    session.client.resume_step(2).unwrap();
+    session.wait_for_resumes(1);
+    session
+        .notifier
+        .target_thread_stop_signal(2, StopReason::DoneStep);
+    let stop = session.client.wait_for_stop().unwrap();
+    assert!(matches!(
+        stop,
+        StopReply::Stopped {
+            thread: Some(2),
+            ..
+        }
+    ));
+    assert_eq!(session.state.lock().unwrap().resumed, [ResumeAction::Step]);
+
+    session.finish();
+}
+
+#[test]
+fn threads() {
+    let mut session = start_session();
+
+    assert_eq!(session.client.threads().unwrap(), [1, 2]);
+    assert_eq!(session.client.request("vCont?").unwrap(), "vCont;c;C;s;S;t");
+    assert_eq!(session.client.request("T2").unwrap(), "OK");
+    assert_eq!(session.client.request("T3").unwrap(), "E01");
+
+    session.finish();
+}
+
+#[test]
+fn non_stop_notifications() {
+    let mut session = start_session();
+
+    assert_eq!(session.client.request("QNonStop:1").unwrap(), "OK");
+    assert_eq!(session.client.request("vCont;c").unwrap(), "OK");
+    session.wait_for_resumes(2);
+
+    session
+        .notifier
+        .target_thread_stop_signal(1, StopReason::SwBreak);
+    session
+        .notifier
+        .target_thread_stop_signal(2, StopReason::Fault(gdb::signal::Signal::SIGSEGV, 0x10));
+
+    // only the first stop is notified, the rest is fetched with vStopped
+    assert_eq!(
+        session.client.receive_notification().unwrap(),
+        "Stop:T05thread:1;"
+    );
+    assert_eq!(
+        session.client.request("vStopped").unwrap(),
+        "T0b23:00000010;thread:2;"
+    );
+    assert_eq!(session.client.request("vStopped").unwrap(), "OK");
+
+    session.finish();
+}
+
+#[test]
+fn catch_syscalls() {
+    let mut session = start_session();
+
+    assert!(session
+        .client
+        .request("qSupported:")
+        .unwrap()
+        .contains("QCatchSyscalls+"));
+    assert_eq!(
+        session.client.request("QCatchSyscalls:1;4;a").unwrap(),
+        "OK"
+    );
+
+    session.client.resume().unwrap();
+    session.wait_for_resumes(2);
+    session
+        .notifier
+        .target_stop_signal(StopReason::SyscallEntry(4));
+    let StopReply::Stopped { fields, .. } = session.client.wait_for_stop().unwrap() else {
+        panic!("expected a stop");
+    };
+    assert_eq!(fields, [("syscall_entry".into(), "4".into())]);
+
+    session.finish();
+}
+
+#[test]
+fn file_io() {
+    let mut session = start_session();
+
+    session.client.resume().unwrap();
+    session.wait_for_resumes(2);
+    assert!(session.notifier.target_file_io(SemihostingCall::Write {
+        fd: 1,
+        buf: 0x1000,
+        count: 5
+    }));
+    assert_eq!(session.client.receive().unwrap(), "Fwrite,1,1000,5");
+
+    // the reply resumes the target instead of being answered
+    session.client.send("F5").unwrap();
+    session.wait_for_resumes(3);
+    assert_eq!(
+        session.state.lock().unwrap().file_io_replies,
+        [FileIoReply {
+            retcode: 5,
+            errno: None,
+            ctrl_c: false
+        }]
+    );
+
+    session.finish();
+}
+
+#[cfg(unix)]
+#[test]
+fn pipe_transport() {
+    let (stub_end, client_end) = std::os::unix::net::UnixStream::pair().unwrap();
+    client_end
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // the app runs stubs on their own thread
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let stub_connection =
+        gdb::connection::PipeConnection::new(stub_end.try_clone().unwrap(), stub_end, "pipe");
+    assert_send_sync(&stub_connection);
+    let mut session = start_session_over(stub_connection, client_end);
+
+    session.state.lock().unwrap().regs[2] = 10;
+    assert_eq!(session.client.read_register(2).unwrap(), 10);
+
+    session.finish();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_transport() {
+    let path = std::env::temp_dir().join(format!("gdb_stub_test_{}.sock", std::process::id()));
+    let transport: gdb::transport::Transport = format!("unix:{}", path.display()).parse().unwrap();
+    let mut listener = transport.listen().unwrap();
+
+    let client_stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    client_stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let stub_connection = listener.accept_blocking().unwrap();
+    assert!(matches!(
+        stub_connection,
+        gdb::transport::TransportConnection::Unix(_)
+    ));
+    let mut session = start_session_over(stub_connection, client_stream);
+
+    session.client.write_memory(0x20, &[0xAB]).unwrap();
+    assert_eq!(session.client.read_memory(0x20, 1).unwrap(), [0xAB]);
+
+    session.finish();
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,13 +1,15 @@
 use gdb::{
     async_target::GDBAsyncNotifier,
     connection::Connection,
+    file_io::FileIoReply,
     signal::Signal,
     stub::StopReason,
-    target::{InturruptType, Target},
+    target::{InturruptType, MemoryKind, MemoryRegion, ResumeAction, Target},
 };
 use mips_emulator::{
-    cpu::{CpuExternalHandler, Debugger, EmulatorInterface},
+    cpu::{CpuExternalHandler, Debugger, EmulatorInterface, MipsCpu},
     memory::{page_pool::MemoryDefaultAccess, single_cached_memory::SingleCachedMemory},
+    semihosting::{SemihostingCall, SEMIHOSTING_BREAK_CODE},
 };
 
 #[derive(Debug)]
@@ -20,6 +22,7 @@ pub enum TargetError {
     BreakpointDoesntExist(u32),
     BreakpointAlreadyExists,
     InturruptError,
+    InvalidThread(u32),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -32,6 +35,12 @@ pub struct MipsTargetInterface<T: CpuExternalHandler> {
     pub emulator: EmulatorInterface<T>,
     breakpoints: Vec<Breakpoint>,
     first_start: bool,
+    /// Address of the syscall whose entry was just reported, it is stepped over when resumed
+    syscall_entry: Option<u32>,
+    /// Id of the syscall being stepped over, its return is reported when the cpu stops
+    syscall_return: Option<u32>,
+    bad_vaddr: u32,
+    cause: u32,
 }
 
 impl<T: CpuExternalHandler> MipsTargetInterface<T> {
@@ -40,10 +49,21 @@ impl<T: CpuExternalHandler> MipsTargetInterface<T> {
             emulator,
             breakpoints: Default::default(),
             first_start: true,
+            syscall_entry: None,
+            syscall_return: None,
+            bad_vaddr: 0,
+            cause: 0,
         }
     }
 
-    pub(crate) fn breakpoints(&self) -> &[Breakpoint] {
+    /// Keeps the cpu state the first time the target is resumed instead of resetting it,
+    /// for programs that were loaded before the debugger attached
+    pub fn without_reset(mut self) -> Self {
+        self.first_start = false;
+        self
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
         &self.breakpoints
     }
 }
@@ -68,6 +88,7 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
 
     fn step_at(&mut self, addr: Option<u32>) {
         if let Some(addr) = addr {
+            self.syscall_entry = None;
             self.emulator.cpu_mut(|cpu| cpu.set_pc(addr));
         }
         _ = self.emulator.step_new_thread();
@@ -75,9 +96,36 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
 
     fn continue_at(&mut self, addr: Option<u32>) {
         if let Some(addr) = addr {
+            self.syscall_entry = None;
             self.emulator.cpu_mut(|cpu| cpu.set_pc(addr));
         }
-        _ = self.emulator.start_new_thread();
+        if self.syscall_entry.is_some() {
+            // only run the caught syscall so its return can be reported
+            _ = self.emulator.step_new_thread();
+        } else {
+            _ = self.emulator.start_new_thread();
+        }
+    }
+
+    fn threads(&mut self) -> Vec<u32> {
+        // the emulator only runs a single core
+        vec![1]
+    }
+
+    fn select_thread(&mut self, thread: u32) -> Result<(), Self::Error> {
+        if thread == 1 {
+            Ok(())
+        } else {
+            Err(TargetError::InvalidThread(thread))
+        }
+    }
+
+    fn resume_thread(&mut self, _thread: u32, action: ResumeAction) {
+        match action {
+            ResumeAction::Continue => self.continue_at(None),
+            ResumeAction::Step => self.step_at(None),
+            ResumeAction::Stop => _ = self.emulator.stop(),
+        }
     }
 
     fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error> {
@@ -97,12 +145,50 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
             let mut mem = cpu.get_mem::<SingleCachedMemory>();
             let mut vec = Vec::with_capacity(len as usize);
             for i in 0..len {
-                vec.push(mem.get_u8_be(addr.wrapping_add(i)));
+                // reading must not allocate pages so unmapped memory stays unmapped
+                vec.push(
+                    mem.get_u8_o_be(addr.wrapping_add(i))
+                        .ok_or(TargetError::MemoryReadError)?,
+                );
             }
             Ok(vec)
         })
     }
 
+    fn memory_map(&mut self) -> Vec<MemoryRegion> {
+        self.emulator.cpu_mut(|cpu| {
+            let controller = cpu.get_mem_controller();
+            let controller = controller.lock().unwrap();
+
+            let mut map: Vec<MemoryRegion> = Vec::new();
+            for page in controller.allocated_pages() {
+                let protection = controller.page_protection(page);
+                let start = (page as u32) << 16;
+                // pages next to each other with the same protection are reported as one region
+                if let Some(last) = map.last_mut() {
+                    if last.end() == start as u64
+                        && (last.read, last.write, last.execute)
+                            == (protection.read, protection.write, protection.execute)
+                    {
+                        last.size += 0x10000;
+                        continue;
+                    }
+                }
+                map.push(MemoryRegion {
+                    start,
+                    size: 0x10000,
+                    // protections aren't enforced so nothing is rom, gdb would refuse to write
+                    // to it (`load`) and only use hardware breakpoints there
+                    kind: MemoryKind::Ram,
+                    read: protection.read,
+                    write: protection.write,
+                    execute: protection.execute,
+                });
+            }
+            map
+        })
+    }
+
     fn read_registers(&mut self) -> Result<[u32; 38], Self::Error> {
         let mut regs = [0u32; 38];
         unsafe {
@@ -112,8 +198,8 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
             //regs[32] = (0x0); //sr
             regs[33] = cpu.hi();
             regs[34] = cpu.lo();
-            //regs[35] = (0x0); //bad
-            //regs[36] = (0x0); //cause
+            regs[35] = self.bad_vaddr;
+            regs[36] = self.cause;
             regs[37] = cpu.pc();
         }
         Ok(regs)
@@ -126,8 +212,8 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
                 32 => 0,
                 33 => self.emulator.lo(),
                 34 => self.emulator.hi(),
-                35 => 0,
-                36 => 0,
+                35 => self.bad_vaddr,
+                36 => self.cause,
                 37 => self.emulator.pc(),
                 38 => 0, //fsr
                 39 => 0, //fir
@@ -136,8 +222,22 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
         })
     }
 
-    fn write_register(&mut self, _reg: u8, _data: u32) -> Result<(), Self::Error> {
-        todo!()
+    fn write_register(&mut self, reg: u8, data: u32) -> Result<(), Self::Error> {
+        self.emulator.cpu_mut(|cpu| {
+            match reg {
+                // $zero is hardwired
+                0 => {}
+                1..=31 => cpu.reg_mut()[reg as usize] = data,
+                33 => cpu.set_lo(data),
+                34 => cpu.set_hi(data),
+                35 => self.bad_vaddr = data,
+                36 => self.cause = data,
+                37 => cpu.set_pc(data),
+                32 | 38 | 39 => {}
+                _ => Err(TargetError::InvalidRegister(reg))?,
+            }
+            Ok(())
+        })
     }
 
     fn write_registers(&mut self, _data: [u32; 38]) -> Result<(), Self::Error> {
@@ -200,6 +300,14 @@ impl<T: CpuExternalHandler> Target for MipsTargetInterface<T> {
         })
     }
 
+    fn file_io_reply(&mut self, reply: FileIoReply) -> Result<(), Self::Error> {
+        self.emulator.cpu_mut(|cpu| {
+            cpu.reg_mut()[2] = reply.retcode as u32;
+            cpu.reg_mut()[3] = reply.errno.unwrap_or(0);
+        });
+        Ok(())
+    }
+
     fn detach(&mut self) {
         self.emulator.cpu_mut(|cpu| {
             let mut mem = cpu.get_mem::<SingleCachedMemory>();
@@ -219,6 +327,26 @@ impl<C: Connection + Sync + Send + 'static, T: CpuExternalHandler> MipsDebugger<
     pub fn new(gdb_async: GDBAsyncNotifier<C, MipsTargetInterface<T>>) -> Self {
         Self { gdb_async }
     }
+
+    fn fault(&mut self, signal: Signal, addr: u32, cause: u32) {
+        let mut stub = self.gdb_async.gdb.lock().unwrap();
+        stub.target.bad_vaddr = addr;
+        stub.target.cause = cause << 2;
+        drop(stub);
+        self.gdb_async
+            .target_stop_signal(StopReason::Fault(signal, addr));
+    }
+}
+
+// exception codes reported through the cause register
+const EXC_ADDRESS_LOAD: u32 = 4;
+const EXC_ADDRESS_STORE: u32 = 5;
+const EXC_RESERVED_INSTRUCTION: u32 = 10;
+const EXC_OVERFLOW: u32 = 12;
+
+/// Faults are raised after the pc has moved past the faulting instruction
+fn fault_address<T: CpuExternalHandler>(cpu: &MipsCpu<T>) -> u32 {
+    cpu.pc().wrapping_sub(4)
 }
 
 impl<C: Connection + Sync + Send + 'static, T: CpuExternalHandler> Debugger<T>
@@ -238,14 +366,57 @@ impl<C: Connection + Sync + Send + 'static, T: CpuExternalHandler> Debugger<T>
     }
 
     fn stop(&mut self, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
-        self.gdb_async.on_target_stop();
+        let syscall_return = self
+            .gdb_async
+            .gdb
+            .lock()
+            .unwrap()
+            .target
+            .syscall_return
+            .take();
+        if let Some(id) = syscall_return {
+            self.gdb_async
+                .target_stop_signal(StopReason::SyscallReturn(id));
+        } else {
+            self.gdb_async.on_target_stop();
+        }
     }
 
-    fn on_syscall(&mut self, _id: u32, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) -> bool {
-        false
+    fn on_syscall(&mut self, id: u32, cpu: &mut mips_emulator::cpu::MipsCpu<T>) -> bool {
+        let syscall_address = cpu.pc().wrapping_sub(4);
+        let mut stub = self.gdb_async.gdb.lock().unwrap();
+        if stub.target.syscall_entry == Some(syscall_address) {
+            // the entry was already reported, let the syscall run this time
+            stub.target.syscall_entry = None;
+            stub.target.syscall_return = Some(id);
+            return false;
+        }
+        if !stub.catches_syscall(id) {
+            return false;
+        }
+        stub.target.syscall_entry = Some(syscall_address);
+        drop(stub);
+
+        cpu.stop();
+        cpu.set_pc(syscall_address);
+        self.gdb_async
+            .target_stop_signal(StopReason::SyscallEntry(id));
+        true
     }
 
-    fn on_break(&mut self, _id: u32, cpu: &mut mips_emulator::cpu::MipsCpu<T>) -> bool {
+    fn on_break(&mut self, id: u32, cpu: &mut mips_emulator::cpu::MipsCpu<T>) -> bool {
+        if id == SEMIHOSTING_BREAK_CODE {
+            // let the handler service malformed calls, it reports the error back to the guest
+            let Some(call) = SemihostingCall::decode(cpu) else {
+                return false;
+            };
+            // if the client is gone the handler falls back to the host filesystem
+            if !self.gdb_async.target_file_io(call) {
+                return false;
+            }
+            cpu.stop();
+            return true;
+        }
         cpu.stop();
         self.gdb_async
             .target_stop_signal(gdb::stub::StopReason::SwBreak);
@@ -270,7 +441,8 @@ impl<C: Connection + Sync + Send + 'static, T: CpuExternalHandler> Debugger<T>
     }
 
     fn check_syscall_access(&mut self, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) -> bool {
-        false
+        let stub = self.gdb_async.gdb.lock().unwrap();
+        stub.is_catching_syscalls() || stub.target.syscall_entry.is_some()
     }
 
     fn on_memory_read(
@@ -291,16 +463,25 @@ impl<C: Connection + Sync + Send + 'static, T: CpuExternalHandler> Debugger<T>
         false
     }
 
-    fn memory_error(&mut self, _error_id: u32, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
-        self.gdb_async.on_target_stop();
+    fn memory_error(
+        &mut self,
+        error_id: u32,
+        addr: u32,
+        _cpu: &mut mips_emulator::cpu::MipsCpu<T>,
+    ) {
+        let cause = match error_id {
+            0 | 1 => EXC_ADDRESS_LOAD,
+            _ => EXC_ADDRESS_STORE,
+        };
+        self.fault(Signal::SIGSEGV, addr, cause);
     }
 
-    fn arithmitic_error(&mut self, _error_id: u32, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
-        self.gdb_async.on_target_stop();
+    fn arithmitic_error(&mut self, _error_id: u32, cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
+        self.fault(Signal::SIGFPE, fault_address(cpu), EXC_OVERFLOW);
     }
 
-    fn invalid_op_code(&mut self, _cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
-        self.gdb_async.on_target_stop();
+    fn invalid_op_code(&mut self, cpu: &mut mips_emulator::cpu::MipsCpu<T>) {
+        self.fault(Signal::SIGILL, fault_address(cpu), EXC_RESERVED_INSTRUCTION);
     }
 
     fn on_emu_panic(&mut self) {
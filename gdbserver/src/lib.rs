@@ -0,0 +1,5 @@
+//! The gdb target of the emulator, shared by the gui debugger and the headless `gdbserver`
+//!
+//! Kept out of `app` so the server doesn't pull in the gui and audio dependencies
+
+pub mod debug_target;
@@ -0,0 +1,294 @@
+//! Runs the emulator without the gui and serves gdb sessions one after another until killed
+//!
+//! e.g. `gdbserver --listen localhost:1234 program.elf` then `target remote localhost:1234` in gdb,
+//! or `target remote | gdbserver --listen stdio program.elf` to let gdb launch the emulator itself
+
+use std::{collections::BTreeMap, error::Error};
+
+use elf::{
+    external::{
+        header::ExternalElfHeaderTrait, program::ExternalProgramHeader32,
+        program::ExternalProgramHeaderTrait, TernaryResult,
+    },
+    internal::program::ProgramHeaderFlags,
+};
+use gdb::{
+    async_target::create_async_stub,
+    connection::Connection,
+    stub::{DisconnectReason, GDBStub},
+    transport::Transport,
+};
+use gdbserver::debug_target::{MipsDebugger, MipsTargetInterface};
+use mips_emulator::{
+    cpu::{DefaultExternalHandler, EmulatorInterface, MipsCpu},
+    memory::{
+        page_pool::{PageProtection, PagedMemoryInterface},
+        single_cached_memory::SingleCachedMemory,
+    },
+};
+
+const USAGE: &str = "\
+usage: gdbserver [OPTIONS] <PROGRAM>
+
+OPTIONS:
+    -l, --listen <TRANSPORT>  where gdb connects: <host:port>, tcp:<host:port>, unix:<path> or stdio
+                              (default localhost:1234)
+    -r, --raw <ADDRESS>       load PROGRAM as a raw image at ADDRESS instead of as an elf file,
+                              execution starts at ADDRESS
+        --run                 start running PROGRAM immediately instead of halting at its entry point,
+                              it is halted whenever gdb connects
+        --once                exit after the first gdb session
+    -h, --help                print this message
+
+The program is reloaded whenever gdb kills it. With --listen stdio nothing else may use
+stdin/stdout, so only a single session is served and logs are written to stderr";
+
+const PT_LOAD: u32 = 1;
+const EM_MIPS: u16 = 8;
+const ELF_BIG_ENDIAN: u8 = 2;
+
+struct Options {
+    transport: Transport,
+    program: String,
+    raw_address: Option<u32>,
+    run: bool,
+    once: bool,
+}
+
+fn parse_address(address: &str) -> Result<u32, String> {
+    let res = if let Some(hex) = address.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        address.parse()
+    };
+    res.map_err(|err| format!("invalid address {address}: {err}"))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut transport = Transport::Tcp("localhost:1234".into());
+    let mut program = None;
+    let mut raw_address = None;
+    let mut run = false;
+    let mut once = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} expects a value"));
+        match arg.as_str() {
+            "-l" | "--listen" => transport = value()?.parse()?,
+            "-r" | "--raw" => raw_address = Some(parse_address(&value()?)?),
+            "--run" => run = true,
+            "--once" => once = true,
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => Err(format!("unknown option {arg}"))?,
+            _ if program.is_some() => Err(format!("unexpected argument {arg}"))?,
+            _ => program = Some(arg),
+        }
+    }
+
+    Ok(Some(Options {
+        once: once || transport == Transport::Stdio,
+        transport,
+        program: program.ok_or("no program given")?,
+        raw_address,
+        run,
+    }))
+}
+
+/// The memory contents and entry point of the program
+struct Image {
+    entry: u32,
+    /// Address, contents and how the contents may be accessed
+    segments: Vec<(u32, Vec<u8>, PageProtection)>,
+}
+
+impl Image {
+    fn raw(data: Vec<u8>, address: u32) -> Self {
+        Self {
+            entry: address,
+            segments: vec![(address, data, PageProtection::default())],
+        }
+    }
+
+    fn elf(data: &[u8]) -> Result<Self, String> {
+        let elf = match elf::external::from_bytes(data) {
+            TernaryResult::Ok1(elf) => elf,
+            TernaryResult::Ok2(_) => Err("64 bit elf files are not supported")?,
+            TernaryResult::Err(()) => Err("invalid elf file")?,
+        };
+        let header = elf.elf_header();
+        if header.endianness() != ELF_BIG_ENDIAN {
+            Err("only big endian elf files are supported")?
+        }
+        if header.machine() != EM_MIPS {
+            Err(format!(
+                "elf file is not for mips (machine {})",
+                header.machine()
+            ))?
+        }
+        let headers_end = header.program_header_offset() as usize
+            + header.program_header_entry_num() as usize
+                * std::mem::size_of::<ExternalProgramHeader32>();
+        if headers_end > data.len() {
+            Err("program headers are outside of the elf file")?
+        }
+
+        let mut segments = Vec::new();
+        let mut index = 0;
+        while let Some(program) = elf.program_header(index) {
+            index += 1;
+            if program.ph_type() != PT_LOAD {
+                continue;
+            }
+            let start = program.offset() as usize;
+            let file_data = start
+                .checked_add(program.filesz() as usize)
+                .and_then(|end| data.get(start..end))
+                .ok_or(format!("segment {} is outside of the elf file", index - 1))?;
+            // memsz covers the zero initialized data (.bss) after what is stored in the file
+            let mut segment = file_data.to_vec();
+            segment.resize((program.memsz() as usize).max(file_data.len()), 0);
+            let flags = ProgramHeaderFlags::from_bits_truncate(program.flags() as u64);
+            let protection = PageProtection {
+                read: flags.contains(ProgramHeaderFlags::PF_R),
+                write: flags.contains(ProgramHeaderFlags::PF_W),
+                execute: flags.contains(ProgramHeaderFlags::PF_X),
+            };
+            segments.push((program.vaddr(), segment, protection));
+        }
+
+        Ok(Self {
+            entry: header.entry_point(),
+            segments,
+        })
+    }
+
+    fn load(&self, emulator: &mut EmulatorInterface<DefaultExternalHandler>) {
+        emulator.cpu_mut(|cpu| {
+            cpu.clear();
+            for (address, data, _) in &self.segments {
+                unsafe {
+                    cpu.get_mem::<SingleCachedMemory>()
+                        .copy_into_raw(*address, data);
+                }
+            }
+            cpu.set_pc(self.entry);
+
+            // segments sharing a page can access it the way either of them can
+            let mut pages: BTreeMap<u16, PageProtection> = BTreeMap::new();
+            for (address, data, protection) in &self.segments {
+                if data.is_empty() {
+                    continue;
+                }
+                let last = address.wrapping_add(data.len() as u32 - 1);
+                for page in (address >> 16)..=(last >> 16) {
+                    pages
+                        .entry(page as u16)
+                        .and_modify(|page| {
+                            page.read |= protection.read;
+                            page.write |= protection.write;
+                            page.execute |= protection.execute;
+                        })
+                        .or_insert(*protection);
+                }
+            }
+            let controller = cpu.get_mem_controller();
+            let mut controller = controller.lock().unwrap();
+            for (page, protection) in pages {
+                controller.set_page_protection(page, protection);
+            }
+        });
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} - {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(&options.program)
+        .map_err(|err| format!("failed to read {}: {}", options.program, err))?;
+    let image = match options.raw_address {
+        Some(address) => Image::raw(data, address),
+        None => Image::elf(&data)?,
+    };
+
+    let mut emulator = MipsCpu::new_interface(DefaultExternalHandler::default());
+    image.load(&mut emulator);
+    if options.run {
+        emulator.start_new_thread()?;
+    }
+
+    let mut listener = options
+        .transport
+        .listen()
+        .map_err(|err| format!("failed to listen on {}: {}", options.transport, err))?;
+    log::info!("Waiting for gdb on {}", options.transport);
+
+    loop {
+        let connection = listener.accept_blocking()?;
+        log::info!(
+            "gdb connected {}",
+            connection.string_repr().unwrap_or_default()
+        );
+
+        // gdb expects the target to be halted when it connects
+        _ = emulator.stop();
+        let target = MipsTargetInterface::new(emulator.clone()).without_reset();
+        let (stub, notifier) = create_async_stub(GDBStub::new(target, connection));
+        emulator.cpu_mut(|cpu| cpu.attach_debugger(MipsDebugger::new(notifier)));
+
+        match stub.run_blocking() {
+            Ok(DisconnectReason::Kill) => {
+                log::info!("Program killed by gdb, reloading it");
+                _ = emulator.stop();
+                image.load(&mut emulator);
+                if options.run {
+                    emulator.start_new_thread()?;
+                }
+            }
+            Ok(reason) => log::info!("gdb session ended: {:?}", reason),
+            Err(err) => log::error!("gdb session ended with an error: {:?}", err),
+        }
+
+        if options.once {
+            return Ok(());
+        }
+    }
+}
+
+fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Info);
+    }
+
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            eprintln!("{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(options) {
+        log::error!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -7,12 +7,15 @@ use std::{
     time::Duration,
 };
 
-use crate::memory::{
-    emulator_memory::Memory,
-    page_pool::{
-        MemoryDefaultAccess, PagePoolController, PagedMemoryImpl, PagedMemoryInterface,
-        SharedPagePoolMemory,
+use crate::{
+    memory::{
+        emulator_memory::Memory,
+        page_pool::{
+            MemoryDefaultAccess, PagePoolController, PagedMemoryImpl, PagedMemoryInterface,
+            SharedPagePoolMemory,
+        },
     },
+    semihosting::{HostSemihosting, SEMIHOSTING_BREAK_CODE},
 };
 
 //macros
@@ -429,7 +432,8 @@ pub trait Debugger<T: CpuExternalHandler>: 'static + Sync + Send {
     fn on_memory_read(&mut self, addr: u32, len: u32, cpu: &mut MipsCpu<T>) -> bool;
     fn on_memory_write(&mut self, addr: u32, len: u32, cpu: &mut MipsCpu<T>) -> bool;
 
-    fn memory_error(&mut self, error_id: u32, cpu: &mut MipsCpu<T>);
+    /// `addr` is the address the faulting load or store used
+    fn memory_error(&mut self, error_id: u32, addr: u32, cpu: &mut MipsCpu<T>);
     fn arithmitic_error(&mut self, error_id: u32, cpu: &mut MipsCpu<T>);
     fn invalid_op_code(&mut self, cpu: &mut MipsCpu<T>);
 }
@@ -492,6 +496,14 @@ impl<T: CpuExternalHandler> MipsCpu<T> {
     pub fn hi(&self) -> u32 {
         self.hi
     }
+    #[inline(always)]
+    pub fn set_lo(&mut self, lo: u32) {
+        self.lo = lo;
+    }
+    #[inline(always)]
+    pub fn set_hi(&mut self, hi: u32) {
+        self.hi = hi;
+    }
 }
 
 ///
@@ -531,7 +543,9 @@ pub unsafe trait CpuExternalHandler: Sync + Send + Sized + 'static {
 }
 
 #[derive(Default)]
-pub struct DefaultExternalHandler {}
+pub struct DefaultExternalHandler {
+    semihosting: HostSemihosting,
+}
 
 impl DefaultExternalHandler {
     fn opcode_address(cpu: &mut MipsCpu<Self>) -> u32 {
@@ -665,8 +679,12 @@ unsafe impl CpuExternalHandler for DefaultExternalHandler {
         );
     }
 
-    fn breakpoint(&mut self, cpu: &mut MipsCpu<Self>, _call_id: u32) {
-        cpu.stop();
+    fn breakpoint(&mut self, cpu: &mut MipsCpu<Self>, call_id: u32) {
+        if call_id == SEMIHOSTING_BREAK_CODE {
+            self.semihosting.handle(cpu);
+        } else {
+            cpu.stop();
+        }
     }
 }
 
@@ -881,9 +899,9 @@ impl<T: CpuExternalHandler> MipsCpu<T> {
     }
     #[inline(never)]
     #[cold]
-    fn memory_error(&mut self, error_id: u32) {
+    fn memory_error(&mut self, error_id: u32, addr: u32) {
         self.if_has_debugger(|cpu, debugger| {
-            debugger.memory_error(error_id, cpu);
+            debugger.memory_error(error_id, addr, cpu);
         });
         unsafe { core::mem::transmute::<&mut T, &mut T>(&mut self.external_handler) }
             .memory_error(self, error_id);
@@ -1603,7 +1621,7 @@ macro_rules! core_emu {
                         //$self.mem.get_i16_alligned(address) as u32
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(0);
+                            $self.memory_error(0, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1619,7 +1637,7 @@ macro_rules! core_emu {
                         //$self.mem.get_u16_alligned(address) as u32
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(0);
+                            $self.memory_error(0, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1635,7 +1653,7 @@ macro_rules! core_emu {
                         //$self.mem.get_u32_alligned(address) as u32
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(1);
+                            $self.memory_error(1, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1652,7 +1670,7 @@ macro_rules! core_emu {
                         //$self.mem.get_u32_alligned(address) as u32
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(1);
+                            $self.memory_error(1, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1668,7 +1686,7 @@ macro_rules! core_emu {
                         } else {
                             $self.reg[immediate_t!(op)] = 0;
                             drop($debugger_lock);
-                            $self.memory_error(4);
+                            $self.memory_error(4, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1693,7 +1711,7 @@ macro_rules! core_emu {
                             set_mem_alligned!($address, $self.reg[immediate_t!(op)] as u16, u16);
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(3);
+                            $self.memory_error(3, $address);
                             break 'cpu_loop;
                         }
                     }
@@ -1707,7 +1725,7 @@ macro_rules! core_emu {
                             set_mem_alligned!($address, $self.reg[immediate_t!(op)], u32);
                         } else {
                             drop($debugger_lock);
-                            $self.memory_error(4);
+                            $self.memory_error(4, $address);
                             break 'cpu_loop;
                         }
                     }
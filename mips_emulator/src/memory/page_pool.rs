@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     error::Error,
     fmt::Debug,
     mem,
@@ -208,10 +209,34 @@ pub struct PagePool {
 
 //------------------------------------------------------------------------------------------------------
 
+/// How a page may be accessed, this only describes the page to debuggers and is not enforced by the cpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageProtection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl PageProtection {
+    pub const READ_WRITE_EXECUTE: Self = Self {
+        read: true,
+        write: true,
+        execute: true,
+    };
+}
+
+impl Default for PageProtection {
+    fn default() -> Self {
+        Self::READ_WRITE_EXECUTE
+    }
+}
+
 pub struct PagePoolController {
     page_pool: PagePool,
     holders: Vec<NonNull<dyn PagedMemoryImpl>>,
     myself: Option<Weak<Mutex<PagePoolController>>>,
+    /// Pages without an entry are `PageProtection::default()`, protections outlive the pages themselves
+    protections: HashMap<u16, PageProtection>,
 }
 
 impl Debug for PagePoolController {
@@ -256,6 +281,7 @@ impl PagePoolController {
             page_pool: PagePool::default(),
             holders: Vec::new(),
             myself: None,
+            protections: HashMap::new(),
         }));
 
         match arc.lock().as_mut() {
@@ -269,6 +295,23 @@ impl PagePoolController {
         arc
     }
 
+    /// The (sorted) numbers of every page that has been allocated, page `n` starts at address `n << 16`
+    pub fn allocated_pages(&self) -> Vec<u16> {
+        self.page_pool.address_mapping.clone()
+    }
+
+    pub fn page_protection(&self, page: u16) -> PageProtection {
+        self.protections.get(&page).copied().unwrap_or_default()
+    }
+
+    pub fn set_page_protection(&mut self, page: u16, protection: PageProtection) {
+        if protection == PageProtection::default() {
+            self.protections.remove(&page);
+        } else {
+            self.protections.insert(page, protection);
+        }
+    }
+
     pub fn add_holder<T: PagedMemoryImpl + 'static>(
         &mut self,
         paged_memory: Box<T>,
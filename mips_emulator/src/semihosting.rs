@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{IsTerminal, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    cpu::{CpuExternalHandler, MipsCpu},
+    memory::page_pool::MemoryDefaultAccess,
+};
+
+/// `break` code used by guest programs to request a semihosted operation.
+///
+/// The operation is selected with `$v0` and its arguments are passed in `$a0`..`$a3`.
+/// When the call returns `$v0` holds the result (`-1` on failure) and `$v1` holds the errno.
+pub const SEMIHOSTING_BREAK_CODE: u32 = 0xF10;
+
+/// Errno values reported to the guest, these match the GDB File-I/O protocol values
+pub mod errno {
+    pub const EPERM: u32 = 1;
+    pub const ENOENT: u32 = 2;
+    pub const EINTR: u32 = 4;
+    pub const EBADF: u32 = 9;
+    pub const EACCES: u32 = 13;
+    pub const EFAULT: u32 = 14;
+    pub const EBUSY: u32 = 16;
+    pub const EEXIST: u32 = 17;
+    pub const ENODEV: u32 = 19;
+    pub const ENOTDIR: u32 = 20;
+    pub const EISDIR: u32 = 21;
+    pub const EINVAL: u32 = 22;
+    pub const ENFILE: u32 = 23;
+    pub const EMFILE: u32 = 24;
+    pub const EFBIG: u32 = 27;
+    pub const ENOSPC: u32 = 28;
+    pub const ESPIPE: u32 = 29;
+    pub const EROFS: u32 = 30;
+    pub const ENAMETOOLONG: u32 = 91;
+    pub const EUNKNOWN: u32 = 9999;
+}
+
+/// Flags accepted by the open call, these match the GDB File-I/O protocol values
+pub mod open_flags {
+    pub const O_RDONLY: u32 = 0x0;
+    pub const O_WRONLY: u32 = 0x1;
+    pub const O_RDWR: u32 = 0x2;
+    pub const O_APPEND: u32 = 0x8;
+    pub const O_CREAT: u32 = 0x200;
+    pub const O_TRUNC: u32 = 0x400;
+    pub const O_EXCL: u32 = 0x800;
+}
+
+/// Whence values accepted by the lseek call
+pub mod seek {
+    pub const SEEK_SET: u32 = 0;
+    pub const SEEK_CUR: u32 = 1;
+    pub const SEEK_END: u32 = 2;
+}
+
+/// Longest path or command the guest can hand to the host (including the null terminator)
+const MAX_STRING_LEN: u32 = 4096;
+
+/// Most bytes a single read or write moves, larger requests return a short count like a pipe would
+const MAX_TRANSFER: u32 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemihostingCall {
+    /// `path_len` includes the null terminator
+    Open {
+        path: u32,
+        path_len: u32,
+        flags: u32,
+        mode: u32,
+    },
+    Close {
+        fd: u32,
+    },
+    Read {
+        fd: u32,
+        buf: u32,
+        count: u32,
+    },
+    Write {
+        fd: u32,
+        buf: u32,
+        count: u32,
+    },
+    Lseek {
+        fd: u32,
+        offset: i32,
+        whence: u32,
+    },
+    GetTimeOfDay {
+        tv: u32,
+        tz: u32,
+    },
+    IsATty {
+        fd: u32,
+    },
+    /// `command_len` includes the null terminator
+    System {
+        command: u32,
+        command_len: u32,
+    },
+}
+
+impl SemihostingCall {
+    pub const OPEN: u32 = 1;
+    pub const CLOSE: u32 = 2;
+    pub const READ: u32 = 3;
+    pub const WRITE: u32 = 4;
+    pub const LSEEK: u32 = 5;
+    pub const GET_TIME_OF_DAY: u32 = 6;
+    pub const IS_A_TTY: u32 = 7;
+    pub const SYSTEM: u32 = 8;
+
+    /// Decodes the call the guest requested from the cpus registers.
+    ///
+    /// Strings are passed as a pointer to a null terminated string, their length is found by scanning guest memory.
+    /// Returns `None` if `$v0` doesnt name a known operation or a string is unterminated
+    pub fn decode<T: CpuExternalHandler>(cpu: &mut MipsCpu<T>) -> Option<Self> {
+        let reg = *cpu.reg();
+        let (a0, a1, a2) = (reg[4], reg[5], reg[6]);
+        Some(match reg[2] {
+            Self::OPEN => Self::Open {
+                path: a0,
+                path_len: string_len(cpu, a0)?,
+                flags: a1,
+                mode: a2,
+            },
+            Self::CLOSE => Self::Close { fd: a0 },
+            Self::READ => Self::Read {
+                fd: a0,
+                buf: a1,
+                count: a2,
+            },
+            Self::WRITE => Self::Write {
+                fd: a0,
+                buf: a1,
+                count: a2,
+            },
+            Self::LSEEK => Self::Lseek {
+                fd: a0,
+                offset: a1 as i32,
+                whence: a2,
+            },
+            Self::GET_TIME_OF_DAY => Self::GetTimeOfDay { tv: a0, tz: a1 },
+            Self::IS_A_TTY => Self::IsATty { fd: a0 },
+            Self::SYSTEM => Self::System {
+                command: a0,
+                command_len: if a0 == 0 { 0 } else { string_len(cpu, a0)? },
+            },
+            _ => None?,
+        })
+    }
+}
+
+/// Writes the result of a semihosted call back into the guests registers
+pub fn set_result<T: CpuExternalHandler>(cpu: &mut MipsCpu<T>, result: Result<u32, u32>) {
+    let (ret, errno) = match result {
+        Ok(ret) => (ret, 0),
+        Err(errno) => (u32::MAX, errno),
+    };
+    cpu.reg_mut()[2] = ret;
+    cpu.reg_mut()[3] = errno;
+}
+
+fn string_len<T: CpuExternalHandler>(cpu: &mut MipsCpu<T>, addr: u32) -> Option<u32> {
+    (0..MAX_STRING_LEN)
+        .find(|i| unsafe { cpu.mem().get_u8_be(addr.wrapping_add(*i)) } == 0)
+        .map(|len| len + 1)
+}
+
+fn read_guest<T: CpuExternalHandler>(cpu: &mut MipsCpu<T>, addr: u32, len: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| unsafe { cpu.mem().get_u8_be(addr.wrapping_add(i)) })
+        .collect()
+}
+
+fn write_guest<T: CpuExternalHandler>(cpu: &mut MipsCpu<T>, addr: u32, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        unsafe { cpu.mem().set_u8_be(addr.wrapping_add(i as u32), *byte) }
+    }
+}
+
+fn io_errno(err: std::io::Error) -> u32 {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::NotFound => errno::ENOENT,
+        ErrorKind::PermissionDenied => errno::EACCES,
+        ErrorKind::AlreadyExists => errno::EEXIST,
+        ErrorKind::InvalidInput => errno::EINVAL,
+        ErrorKind::Interrupted => errno::EINTR,
+        ErrorKind::IsADirectory => errno::EISDIR,
+        ErrorKind::NotADirectory => errno::ENOTDIR,
+        ErrorKind::ReadOnlyFilesystem => errno::EROFS,
+        ErrorKind::StorageFull => errno::ENOSPC,
+        ErrorKind::FileTooLarge => errno::EFBIG,
+        ErrorKind::InvalidFilename => errno::ENAMETOOLONG,
+        ErrorKind::ResourceBusy => errno::EBUSY,
+        _ => errno::EUNKNOWN,
+    }
+}
+
+/// Services semihosted calls directly on the host filesystem.
+///
+/// This is used when no debugger is attached to forward the calls to
+#[derive(Default)]
+pub struct HostSemihosting {
+    files: HashMap<u32, File>,
+}
+
+impl HostSemihosting {
+    /// The first descriptor handed out, 0 1 and 2 are the hosts stdin, stdout and stderr
+    const FIRST_FD: u32 = 3;
+
+    /// Performs the call requested by the guest and writes the result back into its registers
+    pub fn handle<T: CpuExternalHandler>(&mut self, cpu: &mut MipsCpu<T>) {
+        let result = match SemihostingCall::decode(cpu) {
+            Some(call) => self.perform(cpu, call),
+            None => Err(errno::EINVAL),
+        };
+        set_result(cpu, result);
+    }
+
+    pub fn perform<T: CpuExternalHandler>(
+        &mut self,
+        cpu: &mut MipsCpu<T>,
+        call: SemihostingCall,
+    ) -> Result<u32, u32> {
+        match call {
+            SemihostingCall::Open {
+                path,
+                path_len,
+                flags,
+                mode,
+            } => {
+                let path = read_guest(cpu, path, path_len - 1);
+                let path = String::from_utf8(path).map_err(|_| errno::EINVAL)?;
+                self.open(&path, flags, mode)
+            }
+            SemihostingCall::Close { fd } => match fd {
+                0..=2 => Ok(0),
+                _ => self.files.remove(&fd).map(|_| 0).ok_or(errno::EBADF),
+            },
+            SemihostingCall::Read { fd, buf, count } => {
+                let mut data = vec![0; count.min(MAX_TRANSFER) as usize];
+                let read = match fd {
+                    0 => std::io::stdin().read(&mut data),
+                    1 | 2 => Err(errno::EBADF)?,
+                    _ => self.file(fd)?.read(&mut data),
+                }
+                .map_err(io_errno)?;
+                write_guest(cpu, buf, &data[..read]);
+                Ok(read as u32)
+            }
+            SemihostingCall::Write { fd, buf, count } => {
+                let data = read_guest(cpu, buf, count.min(MAX_TRANSFER));
+                match fd {
+                    0 => Err(errno::EBADF)?,
+                    1 => std::io::stdout().write(&data),
+                    2 => std::io::stderr().write(&data),
+                    _ => self.file(fd)?.write(&data),
+                }
+                .map(|written| written as u32)
+                .map_err(io_errno)
+            }
+            SemihostingCall::Lseek { fd, offset, whence } => {
+                let pos = match whence {
+                    seek::SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+                    seek::SEEK_CUR => SeekFrom::Current(offset as i64),
+                    seek::SEEK_END => SeekFrom::End(offset as i64),
+                    _ => Err(errno::EINVAL)?,
+                };
+                let pos = match fd {
+                    0..=2 => Err(errno::ESPIPE)?,
+                    _ => self.file(fd)?.seek(pos).map_err(io_errno)?,
+                };
+                u32::try_from(pos).map_err(|_| errno::EFBIG)
+            }
+            SemihostingCall::GetTimeOfDay { tv, tz } => {
+                if tz != 0 {
+                    return Err(errno::EINVAL);
+                }
+                let time = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_err(|_| errno::EUNKNOWN)?;
+                // struct timeval as laid out by the File-I/O protocol: a 4 byte tv_sec and an 8 byte tv_usec
+                let mut timeval = [0u8; 12];
+                timeval[..4].copy_from_slice(&(time.as_secs() as u32).to_be_bytes());
+                timeval[4..].copy_from_slice(&(time.subsec_micros() as u64).to_be_bytes());
+                write_guest(cpu, tv, &timeval);
+                Ok(0)
+            }
+            SemihostingCall::IsATty { fd } => Ok(match fd {
+                0 => std::io::stdin().is_terminal() as u32,
+                1 => std::io::stdout().is_terminal() as u32,
+                2 => std::io::stderr().is_terminal() as u32,
+                _ => {
+                    self.file(fd)?;
+                    0
+                }
+            }),
+            // running host commands from the guest is never allowed
+            SemihostingCall::System { .. } => Err(errno::EPERM),
+        }
+    }
+
+    fn file(&mut self, fd: u32) -> Result<&mut File, u32> {
+        self.files.get_mut(&fd).ok_or(errno::EBADF)
+    }
+
+    fn open(&mut self, path: &str, flags: u32, mode: u32) -> Result<u32, u32> {
+        let mut options = OpenOptions::new();
+        match flags & 0b11 {
+            open_flags::O_RDONLY => options.read(true),
+            open_flags::O_WRONLY => options.write(true),
+            open_flags::O_RDWR => options.read(true).write(true),
+            _ => Err(errno::EINVAL)?,
+        };
+        options
+            .append(flags & open_flags::O_APPEND != 0)
+            .truncate(flags & open_flags::O_TRUNC != 0);
+        if flags & open_flags::O_CREAT != 0 {
+            if flags & open_flags::O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        let file = options.open(path).map_err(io_errno)?;
+        let fd = (Self::FIRST_FD..)
+            .find(|fd| !self.files.contains_key(fd))
+            .ok_or(errno::EMFILE)?;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+}